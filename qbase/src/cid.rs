@@ -1,5 +1,11 @@
 use bytes::BufMut;
 use nom::{number::streaming::be_u8, IResult};
+use rand::Rng;
+use ring::hmac;
+use std::{fmt::Debug, sync::Arc};
+
+mod quic_lb;
+pub use quic_lb::QuicLbGenerator;
 
 pub const MAX_CID_SIZE: usize = 20;
 pub const RESET_TOKEN_SIZE: usize = 16;
@@ -44,6 +50,17 @@ impl ConnectionId {
         res
     }
 
+    /// 随机生成指定长度的连接ID
+    pub fn random_gen(len: usize) -> Self {
+        debug_assert!(len <= MAX_CID_SIZE);
+        let mut bytes = [0; MAX_CID_SIZE];
+        rand::thread_rng().fill(&mut bytes[..len]);
+        Self {
+            len: len as u8,
+            bytes,
+        }
+    }
+
     pub fn from_buf(input: &[u8], len: usize) -> IResult<&[u8], Self> {
         debug_assert!(len <= MAX_CID_SIZE);
         let (input, bytes) = nom::bytes::complete::take(len)(input)?;
@@ -58,22 +75,74 @@ impl ::std::ops::Deref for ConnectionId {
     }
 }
 
-#[derive(Debug, Copy, Clone, Default)]
+/// 连接ID生成器，端点与连接ID管理器都通过它生成我方的连接ID。
+/// 短包头中没有目标连接ID的长度，端点收包时，也要靠生成器从连接ID本身解析出其长度。
+pub trait ConnectionIdGenerator: Debug + Send + Sync {
+    fn generate(&self) -> ConnectionId;
+
+    /// 传入短包头中目标连接ID起始处的字节，返回该连接ID的长度，无法识别则返回None
+    fn decode_len(&self, dcid: &[u8]) -> Option<usize>;
+}
+
+pub type ArcCidGenerator = Arc<dyn ConnectionIdGenerator>;
+
+/// 默认的连接ID生成器，生成固定长度的随机连接ID
+#[derive(Debug, Clone, Copy)]
+pub struct RandomCidGenerator {
+    len: usize,
+}
+
+impl RandomCidGenerator {
+    pub fn new(len: usize) -> Self {
+        assert!(len <= MAX_CID_SIZE);
+        Self { len }
+    }
+}
+
+impl Default for RandomCidGenerator {
+    fn default() -> Self {
+        Self::new(8)
+    }
+}
+
+impl ConnectionIdGenerator for RandomCidGenerator {
+    fn generate(&self) -> ConnectionId {
+        ConnectionId::random_gen(self.len)
+    }
+
+    fn decode_len(&self, _dcid: &[u8]) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
 pub struct ResetToken([u8; RESET_TOKEN_SIZE]);
 
 impl ResetToken {
     pub fn new_with(bytes: &[u8]) -> Self {
         Self(bytes.try_into().unwrap())
     }
-}
 
-impl PartialEq for ResetToken {
-    fn eq(&self, other: &Self) -> bool {
-        self.0[..] == other.0[..]
+    pub fn random_gen() -> Self {
+        Self(rand::random())
     }
 }
 
-impl Eq for ResetToken {}
+/// 派生无状态重置令牌的静态密钥，令牌为连接ID的HMAC。
+/// 端点重启后只要密钥不变，依然能为重启前签发的连接ID派生出相同的令牌，进而发送无状态重置。
+#[derive(Debug, Clone)]
+pub struct ResetTokenKey(hmac::Key);
+
+impl ResetTokenKey {
+    pub fn new(secret: &[u8]) -> Self {
+        Self(hmac::Key::new(hmac::HMAC_SHA256, secret))
+    }
+
+    pub fn derive(&self, cid: &ConnectionId) -> ResetToken {
+        let tag = hmac::sign(&self.0, cid);
+        ResetToken::new_with(&tag.as_ref()[..RESET_TOKEN_SIZE])
+    }
+}
 
 pub fn be_reset_token(input: &[u8]) -> IResult<&[u8], ResetToken> {
     let (input, bytes) = nom::bytes::complete::take(RESET_TOKEN_SIZE)(input)?;
@@ -96,3 +165,27 @@ impl std::ops::Deref for ResetToken {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derive_reset_token() {
+        let cid = ConnectionId::from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        let key = ResetTokenKey::new(b"static key");
+        // 同一密钥对同一连接ID派生出的令牌不变，以便重启后依然能发送无状态重置
+        assert_eq!(
+            key.derive(&cid),
+            ResetTokenKey::new(b"static key").derive(&cid)
+        );
+        assert_ne!(
+            key.derive(&cid),
+            ResetTokenKey::new(b"other key").derive(&cid)
+        );
+        assert_ne!(
+            key.derive(&cid),
+            key.derive(&ConnectionId::from_slice(&[8, 7, 6, 5, 4, 3, 2, 1]))
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use super::{ConnectionId, ConnectionIdGenerator, MAX_CID_SIZE};
+use aes::{
+    cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+    Aes128,
+};
+use rand::Rng;
+use std::fmt;
+
+/// 配置ID占第一个字节的高3位，0b111保留给不可路由的连接ID
+pub const MAX_CONFIG_ID: u8 = 0b110;
+pub const MIN_NONCE_LEN: usize = 4;
+
+/// 可被4层负载均衡器路由的连接ID生成器，Ref. `<https://datatracker.ietf.org/doc/draft-ietf-quic-load-balancers/>`
+///
+/// 连接ID的格式为`第一个字节 | 服务器ID | nonce`，第一个字节的高3位是配置ID，
+/// 低5位是其后服务器ID与nonce的总长度，即连接ID长度自描述，短包头据此解析出目标连接ID的长度。
+/// 明文模式下服务器ID直接可见；加密模式下，服务器ID与nonce一起用AES-128-ECB加密，
+/// 两者总长度恰为16字节时加密一轮，否则使用四轮Feistel网络。
+#[derive(Clone)]
+pub struct QuicLbGenerator {
+    config_id: u8,
+    server_id: Vec<u8>,
+    nonce_len: usize,
+    cipher: Option<Aes128>,
+}
+
+impl fmt::Debug for QuicLbGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuicLbGenerator")
+            .field("config_id", &self.config_id)
+            .field("server_id", &self.server_id)
+            .field("nonce_len", &self.nonce_len)
+            .field("encrypted", &self.cipher.is_some())
+            .finish()
+    }
+}
+
+impl QuicLbGenerator {
+    fn new(config_id: u8, server_id: &[u8], nonce_len: usize, cipher: Option<Aes128>) -> Self {
+        assert!(config_id <= MAX_CONFIG_ID);
+        assert!(!server_id.is_empty());
+        assert!(nonce_len >= MIN_NONCE_LEN);
+        assert!(1 + server_id.len() + nonce_len <= MAX_CID_SIZE);
+        Self {
+            config_id,
+            server_id: server_id.to_vec(),
+            nonce_len,
+            cipher,
+        }
+    }
+
+    pub fn plaintext(config_id: u8, server_id: &[u8], nonce_len: usize) -> Self {
+        Self::new(config_id, server_id, nonce_len, None)
+    }
+
+    pub fn encrypted(config_id: u8, server_id: &[u8], nonce_len: usize, key: [u8; 16]) -> Self {
+        let cipher = Aes128::new(&GenericArray::from(key));
+        Self::new(config_id, server_id, nonce_len, Some(cipher))
+    }
+
+    fn plaintext_len(&self) -> usize {
+        self.server_id.len() + self.nonce_len
+    }
+
+    /// 负载均衡器从连接ID中解出服务器ID，配置不匹配则返回None
+    pub fn decode_server_id(&self, cid: &[u8]) -> Option<Vec<u8>> {
+        let plaintext_len = self.plaintext_len();
+        if cid.len() != 1 + plaintext_len || cid[0] >> 5 != self.config_id {
+            return None;
+        }
+        let mut plaintext = cid[1..].to_vec();
+        if let Some(cipher) = &self.cipher {
+            if plaintext_len == 16 {
+                cipher.decrypt_block(GenericArray::from_mut_slice(&mut plaintext));
+            } else {
+                four_pass_decrypt(cipher, &mut plaintext);
+            }
+        }
+        plaintext.truncate(self.server_id.len());
+        Some(plaintext)
+    }
+}
+
+impl ConnectionIdGenerator for QuicLbGenerator {
+    fn generate(&self) -> ConnectionId {
+        let mut nonce = [0u8; MAX_CID_SIZE];
+        let nonce = &mut nonce[..self.nonce_len];
+        rand::thread_rng().fill(nonce);
+        self.encode(nonce)
+    }
+
+    fn decode_len(&self, dcid: &[u8]) -> Option<usize> {
+        dcid.first()
+            .map(|first| (first & 0x1f) as usize + 1)
+            .filter(|len| *len <= MAX_CID_SIZE)
+    }
+}
+
+impl QuicLbGenerator {
+    /// 以给定的nonce编码连接ID，nonce须是随机的，或者是不重复的计数
+    fn encode(&self, nonce: &[u8]) -> ConnectionId {
+        assert_eq!(nonce.len(), self.nonce_len);
+        let plaintext_len = self.plaintext_len();
+        let server_id_len = self.server_id.len();
+        let mut bytes = [0u8; MAX_CID_SIZE];
+        bytes[0] = self.config_id << 5 | plaintext_len as u8;
+        bytes[1..1 + server_id_len].copy_from_slice(&self.server_id);
+        bytes[1 + server_id_len..1 + plaintext_len].copy_from_slice(nonce);
+        if let Some(cipher) = &self.cipher {
+            let plaintext = &mut bytes[1..1 + plaintext_len];
+            if plaintext_len == 16 {
+                cipher.encrypt_block(GenericArray::from_mut_slice(plaintext));
+            } else {
+                four_pass_encrypt(cipher, plaintext);
+            }
+        }
+        ConnectionId::from_slice(&bytes[..1 + plaintext_len])
+    }
+}
+
+/// 将text对半拆开，长度为奇数时，中间字节的高4位归左半，低4位归右半
+fn split(text: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let half = text.len().div_ceil(2);
+    let mut left = text[..half].to_vec();
+    let mut right = text[text.len() - half..].to_vec();
+    if text.len() % 2 == 1 {
+        left[half - 1] &= 0xf0;
+        right[0] &= 0x0f;
+    }
+    (left, right)
+}
+
+fn merge(left: &[u8], right: &[u8], text: &mut [u8]) {
+    let half = left.len();
+    text[..half].copy_from_slice(left);
+    if text.len() % 2 == 1 {
+        text[half - 1] |= right[0];
+        text[half..].copy_from_slice(&right[1..]);
+    } else {
+        text[half..].copy_from_slice(right);
+    }
+}
+
+/// 将半边扩展成一个AES分组加密，最后两个字节分别是明文长度与轮次
+fn round(cipher: &Aes128, half: &[u8], plaintext_len: usize, index: u8) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[..half.len()].copy_from_slice(half);
+    block[14] = plaintext_len as u8;
+    block[15] = index;
+    cipher.encrypt_block(GenericArray::from_mut_slice(&mut block));
+    block
+}
+
+fn xor_left(left: &mut [u8], block: &[u8; 16], odd: bool) {
+    left.iter_mut().zip(block.iter()).for_each(|(l, b)| *l ^= b);
+    if odd {
+        *left.last_mut().unwrap() &= 0xf0;
+    }
+}
+
+/// 与左半一样取分组的前几个字节，长度为奇数时只留第一个字节的低4位
+fn xor_right(right: &mut [u8], block: &[u8; 16], odd: bool) {
+    right
+        .iter_mut()
+        .zip(block.iter())
+        .for_each(|(r, b)| *r ^= b);
+    if odd {
+        right[0] &= 0x0f;
+    }
+}
+
+fn four_pass_encrypt(cipher: &Aes128, text: &mut [u8]) {
+    let len = text.len();
+    let odd = len % 2 == 1;
+    let (mut left, mut right) = split(text);
+    xor_right(&mut right, &round(cipher, &left, len, 1), odd);
+    xor_left(&mut left, &round(cipher, &right, len, 2), odd);
+    xor_right(&mut right, &round(cipher, &left, len, 3), odd);
+    xor_left(&mut left, &round(cipher, &right, len, 4), odd);
+    merge(&left, &right, text);
+}
+
+fn four_pass_decrypt(cipher: &Aes128, text: &mut [u8]) {
+    let len = text.len();
+    let odd = len % 2 == 1;
+    let (mut left, mut right) = split(text);
+    xor_left(&mut left, &round(cipher, &right, len, 4), odd);
+    xor_right(&mut right, &round(cipher, &left, len, 3), odd);
+    xor_left(&mut left, &round(cipher, &right, len, 2), odd);
+    xor_right(&mut right, &round(cipher, &left, len, 1), odd);
+    merge(&left, &right, text);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plaintext() {
+        let generator = QuicLbGenerator::plaintext(1, &[0x12, 0x34], 6);
+        let cid = generator.generate();
+        assert_eq!(cid.len(), 9);
+        assert_eq!(cid[0], 0x28);
+        assert_eq!(&cid[1..3], &[0x12, 0x34]);
+        assert_eq!(generator.decode_len(&cid), Some(9));
+        assert_eq!(generator.decode_server_id(&cid), Some(vec![0x12, 0x34]));
+    }
+
+    #[test]
+    fn test_single_pass_encrypted() {
+        let generator = QuicLbGenerator::encrypted(2, &[1, 2, 3, 4, 5, 6], 10, [7; 16]);
+        let cid = generator.generate();
+        assert_eq!(cid.len(), 17);
+        assert_eq!(cid[0] >> 5, 2);
+        assert_eq!(generator.decode_len(&cid), Some(17));
+        assert_eq!(
+            generator.decode_server_id(&cid),
+            Some(vec![1, 2, 3, 4, 5, 6])
+        );
+    }
+
+    #[test]
+    fn test_four_pass_encrypted() {
+        // 奇数长度与偶数长度各验证一次
+        for nonce_len in [4, 5] {
+            let generator = QuicLbGenerator::encrypted(0, &[0xab, 0xcd, 0xef], nonce_len, [9; 16]);
+            let cid = generator.generate();
+            assert_eq!(cid.len(), 1 + 3 + nonce_len);
+            assert_eq!(generator.decode_len(&cid), Some(cid.len()));
+            assert_eq!(
+                generator.decode_server_id(&cid),
+                Some(vec![0xab, 0xcd, 0xef])
+            );
+        }
+    }
+
+    #[test]
+    fn test_config_mismatch() {
+        let generator = QuicLbGenerator::plaintext(1, &[0x12], 4);
+        let other = QuicLbGenerator::plaintext(3, &[0x12], 4);
+        assert_eq!(generator.decode_server_id(&other.generate()), None);
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_draft_vectors() {
+        // Ref. draft-ietf-quic-load-balancers, Appendix B. Test Vectors
+        let generator = QuicLbGenerator::plaintext(0, &hex("c4605e"), 4);
+        assert_eq!(
+            &generator.encode(&hex("4504cc4f"))[..],
+            &hex("07c4605e4504cc4f")[..]
+        );
+
+        // 分别是奇数长度的四轮、恰为16字节的单轮、偶数长度的四轮加密
+        let key: [u8; 16] = hex("8f95f09245765f80256934e50c66207f").try_into().unwrap();
+        for (config_id, server_id, nonce, cid) in [
+            (0, "ed793a", "ee080dbf", "0720b1d07b359d3c"),
+            (
+                2,
+                "ed793a51d49b8f5f",
+                "ee080dbf48c0d1e5",
+                "504dd2d05a7b0de9b2b9907afb5ecf8cc3",
+            ),
+            (
+                0,
+                "ed793a51d49b8f5fab",
+                "ee080dbf48c0d1e55d",
+                "125779c9cc86beb3a3a4a3ca96fce4bfe0cdbc",
+            ),
+        ] {
+            let (server_id, nonce) = (hex(server_id), hex(nonce));
+            let generator = QuicLbGenerator::encrypted(config_id, &server_id, nonce.len(), key);
+            let encoded = generator.encode(&nonce);
+            assert_eq!(&encoded[..], &hex(cid)[..]);
+            assert_eq!(generator.decode_server_id(&encoded), Some(server_id));
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// 时钟，拥塞控制、丢包检测等凡是要取当前时间的地方，都从时钟取，而不直接调用`Instant::now()`，
+/// 以便测试时换成手动拨动的[`MockClock`]，让结果可以精确预期
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// 真实的系统时钟
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// 手动拨动的时钟，不拨动时间就静止不动
+#[derive(Debug, Clone)]
+pub struct MockClock(Arc<Mutex<Instant>>);
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new(Instant::now())
+    }
+}
+
+impl MockClock {
+    pub fn new(start: Instant) -> Self {
+        Self(Arc::new(Mutex::new(start)))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+
+    pub fn set(&self, now: Instant) {
+        *self.0.lock().unwrap() = now;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let start = Instant::now();
+        let clock = MockClock::new(start);
+        assert_eq!(clock.now(), start);
+
+        // 克隆的时钟与原时钟同步拨动
+        let shared: Arc<dyn Clock> = Arc::new(clock.clone());
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(shared.now(), start + Duration::from_millis(100));
+        clock.set(start);
+        assert_eq!(shared.now(), start);
+    }
+}
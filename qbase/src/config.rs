@@ -1,7 +1,10 @@
-use crate::cid::{ConnectionId, ResetToken};
+use crate::{
+    cid::{ConnectionId, ResetToken},
+    error::{Error, ErrorKind},
+};
 
 use super::varint::VarInt;
-use getset::{Getters, MutGetters, Setters};
+use getset::{CopyGetters, Getters, MutGetters, Setters};
 use std::{
     net::{SocketAddrV4, SocketAddrV6},
     time::Duration,
@@ -10,7 +13,7 @@ use std::{
 /// Ref. `<https://www.iana.org/assignments/quic/quic.xhtml>`
 
 // QUIC的config配置
-#[derive(Getters, Setters, MutGetters, Debug, PartialEq)]
+#[derive(Getters, CopyGetters, Setters, MutGetters, Debug, Clone, PartialEq)]
 pub struct TransportParameters {
     #[getset(get = "pub", set = "pub")]
     original_destination_connection_id: Option<ConnectionId>,
@@ -54,9 +57,15 @@ pub struct TransportParameters {
     max_datagram_frame_size: VarInt,
     #[getset(get_copy = "pub", set = "pub")]
     grease_quic_bit: bool,
+    /// 是否启用多路径扩展，双方都启用才能同时使用多条路径，Ref. draft-ietf-quic-multipath
+    #[getset(get_copy = "pub", set = "pub")]
+    enable_multipath: bool,
 }
 
-#[derive(Getters, Setters, MutGetters, Debug, PartialEq)]
+/// 多路径扩展的传输参数标签，不是单字节，须以变长整数编码
+const ENABLE_MULTIPATH_TAG: u64 = 0x0f739bbc1b666d05;
+
+#[derive(Getters, CopyGetters, Setters, MutGetters, Debug, Clone, Copy, PartialEq)]
 pub struct PreferredAddress {
     #[getset(get_copy = "pub", set = "pub")]
     address_v4: Option<SocketAddrV4>,
@@ -68,11 +77,69 @@ pub struct PreferredAddress {
     stateless_reset_token: ResetToken,
 }
 
+impl TransportParameters {
+    /// 服务端接受了0-RTT，新的传输参数不得减小客户端所记住的那些限制，
+    /// 否则客户端在0-RTT中按旧限制发送的数据就可能越界，Ref. RFC 9000 §7.4.1
+    pub fn validate_remembered(&self, remembered: &TransportParameters) -> Result<(), Error> {
+        let limits = [
+            (
+                self.active_connection_id_limit,
+                remembered.active_connection_id_limit,
+            ),
+            (self.initial_max_data, remembered.initial_max_data),
+            (
+                self.initial_max_stream_data_bidi_local,
+                remembered.initial_max_stream_data_bidi_local,
+            ),
+            (
+                self.initial_max_stream_data_bidi_remote,
+                remembered.initial_max_stream_data_bidi_remote,
+            ),
+            (
+                self.initial_max_stream_data_uni,
+                remembered.initial_max_stream_data_uni,
+            ),
+            (
+                self.initial_max_streams_bidi,
+                remembered.initial_max_streams_bidi,
+            ),
+            (
+                self.initial_max_streams_uni,
+                remembered.initial_max_streams_uni,
+            ),
+        ];
+        if limits.iter().any(|(new, old)| new < old) {
+            return Err(Error::new_with_default_fty(
+                ErrorKind::ProtocolViolation,
+                "server reduced the remembered transport parameters after accepting 0-RTT",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl PreferredAddress {
+    /// 服务端的首选地址，connection_id的序号为1，Ref. RFC 9000 §5.1.1
+    pub fn new(
+        address_v4: Option<SocketAddrV4>,
+        address_v6: Option<SocketAddrV6>,
+        connection_id: ConnectionId,
+        stateless_reset_token: ResetToken,
+    ) -> Self {
+        Self {
+            address_v4,
+            address_v6,
+            connection_id,
+            stateless_reset_token,
+        }
+    }
+}
+
 pub mod ext {
     use std::time::Duration;
 
     use bytes::BufMut;
-    use nom::{combinator::map, number::complete::be_u8};
+    use nom::combinator::map;
 
     use crate::{
         cid::{
@@ -82,7 +149,7 @@ pub mod ext {
         varint::{be_varint, VarInt, WriteVarInt},
     };
 
-    use super::{PreferredAddress, TransportParameters};
+    use super::{PreferredAddress, TransportParameters, ENABLE_MULTIPATH_TAG};
     pub fn be_transport_parameters(input: &[u8]) -> nom::IResult<&[u8], TransportParameters> {
         let be_connection_id = |input| {
             let (remain, cid) = cid::be_connection_id(input)?;
@@ -91,7 +158,7 @@ pub mod ext {
 
         let be_max_idle_timeout = |input| {
             let (remain, timeout) = be_varint(input)?;
-            Ok((remain, Duration::from_secs(timeout.0)))
+            Ok((remain, Duration::from_millis(timeout.0)))
         };
 
         let be_preferred_address = |input| {
@@ -107,9 +174,10 @@ pub mod ext {
         let mut remain = input;
         let mut tp = TransportParameters::default();
         while !remain.is_empty() {
-            let tag: u8;
-            (remain, tag) = be_u8(remain)?;
-            match tag {
+            // 单字节的标签与其变长整数编码一致，以变长整数解析可兼容扩展的多字节标签
+            let tag: VarInt;
+            (remain, tag) = be_varint(remain)?;
+            match tag.into_inner() {
                 0x00 => (remain, tp.original_destination_connection_id) = be_connection_id(remain)?,
                 0x01 => (remain, tp.max_idle_timeout) = be_max_idle_timeout(remain)?,
                 0x02 => (remain, tp.statelss_reset_token) = be_reset_token(remain)?,
@@ -127,6 +195,7 @@ pub mod ext {
                 0x0e => (remain, tp.active_connection_id_limit) = be_varint(remain)?,
                 0x0f => (remain, tp.initial_source_connection_id) = be_connection_id(remain)?,
                 0x10 => (remain, tp.retry_source_connection_id) = be_connection_id(remain)?,
+                ENABLE_MULTIPATH_TAG => tp.enable_multipath = true,
                 _ => {
                     unreachable!("unknown transport parameter tag: {}", tag)
                 }
@@ -136,7 +205,7 @@ pub mod ext {
         Ok((remain, tp))
     }
 
-    pub(crate) trait BufMutExt {
+    pub trait BufMutExt {
         fn put_transport_parameters(&mut self, params: &TransportParameters);
         fn put_preferred_address(&mut self, addr: &super::PreferredAddress);
     }
@@ -173,7 +242,12 @@ pub mod ext {
                 };
 
             put_connection_id(self, 0x00, &params.original_destination_connection_id);
-            put_varint(self, 0x01, VarInt(params.max_idle_timeout.as_secs()));
+            // 单位为毫秒，Ref. RFC 9000 §18.2
+            put_varint(
+                self,
+                0x01,
+                VarInt(params.max_idle_timeout.as_millis() as u64),
+            );
             put_reset_token(self, 0x02, &params.statelss_reset_token);
             put_varint(self, 0x03, params.max_udp_payload_size);
             put_varint(self, 0x04, params.initial_max_data);
@@ -191,6 +265,9 @@ pub mod ext {
             put_varint(self, 0x0e, params.active_connection_id_limit);
             put_connection_id(self, 0x0f, &params.initial_source_connection_id);
             put_connection_id(self, 0x10, &params.retry_source_connection_id);
+            if params.enable_multipath {
+                self.put_varint(&VarInt(ENABLE_MULTIPATH_TAG));
+            }
         }
 
         fn put_preferred_address(&mut self, addr: &super::PreferredAddress) {
@@ -277,6 +354,7 @@ impl Default for TransportParameters {
             version_information: None,
             max_datagram_frame_size: VarInt(0),
             grease_quic_bit: false,
+            enable_multipath: false,
         }
     }
 }
@@ -326,6 +404,7 @@ mod test {
             version_information: None,
             max_datagram_frame_size: VarInt(0),
             grease_quic_bit: false,
+            enable_multipath: true,
         };
 
         let mut buf = bytes::BytesMut::new();
@@ -333,4 +412,20 @@ mod test {
         let params2 = ext::be_transport_parameters(&buf).unwrap().1;
         assert_eq!(params, params2);
     }
+
+    #[test]
+    fn validate_remembered() {
+        let mut remembered = TransportParameters::default();
+        remembered.set_initial_max_data(VarInt(1000));
+        remembered.set_initial_max_streams_bidi(VarInt(10));
+
+        let mut params = remembered.clone();
+        params.set_initial_max_data(VarInt(2000));
+        params.set_max_idle_timeout(Duration::from_secs(1));
+        assert!(params.validate_remembered(&remembered).is_ok());
+
+        params.set_initial_max_streams_bidi(VarInt(5));
+        let err = params.validate_remembered(&remembered).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ProtocolViolation);
+    }
 }
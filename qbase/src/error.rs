@@ -147,11 +147,23 @@ impl From<Error> for crate::frame::ConnectionCloseFrame {
         Self {
             error_kind: e.kind,
             frame_type: Some(e.frame_type),
+            app_error_code: VarInt(0),
             reason: e.reason,
         }
     }
 }
 
+/// 对方发来的CONNECTION_CLOSE，以错误的形式通知到各个流。应用层的关闭没有帧类型，以Padding代替
+impl From<crate::frame::ConnectionCloseFrame> for Error {
+    fn from(frame: crate::frame::ConnectionCloseFrame) -> Self {
+        Self {
+            kind: frame.error_kind,
+            frame_type: frame.frame_type.unwrap_or(FrameType::Padding),
+            reason: frame.reason,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -28,8 +28,11 @@ mod max_streams;
 mod new_connection_id;
 mod new_token;
 mod padding;
+mod path_abandon;
+mod path_ack;
 mod path_challenge;
 mod path_response;
+mod path_status;
 mod ping;
 mod reset_stream;
 mod retire_connection_id;
@@ -53,8 +56,11 @@ pub use max_streams::MaxStreamsFrame;
 pub use new_connection_id::NewConnectionIdFrame;
 pub use new_token::NewTokenFrame;
 pub use padding::PaddingFrame;
+pub use path_abandon::PathAbandonFrame;
+pub use path_ack::PathAckFrame;
 pub use path_challenge::PathChallengeFrame;
 pub use path_response::PathResponseFrame;
+pub use path_status::{PathStatus, PathStatusFrame};
 pub use ping::PingFrame;
 pub use reset_stream::ResetStreamFrame;
 pub use retire_connection_id::RetireConnectionIdFrame;
@@ -88,6 +94,10 @@ pub enum FrameType {
     PathResponse,
     ConnectionClose(u8),
     HandshakeDone,
+    // 多路径扩展的帧，Ref. draft-ietf-quic-multipath
+    PathAck(u8),
+    PathAbandon,
+    PathStatus,
 }
 
 impl TryFrom<VarInt> for FrameType {
@@ -120,6 +130,10 @@ impl TryFrom<VarInt> for FrameType {
             // The last bit is the layer flag bit, 0 indicates application layer, 1 indicates transport layer.
             ty @ (0x1c | 0x1d) => FrameType::ConnectionClose(ty as u8 & 0x1),
             0x1e => FrameType::HandshakeDone,
+            // The last bit is the ECN flag.
+            ty @ (0x15228c00 | 0x15228c01) => FrameType::PathAck(ty as u8 & 0b1),
+            0x15228c05 => FrameType::PathAbandon,
+            0x15228c06 => FrameType::PathStatus,
             _ => return Err(Self::Error::InvalidType(frame_type)),
         })
     }
@@ -148,6 +162,9 @@ impl From<FrameType> for VarInt {
             FrameType::PathResponse => VarInt(0x1b),
             FrameType::ConnectionClose(layer) => VarInt(0x1c | layer as u64),
             FrameType::HandshakeDone => VarInt(0x1e),
+            FrameType::PathAck(ecn) => VarInt(0x15228c00 | ecn as u64),
+            FrameType::PathAbandon => VarInt(0x15228c05),
+            FrameType::PathStatus => VarInt(0x15228c06),
         }
     }
 }
@@ -162,6 +179,8 @@ pub enum ConnFrame {
     NewConnectionId(NewConnectionIdFrame),
     RetireConnectionId(RetireConnectionIdFrame),
     HandshakeDone(HandshakeDoneFrame),
+    PathAbandon(PathAbandonFrame),
+    PathStatus(PathStatusFrame),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -195,6 +214,7 @@ pub enum PureFrame {
     Padding(PaddingFrame),
     Ping(PingFrame),
     Ack(AckFrame),
+    PathAck(PathAckFrame),
     Conn(ConnFrame),
     Stream(StreamCtlFrame),
     Path(PathFrame),
@@ -57,6 +57,7 @@ impl super::BeFrame for AckFrame {
     fn encoding_size(&self) -> usize {
         1 + self.largest.encoding_size()
             + self.delay.encoding_size()
+            + VarInt::try_from(self.ranges.len()).unwrap().encoding_size()
             + self.first_range.encoding_size()
             + self
                 .ranges
@@ -159,6 +160,7 @@ pub(super) fn be_ecn_counts(input: &[u8]) -> nom::IResult<&[u8], EcnCounts> {
 
 pub trait WriteAckFrame {
     fn put_ecn_counts(&mut self, ecn: &EcnCounts);
+    fn put_ack_body(&mut self, frame: &AckFrame);
     fn put_ack_frame(&mut self, frame: &AckFrame);
 }
 
@@ -169,12 +171,8 @@ impl<T: bytes::BufMut> WriteAckFrame for T {
         self.put_varint(&ecn.ce);
     }
 
-    fn put_ack_frame(&mut self, frame: &AckFrame) {
-        let mut frame_type = ACK_FRAME_TYPE;
-        if frame.ecn.is_some() {
-            frame_type |= ECN_OPT;
-        }
-        self.put_u8(frame_type);
+    /// 写入AckFrame除帧类型以外的部分，PathAckFrame复用之
+    fn put_ack_body(&mut self, frame: &AckFrame) {
         self.put_varint(&frame.largest);
         self.put_varint(&frame.delay);
 
@@ -189,6 +187,15 @@ impl<T: bytes::BufMut> WriteAckFrame for T {
             self.put_ecn_counts(ecn);
         }
     }
+
+    fn put_ack_frame(&mut self, frame: &AckFrame) {
+        let mut frame_type = ACK_FRAME_TYPE;
+        if frame.ecn.is_some() {
+            frame_type |= ECN_OPT;
+        }
+        self.put_u8(frame_type);
+        self.put_ack_body(frame);
+    }
 }
 
 #[cfg(test)]
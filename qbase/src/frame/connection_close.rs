@@ -12,15 +12,20 @@ use std::borrow::Cow;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConnectionCloseFrame {
+    // 应用层的关闭，错误类型总是Application
     pub error_kind: ErrorKind,
+    // 传输层的关闭才有引发错误的帧类型，应用层的关闭为None
     pub frame_type: Option<FrameType>,
+    // 应用层的关闭所带的错误码，由应用层协议定义，传输层的关闭不用
+    pub app_error_code: VarInt,
     pub reason: Cow<'static, str>, //String,
 }
 
 const CONNECTION_CLOSE_FRAME_TYPE: u8 = 0x1c;
 
-const QUIC_LAYER: u8 = 1;
-const APP_LAYER: u8 = 0;
+// 传输层的关闭为0x1c，应用层的为0x1d
+const QUIC_LAYER: u8 = 0;
+const APP_LAYER: u8 = 1;
 
 impl super::BeFrame for ConnectionCloseFrame {
     fn frame_type(&self) -> FrameType {
@@ -52,7 +57,7 @@ impl super::BeFrame for ConnectionCloseFrame {
     }
 
     fn encoding_size(&self) -> usize {
-        1 + VarInt::from(self.error_kind).encoding_size()
+        1 + self.error_code().encoding_size()
             + if let Some(frame_type) = self.frame_type {
                 VarInt::from(frame_type).encoding_size()
             } else {
@@ -65,17 +70,41 @@ impl super::BeFrame for ConnectionCloseFrame {
 }
 
 impl ConnectionCloseFrame {
+    /// frame_type为None即是应用层的关闭，其错误码为0；要带上应用层的错误码，用[`Self::new_app`]
     pub fn new(
         error_kind: ErrorKind,
         frame_type: Option<FrameType>,
         reason: Cow<'static, str>,
     ) -> Self {
+        let error_kind = match frame_type {
+            Some(_) => error_kind,
+            None => ErrorKind::Application,
+        };
         Self {
             error_kind,
             frame_type,
+            app_error_code: VarInt(0),
+            reason,
+        }
+    }
+
+    /// 应用层的关闭，以类型为0x1d的CONNECTION_CLOSE帧发送
+    pub fn new_app(error_code: VarInt, reason: Cow<'static, str>) -> Self {
+        Self {
+            error_kind: ErrorKind::Application,
+            frame_type: None,
+            app_error_code: error_code,
             reason,
         }
     }
+
+    /// 帧中的错误码，传输层的是错误类型的编码，应用层的则是应用层的错误码
+    pub fn error_code(&self) -> VarInt {
+        match self.frame_type {
+            Some(_) => self.error_kind.into(),
+            None => self.app_error_code,
+        }
+    }
 }
 
 // nom parser for CONNECTION_CLOSE_FRAME
@@ -86,19 +115,25 @@ pub fn connection_close_frame_at_layer(
     use nom::bytes::streaming::take;
     move |input: &[u8]| {
         let (remain, error_code) = be_varint(input)?;
-        let kind = ErrorKind::try_from(error_code).map_err(|_e| {
-            nom::Err::Error(nom::error::make_error(input, nom::error::ErrorKind::Alt))
-        })?;
-        let (remain, frame_type) = if layer == QUIC_LAYER {
+        // 应用层的错误码由应用层协议定义，不必是传输层的错误类型
+        let (remain, kind, frame_type) = if layer == QUIC_LAYER {
+            let kind = ErrorKind::try_from(error_code).map_err(|_e| {
+                nom::Err::Error(nom::error::make_error(input, nom::error::ErrorKind::Alt))
+            })?;
             let (remain, frame_type) = be_varint(remain)?;
             (
                 remain,
+                kind,
                 Some(FrameType::try_from(frame_type).map_err(|_e| {
                     nom::Err::Error(nom::error::make_error(input, nom::error::ErrorKind::Alt))
                 })?),
             )
         } else {
-            (remain, None)
+            (remain, ErrorKind::Application, None)
+        };
+        let app_error_code = match frame_type {
+            Some(_) => VarInt(0),
+            None => error_code,
         };
         let (remain, rease_length) = be_varint(remain)?;
         let (remain, reason) = take(rease_length.into_inner() as usize)(remain)?;
@@ -108,6 +143,7 @@ pub fn connection_close_frame_at_layer(
             ConnectionCloseFrame {
                 error_kind: kind,
                 frame_type,
+                app_error_code,
                 reason: Cow::Owned(cow),
             },
         ))
@@ -126,7 +162,7 @@ impl<T: bytes::BufMut> WriteConnectionCloseFrame for T {
             APP_LAYER
         };
         self.put_u8(CONNECTION_CLOSE_FRAME_TYPE | layer);
-        self.put_varint(&frame.error_kind.into());
+        self.put_varint(&frame.error_code());
         if let Some(frame_type) = frame.frame_type {
             self.put_varint(&frame_type.into());
         }
@@ -145,7 +181,7 @@ mod tests {
         use crate::varint::be_varint;
         use nom::combinator::flat_map;
         let buf = vec![
-            super::CONNECTION_CLOSE_FRAME_TYPE,
+            super::CONNECTION_CLOSE_FRAME_TYPE | super::APP_LAYER,
             0x0c,
             5,
             b'w',
@@ -155,8 +191,10 @@ mod tests {
             b'g',
         ];
         let (input, frame) = flat_map(be_varint, |frame_type| {
-            if frame_type.into_inner() == super::CONNECTION_CLOSE_FRAME_TYPE as u64 {
-                connection_close_frame_at_layer(0)
+            if frame_type.into_inner()
+                == (super::CONNECTION_CLOSE_FRAME_TYPE | super::APP_LAYER) as u64
+            {
+                connection_close_frame_at_layer(super::APP_LAYER)
             } else {
                 panic!("wrong frame type: {}", frame_type)
             }
@@ -168,6 +206,7 @@ mod tests {
             super::ConnectionCloseFrame {
                 error_kind: ErrorKind::Application,
                 frame_type: None,
+                app_error_code: crate::varint::VarInt(0x0c),
                 reason: "wrong".into(),
             }
         );
@@ -177,11 +216,11 @@ mod tests {
     fn test_write_connection_close_frame() {
         use super::{FrameType, WriteConnectionCloseFrame};
         let mut buf = Vec::<u8>::new();
-        let frame = super::ConnectionCloseFrame {
-            error_kind: ErrorKind::FlowControl,
-            frame_type: Some(FrameType::Stream(0b110)),
-            reason: "wrong".into(),
-        };
+        let frame = super::ConnectionCloseFrame::new(
+            ErrorKind::FlowControl,
+            Some(FrameType::Stream(0b110)),
+            "wrong".into(),
+        );
         buf.put_connection_close_frame(&frame);
         assert_eq!(
             buf,
@@ -198,4 +237,23 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_app_connection_close_frame() {
+        use super::{connection_close_frame_at_layer, WriteConnectionCloseFrame, APP_LAYER};
+        use crate::{frame::BeFrame, varint::VarInt};
+
+        // 应用层的错误码不必是传输层的错误类型
+        let frame = super::ConnectionCloseFrame::new_app(VarInt(0x1234), "bye".into());
+        let mut buf = Vec::<u8>::new();
+        buf.put_connection_close_frame(&frame);
+        assert_eq!(buf, vec![0x1d, 0x52, 0x34, 3, b'b', b'y', b'e']);
+        assert_eq!(buf.len(), frame.encoding_size());
+
+        let (remain, parsed) = connection_close_frame_at_layer(APP_LAYER)(&buf[1..]).unwrap();
+        assert!(remain.is_empty());
+        assert_eq!(parsed, frame);
+        assert_eq!(parsed.error_kind, ErrorKind::Application);
+        assert_eq!(parsed.error_code(), VarInt(0x1234));
+    }
 }
@@ -3,10 +3,12 @@ use super::{
     crypto::be_crypto_frame, data_blocked::be_data_blocked_frame, max_data::be_max_data_frame,
     max_stream_data::be_max_stream_data_frame, max_streams::max_streams_frame_with_dir,
     new_connection_id::be_new_connection_id_frame, new_token::be_new_token_frame,
-    new_token::WriteNewTokenFrame, path_challenge::be_path_challenge_frame,
-    path_response::be_path_response_frame, reset_stream::be_reset_stream_frame,
-    retire_connection_id::be_retire_connection_id_frame, stop_sending::be_stop_sending_frame,
-    stream::stream_frame_with_flag, stream_data_blocked::be_stream_data_blocked_frame,
+    new_token::WriteNewTokenFrame, path_abandon::be_path_abandon_frame,
+    path_ack::path_ack_frame_with_flag, path_challenge::be_path_challenge_frame,
+    path_response::be_path_response_frame, path_status::be_path_status_frame,
+    reset_stream::be_reset_stream_frame, retire_connection_id::be_retire_connection_id_frame,
+    stop_sending::be_stop_sending_frame, stream::stream_frame_with_flag,
+    stream_data_blocked::be_stream_data_blocked_frame,
     streams_blocked::streams_blocked_frame_with_dir, *,
 };
 use bytes::Bytes;
@@ -53,6 +55,15 @@ fn complete_frame(
         FrameType::Ack(ecn) => {
             map(ack_frame_with_flag(ecn), |f| Frame::Pure(PureFrame::Ack(f)))(input)
         }
+        FrameType::PathAck(ecn) => map(path_ack_frame_with_flag(ecn), |f| {
+            Frame::Pure(PureFrame::PathAck(f))
+        })(input),
+        FrameType::PathAbandon => map(be_path_abandon_frame, |f| {
+            Frame::Pure(PureFrame::Conn(ConnFrame::PathAbandon(f)))
+        })(input),
+        FrameType::PathStatus => map(be_path_status_frame, |f| {
+            Frame::Pure(PureFrame::Conn(ConnFrame::PathStatus(f)))
+        })(input),
         FrameType::ResetStream => map(be_reset_stream_frame, |f| {
             Frame::Pure(PureFrame::Stream(f.into()))
         })(input),
@@ -126,7 +137,8 @@ use super::{
     data_blocked::WriteDataBlockedFrame, handshake_done::WriteHandshakeDoneFrame,
     max_data::WriteMaxDataFrame, max_stream_data::WriteMaxStreamDataFrame,
     max_streams::WriteMaxStreamsFrame, new_connection_id::WriteNewConnectionIdFrame,
-    path_challenge::WritePathChallengeFrame, path_response::WritePathResponseFrame,
+    path_abandon::WritePathAbandonFrame, path_challenge::WritePathChallengeFrame,
+    path_response::WritePathResponseFrame, path_status::WritePathStatusFrame,
     reset_stream::WriteResetStreamFrame, retire_connection_id::WriteRetireConnectionIdFrame,
     stop_sending::WriteStopSendingFrame, stream_data_blocked::WriteStreamDataBlockedFrame,
     streams_blocked::WriteStreamsBlockedFrame,
@@ -134,7 +146,8 @@ use super::{
 
 pub use super::{
     ack::WriteAckFrame, connection_close::WriteConnectionCloseFrame, crypto::WriteCryptoFrame,
-    padding::WritePaddingFrame, ping::WritePingFrame, stream::WriteStreamFrame,
+    padding::WritePaddingFrame, path_ack::WritePathAckFrame, ping::WritePingFrame,
+    stream::WriteStreamFrame,
 };
 
 pub trait WriteFrame<F> {
@@ -155,6 +168,8 @@ impl<T: bytes::BufMut> WriteFrame<ConnFrame> for T {
             ConnFrame::NewConnectionId(frame) => self.put_new_connection_id_frame(frame),
             ConnFrame::RetireConnectionId(frame) => self.put_retire_connection_id_frame(frame),
             ConnFrame::HandshakeDone(_) => self.put_handshake_done_frame(),
+            ConnFrame::PathAbandon(frame) => self.put_path_abandon_frame(frame),
+            ConnFrame::PathStatus(frame) => self.put_path_status_frame(frame),
         }
     }
 }
@@ -187,6 +202,7 @@ impl<T: bytes::BufMut> WriteFrame<PureFrame> for T {
             PureFrame::Padding(_) => self.put_padding_frame(),
             PureFrame::Ping(_) => self.put_ping_frame(),
             PureFrame::Ack(frame) => self.put_ack_frame(frame),
+            PureFrame::PathAck(frame) => self.put_path_ack_frame(frame),
             PureFrame::Conn(frame) => self.put_frame(frame),
             PureFrame::Stream(frame) => self.put_frame(frame),
             PureFrame::Path(frame) => self.put_frame(frame),
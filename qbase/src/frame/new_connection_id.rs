@@ -41,11 +41,15 @@ impl super::BeFrame for NewConnectionIdFrame {
     }
 
     fn encoding_size(&self) -> usize {
-        todo!()
+        1 + self.sequence.encoding_size()
+            + self.retire_prior_to.encoding_size()
+            + 1
+            + self.id.len as usize
+            + RESET_TOKEN_SIZE
     }
 
     fn max_encoding_size(&self) -> usize {
-        todo!()
+        1 + 8 + 8 + 1 + crate::cid::MAX_CID_SIZE + RESET_TOKEN_SIZE
     }
 }
 
@@ -0,0 +1,93 @@
+// PATH_ABANDON Frame {
+//   Type (i) = 0x15228c05,
+//   Path Identifier (i),
+//   Error Code (i),
+// }
+
+use crate::{
+    packet::r#type::Type,
+    varint::{be_varint, VarInt, WriteVarInt},
+};
+
+/// 通知对方放弃某条路径，该路径上不再收发数据包
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathAbandonFrame {
+    pub path_id: VarInt,
+    pub error_code: VarInt,
+}
+
+const PATH_ABANDON_FRAME_TYPE: u64 = 0x15228c05;
+
+impl super::BeFrame for PathAbandonFrame {
+    fn frame_type(&self) -> super::FrameType {
+        super::FrameType::PathAbandon
+    }
+
+    fn belongs_to(&self, packet_type: Type) -> bool {
+        use crate::packet::r#type::short::OneRtt;
+        // ___1
+        matches!(packet_type, Type::Short(OneRtt(_)))
+    }
+
+    fn max_encoding_size(&self) -> usize {
+        4 + 8 + 8
+    }
+
+    fn encoding_size(&self) -> usize {
+        4 + self.path_id.encoding_size() + self.error_code.encoding_size()
+    }
+}
+
+// nom parser for PATH_ABANDON_FRAME
+pub fn be_path_abandon_frame(input: &[u8]) -> nom::IResult<&[u8], PathAbandonFrame> {
+    use nom::{combinator::map, sequence::pair};
+    map(pair(be_varint, be_varint), |(path_id, error_code)| {
+        PathAbandonFrame {
+            path_id,
+            error_code,
+        }
+    })(input)
+}
+
+// BufMut extension trait for PATH_ABANDON_FRAME
+pub trait WritePathAbandonFrame {
+    fn put_path_abandon_frame(&mut self, frame: &PathAbandonFrame);
+}
+
+impl<T: bytes::BufMut> WritePathAbandonFrame for T {
+    fn put_path_abandon_frame(&mut self, frame: &PathAbandonFrame) {
+        self.put_varint(&VarInt(PATH_ABANDON_FRAME_TYPE));
+        self.put_varint(&frame.path_id);
+        self.put_varint(&frame.error_code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{be_path_abandon_frame, PathAbandonFrame, WritePathAbandonFrame};
+    use crate::varint::VarInt;
+
+    #[test]
+    fn test_read_path_abandon_frame() {
+        let buf = vec![0x01, 0x52, 0x34];
+        let (remain, frame) = be_path_abandon_frame(&buf).unwrap();
+        assert_eq!(remain, &[]);
+        assert_eq!(
+            frame,
+            PathAbandonFrame {
+                path_id: VarInt(1),
+                error_code: VarInt(0x1234),
+            }
+        );
+    }
+
+    #[test]
+    fn test_write_path_abandon_frame() {
+        let mut buf = Vec::new();
+        buf.put_path_abandon_frame(&PathAbandonFrame {
+            path_id: VarInt(1),
+            error_code: VarInt(0x1234),
+        });
+        assert_eq!(buf, vec![0x95, 0x22, 0x8c, 0x05, 0x01, 0x52, 0x34]);
+    }
+}
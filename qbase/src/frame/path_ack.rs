@@ -0,0 +1,124 @@
+// PATH_ACK Frame {
+//   Type (i) = 0x15228c00..0x15228c01,
+//   Path Identifier (i),
+//   Largest Acknowledged (i),
+//   ACK Delay (i),
+//   ACK Range Count (i),
+//   First ACK Range (i),
+//   ACK Range (..) ...,
+//   [ECN Counts (..)],
+// }
+
+use super::ack::{ack_frame_with_flag, AckFrame, WriteAckFrame};
+use crate::{
+    packet::r#type::Type,
+    varint::{be_varint, VarInt, WriteVarInt},
+};
+
+/// 多路径下，确认某条路径包号空间中的数据包
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PathAckFrame {
+    pub path_id: VarInt,
+    pub ack: AckFrame,
+}
+
+const PATH_ACK_FRAME_TYPE: u64 = 0x15228c00;
+
+const ECN_OPT: u64 = 0x1;
+
+impl super::BeFrame for PathAckFrame {
+    fn frame_type(&self) -> super::FrameType {
+        super::FrameType::PathAck(if self.ack.ecn.is_some() { 1 } else { 0 })
+    }
+
+    fn belongs_to(&self, packet_type: Type) -> bool {
+        use crate::packet::r#type::short::OneRtt;
+        // ___1，多路径仅在握手完成后使用
+        matches!(packet_type, Type::Short(OneRtt(_)))
+    }
+
+    fn max_encoding_size(&self) -> usize {
+        // 帧类型占4字节，而AckFrame的估计中帧类型只占1字节
+        3 + 8 + self.ack.max_encoding_size()
+    }
+
+    fn encoding_size(&self) -> usize {
+        3 + self.path_id.encoding_size() + self.ack.encoding_size()
+    }
+}
+
+pub fn path_ack_frame_with_flag(
+    ecn_flag: u8,
+) -> impl Fn(&[u8]) -> nom::IResult<&[u8], PathAckFrame> {
+    move |input: &[u8]| {
+        let (input, path_id) = be_varint(input)?;
+        let (input, ack) = ack_frame_with_flag(ecn_flag)(input)?;
+        Ok((input, PathAckFrame { path_id, ack }))
+    }
+}
+
+pub trait WritePathAckFrame {
+    fn put_path_ack_frame(&mut self, frame: &PathAckFrame);
+}
+
+impl<T: bytes::BufMut> WritePathAckFrame for T {
+    fn put_path_ack_frame(&mut self, frame: &PathAckFrame) {
+        let mut frame_type = PATH_ACK_FRAME_TYPE;
+        if frame.ack.ecn.is_some() {
+            frame_type |= ECN_OPT;
+        }
+        self.put_varint(&VarInt(frame_type));
+        self.put_varint(&frame.path_id);
+        self.put_ack_body(&frame.ack);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{path_ack_frame_with_flag, PathAckFrame, WritePathAckFrame};
+    use crate::{
+        frame::{ack::AckFrame, BeFrame},
+        varint::VarInt,
+    };
+
+    #[test]
+    fn test_read_path_ack_frame() {
+        let input = vec![0x01, 0x52, 0x34, 0x52, 0x34, 0x01, 0x52, 0x34, 3, 20];
+        let (input, frame) = path_ack_frame_with_flag(0)(&input).unwrap();
+        assert_eq!(input, &[]);
+        assert_eq!(
+            frame,
+            PathAckFrame {
+                path_id: VarInt(1),
+                ack: AckFrame {
+                    largest: VarInt(0x1234),
+                    delay: VarInt(0x1234),
+                    first_range: VarInt(0x1234),
+                    ranges: vec![(VarInt(3), VarInt(20))],
+                    ecn: None,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_write_path_ack_frame() {
+        let mut buf = Vec::new();
+        let frame = PathAckFrame {
+            path_id: VarInt(1),
+            ack: AckFrame {
+                largest: VarInt(0x1234),
+                delay: VarInt(0x1234),
+                first_range: VarInt(0x1234),
+                ranges: vec![(VarInt(3), VarInt(20))],
+                ecn: None,
+            },
+        };
+        buf.put_path_ack_frame(&frame);
+        assert_eq!(
+            buf,
+            vec![0x95, 0x22, 0x8c, 0x00, 0x01, 0x52, 0x34, 0x52, 0x34, 0x01, 0x52, 0x34, 3, 20]
+        );
+        assert_eq!(buf.len(), frame.encoding_size());
+    }
+}
@@ -16,6 +16,13 @@ impl PathChallengeFrame {
         frame.data.copy_from_slice(data);
         frame
     }
+
+    /// 路径验证时生成不可预测的挑战数据
+    pub fn random() -> Self {
+        Self {
+            data: rand::random(),
+        }
+    }
 }
 
 const PATH_CHALLENGE_FRAME_TYPE: u8 = 0x1a;
@@ -0,0 +1,119 @@
+// PATH_STATUS Frame {
+//   Type (i) = 0x15228c06,
+//   Path Identifier (i),
+//   Path Status sequence number (i),
+//   Path Status (i),
+// }
+
+use crate::{
+    packet::r#type::Type,
+    varint::{be_varint, VarInt, WriteVarInt},
+};
+
+/// 路径状态，Standby的路径仅在没有Available的路径时才使用，作为备份
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathStatus {
+    Standby = 1,
+    Available = 2,
+}
+
+/// 通知对方某条路径的状态，序号更大的状态才生效，避免乱序到达的旧状态覆盖新状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathStatusFrame {
+    pub path_id: VarInt,
+    pub seq: VarInt,
+    pub status: PathStatus,
+}
+
+const PATH_STATUS_FRAME_TYPE: u64 = 0x15228c06;
+
+impl super::BeFrame for PathStatusFrame {
+    fn frame_type(&self) -> super::FrameType {
+        super::FrameType::PathStatus
+    }
+
+    fn belongs_to(&self, packet_type: Type) -> bool {
+        use crate::packet::r#type::short::OneRtt;
+        // ___1
+        matches!(packet_type, Type::Short(OneRtt(_)))
+    }
+
+    fn max_encoding_size(&self) -> usize {
+        4 + 8 + 8 + 1
+    }
+
+    fn encoding_size(&self) -> usize {
+        4 + self.path_id.encoding_size() + self.seq.encoding_size() + 1
+    }
+}
+
+// nom parser for PATH_STATUS_FRAME
+pub fn be_path_status_frame(input: &[u8]) -> nom::IResult<&[u8], PathStatusFrame> {
+    let (remain, (path_id, seq, status)) =
+        nom::sequence::tuple((be_varint, be_varint, be_varint))(input)?;
+    let status = match status.into_inner() {
+        1 => PathStatus::Standby,
+        2 => PathStatus::Available,
+        _ => {
+            return Err(nom::Err::Error(nom::error::make_error(
+                input,
+                nom::error::ErrorKind::Alt,
+            )))
+        }
+    };
+    Ok((
+        remain,
+        PathStatusFrame {
+            path_id,
+            seq,
+            status,
+        },
+    ))
+}
+
+// BufMut extension trait for PATH_STATUS_FRAME
+pub trait WritePathStatusFrame {
+    fn put_path_status_frame(&mut self, frame: &PathStatusFrame);
+}
+
+impl<T: bytes::BufMut> WritePathStatusFrame for T {
+    fn put_path_status_frame(&mut self, frame: &PathStatusFrame) {
+        self.put_varint(&VarInt(PATH_STATUS_FRAME_TYPE));
+        self.put_varint(&frame.path_id);
+        self.put_varint(&frame.seq);
+        self.put_varint(&VarInt(frame.status as u64));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{be_path_status_frame, PathStatus, PathStatusFrame, WritePathStatusFrame};
+    use crate::varint::VarInt;
+
+    #[test]
+    fn test_read_path_status_frame() {
+        let buf = vec![0x01, 0x02, 0x01];
+        let (remain, frame) = be_path_status_frame(&buf).unwrap();
+        assert_eq!(remain, &[]);
+        assert_eq!(
+            frame,
+            PathStatusFrame {
+                path_id: VarInt(1),
+                seq: VarInt(2),
+                status: PathStatus::Standby,
+            }
+        );
+        assert!(be_path_status_frame(&[0x01, 0x02, 0x03]).is_err());
+    }
+
+    #[test]
+    fn test_write_path_status_frame() {
+        let mut buf = Vec::new();
+        buf.put_path_status_frame(&PathStatusFrame {
+            path_id: VarInt(1),
+            seq: VarInt(2),
+            status: PathStatus::Available,
+        });
+        assert_eq!(buf, vec![0x95, 0x22, 0x8c, 0x06, 0x01, 0x02, 0x02]);
+    }
+}
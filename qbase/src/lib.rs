@@ -1,9 +1,11 @@
 pub mod cid;
+pub mod clock;
 pub mod config;
 pub mod error;
 pub mod frame;
 pub mod packet;
 pub mod streamid;
+pub mod token;
 pub mod util;
 pub mod varint;
 
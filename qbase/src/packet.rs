@@ -18,7 +18,8 @@ pub use header::{
 pub mod number;
 pub use number::{take_pn_len, PacketNumber, WritePacketNumber};
 
-use self::header::GetDcid;
+use self::{header::GetDcid, r#type::HEADER_FORM_MASK};
+use crate::cid::ArcCidGenerator;
 
 pub mod decrypt;
 pub mod encrypt;
@@ -69,13 +70,14 @@ pub enum Packet {
 #[derive(Debug)]
 pub struct PacketReader {
     raw: BytesMut,
-    dcid_len: usize,
+    // 短包头中没有目标连接ID的长度，需由我方的连接ID生成器解析得出
+    cid_gen: ArcCidGenerator,
     // TODO: 添加level，各种包类型顺序不能错乱，否则失败
 }
 
 impl PacketReader {
-    pub fn new(raw: BytesMut, dcid_len: usize) -> Self {
-        Self { raw, dcid_len }
+    pub fn new(raw: BytesMut, cid_gen: ArcCidGenerator) -> Self {
+        Self { raw, cid_gen }
     }
 }
 
@@ -87,9 +89,20 @@ impl Iterator for PacketReader {
             return None;
         }
 
-        match ext::be_packet(&self.raw, self.dcid_len) {
+        let dcid_len = match self.raw[0] & HEADER_FORM_MASK {
+            0 => match self.cid_gen.decode_len(&self.raw[1..]) {
+                Some(dcid_len) => dcid_len,
+                None => {
+                    self.raw.clear();
+                    return Some(Err(error::Error::UnrecognizedDcid));
+                }
+            },
+            // 长包头自带连接ID的长度
+            _ => 0,
+        };
+        match ext::be_packet(&self.raw, dcid_len) {
             Ok((consumed, packet)) => {
-                self.raw.truncate(consumed);
+                let _ = self.raw.split_to(consumed);
                 Some(Ok(packet))
             }
             Err(e) => {
@@ -197,9 +210,31 @@ pub mod ext {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::cid::{ConnectionIdGenerator, QuicLbGenerator};
+    use std::sync::Arc;
 
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn test_read_short_packet_with_self_described_dcid() {
+        let cid_gen = Arc::new(QuicLbGenerator::plaintext(1, &[0x12, 0x34], 7));
+        let dcid = cid_gen.generate();
+        let mut raw = BytesMut::new();
+        raw.extend_from_slice(&[0x40]);
+        raw.extend_from_slice(&dcid);
+        raw.extend_from_slice(&[0u8; 24]);
+
+        let mut reader = PacketReader::new(raw, cid_gen);
+        match reader.next() {
+            Some(Ok(Packet::Space(packet @ SpacePacket::OneRtt(_)))) => {
+                assert_eq!(packet.get_dcid(), &dcid);
+            }
+            _ => panic!("should be a 1-RTT packet"),
+        }
+        assert!(reader.next().is_none());
+    }
 }
@@ -22,6 +22,8 @@ pub enum Error {
     InvalidReservedBits(u8, u8),
     #[error("Fail to decrypt packet")]
     DecryptPacketFailure,
+    #[error("Unrecognized destination connection id in short header")]
+    UnrecognizedDcid,
 }
 
 impl nom::error::ParseError<&[u8]> for Error {
@@ -1,6 +1,8 @@
 use super::KeyPhaseBit;
+use crate::error::{Error, ErrorKind};
 use rustls::quic::{HeaderProtectionKey, Keys, PacketKey, Secrets};
 use std::{
+    collections::HashMap,
     future::Future,
     pin::Pin,
     sync::{Arc, Mutex},
@@ -67,6 +69,17 @@ impl ArcKeys {
     }
 }
 
+impl std::fmt::Debug for ArcKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = match &*self.0.lock().unwrap() {
+            KeysState::Pending(_) => "Pending",
+            KeysState::Ready(_) => "Ready",
+            KeysState::Invalid => "Invalid",
+        };
+        f.debug_tuple("ArcKeys").field(&state).finish()
+    }
+}
+
 pub struct GetRemoteKeys(Arc<Mutex<KeysState>>);
 
 impl Future for GetRemoteKeys {
@@ -95,20 +108,142 @@ enum OneRttKeysState {
     Invalid,
 }
 
+/// Usage of the packet keys in each key phase, to enforce the AEAD limits in RFC 9001 §6.6.
+/// - The confidentiality limit bounds the number of packets protected with the same key,
+///   a key update must be initiated before it is reached.
+/// - The integrity limit bounds the number of packets that fail authentication across all
+///   keys during the lifetime of the connection. Once exceeded, the connection must be
+///   closed with AEAD_LIMIT_REACHED.
+#[derive(Debug, Clone)]
+pub struct AeadUsage {
+    confidentiality_limit: u64,
+    integrity_limit: u64,
+    // The number of packets encrypted with the local key of each key phase
+    encrypted: [u64; 2],
+    // The number of packets failed to be decrypted with the remote key of each key phase,
+    // never reset by key updates, because the integrity limit applies to all keys.
+    invalid: [u64; 2],
+}
+
+impl AeadUsage {
+    pub fn new(confidentiality_limit: u64, integrity_limit: u64) -> Self {
+        Self {
+            confidentiality_limit,
+            integrity_limit,
+            encrypted: [0; 2],
+            invalid: [0; 2],
+        }
+    }
+
+    /// New local keys are installed in the key phase, so it starts counting again.
+    pub fn reset(&mut self, key_phase: KeyPhaseBit) {
+        self.encrypted[key_phase.as_index()] = 0;
+    }
+
+    pub fn on_pkt_encrypted(&mut self, key_phase: KeyPhaseBit) {
+        self.encrypted[key_phase.as_index()] += 1;
+    }
+
+    /// A key update should be initiated when 3/4 of the confidentiality limit is used up,
+    /// leaving enough room for the peer to respond to the key update.
+    pub fn need_update(&self, key_phase: KeyPhaseBit) -> bool {
+        let threshold = self.confidentiality_limit - self.confidentiality_limit / 4;
+        self.encrypted[key_phase.as_index()] >= threshold
+    }
+
+    /// Once the confidentiality limit is reached, the keys must not be used to
+    /// protect any more packets.
+    pub fn is_exhausted(&self, key_phase: KeyPhaseBit) -> bool {
+        self.encrypted[key_phase.as_index()] >= self.confidentiality_limit
+    }
+
+    /// Returns an AEAD_LIMIT_REACHED error if the total number of packets failed to be
+    /// authenticated exceeds the integrity limit.
+    pub fn on_pkt_decrypt_failed(&mut self, key_phase: KeyPhaseBit) -> Result<(), Error> {
+        self.invalid[key_phase.as_index()] += 1;
+        let total = self.invalid.iter().sum::<u64>();
+        if total > self.integrity_limit {
+            Err(Error::new_with_default_fty(
+                ErrorKind::AeadLimitReached,
+                format!(
+                    "{total} packets failed to be authenticated, exceed the integrity limit {}",
+                    self.integrity_limit
+                ),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Tracks whether a packet sent in the current key phase has been acknowledged. An endpoint
+/// must not initiate a subsequent key update before that, see RFC 9001 §6.5.
+#[derive(Debug, Default)]
+struct PhaseAcked {
+    // The first packet number sent in the current key phase, in each packet number space
+    first_sent: HashMap<u64, u64>,
+    acked: bool,
+}
+
+impl PhaseAcked {
+    fn on_pkt_sent(&mut self, pn_space: u64, pn: u64) {
+        self.first_sent.entry(pn_space).or_insert(pn);
+    }
+
+    /// The largest acknowledged packet is always acknowledged, and packet numbers increase
+    /// along with key phases, so it was sent in the current key phase if it is not less
+    /// than the first one.
+    fn on_ack_rcvd(&mut self, pn_space: u64, largest: u64) {
+        if self
+            .first_sent
+            .get(&pn_space)
+            .is_some_and(|first| largest >= *first)
+        {
+            self.acked = true;
+        }
+    }
+}
+
+/// 1-RTT密钥上需要连接去处理的事件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OneRttKeyEvent {
+    /// 发生了密钥更新，无论是我方主动还是对方发起，旧密钥都要在3倍PTO后淘汰
+    Updated,
+    /// 当前密钥已达机密性上限，又来不及更新，连接须以AEAD_LIMIT_REACHED关闭
+    Exhausted,
+}
+
 pub struct OneRttPacketKeys {
     cur_key_phase: KeyPhaseBit,
     secrets: Secrets,
     remote: [Option<Arc<PacketKey>>; 2],
     local: Arc<PacketKey>,
+    usage: AeadUsage,
+    phase_acked: PhaseAcked,
+    // 发生了密钥更新，尚未被连接取走
+    updated: bool,
+    // 等待密钥事件的任务
+    waker: Option<Waker>,
 }
 
 impl OneRttPacketKeys {
     fn new(remote: PacketKey, local: PacketKey, secrets: Secrets) -> Self {
+        let usage = AeadUsage::new(local.confidentiality_limit(), remote.integrity_limit());
         Self {
             cur_key_phase: KeyPhaseBit::default(),
             secrets,
             remote: [Some(Arc::new(remote)), None],
             local: Arc::new(local),
+            usage,
+            phase_acked: PhaseAcked::default(),
+            updated: false,
+            waker: None,
+        }
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
         }
     }
 
@@ -118,6 +253,60 @@ impl OneRttPacketKeys {
         let key_set = self.secrets.next_packet_keys();
         self.remote[self.cur_key_phase.as_index()] = Some(Arc::new(key_set.remote));
         self.local = Arc::new(key_set.local);
+        self.usage.reset(self.cur_key_phase);
+        self.phase_acked = PhaseAcked::default();
+        self.updated = true;
+        self.wake();
+    }
+
+    /// Whether the local key of the current key phase is approaching its confidentiality
+    /// limit and should be updated. A subsequent key update can't be initiated until the
+    /// old keys of the previous key update have been phased out, and a packet sent in the
+    /// current key phase has been acknowledged.
+    pub fn need_update(&self) -> bool {
+        self.usage.need_update(self.cur_key_phase)
+            && self.remote[(!self.cur_key_phase).as_index()].is_none()
+            && self.phase_acked.acked
+    }
+
+    /// Whether the local key of the current key phase has reached the confidentiality limit,
+    /// no more packets can be sent until the key is updated.
+    pub fn is_exhausted(&self) -> bool {
+        self.usage.is_exhausted(self.cur_key_phase)
+    }
+
+    /// Must be called after a packet numbered pn in pn_space is encrypted with the local key
+    /// of the current key phase.
+    pub fn on_pkt_encrypted(&mut self, pn_space: u64, pn: u64) {
+        self.usage.on_pkt_encrypted(self.cur_key_phase);
+        self.phase_acked.on_pkt_sent(pn_space, pn);
+        if self.is_exhausted() {
+            self.wake();
+        }
+    }
+
+    /// Must be called when an ACK frame in a 1-RTT packet acknowledges packets in pn_space,
+    /// largest being its largest acknowledged packet number.
+    pub fn on_ack_rcvd(&mut self, pn_space: u64, largest: u64) {
+        self.phase_acked.on_ack_rcvd(pn_space, largest);
+    }
+
+    /// 密钥耗尽优先于密钥更新，耗尽了连接就要关闭，不必再淘汰旧密钥
+    pub fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<OneRttKeyEvent> {
+        if self.is_exhausted() {
+            Poll::Ready(OneRttKeyEvent::Exhausted)
+        } else if std::mem::take(&mut self.updated) {
+            Poll::Ready(OneRttKeyEvent::Updated)
+        } else {
+            self.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// Must be called when a packet with the key phase fails to be decrypted.
+    /// Exceeding the integrity limit is a connection error of type AEAD_LIMIT_REACHED.
+    pub fn on_pkt_decrypt_failed(&mut self, key_phase: KeyPhaseBit) -> Result<(), Error> {
+        self.usage.on_pkt_decrypt_failed(key_phase)
     }
 
     /// Old key must be phased out within a certain period of time. If the old one don't go,
@@ -184,7 +373,10 @@ impl ArcOneRttKeys {
                 }
                 *state = OneRttKeysState::Invalid;
             }
-            OneRttKeysState::Ready { .. } => *state = OneRttKeysState::Invalid,
+            OneRttKeysState::Ready { pk, .. } => {
+                pk.lock().unwrap().wake();
+                *state = OneRttKeysState::Invalid;
+            }
             OneRttKeysState::Invalid => {}
         }
     }
@@ -202,6 +394,33 @@ impl ArcOneRttKeys {
     pub fn get_remote_keys(&self) -> GetRemoteOneRttKeys {
         GetRemoteOneRttKeys(self.0.clone())
     }
+
+    /// See [`OneRttPacketKeys::on_ack_rcvd`], ignored if the 1-RTT keys are not ready.
+    pub fn on_ack_rcvd(&self, pn_space: u64, largest: u64) {
+        if let Some((_, pk)) = self.get_local_keys() {
+            pk.lock().unwrap().on_ack_rcvd(pn_space, largest);
+        }
+    }
+
+    /// 等待下一个密钥事件，密钥失效后得到None。须在1-RTT密钥就绪之后等待
+    pub fn key_event(&self) -> OneRttKeyEvents {
+        OneRttKeyEvents(self.0.clone())
+    }
+}
+
+pub struct OneRttKeyEvents(Arc<Mutex<OneRttKeysState>>);
+
+impl Future for OneRttKeyEvents {
+    type Output = Option<OneRttKeyEvent>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let keys = self.0.lock().unwrap();
+        match &*keys {
+            OneRttKeysState::Pending(_) => panic!("key_event polled before 1-RTT keys are ready"),
+            OneRttKeysState::Ready { pk, .. } => pk.lock().unwrap().poll_event(cx).map(Some),
+            OneRttKeysState::Invalid => Poll::Ready(None),
+        }
+    }
 }
 
 pub struct GetRemoteOneRttKeys(Arc<Mutex<OneRttKeysState>>);
@@ -222,3 +441,61 @@ impl Future for GetRemoteOneRttKeys {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confidentiality_limit() {
+        let mut usage = AeadUsage::new(8, 16);
+        let phase = KeyPhaseBit::default();
+        for _ in 0..5 {
+            usage.on_pkt_encrypted(phase);
+        }
+        assert!(!usage.need_update(phase));
+        usage.on_pkt_encrypted(phase);
+        assert!(usage.need_update(phase));
+        assert!(!usage.is_exhausted(phase));
+        usage.on_pkt_encrypted(phase);
+        usage.on_pkt_encrypted(phase);
+        assert!(usage.is_exhausted(phase));
+        // the other key phase is not affected
+        assert!(!usage.need_update(!phase));
+
+        usage.reset(phase);
+        assert!(!usage.need_update(phase));
+        assert!(!usage.is_exhausted(phase));
+    }
+
+    #[test]
+    fn test_phase_acked() {
+        let mut phase_acked = PhaseAcked::default();
+        // nothing has been sent in the current key phase yet
+        phase_acked.on_ack_rcvd(0, 10);
+        assert!(!phase_acked.acked);
+
+        phase_acked.on_pkt_sent(0, 11);
+        phase_acked.on_pkt_sent(0, 12);
+        phase_acked.on_pkt_sent(1, 3);
+        // only packets sent in the previous key phase are acknowledged
+        phase_acked.on_ack_rcvd(0, 10);
+        phase_acked.on_ack_rcvd(2, 20);
+        assert!(!phase_acked.acked);
+        phase_acked.on_ack_rcvd(1, 3);
+        assert!(phase_acked.acked);
+    }
+
+    #[test]
+    fn test_integrity_limit() {
+        let mut usage = AeadUsage::new(8, 3);
+        let phase = KeyPhaseBit::default();
+        assert!(usage.on_pkt_decrypt_failed(phase).is_ok());
+        assert!(usage.on_pkt_decrypt_failed(!phase).is_ok());
+        // key updates don't reset the integrity limit
+        usage.reset(phase);
+        assert!(usage.on_pkt_decrypt_failed(phase).is_ok());
+        let err = usage.on_pkt_decrypt_failed(!phase).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::AeadLimitReached);
+    }
+}
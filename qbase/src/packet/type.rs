@@ -6,7 +6,7 @@ pub mod long;
 pub mod short;
 
 /// header form bit
-const HEADER_FORM_MASK: u8 = 0x80;
+pub(crate) const HEADER_FORM_MASK: u8 = 0x80;
 /// The next bit (0x40) of byte 0 is set to 1, unless the packet is a Version Negotiation packet.
 const FIXED_BIT: u8 = 0x40;
 
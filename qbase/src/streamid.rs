@@ -1,4 +1,7 @@
-use super::varint::{be_varint, VarInt, WriteVarInt};
+use super::{
+    error::{Error as QuicError, ErrorKind},
+    varint::{be_varint, VarInt, WriteVarInt},
+};
 use std::{
     fmt, ops,
     sync::{Arc, Mutex},
@@ -238,6 +241,20 @@ impl LocalStreamIds {
         }
     }
 
+    fn reset_max_sid(&mut self, dir: Dir, val: u64) -> Result<(), QuicError> {
+        if val > MAX_STREAM_ID {
+            return Err(QuicError::new_with_default_fty(
+                ErrorKind::TransportParameter,
+                format!("initial max streams {val} exceeds 2^60"),
+            ));
+        }
+        self.max[dir as usize] = StreamId::new(self.role, dir, val);
+        if let Some(waker) = self.wakers[dir as usize].take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
     fn poll_alloc_sid(&mut self, cx: &mut Context<'_>, dir: Dir) -> Poll<Option<StreamId>> {
         let idx = dir as usize;
         let cur = &mut self.unallocated[idx];
@@ -351,6 +368,19 @@ impl ArcLocalStreamIds {
         self.0.lock().unwrap().permit_max_sid(dir, val);
     }
 
+    /// 0-RTT被拒后，按对方实际的传输参数重置可创建流的上限，与MAX_STREAMS不同，它可能比原来的小。
+    /// 已分配出去的流ID不会收回，超出新上限的流由上层自行终止。
+    /// 上限来自对方的传输参数，超过2^60是对方的TRANSPORT_PARAMETER_ERROR
+    pub fn reset_max_sid(&self, dir: Dir, val: u64) -> Result<(), QuicError> {
+        self.0.lock().unwrap().reset_max_sid(dir, val)
+    }
+
+    /// 判断一个我方创建的流ID是否仍在可创建流的上限之内
+    pub fn is_permitted(&self, sid: StreamId) -> bool {
+        let guard = self.0.lock().unwrap();
+        sid <= guard.max[sid.dir() as usize]
+    }
+
     /// We are creating a new stream, and it should be incremented based on the previous stream ID. However,
     /// it should not exceed the maximum stream ID limit set by peer. Returning None indicates
     /// that it is limited to create a new stream, and we need to send a STREAMS_BLOCKED frame
@@ -468,6 +498,34 @@ mod tests {
         assert!(local.0.lock().unwrap().wakers[1].is_some());
     }
 
+    #[test]
+    fn test_reset_max_sid() {
+        let StreamIds { local, remote: _ } = StreamIds::with_role_and_limit(Role::Client, 2, 0);
+        let waker = empty_waker();
+        let mut cx = Context::from_waker(&waker);
+        for _ in 0..3 {
+            assert!(local.poll_alloc_sid(&mut cx, Dir::Bi).is_ready());
+        }
+        assert!(local.is_permitted(StreamId(8)));
+
+        // 上限可以减小，已分配的流ID不收回，但超出上限的不再被允许
+        local.reset_max_sid(Dir::Bi, 1).unwrap();
+        assert!(local.is_permitted(StreamId(4)));
+        assert!(!local.is_permitted(StreamId(8)));
+        assert_eq!(local.poll_alloc_sid(&mut cx, Dir::Bi), Poll::Pending);
+
+        local.permit_max_sid(Dir::Bi, 3);
+        assert_eq!(
+            local.poll_alloc_sid(&mut cx, Dir::Bi),
+            Poll::Ready(Some(StreamId(12)))
+        );
+        // 对方的传输参数超出了流ID的上限
+        let err = local
+            .reset_max_sid(Dir::Uni, MAX_STREAM_ID + 1)
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::TransportParameter);
+    }
+
     #[test]
     fn test_try_accept_sid() {
         let StreamIds { local: _, remote } = StreamIds::with_role_and_limit(Role::Client, 10, 10);
@@ -0,0 +1,236 @@
+// 服务端签发的地址验证令牌，经NEW_TOKEN帧交给客户端，客户端下次连接时放在Initial包中，
+// 服务端据此认定客户端的地址，无需Retry，也不受反放大限制，Ref. RFC 9000 §8.1.3
+// Token {
+//   Key Id (8),
+//   Nonce (96),
+//   Issued At (64),  // 加密，签发时的Unix时间戳，单位为秒
+//   Tag (128),
+// }
+// 加密时以客户端的IP地址作为附加数据，令牌换了地址就无法通过验证。
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_128_GCM, NONCE_LEN};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub const TOKEN_SIZE: usize = 1 + NONCE_LEN + 8 + 16;
+/// 令牌默认的有效期
+pub const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+/// 每个服务端最多缓存的令牌数，令牌只用一次，多了也没用
+const MAX_TOKENS_PER_SERVER: usize = 4;
+
+#[derive(Debug)]
+struct TokenKey {
+    id: u8,
+    key: LessSafeKey,
+}
+
+impl TokenKey {
+    fn random(id: u8) -> Self {
+        let mut bytes = [0u8; 16];
+        rand::Rng::fill(&mut rand::thread_rng(), &mut bytes);
+        let key = UnboundKey::new(&AES_128_GCM, &bytes).unwrap();
+        Self {
+            id,
+            key: LessSafeKey::new(key),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RawTokenKeys {
+    current: TokenKey,
+    // 轮换之前的密钥，它签发的令牌在下次轮换之前依然有效
+    previous: Option<TokenKey>,
+    lifetime: Duration,
+}
+
+/// 服务端签发、验证令牌所用的密钥，需定期轮换。
+/// 轮换后上一把密钥签发的令牌仍可验证，因此轮换周期不应短于令牌的有效期
+#[derive(Debug, Clone)]
+pub struct ArcTokenKeys(Arc<Mutex<RawTokenKeys>>);
+
+impl Default for ArcTokenKeys {
+    fn default() -> Self {
+        Self::new(DEFAULT_TOKEN_LIFETIME)
+    }
+}
+
+impl ArcTokenKeys {
+    pub fn new(lifetime: Duration) -> Self {
+        Self(Arc::new(Mutex::new(RawTokenKeys {
+            current: TokenKey::random(0),
+            previous: None,
+            lifetime,
+        })))
+    }
+
+    /// 换一把新的密钥签发令牌，只保留上一把密钥用于验证
+    pub fn rotate(&self) {
+        let mut guard = self.0.lock().unwrap();
+        let next = TokenKey::random(guard.current.id.wrapping_add(1));
+        guard.previous = Some(std::mem::replace(&mut guard.current, next));
+    }
+
+    /// 为ip签发一个令牌
+    pub fn mint(&self, ip: IpAddr, now: SystemTime) -> Vec<u8> {
+        let guard = self.0.lock().unwrap();
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::Rng::fill(&mut rand::thread_rng(), &mut nonce);
+        let issued_at = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        let mut token = Vec::with_capacity(TOKEN_SIZE);
+        token.push(guard.current.id);
+        token.extend_from_slice(&nonce);
+        let mut body = issued_at.to_be_bytes();
+        let tag = guard
+            .current
+            .key
+            .seal_in_place_separate_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(ip_octets(&ip)),
+                &mut body,
+            )
+            .unwrap();
+        token.extend_from_slice(&body);
+        token.extend_from_slice(tag.as_ref());
+        token
+    }
+
+    /// 验证令牌是否为ip签发、且仍在有效期内
+    pub fn validate(&self, token: &[u8], ip: IpAddr, now: SystemTime) -> bool {
+        if token.len() != TOKEN_SIZE {
+            return false;
+        }
+        let guard = self.0.lock().unwrap();
+        let key = match token[0] {
+            id if id == guard.current.id => &guard.current,
+            id if guard.previous.as_ref().is_some_and(|k| k.id == id) => {
+                guard.previous.as_ref().unwrap()
+            }
+            _ => return false,
+        };
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&token[1..1 + NONCE_LEN]);
+        let mut body = token[1 + NONCE_LEN..].to_vec();
+        let Ok(issued_at) = key.key.open_in_place(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(ip_octets(&ip)),
+            &mut body,
+        ) else {
+            return false;
+        };
+        let issued_at = u64::from_be_bytes(issued_at[..8].try_into().unwrap());
+        let issued_at = UNIX_EPOCH + Duration::from_secs(issued_at);
+        match now.duration_since(issued_at) {
+            Ok(elapsed) => elapsed <= guard.lifetime,
+            // 签发时间在未来，只能是伪造的
+            Err(_) => false,
+        }
+    }
+}
+
+fn ip_octets(ip: &IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+/// 客户端缓存的各服务端签发的令牌，按服务端名称区分。
+/// 令牌只能用一次，否则不同的连接会因同一令牌被关联起来，Ref. RFC 9000 §8.1.3
+#[derive(Debug, Clone, Default)]
+pub struct ArcTokenCache(Arc<Mutex<HashMap<String, VecDeque<Vec<u8>>>>>);
+
+impl ArcTokenCache {
+    pub fn push(&self, server: &str, token: Vec<u8>) {
+        let mut guard = self.0.lock().unwrap();
+        let tokens = guard.entry(server.to_owned()).or_default();
+        if tokens.len() == MAX_TOKENS_PER_SERVER {
+            tokens.pop_front();
+        }
+        tokens.push_back(token);
+    }
+
+    /// 取出最新的令牌，取出即不再缓存
+    pub fn pop(&self, server: &str) -> Option<Vec<u8>> {
+        let mut guard = self.0.lock().unwrap();
+        let tokens = guard.get_mut(server)?;
+        let token = tokens.pop_back();
+        if tokens.is_empty() {
+            guard.remove(server);
+        }
+        token
+    }
+
+    /// 某个服务端的令牌缓存，由连向该服务端的连接持有
+    pub fn server(&self, server: impl Into<String>) -> ServerTokens {
+        ServerTokens {
+            cache: self.clone(),
+            server: server.into(),
+        }
+    }
+}
+
+/// 连向某个服务端的连接所用的令牌缓存
+#[derive(Debug, Clone)]
+pub struct ServerTokens {
+    cache: ArcTokenCache,
+    server: String,
+}
+
+impl ServerTokens {
+    pub fn push(&self, token: Vec<u8>) {
+        self.cache.push(&self.server, token);
+    }
+
+    pub fn pop(&self) -> Option<Vec<u8>> {
+        self.cache.pop(&self.server)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token() {
+        let keys = ArcTokenKeys::new(Duration::from_secs(60));
+        let ip: IpAddr = "1.1.1.1".parse().unwrap();
+        let now = SystemTime::now();
+        let token = keys.mint(ip, now);
+        assert_eq!(token.len(), TOKEN_SIZE);
+        assert!(keys.validate(&token, ip, now));
+        // 换了地址、过了有效期、被篡改，都无法通过验证
+        assert!(!keys.validate(&token, "2.2.2.2".parse().unwrap(), now));
+        assert!(!keys.validate(&token, ip, now + Duration::from_secs(61)));
+        let mut forged = token.clone();
+        forged[TOKEN_SIZE - 1] ^= 1;
+        assert!(!keys.validate(&forged, ip, now));
+
+        // 轮换后上一把密钥签发的令牌仍然有效，再轮换一次就失效了
+        keys.rotate();
+        assert!(keys.validate(&token, ip, now));
+        assert!(keys.validate(&keys.mint(ip, now), ip, now));
+        keys.rotate();
+        assert!(!keys.validate(&token, ip, now));
+    }
+
+    #[test]
+    fn test_token_cache() {
+        let cache = ArcTokenCache::default();
+        let server = cache.server("example.com");
+        for i in 0..=MAX_TOKENS_PER_SERVER as u8 {
+            server.push(vec![i]);
+        }
+        assert_eq!(cache.pop("other.com"), None);
+        // 最早的令牌被挤掉了，优先用最新的
+        for i in (1..=MAX_TOKENS_PER_SERVER as u8).rev() {
+            assert_eq!(server.pop(), Some(vec![i]));
+        }
+        assert_eq!(server.pop(), None);
+    }
+}
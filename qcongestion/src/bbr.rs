@@ -22,6 +22,12 @@ mod transmit;
 const INITIAL_RTT: Duration = Duration::from_millis(333);
 
 const MINIMUM_WINDOW_PACKETS: usize = 2;
+
+/// QUIC要求路径至少支持1200字节的UDP载荷，在探测出更大的PMTU之前，以此作为最大数据报大小
+const MAX_DATAGRAM_SIZE: usize = 1200;
+
+/// 初始拥塞窗口的包数，Ref. RFC 9002 §7.2
+const INITIAL_WINDOW_PACKETS: usize = 10;
 /// The static discount factor of 1% used to scale BBR.bw to produce
 /// BBR.pacing_rate.
 const PACING_MARGIN_PERCENT: f64 = 0.01;
@@ -333,6 +339,9 @@ pub struct BBRState {
     // Saved cwnd before loss recovery.
     prior_cwnd: usize,
 
+    // 本次恢复期内判定丢失、尚未发现是误判的包数，全都是误判才撤销这次恢复
+    recovery_lost: usize,
+
     // Whether we have a bandwidth probe samples.
     bw_probe_samples: bool,
 
@@ -389,12 +398,13 @@ pub struct BBRState {
 
 impl Default for BBRState {
     fn default() -> Self {
-        Self::new()
+        Self::new(Instant::now())
     }
 }
 
 impl BBRState {
-    pub fn new() -> BBRState {
+    /// now是创建时的时间，各个时间戳都以此为起点
+    pub fn new(now: Instant) -> BBRState {
         BBRState {
             tx_in_flight: 0,
             lost: 0,
@@ -414,7 +424,8 @@ impl BBRState {
             bw_hi: 0,
             bw_lo: 0,
             bw: 0,
-            min_rtt: Duration::from_secs(0),
+            // 尚无RTT采样，首个采样会替换掉它
+            min_rtt: INITIAL_RTT,
             bdp: 0,
             extra_acked: 0,
             offload_budget: 0,
@@ -423,21 +434,22 @@ impl BBRState {
             inflight_lo: 0,
             bw_latest: 0,
             inflight_latest: 0,
-            max_bw_filter: Minmax::new(0),
+            max_bw_filter: Minmax::new(now, 0),
             cycle_count: 0,
-            extra_acked_interval_start: Instant::now(),
+            extra_acked_interval_start: now,
             extra_acked_delivered: 0,
-            extra_acked_filter: Minmax::new(0),
+            extra_acked_filter: Minmax::new(now, 0),
             filled_pipe: false,
             full_bw: 0,
             full_bw_count: 0,
-            min_rtt_stamp: Instant::now(),
+            min_rtt_stamp: now,
             probe_rtt_min_delay: Duration::from_secs(0),
-            probe_rtt_min_stamp: Instant::now(),
+            probe_rtt_min_stamp: now,
             probe_rtt_expired: false,
             in_recovery: false,
-            start_time: Instant::now(),
+            start_time: now,
             prior_cwnd: 0,
+            recovery_lost: 0,
             bw_probe_samples: false,
             probe_up_cnt: 0,
             prior_bytes_in_flight: 0,
@@ -445,7 +457,7 @@ impl BBRState {
             probe_rtt_round_done: false,
             bw_probe_wait: Duration::from_secs(0),
             rounds_since_probe: 0,
-            cycle_stamp: Instant::now(),
+            cycle_stamp: now,
             ack_phase: BBRAckPhase::Init,
             bw_probe_up_rounds: 0,
             bw_probe_up_acks: 0,
@@ -453,15 +465,15 @@ impl BBRState {
             loss_round_delivered: 0,
             loss_in_round: false,
             loss_events_in_round: 0,
-            congestion_window: 0,
+            congestion_window: MAX_DATAGRAM_SIZE * INITIAL_WINDOW_PACKETS,
             bytes_in_flight: 0,
             congestion_recovery_start_time: None,
-            max_datagram_size: 0,
+            max_datagram_size: MAX_DATAGRAM_SIZE,
             smoothed_rtt: None,
             send_quantum: 0,
-            initial_congestion_window_packets: 0,
+            initial_congestion_window_packets: INITIAL_WINDOW_PACKETS,
             bytes_lost: 0,
-            delivery_rate: delivery_rate::Rate::default(),
+            delivery_rate: delivery_rate::Rate::new(now),
         }
     }
 
@@ -477,6 +489,7 @@ impl BBRState {
 
         self.packet_conservation = false;
         self.in_recovery = false;
+        self.recovery_lost = 0;
         self.restore_cwnd();
     }
 
@@ -495,8 +508,8 @@ impl BBRState {
 }
 
 impl Algorithm for BBRState {
-    fn init(&mut self) {
-        self.init();
+    fn init(&mut self, now: Instant) {
+        self.init(now);
     }
 
     fn on_packet_sent(&mut self, sent: &mut Sent, sent_bytes: usize, now: Instant) {
@@ -510,8 +523,30 @@ impl Algorithm for BBRState {
         self.newly_lost_bytes = largest_lost_pkt.size;
         self.update_on_loss(largest_lost_pkt, now);
 
+        // 恢复期之前发出的包丢了，同属这次恢复；恢复期之后发出的包丢了，才开始新的恢复
         if self.in_congestion_recovery(largest_lost_pkt.time_sent) {
+            self.recovery_lost += 1;
+        } else {
             self.enter_recovery(now);
+            self.recovery_lost = 1;
+        }
+    }
+
+    // 持续拥塞之前的拥塞窗口已不可信，退出恢复时也不再恢复它，窗口降到最小后由带宽模型重新增长
+    fn on_persistent_congestion(&mut self, _now: Instant) {
+        self.prior_cwnd = 0;
+        self.congestion_window = self.max_datagram_size * MINIMUM_WINDOW_PACKETS;
+    }
+
+    // 这次恢复期内的丢包全都是误判，才退出恢复，拥塞窗口回到进入恢复前save_cwnd保存的大小；
+    // 只要还有一个是真的丢了，窗口的缩减就是应当的
+    fn on_spurious_loss(&mut self, lost: &Sent, _now: Instant) {
+        if !self.in_recovery || !self.in_congestion_recovery(lost.time_sent) {
+            return;
+        }
+        self.recovery_lost = self.recovery_lost.saturating_sub(1);
+        if self.recovery_lost == 0 {
+            self.exit_recovery();
         }
     }
 
@@ -519,6 +554,11 @@ impl Algorithm for BBRState {
         self.congestion_window as u64
     }
 
+    // 作废的包不再在途
+    fn on_packet_discarded(&mut self, discarded: &Sent) {
+        self.bytes_in_flight = self.bytes_in_flight.saturating_sub(discarded.size);
+    }
+
     fn on_packet_acked(&mut self, packet: &Acked, now: Instant) {
         // update delivery rate
         self.delivery_rate.update_rate_sample(packet, now);
@@ -543,7 +583,7 @@ impl Algorithm for BBRState {
             self.exit_recovery();
         }
 
-        // update_control_parameters(self, now);
+        self.update_control_parameters(now);
         self.newly_lost_bytes = 0;
     }
 }
@@ -589,12 +629,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_bbr_persistent_congestion() {
+        let now = Instant::now();
+        let mut bbr = super::BBRState::new(now);
+        bbr.init(now);
+        assert_eq!(bbr.cwnd(), 12000);
+        // 持续拥塞，窗口降到最小的2个包，退出恢复时也不会恢复原来的窗口
+        bbr.on_persistent_congestion(now);
+        assert_eq!(bbr.cwnd(), 2400);
+        bbr.restore_cwnd();
+        assert_eq!(bbr.cwnd(), 2400);
+    }
+
+    #[test]
+    fn test_bbr_spurious_loss() {
+        let now = Instant::now();
+        let mut bbr = super::BBRState::new(now);
+        bbr.init(now);
+        assert_eq!(bbr.cwnd(), 12000);
+        let lost = |pkt_num: u64| Sent {
+            pkt_num,
+            size: 1200,
+            time_sent: now,
+            ..Sent::default()
+        };
+        // 丢包进入恢复时保存了原窗口，同一恢复期内又丢了一个包
+        bbr.bytes_in_flight = 1200;
+        let now = now + Duration::from_millis(10);
+        bbr.on_congestion_event(&lost(0), now);
+        bbr.on_congestion_event(&lost(1), now);
+        assert!(bbr.in_recovery);
+        assert!(bbr.cwnd() < 12000);
+
+        // 只有一个是误判，另一个真的丢了，仍在恢复中
+        bbr.on_spurious_loss(&lost(0), now);
+        assert!(bbr.in_recovery);
+        assert!(bbr.cwnd() < 12000);
+        // 两个都是误判，才恢复原窗口
+        bbr.on_spurious_loss(&lost(1), now);
+        assert!(!bbr.in_recovery);
+        assert_eq!(bbr.cwnd(), 12000);
+    }
+
     #[test]
     fn test_bbr_acked() {
-        let mut bbr = super::BBRState::default();
-        bbr.init();
-        let mut packets: VecDeque<Sent> = VecDeque::new();
         let now = Instant::now();
+        let mut bbr = super::BBRState::new(now);
+        bbr.init(now);
+        let mut packets: VecDeque<Sent> = VecDeque::new();
         for pn in 0..5 {
             let mut sent = Sent::default();
             sent.size = 100;
@@ -627,4 +710,51 @@ mod tests {
         }
         assert_eq!(bbr.delivery_rate.sample_delivery_rate(), 0);
     }
+
+    fn ack_of(packet: &Sent, recv_time: Instant) -> Acked {
+        Acked {
+            pkt_num: packet.pkt_num,
+            time_sent: packet.time_sent,
+            size: packet.size,
+            rtt: recv_time.saturating_duration_since(packet.time_sent),
+            delivered: packet.delivered,
+            delivered_time: packet.delivered_time,
+            first_sent_time: packet.first_sent_time,
+            is_app_limited: packet.is_app_limited,
+            tx_in_flight: packet.tx_in_flight,
+            lost: packet.lost,
+        }
+    }
+
+    #[test]
+    fn test_bbr_enter_probe_rtt() {
+        let start = Instant::now();
+        let mut bbr = super::BBRState::new(start);
+        bbr.init(start);
+        assert_eq!(bbr.state, super::BBRStateMachine::Startup);
+
+        // PROBE_RTT_INTERVAL之内确认的包，不会触发ProbeRTT
+        let sent_time = start + super::PROBE_RTT_INTERVAL - Duration::from_millis(100);
+        let mut sent = Sent {
+            size: 100,
+            time_sent: sent_time,
+            ..Default::default()
+        };
+        bbr.on_packet_sent(&mut sent, 100, sent_time);
+        let recv_time = sent_time + Duration::from_millis(50);
+        bbr.on_packet_acked(&ack_of(&sent, recv_time), recv_time);
+        assert_ne!(bbr.state, super::BBRStateMachine::ProbeRTT);
+
+        // 超过PROBE_RTT_INTERVAL没有刷新过最小RTT，进入ProbeRTT
+        let mut sent = Sent {
+            pkt_num: 1,
+            size: 100,
+            time_sent: recv_time,
+            ..Default::default()
+        };
+        bbr.on_packet_sent(&mut sent, 100, recv_time);
+        let recv_time = recv_time + Duration::from_millis(100);
+        bbr.on_packet_acked(&ack_of(&sent, recv_time), recv_time);
+        assert_eq!(bbr.state, super::BBRStateMachine::ProbeRTT);
+    }
 }
@@ -4,9 +4,7 @@ use std::time::Instant;
 
 impl BBRState {
     // 4.2.1.  Initialization
-    pub fn init(&mut self) {
-        let now = Instant::now();
-
+    pub fn init(&mut self, now: Instant) {
         // self.min_rtt = rtt;
         self.min_rtt_stamp = now;
         self.probe_rtt_done_stamp = None;
@@ -58,7 +56,7 @@ mod tests {
     #[test]
     fn test_init() {
         let mut bbr = BBRState::default();
-        bbr.init();
+        bbr.init(Instant::now());
         assert_eq!(bbr.state, BBRStateMachine::Startup);
         assert_eq!(bbr.bytes_in_flight, 0);
         assert_eq!(bbr.pacing_gain, STARTUP_PACING_GAIN);
@@ -11,12 +11,9 @@ pub struct Minmax<T> {
 }
 
 impl<T: PartialOrd + Copy> Minmax<T> {
-    pub fn new(val: T) -> Self {
+    pub fn new(time: Instant, val: T) -> Self {
         Minmax {
-            estimate: [MinmaxSample {
-                time: Instant::now(),
-                value: val,
-            }; 3],
+            estimate: [MinmaxSample { time, value: val }; 3],
         }
     }
 
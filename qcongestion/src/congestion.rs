@@ -1,14 +1,18 @@
-use crate::{bbr, ObserveAck, ObserveLoss, Rtt};
-use qbase::frame::AckFrame;
+use crate::{
+    bbr, new_reno,
+    reordering::{ReorderingConfig, ReorderingThreshold},
+    ObserveAck, ObserveLoss, Rtt,
+};
+use qbase::{clock::Clock, frame::AckFrame};
 use std::{
     cmp::Ordering,
-    collections::VecDeque,
+    collections::{BTreeSet, VecDeque},
     sync::{Arc, Mutex},
-    task::{Context, Poll},
+    task::{Context, Poll, Waker},
     time::{Duration, Instant},
 };
 use std::{
-    fmt::Display,
+    fmt::{Debug, Display},
     ops::{Index, IndexMut, RangeInclusive},
 };
 
@@ -19,6 +23,9 @@ pub enum Epoch {
     Data = 2,
 }
 
+/// 我方在传输参数中通告的max_ack_delay，收到数据空间的ack-eliciting包后，最迟这么久要发出AckFrame
+pub const MAX_ACK_DELAY: Duration = Duration::from_millis(25);
+
 static EPOCHS: [Epoch; 3] = [Epoch::Initial, Epoch::Handshake, Epoch::Data];
 
 impl Epoch {
@@ -64,10 +71,34 @@ where
 }
 
 const K_GRANULARITY: Duration = Duration::from_millis(1);
-const K_PACKET_THRESHOLD: u64 = 3;
+// 判定丢失的包，保留这么多个PTO，期间迟到的确认说明此前的丢包判定是误判
+const K_SPURIOUS_LOSS_PTOS: u32 = 3;
 
+/// 拥塞控制算法，每个连接可以选用不同的算法
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum CongestionAlgorithm {
+    #[default]
     Bbr,
+    /// RFC 9002附录B中的NewReno，保守，适于共享链路
+    NewReno,
+}
+
+impl CongestionAlgorithm {
+    fn build(self, now: Instant) -> Box<dyn Algorithm> {
+        let mut algorithm: Box<dyn Algorithm> = match self {
+            CongestionAlgorithm::Bbr => Box::new(bbr::BBRState::new(now)),
+            CongestionAlgorithm::NewReno => Box::new(new_reno::NewReno::new()),
+        };
+        algorithm.init(now);
+        algorithm
+    }
+}
+
+/// 某个空间的收包记录，只需记下收到的最大包号及其收包时间，以及此后是否有待确认的ack-eliciting包
+#[derive(Debug, Default, Clone, Copy)]
+struct RcvdRecord {
+    largest: Option<(u64, Instant)>,
+    need_ack: bool,
 }
 
 pub struct CongestionController<OA, OL> {
@@ -80,11 +111,37 @@ pub struct CongestionController<OA, OL> {
     max_ack_delay: Duration,
     time_of_last_ack_eliciting_packet: [Option<Instant>; Epoch::count()],
     largest_acked_packet: [Option<u64>; Epoch::count()],
+    // 本路径已确认的最大发包序号；包序阈值按本路径的发包序号计，而非包号，
+    // 因为包号空间为多条路径共享时，其它路径上发出的包也占用包号
+    largest_acked_seq: [Option<u64>; Epoch::count()],
+    // 本路径在各空间中发出的下一个包的序号
+    next_seq: [u64; Epoch::count()],
+    // 本路径已确认的发包序号，只保留不早于最早的在途包、丢失包的，用于持续拥塞的判定
+    acked_seqs: [BTreeSet<u64>; Epoch::count()],
+    reordering: ReorderingThreshold,
     loss_time: [Option<Instant>; Epoch::count()],
     sent_packets: [VecDeque<Sent>; Epoch::count()],
+    // 已判定丢失、但仍可能迟到确认的包，按包号排序
+    lost_packets: [VecDeque<Sent>; Epoch::count()],
     anti_amplification: bool,
     handshake_confirmed: bool,
     has_handshake_keys: bool,
+    clock: Arc<dyn Clock>,
+    // 在途的字节数，即已发出、既未被确认也未判定丢失的in-flight包的大小总和
+    bytes_in_flight: usize,
+    rcvd_records: [RcvdRecord; Epoch::count()],
+    // 拥塞窗口已满时，等待发送信用的任务
+    send_waker: Option<Waker>,
+    // PTO超时后，各空间尚待发送的探测包个数
+    probes: [u8; Epoch::count()],
+    // 等待丢包检测定时器变化的任务
+    timer_waker: Option<Waker>,
+    // 有发送信用却无ack-eliciting的数据可发，直到再发出在途的包
+    app_limited: bool,
+    // 收到ack-eliciting包后，最迟要在此时发出AckFrame
+    ack_timer: Option<Instant>,
+    // 等待ACK延迟定时器变化的任务
+    ack_timer_waker: Option<Waker>,
 }
 
 impl<OA, OL> CongestionController<OA, OL>
@@ -92,13 +149,15 @@ where
     OA: ObserveAck,
     OL: ObserveLoss,
 {
-    pub fn new(algorithm: CongestionAlgorithm, observe_ack: OA, observe_loss: OL) -> Self {
-        let cc = match algorithm {
-            CongestionAlgorithm::Bbr => Box::new(bbr::BBRState::new()),
-        };
-
+    /// 所有的时间都从clock取，测试时可换成手动拨动的时钟
+    pub fn new(
+        algorithm: CongestionAlgorithm,
+        observe_ack: OA,
+        observe_loss: OL,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
         CongestionController {
-            algorithm: cc,
+            algorithm: algorithm.build(clock.now()),
             rtt: Arc::new(Mutex::new(Rtt::default())),
             loss_detection_timer: None,
             // todo : read from transport parameters
@@ -106,16 +165,34 @@ where
             pto_count: 0,
             time_of_last_ack_eliciting_packet: [None, None, None],
             largest_acked_packet: [None, None, None],
+            largest_acked_seq: [None, None, None],
+            next_seq: [0; Epoch::count()],
+            acked_seqs: Default::default(),
+            reordering: ReorderingThreshold::default(),
             loss_time: [None, None, None],
             sent_packets: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            lost_packets: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
             anti_amplification: false,
             handshake_confirmed: false,
             has_handshake_keys: false,
             observe_ack,
             observe_loss,
+            clock,
+            bytes_in_flight: 0,
+            rcvd_records: [RcvdRecord::default(); Epoch::count()],
+            probes: [0; Epoch::count()],
+            timer_waker: None,
+            send_waker: None,
+            app_limited: false,
+            ack_timer: None,
+            ack_timer_waker: None,
         }
     }
 
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
     pub fn on_packet_sent(
         &mut self,
         packet_number: u64,
@@ -140,12 +217,20 @@ where
             tx_in_flight: 0,
             lost: 0,
             has_data: false,
+            ack: None,
+            seq: self.next_seq[pn_space],
         };
+        self.next_seq[pn_space] += 1;
 
+        if ack_eliciting {
+            self.probes[pn_space] = self.probes[pn_space].saturating_sub(1);
+        }
         if in_flight {
             if ack_eliciting {
                 self.time_of_last_ack_eliciting_packet[pn_space] = Some(now);
             }
+            self.set_app_limited(false);
+            self.bytes_in_flight += sent_bytes;
             self.algorithm.on_packet_sent(&mut sent, sent_bytes, now);
             self.set_lost_detection_timer(now);
         }
@@ -158,9 +243,139 @@ where
         self.sent_packets[pn_space].push_back(sent);
     }
 
-    pub fn on_datagram_recv(&mut self, now: Instant) {
+    /// 有发送信用，却没有ack-eliciting的数据可发，此后发出在途的包之前，发送都受限于应用
+    pub fn on_app_limited(&mut self) {
+        self.set_app_limited(true);
+    }
+
+    fn set_app_limited(&mut self, app_limited: bool) {
+        if self.app_limited != app_limited {
+            self.app_limited = app_limited;
+            self.algorithm.set_app_limited(app_limited);
+        }
+    }
+
+    /// 刚发出的包中带有AckFrame，记下其最大包号；此前收到的ack-eliciting包都已确认过了
+    pub fn on_ack_sent(&mut self, pn_space: Epoch, largest: u64) {
+        if let Some(sent) = self.sent_packets[pn_space].back_mut() {
+            sent.ack = Some(largest);
+        }
+        self.rcvd_records[pn_space].need_ack = false;
+        if self.rcvd_records.iter().all(|record| !record.need_ack) {
+            self.set_ack_timer(None);
+        }
+    }
+
+    /// 收到了pn_space中的数据包，据此决定下次发包是否要带上AckFrame
+    pub fn on_packet_rcvd(&mut self, pn_space: Epoch, pn: u64, ack_eliciting: bool, now: Instant) {
+        let record = &mut self.rcvd_records[pn_space];
+        if record.largest.is_none_or(|(largest, _)| pn > largest) {
+            record.largest = Some((pn, now));
+        }
+        if ack_eliciting {
+            record.need_ack = true;
+            // Initial、Handshake包要立即确认，数据空间的包可延迟至多max_ack_delay再确认，
+            // Ref. RFC 9000 §13.2.1
+            let deadline = match pn_space {
+                Epoch::Data => now + MAX_ACK_DELAY,
+                _ => now,
+            };
+            if self.ack_timer.is_none_or(|timer| deadline < timer) {
+                self.set_ack_timer(Some(deadline));
+            }
+        }
+    }
+
+    fn set_ack_timer(&mut self, timer: Option<Instant>) {
+        if self.ack_timer != timer {
+            self.ack_timer = timer;
+            if let Some(waker) = self.ack_timer_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// 有待确认的ack-eliciting包时，返回要确认的最大包号及其收包时间
+    pub fn need_ack(&self, pn_space: Epoch) -> Option<(u64, Instant)> {
+        let record = &self.rcvd_records[pn_space];
+        record.largest.filter(|_| record.need_ack)
+    }
+
+    /// 拥塞窗口中尚可发送的字节数
+    pub fn send_credit(&self) -> usize {
+        (self.algorithm.cwnd() as usize).saturating_sub(self.bytes_in_flight)
+    }
+
+    pub fn bytes_in_flight(&self) -> usize {
+        self.bytes_in_flight
+    }
+
+    pub fn rtt(&self) -> Arc<Mutex<Rtt>> {
+        self.rtt.clone()
+    }
+
+    // 在途的数据少了，拥塞窗口腾出了空间，唤醒等待发送的任务
+    /// PTO超时后，space中是否还有探测包待发送
+    pub fn need_probe(&self, space: Epoch) -> bool {
+        self.probes[space] > 0
+    }
+
+    /// 握手密钥已就绪，此后对方地址验证不再成问题，没有在途数据时的探测包改用Handshake包
+    pub fn on_handshake_keys(&mut self, now: Instant) {
+        self.has_handshake_keys = true;
+        self.set_lost_detection_timer(now);
+    }
+
+    /// 0-RTT被拒绝，数据空间中在途的都是0-RTT包，对方不会处理它们，全部作废。
+    /// 它们从在途数据中移除而不算丢包，不触发拥塞响应，其中的数据由空间在1-RTT包中重传，
+    /// 与丢弃一个包号空间的密钥同样处理，Ref. RFC 9002 §6.4、§A.11
+    pub fn on_0rtt_rejected(&mut self, now: Instant) {
+        let space = Epoch::Data;
+        for sent in std::mem::take(&mut self.sent_packets[space]) {
+            if sent.in_flight {
+                self.bytes_in_flight -= sent.size;
+                self.algorithm.on_packet_discarded(&sent);
+            }
+        }
+        self.lost_packets[space].clear();
+        self.acked_seqs[space].clear();
+        self.time_of_last_ack_eliciting_packet[space] = None;
+        self.loss_time[space] = None;
+        self.pto_count = 0;
+        self.set_lost_detection_timer(now);
+        self.wake_sender();
+    }
+
+    fn wake_sender(&mut self) {
+        // 探测包不受拥塞窗口的限制
+        if self.send_credit() > 0 || self.probes.iter().any(|n| *n > 0) {
+            if let Some(waker) = self.send_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    // 空间中不再有在途的ack-eliciting包，PTO就不必从它算起了
+    fn update_ack_eliciting_in_flight(&mut self, pn_space: Epoch) {
+        if !self.sent_packets[pn_space]
+            .iter()
+            .any(|sent| sent.ack_eliciting && sent.in_flight)
+        {
+            self.time_of_last_ack_eliciting_packet[pn_space] = None;
+        }
+    }
+
+    /// 发送受反放大限制，已无包可发，取消PTO定时器，直到收到新的数据报，Ref. RFC 9002 §6.2.2.1
+    pub fn on_anti_amplification_limited(&mut self, now: Instant) {
+        self.anti_amplification = true;
+        self.set_lost_detection_timer(now);
+    }
+
+    /// 收到了数据报，at_limit表示收到之后是否仍受反放大限制
+    pub fn on_datagram_recv(&mut self, at_limit: bool, now: Instant) {
+        let was_at_limit = std::mem::replace(&mut self.anti_amplification, at_limit);
         // If this datagram unblocks the server, arm the PTO timer to avoid deadlock.
-        if self.anti_amplification {
+        if was_at_limit {
             self.set_lost_detection_timer(now);
             if let Some(loss_detection_timer) = self.loss_detection_timer {
                 if loss_detection_timer < now {
@@ -172,43 +387,63 @@ where
     }
 
     pub fn on_acked(&mut self, space: Epoch, ack_frame: &AckFrame) {
-        let largest_acked = ack_frame.largest.into();
+        let largest_acked = ack_frame.largest.into_inner();
         let ack_delay = Duration::from_micros(ack_frame.delay.into());
-        let now = Instant::now();
+        let now = self.clock.now();
+        // 乱序到达的AckFrame，其largest可能还不如已确认的大
+        if self.largest_acked_packet[space].is_none_or(|largest| largest_acked > largest) {
+            self.largest_acked_packet[space] = Some(largest_acked);
+        }
+        self.remove_expired_lost_packets(space, now);
+        // 确认的区间由对方决定，可能大得离谱，只挑出其中本路径发出过的包号，
+        // 包括在途的、以及判定丢失但仍可能迟到确认的
+        let mut pns = Vec::new();
         for range in ack_frame.iter() {
-            for pn in range {
-                if pn == largest_acked {
-                    if let Some(largest_packet_acked) = self.largest_acked_packet[space] {
-                        assert!(pn > largest_packet_acked);
-                    }
-                    self.largest_acked_packet[space] = Some(pn);
-                    let ack = self.on_packet_acked(pn, space, now);
-
-                    let rtt = ack.as_ref().unwrap().rtt;
-                    self.rtt
-                        .lock()
-                        .unwrap()
-                        .update(rtt, ack_delay, self.handshake_confirmed);
-                } else {
-                    self.on_packet_acked(pn, space, now);
-                }
+            for packets in [&self.sent_packets[space], &self.lost_packets[space]] {
+                let start = packets.partition_point(|p| p.pkt_num < *range.start());
+                pns.extend(
+                    packets
+                        .range(start..)
+                        .map(|p| p.pkt_num)
+                        .take_while(|pn| range.contains(pn)),
+                );
             }
         }
-    }
+        // 先移除该帧新确认的所有包，再采样RTT，最后只做一次丢包检测，
+        // 以免同一帧确认的包在其间被判定丢失，Ref. RFC 9002 §A.7
+        pns.sort_unstable();
+        let newly_acked: Vec<Sent> = pns
+            .into_iter()
+            .filter_map(|pn| self.remove_acked_packet(pn, space, now))
+            .collect();
+        if newly_acked.is_empty() {
+            self.wake_sender();
+            return;
+        }
 
-    pub fn on_packet_acked(
-        &mut self,
-        packet_number: u64,
-        pn_space: Epoch,
-        now: Instant,
-    ) -> Option<Acked> {
-        let sent: Option<Sent> = self.sent_packets[pn_space]
-            .binary_search_by_key(&packet_number, |p| p.pkt_num)
-            .ok()
-            .and_then(|idx| self.sent_packets[pn_space].remove(idx));
+        // 最大包号是新确认的，且新确认的包中有ack-eliciting的，才能采样RTT，
+        // 重复确认的不可再采样，Ref. RFC 9002 §5.1
+        if let Some(largest) = newly_acked
+            .iter()
+            .find(|sent| sent.pkt_num == largest_acked)
+        {
+            if newly_acked.iter().any(|sent| sent.ack_eliciting) {
+                self.rtt.lock().unwrap().update(
+                    now - largest.time_sent,
+                    ack_delay,
+                    self.handshake_confirmed,
+                    now,
+                );
+            }
+        }
 
-        let acked = match sent {
-            Some(sent) => Acked {
+        let loss_packets = self.detect_and_remove_lost_packets(space, now);
+        if !loss_packets.is_empty() {
+            self.on_packets_lost(loss_packets, space, now);
+        }
+
+        for sent in newly_acked {
+            let acked = Acked {
                 pkt_num: sent.pkt_num,
                 time_sent: sent.time_sent,
                 size: sent.size,
@@ -219,28 +454,182 @@ where
                 is_app_limited: sent.is_app_limited,
                 tx_in_flight: sent.tx_in_flight,
                 lost: sent.lost,
-            },
-            None => return None,
+            };
+            self.algorithm.on_packet_acked(&acked, now);
+        }
+        if self.peer_completed_address_validation() {
+            self.pto_count = 0;
+        }
+        self.set_lost_detection_timer(now);
+        self.wake_sender();
+    }
+
+    /// 从在途包中移除被确认的包，返回新确认的包；不在途的，可能是此前误判丢失的包迟到的确认
+    fn remove_acked_packet(
+        &mut self,
+        packet_number: u64,
+        pn_space: Epoch,
+        now: Instant,
+    ) -> Option<Sent> {
+        let sent: Option<Sent> = self.sent_packets[pn_space]
+            .binary_search_by_key(&packet_number, |p| p.pkt_num)
+            .ok()
+            .and_then(|idx| self.sent_packets[pn_space].remove(idx));
+
+        let Some(sent) = sent else {
+            self.on_spurious_loss(packet_number, pn_space, now);
+            return None;
         };
+        if self.largest_acked_seq[pn_space].is_none_or(|seq| sent.seq > seq) {
+            self.largest_acked_seq[pn_space] = Some(sent.seq);
+        }
+        self.acked_seqs[pn_space].insert(sent.seq);
+        if sent.in_flight {
+            self.bytes_in_flight -= sent.size;
+            self.update_ack_eliciting_in_flight(pn_space);
+        }
+        // 该包携带的AckFrame被确认了，其所确认的收包记录都可以失活
+        if let Some(largest) = sent.ack {
+            self.observe_ack.inactivate_rcvd_record(pn_space, largest);
+        }
+        Some(sent)
+    }
 
-        let loss_packets = self.detect_and_remove_lost_packets(pn_space, now);
-        if !loss_packets.is_empty() {
-            self.on_packets_lost(loss_packets, pn_space, now);
+    fn on_packets_lost(&mut self, packets: Vec<Sent>, pn_space: Epoch, now: Instant) {
+        let persistent_congestion = self.in_persistent_congestion(&packets, pn_space);
+        for mut lost in packets {
+            self.observe_loss.may_loss_pkt(pn_space, lost.pkt_num);
+            if lost.in_flight {
+                self.bytes_in_flight -= lost.size;
+                self.algorithm.on_congestion_event(&lost, now);
+            }
+            lost.time_lost = Some(now);
+            let lost_packets = &mut self.lost_packets[pn_space];
+            let idx = lost_packets
+                .binary_search_by_key(&lost.pkt_num, |p| p.pkt_num)
+                .unwrap_or_else(|idx| idx);
+            lost_packets.insert(idx, lost);
+        }
+        if persistent_congestion {
+            self.algorithm.on_persistent_congestion(now);
         }
+        self.update_ack_eliciting_in_flight(pn_space);
+        self.wake_sender();
+    }
 
-        self.algorithm.on_packet_acked(&acked, now);
-        if self.peer_completed_address_validation() {
-            self.pto_count = 0;
+    /// 已判定丢失的包又被确认了，说明是乱序而非丢包，撤销此前的拥塞响应。
+    /// 该包的数据若尚未重传，由Space在确认时取消重传
+    fn on_spurious_loss(&mut self, packet_number: u64, pn_space: Epoch, now: Instant) {
+        let Some(lost) = self.lost_packets[pn_space]
+            .binary_search_by_key(&packet_number, |p| p.pkt_num)
+            .ok()
+            .and_then(|idx| self.lost_packets[pn_space].remove(idx))
+        else {
+            return;
+        };
+        if let Some(largest) = lost.ack {
+            self.observe_ack.inactivate_rcvd_record(pn_space, largest);
+        }
+        self.acked_seqs[pn_space].insert(lost.seq);
+        // 其后已确认的包数，即是这次乱序的程度
+        let reordering =
+            self.largest_acked_seq[pn_space].map_or(0, |largest| largest.saturating_sub(lost.seq));
+        self.reordering.on_spurious_loss(reordering, now);
+        if lost.in_flight {
+            self.algorithm.on_spurious_loss(&lost, now);
+        }
+        // 阈值放宽了，按原阈值算出的丢包时刻已经过早，到时将判不出丢包
+        for pn_space in [Epoch::Initial, Epoch::Handshake, Epoch::Data] {
+            self.recompute_loss_time(pn_space);
         }
         self.set_lost_detection_timer(now);
-        Some(acked)
     }
 
-    fn on_packets_lost(&mut self, packets: Vec<Sent>, _pn_space: Epoch, now: Instant) {
-        // todo: 通知 space 丢包的 pkt_num， 使用回调函数
-        for lost in packets {
-            self.algorithm.on_congestion_event(&lost, now);
+    /// 按当前的丢包判定阈值，重新计算尚未判定丢失的包最早何时超过时间阈值，只计算不判定丢包
+    fn recompute_loss_time(&mut self, pn_space: Epoch) {
+        if self.loss_time[pn_space].is_none() {
+            return;
         }
+        let Some(largest_acked) = self.largest_acked_packet[pn_space] else {
+            self.loss_time[pn_space] = None;
+            return;
+        };
+        let loss_delay = self
+            .rtt
+            .lock()
+            .unwrap()
+            .loss_delay(self.reordering.time_threshold());
+        self.loss_time[pn_space] = self.sent_packets[pn_space]
+            .iter()
+            .take_while(|sent| sent.pkt_num <= largest_acked)
+            .map(|sent| sent.time_sent + loss_delay)
+            .min();
+    }
+
+    fn remove_expired_lost_packets(&mut self, pn_space: Epoch, now: Instant) {
+        let retain = self.rtt.lock().unwrap().pto_base_duration(0) * K_SPURIOUS_LOSS_PTOS;
+        self.lost_packets[pn_space].retain(|lost| {
+            lost.time_lost
+                .is_some_and(|time_lost| time_lost + retain > now)
+        });
+        // 持续拥塞只看在途包、丢失包之间有无被确认的，比它们都早的确认记录不再有用
+        let oldest = [&self.sent_packets[pn_space], &self.lost_packets[pn_space]]
+            .iter()
+            .filter_map(|packets| packets.front().map(|p| p.seq))
+            .min();
+        self.acked_seqs[pn_space] = match oldest {
+            Some(oldest) => self.acked_seqs[pn_space].split_off(&oldest),
+            None => BTreeSet::new(),
+        };
+    }
+
+    /// 持续拥塞：两个ack-eliciting包都丢了，期间发出的包也无一被确认，且两者的发送时间相隔超过了
+    /// 持续拥塞的判定时长。只考虑首次RTT采样之后发出的包，Ref. RFC 9002 §7.6.2。
+    /// 期间发出的包按本路径的发包序号计，包号空间为多条路径共享时，其间的包号可能是其它路径发出的
+    fn in_persistent_congestion(&self, lost_packets: &[Sent], pn_space: Epoch) -> bool {
+        let (first_rtt_sample, duration) = {
+            let rtt = self.rtt.lock().unwrap();
+            let Some(first_rtt_sample) = rtt.first_rtt_sample() else {
+                return false;
+            };
+            (
+                first_rtt_sample,
+                rtt.persistent_congestion_duration(self.max_ack_delay),
+            )
+        };
+
+        // 两个丢包之间本路径发出的包，有一个被确认了，持续拥塞就此中断
+        let acked_between = |from: u64, to: u64| {
+            self.acked_seqs[pn_space]
+                .range(from + 1..to)
+                .next()
+                .is_some()
+        };
+        let mut start: Option<&Sent> = None;
+        let mut prev: Option<u64> = None;
+        for lost in lost_packets
+            .iter()
+            .filter(|lost| lost.time_sent > first_rtt_sample)
+        {
+            if prev.is_some_and(|prev| acked_between(prev, lost.seq)) {
+                start = None;
+            }
+            prev = Some(lost.seq);
+            if !lost.ack_eliciting {
+                continue;
+            }
+            match start {
+                None => start = Some(lost),
+                Some(start) if lost.time_sent - start.time_sent > duration => return true,
+                Some(_) => {}
+            }
+        }
+        false
+    }
+
+    /// 设置该路径丢包判定阈值的自适应配置，已放宽的阈值随之复原
+    pub fn set_reordering(&mut self, config: ReorderingConfig) {
+        self.reordering = ReorderingThreshold::new(config);
     }
 
     pub fn get_congestion_window(&self) -> u64 {
@@ -248,52 +637,75 @@ where
     }
 
     fn set_lost_detection_timer(&mut self, _now: Instant) {
+        let timer = self.lost_detection_time();
+        if timer != self.loss_detection_timer {
+            self.loss_detection_timer = timer;
+            if let Some(waker) = self.timer_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn lost_detection_time(&mut self) -> Option<Instant> {
         let (earliest_loss_time, _) = self.get_loss_time_and_space();
-        if let Some(earliest_loss_time) = earliest_loss_time {
-            self.loss_detection_timer = Some(earliest_loss_time);
-            return;
+        if earliest_loss_time.is_some() {
+            return earliest_loss_time;
         }
 
         if self.anti_amplification {
             // server's timer is not set if nothing can be sent
-            self.loss_detection_timer = None;
-            return;
+            return None;
         }
 
         if self.no_ack_eliciting_in_flight() && self.peer_completed_address_validation() {
-            self.loss_detection_timer = None;
-            return;
+            return None;
         }
         let (timeout, _) = self.get_pto_time_and_space();
-        self.loss_detection_timer = timeout;
+        timeout
     }
 
     fn on_loss_detection_timeout(&mut self, now: Instant) {
         let (earliest_loss_time, space) = self.get_loss_time_and_space();
         if earliest_loss_time.is_some() {
             let loss_packet = self.detect_and_remove_lost_packets(space, now);
-            // 触发了 timeout loss 不为空
-            assert!(!loss_packet.is_empty());
-            self.on_packets_lost(loss_packet, space, now);
+            // 阈值在定时器设下之后放宽了，到时可能判不出丢包，只需按新的丢包时刻重设定时器
+            if !loss_packet.is_empty() {
+                self.on_packets_lost(loss_packet, space, now);
+            }
             self.set_lost_detection_timer(now);
             return;
         }
 
         if self.no_ack_eliciting_in_flight() {
-            assert!(self.peer_completed_address_validation());
-            // if self.has_handshake_keys {
-            //     // sen one ack eliciting handshake packet
-            // } else {
-            //     // send one ack eliciting padded Inital packet
-            // }
+            // 客户端没有在途的数据，对方却还没验证完地址，须发探测包以免双方都在等待，
+            // Ref. RFC 9002 §6.2.2.1
+            debug_assert!(!self.peer_completed_address_validation());
+            if self.has_handshake_keys {
+                // send one ack eliciting handshake packet
+                self.probes[Epoch::Handshake] = 1;
+            } else {
+                // send one ack eliciting padded Inital packet
+                self.probes[Epoch::Initial] = 1;
+            }
         } else {
-            let (timeout, _) = self.get_pto_time_and_space();
+            let (timeout, space) = self.get_pto_time_and_space();
             if timeout.is_some() {
-                // send one ack eliciting packet in space
+                // send one or two ack eliciting packets in space
+                self.probes[space] = 2;
+                // 探测包尽量携带尚未确认的数据，最早的两个在途ack-eliciting包中的帧重新排队发送；
+                // 这两个包仍算在途，此后被确认或判定丢失都照常处理，Ref. RFC 9002 §6.2.4
+                for sent in self.sent_packets[space]
+                    .iter()
+                    .filter(|sent| sent.ack_eliciting)
+                    .take(2)
+                {
+                    self.observe_loss.may_loss_pkt(space, sent.pkt_num);
+                }
             }
         }
         self.pto_count += 1;
         self.set_lost_detection_timer(now);
+        self.wake_sender();
     }
 
     fn get_loss_time_and_space(&self) -> (Option<Instant>, Epoch) {
@@ -310,10 +722,12 @@ where
         (time, space)
     }
 
-    fn get_pto_time_and_space(&self) -> (Option<Instant>, u8) {
+    fn get_pto_time_and_space(&self) -> (Option<Instant>, Epoch) {
         let smoothed_rtt = self.rtt.lock().unwrap().smoothed_rtt;
         let rttvar = self.rtt.lock().unwrap().rttvar;
-        let mut duration = smoothed_rtt + std::cmp::max(K_GRANULARITY, rttvar * 4);
+        // 每次PTO超时，PTO都要翻倍，Ref. RFC 9002 §6.2.1
+        let backoff = 2_u32.pow(self.pto_count);
+        let mut duration = (smoothed_rtt + std::cmp::max(K_GRANULARITY, rttvar * 4)) * backoff;
 
         if self.no_ack_eliciting_in_flight() {
             let eoch = if self.has_handshake_keys {
@@ -321,7 +735,7 @@ where
             } else {
                 Epoch::Initial
             };
-            return (Some(Instant::now() + duration), eoch as u8);
+            return (Some(self.clock.now() + duration), eoch);
         }
 
         let mut pto_timeout = None;
@@ -333,9 +747,9 @@ where
             }
             if *pn_space == Epoch::Data {
                 if !self.handshake_confirmed {
-                    return (pto_timeout, pto_space as u8);
+                    return (pto_timeout, pto_space);
                 }
-                duration += self.max_ack_delay * 2_u32.pow(self.pto_count);
+                duration += self.max_ack_delay * backoff;
             }
 
             if self.time_of_last_ack_eliciting_packet[*pn_space].is_none() {
@@ -348,7 +762,7 @@ where
                 pto_space = *pn_space;
             }
         }
-        (pto_timeout, pto_space as u8)
+        (pto_timeout, pto_space)
     }
 
     fn detect_and_remove_lost_packets(&mut self, pn_space: Epoch, now: Instant) -> Vec<Sent> {
@@ -356,7 +770,14 @@ where
         let largest_acked = self.largest_acked_packet[pn_space].unwrap();
         self.loss_time[pn_space] = None;
 
-        let loss_delay = self.rtt.lock().unwrap().loss_delay();
+        self.reordering.decay(now);
+        let packet_threshold = self.reordering.packet_threshold();
+        let largest_acked_seq = self.largest_acked_seq[pn_space];
+        let loss_delay = self
+            .rtt
+            .lock()
+            .unwrap()
+            .loss_delay(self.reordering.time_threshold());
         let lost_send_time = now.checked_sub(loss_delay).unwrap();
 
         let mut lost_packets = Vec::new();
@@ -368,9 +789,9 @@ where
                 continue;
             }
 
-            // todo: 多路径下，不能用 largest_acked >= self.sent_packets[pn_space][i].pkt_num + K_PACKET_THRESHOLD
-            if self.sent_packets[pn_space][i].time_sent <= lost_send_time
-                || largest_acked >= self.sent_packets[pn_space][i].pkt_num + K_PACKET_THRESHOLD
+            let sent = &self.sent_packets[pn_space][i];
+            if sent.time_sent <= lost_send_time
+                || largest_acked_seq.is_some_and(|seq| seq >= sent.seq + packet_threshold)
             {
                 let lost_packet = self.sent_packets[pn_space].remove(i);
                 lost_packets.push(lost_packet.unwrap());
@@ -402,17 +823,158 @@ where
     }
 }
 
-impl<OA, OL> super::CongestionControl for CongestionController<OA, OL>
+/// 路径的拥塞控制器，发包前由它给出发送信用，发包、收包、收到AckFrame都要告知它
+#[derive(Clone)]
+pub struct ArcCC<OA, OL>(Arc<Mutex<CongestionController<OA, OL>>>);
+
+impl<OA, OL> ArcCC<OA, OL>
+where
+    OA: ObserveAck,
+    OL: ObserveLoss,
+{
+    /// clock须与连接中其它计时之处所用的时钟一致，比如生成AckFrame时计算ack delay
+    pub fn new(
+        algorithm: CongestionAlgorithm,
+        observe_ack: OA,
+        observe_loss: OL,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self(Arc::new(Mutex::new(CongestionController::new(
+            algorithm,
+            observe_ack,
+            observe_loss,
+            clock,
+        ))))
+    }
+
+    /// 拥塞控制器所用的RTT估计，路径的PTO、路径验证等都以它为准
+    pub fn rtt(&self) -> Arc<Mutex<Rtt>> {
+        self.0.lock().unwrap().rtt()
+    }
+
+    pub fn cwnd(&self) -> u64 {
+        self.0.lock().unwrap().get_congestion_window()
+    }
+
+    pub fn bytes_in_flight(&self) -> usize {
+        self.0.lock().unwrap().bytes_in_flight()
+    }
+
+    pub fn on_0rtt_rejected(&self) {
+        let mut cc = self.0.lock().unwrap();
+        let now = cc.now();
+        cc.on_0rtt_rejected(now);
+    }
+
+    pub fn on_handshake_keys(&self) {
+        let mut cc = self.0.lock().unwrap();
+        let now = cc.now();
+        cc.on_handshake_keys(now);
+    }
+
+    /// 发送受反放大限制，已无包可发
+    pub fn on_anti_amplification_limited(&self) {
+        let mut cc = self.0.lock().unwrap();
+        let now = cc.now();
+        cc.on_anti_amplification_limited(now);
+    }
+
+    pub fn set_reordering(&self, config: ReorderingConfig) {
+        self.0.lock().unwrap().set_reordering(config);
+    }
+
+    /// 收到了数据报，at_limit表示收到之后是否仍受反放大限制
+    pub fn on_datagram_recv(&self, at_limit: bool) {
+        let mut cc = self.0.lock().unwrap();
+        let now = cc.now();
+        cc.on_datagram_recv(at_limit, now);
+    }
+
+    /// 丢包检测定时器的截止时间，与seen不同时立即返回；否则登记cx的waker，定时器变化时唤醒
+    pub fn poll_loss_detection_timer(
+        &self,
+        cx: &mut Context<'_>,
+        seen: Option<Instant>,
+    ) -> Poll<Option<Instant>> {
+        let mut cc = self.0.lock().unwrap();
+        if cc.loss_detection_timer != seen {
+            Poll::Ready(cc.loss_detection_timer)
+        } else {
+            cc.timer_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// ACK延迟定时器的截止时间，与seen不同时立即返回；否则登记cx的waker，定时器变化时唤醒
+    pub fn poll_ack_timer(
+        &self,
+        cx: &mut Context<'_>,
+        seen: Option<Instant>,
+    ) -> Poll<Option<Instant>> {
+        let mut cc = self.0.lock().unwrap();
+        if cc.ack_timer != seen {
+            Poll::Ready(cc.ack_timer)
+        } else {
+            cc.ack_timer_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// ACK延迟定时器到期，唤醒等待发送的任务，将AckFrame发出去
+    pub fn on_ack_timeout(&self) {
+        let mut cc = self.0.lock().unwrap();
+        let now = cc.now();
+        if cc.ack_timer.is_some_and(|timer| timer <= now) {
+            cc.ack_timer = None;
+            if let Some(waker) = cc.send_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// 丢包检测定时器到期，判定超时的丢包，或者触发PTO安排发送探测包。
+    /// 定时器期间被推迟了的，不算到期
+    pub fn on_loss_detection_timeout(&self) {
+        let mut cc = self.0.lock().unwrap();
+        let now = cc.now();
+        if cc.loss_detection_timer.is_some_and(|timer| timer <= now) {
+            cc.on_loss_detection_timeout(now);
+        }
+    }
+}
+
+impl<OA, OL> Debug for ArcCC<OA, OL> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cc = self.0.lock().unwrap();
+        f.debug_struct("ArcCC")
+            .field("cwnd", &cc.algorithm.cwnd())
+            .field("bytes_in_flight", &cc.bytes_in_flight)
+            .finish()
+    }
+}
+
+impl<OA, OL> super::CongestionControl for ArcCC<OA, OL>
 where
     OA: ObserveAck,
     OL: ObserveLoss,
 {
     fn poll_send(&self, cx: &mut Context<'_>) -> Poll<usize> {
-        todo!()
+        let mut cc = self.0.lock().unwrap();
+        match cc.send_credit() {
+            0 => {
+                cc.send_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            credit => Poll::Ready(credit),
+        }
     }
 
     fn need_ack(&self, space: Epoch) -> Option<(u64, Instant)> {
-        todo!()
+        self.0.lock().unwrap().need_ack(space)
+    }
+
+    fn need_probe(&self, space: Epoch) -> bool {
+        self.0.lock().unwrap().need_probe(space)
     }
 
     fn on_pkt_sent(
@@ -424,15 +986,26 @@ where
         in_flight: bool,
         ack: Option<u64>,
     ) {
-        todo!()
+        let mut cc = self.0.lock().unwrap();
+        let now = cc.now();
+        cc.on_packet_sent(pn, space, is_ack_elicition, in_flight, sent_bytes, now);
+        if let Some(largest) = ack {
+            cc.on_ack_sent(space, largest);
+        }
     }
 
     fn on_ack(&self, space: Epoch, ack_frame: &AckFrame) {
-        todo!()
+        self.0.lock().unwrap().on_acked(space, ack_frame);
+    }
+
+    fn on_app_limited(&self) {
+        self.0.lock().unwrap().on_app_limited();
     }
 
     fn on_recv_pkt(&self, space: Epoch, pn: u64, is_ack_elicition: bool) {
-        todo!()
+        let mut cc = self.0.lock().unwrap();
+        let now = cc.now();
+        cc.on_packet_rcvd(space, pn, is_ack_elicition, now);
     }
 }
 
@@ -488,6 +1061,12 @@ pub struct Sent {
     pub lost: u64,
 
     pub has_data: bool,
+
+    /// 该包中携带的AckFrame的最大包号，该包被确认后，此前的收包记录都不必再确认了
+    pub ack: Option<u64>,
+
+    /// 该包是本路径在其空间中发出的第几个包，用于包序阈值的判定
+    pub seq: u64,
 }
 
 impl Default for Sent {
@@ -507,6 +1086,8 @@ impl Default for Sent {
             tx_in_flight: 0,
             lost: 0,
             has_data: false,
+            ack: None,
+            seq: 0,
         }
     }
 }
@@ -529,8 +1110,8 @@ impl Ord for Sent {
     }
 }
 
-pub trait Algorithm {
-    fn init(&mut self);
+pub trait Algorithm: Send {
+    fn init(&mut self, now: Instant);
 
     fn on_packet_sent(&mut self, sent: &mut Sent, sent_bytes: usize, now: Instant);
 
@@ -538,12 +1119,26 @@ pub trait Algorithm {
 
     fn on_congestion_event(&mut self, lost: &Sent, now: Instant);
 
+    /// 判定为持续拥塞，拥塞窗口要降到最小，Ref. RFC 9002 §7.6
+    fn on_persistent_congestion(&mut self, now: Instant);
+
+    /// 判定丢失的包后来又被确认了，此前因它做出的拥塞响应应予撤销
+    fn on_spurious_loss(&mut self, lost: &Sent, now: Instant);
+
+    /// 发送是否受限于应用而非拥塞窗口，受限时窗口没有用满，不应增长，Ref. RFC 9002 §7.8。
+    /// 自行判断应用受限的算法，比如BBR根据发送速率采样判断，可忽略
+    fn set_app_limited(&mut self, _app_limited: bool) {}
+
+    /// 在途的包被作废了，既没有被确认也不算丢失，比如被拒绝的0-RTT包
+    fn on_packet_discarded(&mut self, _discarded: &Sent) {}
+
     fn cwnd(&self) -> u64;
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use qbase::clock::{MockClock, RealClock};
 
     struct Mock;
 
@@ -557,7 +1152,8 @@ mod tests {
 
     #[test]
     fn test_on_packet_sent_multiple_packets() {
-        let mut congestion = CongestionController::new(CongestionAlgorithm::Bbr, Mock, Mock);
+        let mut congestion =
+            CongestionController::new(CongestionAlgorithm::Bbr, Mock, Mock, Arc::new(RealClock));
         let now = Instant::now();
         for i in 1..=5 {
             congestion.on_packet_sent(i, Epoch::Initial, true, true, 1000, now);
@@ -576,7 +1172,8 @@ mod tests {
 
     #[test]
     fn test_on_packet_sent_different_epochs() {
-        let mut congestion = CongestionController::new(CongestionAlgorithm::Bbr, Mock, Mock);
+        let mut congestion =
+            CongestionController::new(CongestionAlgorithm::Bbr, Mock, Mock, Arc::new(RealClock));
         let now = Instant::now();
         congestion.on_packet_sent(1, Epoch::Initial, true, true, 1000, now);
         congestion.on_packet_sent(2, Epoch::Handshake, true, true, 1000, now);
@@ -598,7 +1195,8 @@ mod tests {
 
     #[test]
     fn test_detect_and_remove_lost_packets() {
-        let mut congestion = CongestionController::new(CongestionAlgorithm::Bbr, Mock, Mock);
+        let mut congestion =
+            CongestionController::new(CongestionAlgorithm::Bbr, Mock, Mock, Arc::new(RealClock));
         let now = Instant::now();
         let pn_space = Epoch::Initial;
         for i in 1..=5 {
@@ -606,7 +1204,9 @@ mod tests {
         }
         // ack 5，检测出 1,2 因为乱序丢包
         congestion.largest_acked_packet[pn_space] = Some(5);
-        congestion.sent_packets[pn_space].pop_back();
+        congestion.largest_acked_seq[pn_space] = congestion.sent_packets[pn_space]
+            .pop_back()
+            .map(|sent| sent.seq);
         let lost_packets = congestion.detect_and_remove_lost_packets(pn_space, now);
         assert_eq!(lost_packets.len(), 2);
         for (i, lost) in lost_packets.iter().enumerate() {
@@ -623,6 +1223,755 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_anti_amplification() {
+        let mut congestion =
+            CongestionController::new(CongestionAlgorithm::Bbr, Mock, Mock, Arc::new(RealClock));
+        let now = Instant::now();
+        congestion.on_packet_sent(1, Epoch::Initial, true, true, 1200, now);
+        congestion.set_lost_detection_timer(now);
+        assert!(congestion.loss_detection_timer.is_some());
+
+        // 达到反放大限制，无包可发，PTO定时器取消
+        congestion.on_anti_amplification_limited(now);
+        assert_eq!(congestion.loss_detection_timer, None);
+        // 收到的数据报仍不足以解除限制
+        congestion.on_datagram_recv(true, now);
+        assert_eq!(congestion.loss_detection_timer, None);
+        // 解除了限制，重新设置PTO定时器
+        congestion.on_datagram_recv(false, now);
+        assert!(congestion.loss_detection_timer.is_some());
+    }
+
+    /// 只记录持续拥塞事件的拥塞控制算法
+    struct PersistentCongestion(Arc<Mutex<u32>>);
+
+    impl Algorithm for PersistentCongestion {
+        fn init(&mut self, _: Instant) {}
+
+        fn on_packet_sent(&mut self, _: &mut Sent, _: usize, _: Instant) {}
+
+        fn on_packet_acked(&mut self, _: &Acked, _: Instant) {}
+
+        fn on_congestion_event(&mut self, _: &Sent, _: Instant) {}
+
+        fn on_persistent_congestion(&mut self, _: Instant) {
+            *self.0.lock().unwrap() += 1;
+        }
+
+        fn on_spurious_loss(&mut self, _: &Sent, _: Instant) {}
+
+        fn cwnd(&self) -> u64 {
+            u64::MAX
+        }
+    }
+
+    #[test]
+    fn test_persistent_congestion() {
+        use qbase::varint::VarInt;
+
+        let start = Instant::now();
+        let clock = MockClock::new(start);
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::Bbr,
+            Mock,
+            Mock,
+            Arc::new(clock.clone()),
+        );
+        let events = Arc::new(Mutex::new(0));
+        congestion.algorithm = Box::new(PersistentCongestion(events.clone()));
+        let ack = |largest: u32| AckFrame {
+            largest: VarInt::from_u32(largest),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(0),
+            ranges: vec![],
+            ecn: None,
+        };
+
+        // 首次RTT采样之前发出的包，即便丢了很久，也不算持续拥塞
+        for pn in 0..4 {
+            congestion.on_packet_sent(pn, Epoch::Initial, true, true, 1200, clock.now());
+            clock.advance(Duration::from_secs(1));
+        }
+        congestion.on_acked(Epoch::Initial, &ack(3));
+        assert_eq!(*events.lock().unwrap(), 0);
+
+        // RTT采样为1s，持续拥塞的判定时长为(1s + 4 * 0.5s) * 3 = 9s
+        for pn in 4..9 {
+            clock.advance(Duration::from_secs(4));
+            congestion.on_packet_sent(pn, Epoch::Initial, true, true, 1200, clock.now());
+        }
+        let sent = |pn: u64| {
+            congestion.sent_packets[Epoch::Initial]
+                .iter()
+                .find(|sent| sent.pkt_num == pn)
+                .unwrap()
+                .clone()
+        };
+        let (p4, p5, p6, p7) = (sent(4), sent(5), sent(6), sent(7));
+        // 相隔12s，其间的5号包也丢了，或者仍在途，都是持续拥塞
+        assert!(congestion.in_persistent_congestion(&[p4.clone(), p5, p7.clone()], Epoch::Initial));
+        assert!(congestion.in_persistent_congestion(&[p4.clone(), p7.clone()], Epoch::Initial));
+        // 相隔仅8s，不到判定时长
+        assert!(!congestion.in_persistent_congestion(&[p4.clone(), p6], Epoch::Initial));
+        // 其间的5号包被确认了，持续拥塞就此中断
+        congestion.remove_acked_packet(5, Epoch::Initial, clock.now());
+        assert!(!congestion.in_persistent_congestion(&[p4, p7], Epoch::Initial));
+
+        // 确认了8号包，4、6、7号包都判为丢失，其间只有5号包被确认，不算持续拥塞
+        congestion.on_acked(Epoch::Initial, &ack(8));
+        assert_eq!(*events.lock().unwrap(), 0);
+        assert!(congestion.sent_packets[Epoch::Initial].is_empty());
+
+        // 9~12号包都丢了，跨越了12s，拥塞控制算法要收到持续拥塞事件
+        for pn in 9..14 {
+            clock.advance(Duration::from_secs(4));
+            congestion.on_packet_sent(pn, Epoch::Initial, true, true, 1200, clock.now());
+        }
+        congestion.on_acked(Epoch::Initial, &ack(13));
+        assert_eq!(*events.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_persistent_congestion_on_shared_pn_space() {
+        use qbase::varint::VarInt;
+
+        let start = Instant::now();
+        let clock = MockClock::new(start);
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::Bbr,
+            Mock,
+            Mock,
+            Arc::new(clock.clone()),
+        );
+        let ack = |largest: u32| AckFrame {
+            largest: VarInt::from_u32(largest),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(0),
+            ranges: vec![],
+            ecn: None,
+        };
+        let pn_space = Epoch::Data;
+        congestion.on_packet_sent(0, pn_space, true, true, 1200, clock.now());
+        clock.advance(Duration::from_secs(1));
+        congestion.on_acked(pn_space, &ack(0));
+
+        // 包号空间为多条路径共享，其间的包号是其它路径发出的，不能视作本路径的包被确认了
+        for pn in [2, 12] {
+            clock.advance(Duration::from_secs(10));
+            congestion.on_packet_sent(pn, pn_space, true, true, 1200, clock.now());
+        }
+        let lost: Vec<Sent> = congestion.sent_packets[pn_space].iter().cloned().collect();
+        assert!(congestion.in_persistent_congestion(&lost, pn_space));
+
+        // 本路径其间发出的包被确认了，持续拥塞就此中断
+        clock.advance(Duration::from_secs(1));
+        congestion.on_packet_sent(13, pn_space, true, true, 1200, clock.now());
+        clock.advance(Duration::from_secs(10));
+        congestion.on_packet_sent(20, pn_space, true, true, 1200, clock.now());
+        congestion.remove_acked_packet(13, pn_space, clock.now());
+        let lost: Vec<Sent> = congestion.sent_packets[pn_space]
+            .iter()
+            .filter(|sent| sent.pkt_num != 2)
+            .cloned()
+            .collect();
+        assert!(!congestion.in_persistent_congestion(&lost, pn_space));
+    }
+
+    struct SpuriousLoss(Arc<Mutex<Vec<u64>>>);
+
+    impl Algorithm for SpuriousLoss {
+        fn init(&mut self, _: Instant) {}
+
+        fn on_packet_sent(&mut self, _: &mut Sent, _: usize, _: Instant) {}
+
+        fn on_packet_acked(&mut self, _: &Acked, _: Instant) {}
+
+        fn on_congestion_event(&mut self, _: &Sent, _: Instant) {}
+
+        fn on_persistent_congestion(&mut self, _: Instant) {}
+
+        fn on_spurious_loss(&mut self, lost: &Sent, _: Instant) {
+            self.0.lock().unwrap().push(lost.pkt_num);
+        }
+
+        fn cwnd(&self) -> u64 {
+            u64::MAX
+        }
+    }
+
+    #[test]
+    fn test_spurious_loss() {
+        use qbase::varint::VarInt;
+
+        let start = Instant::now();
+        let clock = MockClock::new(start);
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::Bbr,
+            Mock,
+            Mock,
+            Arc::new(clock.clone()),
+        );
+        let spurious = Arc::new(Mutex::new(vec![]));
+        congestion.algorithm = Box::new(SpuriousLoss(spurious.clone()));
+        let ack = |pn: u32| AckFrame {
+            largest: VarInt::from_u32(pn),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(0),
+            ranges: vec![],
+            ecn: None,
+        };
+
+        for pn in 0..5 {
+            congestion.on_packet_sent(pn, Epoch::Data, true, true, 1200, clock.now());
+        }
+        clock.advance(Duration::from_millis(100));
+        // 确认了4号包，0、1号包超过了包序阈值，判为丢失
+        congestion.on_acked(Epoch::Data, &ack(4));
+        assert_eq!(congestion.bytes_in_flight(), 1200 * 2);
+        assert_eq!(congestion.lost_packets[Epoch::Data].len(), 2);
+
+        // 0号包迟到的确认，说明此前是误判，其后已确认了4个包，包序阈值随之放宽
+        congestion.on_acked(Epoch::Data, &ack(0));
+        assert_eq!(*spurious.lock().unwrap(), vec![0]);
+        assert_eq!(congestion.lost_packets[Epoch::Data].len(), 1);
+        assert_eq!(congestion.reordering.packet_threshold(), 5);
+        // 重复确认不会再次撤销
+        congestion.on_acked(Epoch::Data, &ack(0));
+        assert_eq!(*spurious.lock().unwrap(), vec![0]);
+
+        // 超过3个PTO才到的确认，不再视作误判
+        clock.advance(Duration::from_secs(1));
+        congestion.on_acked(Epoch::Data, &ack(1));
+        assert_eq!(*spurious.lock().unwrap(), vec![0]);
+        assert!(congestion.lost_packets[Epoch::Data].is_empty());
+    }
+
+    #[test]
+    fn test_ack_frame_acks_before_loss_detection() {
+        use qbase::varint::VarInt;
+
+        let clock = MockClock::new(Instant::now());
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::Bbr,
+            Mock,
+            Mock,
+            Arc::new(clock.clone()),
+        );
+        let spurious = Arc::new(Mutex::new(vec![]));
+        congestion.algorithm = Box::new(SpuriousLoss(spurious.clone()));
+
+        // 先采样一次RTT，此后RTT都是100ms
+        congestion.on_packet_sent(0, Epoch::Data, true, true, 1200, clock.now());
+        clock.advance(Duration::from_millis(100));
+        congestion.on_acked(
+            Epoch::Data,
+            &AckFrame {
+                largest: VarInt::from_u32(0),
+                delay: VarInt::from_u32(0),
+                first_range: VarInt::from_u32(0),
+                ranges: vec![],
+                ecn: None,
+            },
+        );
+        for pn in 1..5 {
+            congestion.on_packet_sent(pn, Epoch::Data, true, true, 1200, clock.now());
+        }
+        // 确认迟迟才到，早已超过了时间阈值，但同一帧确认的包都不能判为丢失
+        clock.advance(Duration::from_secs(1));
+        congestion.on_acked(
+            Epoch::Data,
+            &AckFrame {
+                largest: VarInt::from_u32(4),
+                delay: VarInt::from_u32(0),
+                first_range: VarInt::from_u32(3),
+                ranges: vec![],
+                ecn: None,
+            },
+        );
+        assert!(congestion.lost_packets[Epoch::Data].is_empty());
+        assert!(spurious.lock().unwrap().is_empty());
+        assert_eq!(congestion.reordering.packet_threshold(), 3);
+        assert_eq!(congestion.bytes_in_flight(), 0);
+        // 采样的是最大包号的RTT
+        assert_eq!(
+            congestion.rtt.lock().unwrap().loss_delay(1.0),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn test_loss_time_after_spurious_loss() {
+        use qbase::varint::VarInt;
+
+        let start = Instant::now();
+        let clock = MockClock::new(start);
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::Bbr,
+            Mock,
+            Mock,
+            Arc::new(clock.clone()),
+        );
+        let ack = |pn: u32| AckFrame {
+            largest: VarInt::from_u32(pn),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(0),
+            ranges: vec![],
+            ecn: None,
+        };
+
+        // 先采样一次RTT，此后RTT都是100ms
+        congestion.on_packet_sent(0, Epoch::Data, true, true, 1200, clock.now());
+        clock.advance(Duration::from_millis(100));
+        congestion.on_acked(Epoch::Data, &ack(0));
+        let sent_time = clock.now();
+        for pn in 1..6 {
+            congestion.on_packet_sent(pn, Epoch::Data, true, true, 1200, sent_time);
+        }
+        clock.advance(Duration::from_millis(100));
+        // 1、2号包超过了包序阈值，3、4号包还没到时间阈值，要在9/8个RTT时判定
+        congestion.on_acked(Epoch::Data, &ack(5));
+        assert_eq!(congestion.lost_packets[Epoch::Data].len(), 2);
+        assert_eq!(
+            congestion.loss_time[Epoch::Data],
+            Some(sent_time + Duration::from_millis(100).mul_f32(1.125))
+        );
+
+        // 误判丢包放宽了时间阈值，丢包时刻随之推迟
+        congestion.on_acked(Epoch::Data, &ack(1));
+        assert_eq!(congestion.reordering.time_threshold(), 1.375);
+        assert_eq!(
+            congestion.loss_time[Epoch::Data],
+            Some(sent_time + Duration::from_millis(100).mul_f32(1.375))
+        );
+
+        // 原先的丢包时刻已过，定时器触发也判不出丢包，不会出错
+        clock.advance(Duration::from_millis(13));
+        congestion.on_loss_detection_timeout(clock.now());
+        assert_eq!(congestion.sent_packets[Epoch::Data].len(), 2);
+        clock.advance(Duration::from_millis(25));
+        congestion.on_loss_detection_timeout(clock.now());
+        assert!(congestion.sent_packets[Epoch::Data].is_empty());
+    }
+
+    #[test]
+    fn test_ack_huge_range() {
+        use qbase::varint::VarInt;
+
+        let mut congestion =
+            CongestionController::new(CongestionAlgorithm::Bbr, Mock, Mock, Arc::new(RealClock));
+        let now = congestion.now();
+        for pn in 0..5 {
+            congestion.on_packet_sent(pn, Epoch::Data, true, true, 1200, now);
+        }
+        // 对方确认的区间覆盖了整个包号空间，只遍历本路径发出过的包，不会卡住
+        let max = VarInt::from_u64((1 << 62) - 1).unwrap();
+        congestion.on_acked(
+            Epoch::Data,
+            &AckFrame {
+                largest: max,
+                delay: VarInt::from_u32(0),
+                first_range: max,
+                ranges: vec![],
+                ecn: None,
+            },
+        );
+        assert_eq!(congestion.bytes_in_flight(), 0);
+        assert!(congestion.sent_packets[Epoch::Data].is_empty());
+        assert!(congestion.lost_packets[Epoch::Data].is_empty());
+    }
+
+    #[test]
+    fn test_packet_threshold_on_shared_pn_space() {
+        let mut congestion =
+            CongestionController::new(CongestionAlgorithm::Bbr, Mock, Mock, Arc::new(RealClock));
+        let now = Instant::now();
+        let pn_space = Epoch::Data;
+        // 包号空间为多条路径共享，本路径只发出了偶数号的包
+        for pn in (0..10).step_by(2) {
+            congestion.on_packet_sent(pn, pn_space, true, true, 1000, now);
+        }
+        congestion.on_acked(
+            pn_space,
+            &AckFrame {
+                largest: qbase::varint::VarInt::from_u32(8),
+                delay: qbase::varint::VarInt::from_u32(0),
+                first_range: qbase::varint::VarInt::from_u32(0),
+                ranges: vec![],
+                ecn: None,
+            },
+        );
+        // 包序阈值按本路径的发包序号计，8号包之前只有0、2号包落后了3个以上
+        let lost: Vec<u64> = congestion.lost_packets[pn_space]
+            .iter()
+            .map(|sent| sent.pkt_num)
+            .collect();
+        assert_eq!(lost, vec![0, 2]);
+        assert_eq!(congestion.sent_packets[pn_space].len(), 2);
+    }
+
+    #[test]
+    fn test_new_reno() {
+        use qbase::varint::VarInt;
+
+        let clock = MockClock::new(Instant::now());
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::NewReno,
+            Mock,
+            Mock,
+            Arc::new(clock.clone()),
+        );
+        assert_eq!(congestion.get_congestion_window(), 12000);
+        for pn in 0..5 {
+            congestion.on_packet_sent(pn, Epoch::Data, true, true, 1200, clock.now());
+        }
+        clock.advance(Duration::from_millis(100));
+        // 确认了4号包，0、1号包判为丢失，窗口减半进入恢复；4号包在恢复前发出，确认它也不增长窗口
+        congestion.on_acked(
+            Epoch::Data,
+            &AckFrame {
+                largest: VarInt::from_u32(4),
+                delay: VarInt::from_u32(0),
+                first_range: VarInt::from_u32(0),
+                ranges: vec![],
+                ecn: None,
+            },
+        );
+        assert_eq!(congestion.get_congestion_window(), 6000);
+    }
+
+    #[test]
+    fn test_new_reno_app_limited() {
+        use qbase::varint::VarInt;
+
+        let clock = MockClock::new(Instant::now());
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::NewReno,
+            Mock,
+            Mock,
+            Arc::new(clock.clone()),
+        );
+        let ack = |pn: u32| AckFrame {
+            largest: VarInt::from_u32(pn),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(0),
+            ranges: vec![],
+            ecn: None,
+        };
+        congestion.on_packet_sent(0, Epoch::Data, true, true, 1200, clock.now());
+        // 发出一个包后就没有数据可发了，窗口远没有用满，确认了也不增长
+        congestion.on_app_limited();
+        clock.advance(Duration::from_millis(100));
+        congestion.on_acked(Epoch::Data, &ack(0));
+        assert_eq!(congestion.get_congestion_window(), 12000);
+
+        // 又发出了在途的包，不再受限于应用
+        congestion.on_packet_sent(1, Epoch::Data, true, true, 1200, clock.now());
+        clock.advance(Duration::from_millis(100));
+        congestion.on_acked(Epoch::Data, &ack(1));
+        assert_eq!(congestion.get_congestion_window(), 13200);
+    }
+
+    #[test]
+    fn test_pto_backoff() {
+        let start = Instant::now();
+        let clock = MockClock::new(start);
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::Bbr,
+            Mock,
+            Mock,
+            Arc::new(clock.clone()),
+        );
+        // 初始RTT为333ms，PTO = 333ms + 4 * 166.5ms
+        let pto = Duration::from_millis(999);
+
+        // 没有在途的ack-eliciting包，PTO从当前时间算起
+        assert_eq!(
+            congestion.get_pto_time_and_space(),
+            (Some(start + pto), Epoch::Initial)
+        );
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            congestion.get_pto_time_and_space(),
+            (Some(start + Duration::from_secs(1) + pto), Epoch::Initial)
+        );
+
+        // 从最后一个ack-eliciting包的发送时间算起，每超时一次，PTO就翻倍
+        let sent_time = clock.now();
+        congestion.on_packet_sent(0, Epoch::Initial, true, true, 1200, sent_time);
+        assert_eq!(congestion.loss_detection_timer, Some(sent_time + pto));
+        for backoff in [2, 4, 8] {
+            clock.set(congestion.loss_detection_timer.unwrap());
+            congestion.on_loss_detection_timeout(clock.now());
+            assert_eq!(
+                congestion.loss_detection_timer,
+                Some(sent_time + pto * backoff)
+            );
+        }
+        assert_eq!(congestion.pto_count, 3);
+    }
+
+    #[test]
+    fn test_ack_timer() {
+        use crate::CongestionControl;
+
+        let start = Instant::now();
+        let clock = MockClock::new(start);
+        let cc = ArcCC::new(
+            CongestionAlgorithm::NewReno,
+            Mock,
+            Mock,
+            Arc::new(clock.clone()),
+        );
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(cc.poll_ack_timer(&mut cx, None), Poll::Pending);
+
+        // 数据空间的包可延迟确认，非ack-eliciting的包不必确认
+        cc.on_recv_pkt(Epoch::Data, 0, false);
+        assert_eq!(cc.poll_ack_timer(&mut cx, None), Poll::Pending);
+        cc.on_recv_pkt(Epoch::Data, 1, true);
+        let deadline = start + MAX_ACK_DELAY;
+        assert_eq!(
+            cc.poll_ack_timer(&mut cx, None),
+            Poll::Ready(Some(deadline))
+        );
+
+        // 到期之前不算超时
+        clock.advance(MAX_ACK_DELAY / 2);
+        cc.on_ack_timeout();
+        assert_eq!(
+            cc.poll_ack_timer(&mut cx, None),
+            Poll::Ready(Some(deadline))
+        );
+
+        // Handshake包要立即确认
+        cc.on_recv_pkt(Epoch::Handshake, 0, true);
+        let now = clock.now();
+        assert_eq!(cc.poll_ack_timer(&mut cx, None), Poll::Ready(Some(now)));
+        cc.on_ack_timeout();
+        assert_eq!(cc.poll_ack_timer(&mut cx, None), Poll::Pending);
+
+        // 各空间都确认过了，定时器随之取消
+        cc.on_recv_pkt(Epoch::Data, 2, true);
+        cc.on_pkt_sent(Epoch::Handshake, 0, false, 50, false, Some(0));
+        assert!(matches!(
+            cc.poll_ack_timer(&mut cx, None),
+            Poll::Ready(Some(_))
+        ));
+        cc.on_pkt_sent(Epoch::Data, 0, false, 50, false, Some(2));
+        assert_eq!(cc.poll_ack_timer(&mut cx, None), Poll::Pending);
+    }
+
+    #[test]
+    fn test_congestion_control() {
+        use crate::CongestionControl;
+        use qbase::varint::VarInt;
+
+        let cc = ArcCC::new(CongestionAlgorithm::Bbr, Mock, Mock, Arc::new(RealClock));
+        let mut cx = Context::from_waker(Waker::noop());
+        // 初始拥塞窗口为10个包
+        assert_eq!(cc.poll_send(&mut cx), Poll::Ready(12000));
+        for pn in 0..10 {
+            cc.on_pkt_sent(Epoch::Initial, pn, true, 1200, true, None);
+        }
+        assert_eq!(cc.bytes_in_flight(), 12000);
+        assert_eq!(cc.poll_send(&mut cx), Poll::Pending);
+
+        // 收到ack-eliciting包才需要确认，确认过之后就不必再确认了
+        cc.on_recv_pkt(Epoch::Initial, 0, false);
+        assert_eq!(cc.need_ack(Epoch::Initial), None);
+        cc.on_recv_pkt(Epoch::Initial, 2, true);
+        cc.on_recv_pkt(Epoch::Initial, 1, true);
+        let (largest, _) = cc.need_ack(Epoch::Initial).unwrap();
+        assert_eq!(largest, 2);
+        assert_eq!(cc.need_ack(Epoch::Handshake), None);
+
+        // ACK-only的包不占拥塞窗口
+        cc.on_pkt_sent(Epoch::Initial, 10, false, 50, false, Some(2));
+        assert_eq!(cc.need_ack(Epoch::Initial), None);
+        assert_eq!(cc.bytes_in_flight(), 12000);
+
+        // 对方确认了0~4，重复的确认不影响
+        let ack = AckFrame {
+            largest: VarInt::from_u32(4),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(4),
+            ranges: vec![],
+            ecn: None,
+        };
+        cc.on_ack(Epoch::Initial, &ack);
+        assert_eq!(cc.bytes_in_flight(), 6000);
+        cc.on_ack(Epoch::Initial, &ack);
+        assert_eq!(cc.bytes_in_flight(), 6000);
+        // 拥塞窗口腾出了空间，还会随着确认而增长
+        assert!(matches!(cc.poll_send(&mut cx), Poll::Ready(credit) if credit >= 6000));
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<(Epoch, u64)>>>);
+
+    impl ObserveAck for Recorder {
+        fn inactivate_rcvd_record(&self, space: Epoch, pn: u64) {
+            self.0.lock().unwrap().push((space, pn));
+        }
+    }
+
+    impl ObserveLoss for Recorder {
+        fn may_loss_pkt(&self, space: Epoch, pn: u64) {
+            self.0.lock().unwrap().push((space, pn));
+        }
+    }
+
+    #[test]
+    fn test_observe_loss_and_ack() {
+        use crate::CongestionControl;
+        use qbase::varint::VarInt;
+
+        let acked = Recorder::default();
+        let lost = Recorder::default();
+        let cc = ArcCC::new(
+            CongestionAlgorithm::Bbr,
+            acked.clone(),
+            lost.clone(),
+            Arc::new(RealClock),
+        );
+        for pn in 0..6 {
+            // 4号包携带了确认到7号包的AckFrame
+            let ack = (pn == 4).then_some(7);
+            cc.on_pkt_sent(Epoch::Data, pn, true, 1200, true, ack);
+        }
+
+        // 确认了4、5，0~2超过了包序阈值，判为丢包，要通知到空间
+        let ack = AckFrame {
+            largest: VarInt::from_u32(5),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(1),
+            ranges: vec![],
+            ecn: None,
+        };
+        cc.on_ack(Epoch::Data, &ack);
+        assert_eq!(
+            *lost.0.lock().unwrap(),
+            vec![(Epoch::Data, 0), (Epoch::Data, 1), (Epoch::Data, 2)]
+        );
+        // 4号包所携带的AckFrame被确认了，7号及之前的收包记录都可失活
+        assert_eq!(*acked.0.lock().unwrap(), vec![(Epoch::Data, 7)]);
+        assert_eq!(cc.bytes_in_flight(), 1200);
+    }
+
+    #[test]
+    fn test_0rtt_rejected() {
+        use crate::CongestionControl;
+
+        let clock = MockClock::new(Instant::now());
+        let lost = Recorder::default();
+        let cc = ArcCC::new(
+            CongestionAlgorithm::NewReno,
+            Mock,
+            lost.clone(),
+            Arc::new(clock.clone()),
+        );
+        let mut cx = Context::from_waker(Waker::noop());
+        cc.on_pkt_sent(Epoch::Initial, 0, true, 1200, true, None);
+        for pn in 0..5 {
+            cc.on_pkt_sent(Epoch::Data, pn, true, 1200, true, None);
+        }
+        let cwnd = cc.cwnd();
+        assert_eq!(cc.bytes_in_flight(), 7200);
+
+        // 被拒绝的0-RTT包不再在途，但不算丢包，拥塞窗口不变
+        cc.on_0rtt_rejected();
+        assert_eq!(cc.bytes_in_flight(), 1200);
+        assert_eq!(cc.cwnd(), cwnd);
+        assert!(lost.0.lock().unwrap().is_empty());
+
+        // 此后的PTO只从Initial包算起，到期也不会判定0-RTT包丢失
+        let Poll::Ready(Some(deadline)) = cc.poll_loss_detection_timer(&mut cx, None) else {
+            panic!("loss detection timer is not armed");
+        };
+        clock.set(deadline);
+        cc.on_loss_detection_timeout();
+        assert!(cc.need_probe(Epoch::Initial));
+        assert!(lost
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|(space, _)| *space == Epoch::Initial));
+    }
+
+    #[test]
+    fn test_pto_probe() {
+        use crate::CongestionControl;
+        use qbase::varint::VarInt;
+
+        let clock = MockClock::new(Instant::now());
+        let lost = Recorder::default();
+        let cc = ArcCC::new(
+            CongestionAlgorithm::Bbr,
+            Mock,
+            lost.clone(),
+            Arc::new(clock.clone()),
+        );
+        let mut cx = Context::from_waker(Waker::noop());
+        for pn in 0..3 {
+            cc.on_pkt_sent(Epoch::Initial, pn, true, 1200, true, None);
+        }
+        assert!(!cc.need_probe(Epoch::Initial));
+
+        // 尾包丢了，迟迟等不到确认，PTO到期后要发两个探测包，并重传最早两个包中的数据
+        let timer = cc.poll_loss_detection_timer(&mut cx, None);
+        let Poll::Ready(Some(deadline)) = timer else {
+            panic!("loss detection timer is not armed");
+        };
+        cc.on_loss_detection_timeout();
+        assert!(!cc.need_probe(Epoch::Initial));
+        clock.set(deadline);
+        cc.on_loss_detection_timeout();
+        assert!(cc.need_probe(Epoch::Initial));
+        assert!(!cc.need_probe(Epoch::Handshake));
+        assert_eq!(
+            *lost.0.lock().unwrap(),
+            vec![(Epoch::Initial, 0), (Epoch::Initial, 1)]
+        );
+        // 定时器已经退避，等待者能看到变化
+        assert!(matches!(
+            cc.poll_loss_detection_timer(&mut cx, Some(deadline)),
+            Poll::Ready(Some(timer)) if timer > deadline
+        ));
+        cc.on_pkt_sent(Epoch::Initial, 3, true, 1200, true, None);
+        assert!(cc.need_probe(Epoch::Initial));
+        cc.on_pkt_sent(Epoch::Initial, 4, true, 1200, true, None);
+        assert!(!cc.need_probe(Epoch::Initial));
+
+        // 全部确认了，但对方尚未验证我方地址，客户端仍要发一个填充的Initial包作探测
+        let ack = AckFrame {
+            largest: VarInt::from_u32(4),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(4),
+            ranges: vec![],
+            ecn: None,
+        };
+        cc.on_ack(Epoch::Initial, &ack);
+        let Poll::Ready(Some(deadline)) = cc.poll_loss_detection_timer(&mut cx, None) else {
+            panic!("loss detection timer is not armed");
+        };
+        clock.set(deadline);
+        cc.on_loss_detection_timeout();
+        assert!(cc.need_probe(Epoch::Initial));
+
+        // 有了握手密钥，没有在途数据时就不必再探测了
+        cc.on_pkt_sent(Epoch::Initial, 5, true, 1200, true, None);
+        cc.on_handshake_keys();
+        let ack = AckFrame {
+            largest: VarInt::from_u32(5),
+            first_range: VarInt::from_u32(0),
+            ..ack
+        };
+        cc.on_ack(Epoch::Initial, &ack);
+        assert_eq!(cc.poll_loss_detection_timer(&mut cx, None), Poll::Pending);
+    }
+
     // #[test]
     // fn test_on_packet_acked() {
     //     let mut congestion = Congestion::new(CongestionAlgorithm::Bbr);
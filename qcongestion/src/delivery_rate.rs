@@ -29,8 +29,12 @@ pub struct Rate {
 
 impl Default for Rate {
     fn default() -> Self {
-        let now = Instant::now();
+        Self::new(Instant::now())
+    }
+}
 
+impl Rate {
+    pub fn new(now: Instant) -> Self {
         Rate {
             delivered: 0,
 
@@ -47,9 +51,7 @@ impl Default for Rate {
             rate_sample: RateSample::default(),
         }
     }
-}
 
-impl Rate {
     // 3.2. Transmitting or retransmitting a data packet
     pub fn on_packet_sent(&mut self, pkt: &mut Sent, bytes_in_flight: usize, bytes_lost: u64) {
         // No packets in flight.
@@ -7,6 +7,8 @@ use std::{
 
 pub mod bbr;
 pub mod congestion;
+pub mod new_reno;
+pub mod reordering;
 pub mod rtt;
 pub use rtt::Rtt;
 pub mod delivery_rate;
@@ -19,6 +21,10 @@ pub trait CongestionControl {
     /// 不需要的话，则返回None。每次需要发包，每个Epoch都需要询问
     fn need_ack(&self, space: Epoch) -> Option<(u64, Instant)>;
 
+    /// PTO超时后，询问某个空间是否要发送探测包。探测包不受拥塞窗口的限制，必须是ack-eliciting的，
+    /// 尽量携带尚未确认的CRYPTO、STREAM数据，没有的话就发一个PING，Ref. RFC 9002 §6.2.4
+    fn need_probe(&self, space: Epoch) -> bool;
+
     /* 下面发送PathChallenge和PathResponse帧，像是Path的，单独抽象在另外一个trait比较合适
     /// 发数据空间的包时，询问是否需要发送PathChallengeFrame，可在0RTT和1RTT数据包内发送
     fn need_path_challenge(&self) -> Option<PathChallengeFrame>;
@@ -44,6 +50,10 @@ pub trait CongestionControl {
     /// 如果该包中有ack frame，那么ack.largest之前的收包记录未来就不需要在AckFrame中再同步了，需通知ack观察者
     fn on_ack(&self, space: Epoch, ack_frame: &AckFrame);
 
+    /// 有发送信用，却没有ack-eliciting的数据可发，发送受限于应用而非拥塞窗口，
+    /// 此后发出在途的包之前，确认都不会增长拥塞窗口，Ref. RFC 9002 §7.8
+    fn on_app_limited(&self);
+
     /// 处理AckFrame中的largest及ack_delay字段，供Path的cc采样rtt，不可重复采样
     /// 调用该函数后，也意味着AckFrame都被确认完了，可以判断Path过往发过的包，哪些丢了，并反馈
     /// #[deprecated("duplicate with on_ack")]
@@ -0,0 +1,312 @@
+//! NewReno Congestion Control
+//!
+//! This implementation follows the pseudocode of RFC 9002 Appendix B:
+//! <https://www.rfc-editor.org/rfc/rfc9002#appendix-B>
+
+use std::time::Instant;
+
+use crate::congestion::{Acked, Algorithm, Sent};
+
+/// QUIC要求路径至少支持1200字节的UDP载荷，在探测出更大的PMTU之前，以此作为最大数据报大小
+const MAX_DATAGRAM_SIZE: usize = 1200;
+
+/// 初始拥塞窗口的包数，Ref. RFC 9002 §7.2
+const INITIAL_WINDOW_PACKETS: usize = 10;
+
+/// 最小拥塞窗口的包数，Ref. RFC 9002 §7.2
+const MINIMUM_WINDOW_PACKETS: usize = 2;
+
+/// 进入恢复时，拥塞窗口的缩减因子，Ref. RFC 9002 §7.3.2
+const LOSS_REDUCTION_FACTOR: f64 = 0.5;
+
+#[derive(Debug)]
+pub struct NewReno {
+    max_datagram_size: usize,
+    congestion_window: usize,
+    // 慢启动阈值，窗口小于它时处于慢启动，否则处于拥塞避免
+    ssthresh: usize,
+    // 进入恢复的时间，此前发出的包丢失不再缩减窗口，被确认也不再增长窗口
+    congestion_recovery_start_time: Option<Instant>,
+    // 拥塞避免阶段累计确认的字节数，满一个窗口才增长一个数据报
+    bytes_acked: usize,
+    // 进入恢复前的窗口及慢启动阈值，误判丢包时据此撤销缩减
+    prior_cwnd: usize,
+    prior_ssthresh: usize,
+    // 本次恢复期内判定丢失、尚未发现是误判的包数，全都是误判才撤销窗口缩减
+    recovery_lost: usize,
+    // 发送受限于应用而非拥塞窗口，窗口没有用满
+    app_limited: bool,
+}
+
+impl Default for NewReno {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NewReno {
+    pub fn new() -> Self {
+        let max_datagram_size = MAX_DATAGRAM_SIZE;
+        Self {
+            max_datagram_size,
+            congestion_window: Self::initial_window(max_datagram_size),
+            ssthresh: usize::MAX,
+            congestion_recovery_start_time: None,
+            bytes_acked: 0,
+            prior_cwnd: 0,
+            prior_ssthresh: usize::MAX,
+            recovery_lost: 0,
+            app_limited: false,
+        }
+    }
+
+    fn initial_window(max_datagram_size: usize) -> usize {
+        (INITIAL_WINDOW_PACKETS * max_datagram_size).min(14720.max(2 * max_datagram_size))
+    }
+
+    fn minimum_window(&self) -> usize {
+        MINIMUM_WINDOW_PACKETS * self.max_datagram_size
+    }
+
+    fn in_congestion_recovery(&self, sent_time: Instant) -> bool {
+        self.congestion_recovery_start_time
+            .is_some_and(|start| sent_time <= start)
+    }
+}
+
+impl Algorithm for NewReno {
+    fn init(&mut self, _now: Instant) {
+        *self = Self::new();
+    }
+
+    fn on_packet_sent(&mut self, _sent: &mut Sent, _sent_bytes: usize, _now: Instant) {}
+
+    fn on_packet_acked(&mut self, packet: &Acked, _now: Instant) {
+        // 恢复期间发出的包被确认，不增长窗口。恢复开始的时间要一直保留，
+        // 此前发出的包迟些才判定丢失，仍属这次恢复，不能再次缩减窗口
+        if self.in_congestion_recovery(packet.time_sent) {
+            return;
+        }
+        // 窗口没有用满，确认再多也说明不了网络能承受更大的窗口，Ref. RFC 9002 §B.5
+        if self.app_limited {
+            return;
+        }
+
+        if self.congestion_window < self.ssthresh {
+            // 慢启动
+            self.congestion_window += packet.size;
+        } else {
+            // 拥塞避免，每确认一个窗口的数据，窗口增长一个数据报
+            self.bytes_acked += packet.size;
+            if self.bytes_acked >= self.congestion_window {
+                self.bytes_acked -= self.congestion_window;
+                self.congestion_window += self.max_datagram_size;
+            }
+        }
+    }
+
+    fn on_congestion_event(&mut self, lost: &Sent, now: Instant) {
+        // 一次恢复期内只缩减一次窗口
+        if self.in_congestion_recovery(lost.time_sent) {
+            self.recovery_lost += 1;
+            return;
+        }
+        self.recovery_lost = 1;
+        self.prior_cwnd = self.congestion_window;
+        self.prior_ssthresh = self.ssthresh;
+
+        self.congestion_recovery_start_time = Some(now);
+        self.ssthresh = (self.congestion_window as f64 * LOSS_REDUCTION_FACTOR) as usize;
+        self.congestion_window = self.ssthresh.max(self.minimum_window());
+        self.bytes_acked = 0;
+    }
+
+    // 持续拥塞之前的窗口已不可信，误判丢包也不再恢复它
+    fn on_persistent_congestion(&mut self, _now: Instant) {
+        self.congestion_window = self.minimum_window();
+        self.congestion_recovery_start_time = None;
+        self.bytes_acked = 0;
+        self.prior_cwnd = 0;
+        self.prior_ssthresh = self.ssthresh;
+        self.recovery_lost = 0;
+    }
+
+    // 这次恢复期内的丢包全都是误判，才撤销窗口缩减并结束恢复
+    fn on_spurious_loss(&mut self, lost: &Sent, _now: Instant) {
+        if !self.in_congestion_recovery(lost.time_sent) {
+            return;
+        }
+        self.recovery_lost = self.recovery_lost.saturating_sub(1);
+        if self.recovery_lost > 0 {
+            return;
+        }
+        self.congestion_window = self.congestion_window.max(self.prior_cwnd);
+        self.ssthresh = self.ssthresh.max(self.prior_ssthresh);
+        self.congestion_recovery_start_time = None;
+    }
+
+    fn set_app_limited(&mut self, app_limited: bool) {
+        self.app_limited = app_limited;
+    }
+
+    fn cwnd(&self) -> u64 {
+        self.congestion_window as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::NewReno;
+    use crate::congestion::{Acked, Algorithm, Sent};
+
+    fn ack_of(sent: &Sent, now: Instant) -> Acked {
+        Acked {
+            pkt_num: sent.pkt_num,
+            time_sent: sent.time_sent,
+            size: sent.size,
+            rtt: now - sent.time_sent,
+            delivered: sent.delivered,
+            delivered_time: sent.delivered_time,
+            first_sent_time: sent.first_sent_time,
+            is_app_limited: sent.is_app_limited,
+            tx_in_flight: sent.tx_in_flight,
+            lost: sent.lost,
+        }
+    }
+
+    fn sent_at(pkt_num: u64, time_sent: Instant) -> Sent {
+        Sent {
+            pkt_num,
+            time_sent,
+            size: 1200,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_slow_start_and_recovery() {
+        let now = Instant::now();
+        let mut reno = NewReno::new();
+        reno.init(now);
+        assert_eq!(reno.cwnd(), 12000);
+
+        // 慢启动，每确认一个包，窗口增长一个包
+        let sent = sent_at(0, now);
+        let now = now + Duration::from_millis(100);
+        reno.on_packet_acked(&ack_of(&sent, now), now);
+        assert_eq!(reno.cwnd(), 13200);
+
+        // 丢包，窗口减半，进入恢复
+        let lost = sent_at(1, now);
+        let now = now + Duration::from_millis(100);
+        reno.on_congestion_event(&lost, now);
+        assert_eq!(reno.cwnd(), 6600);
+        // 恢复期内发出的包丢失，不再缩减窗口；被确认，也不增长窗口
+        reno.on_congestion_event(&sent_at(2, now), now);
+        assert_eq!(reno.cwnd(), 6600);
+        reno.on_packet_acked(&ack_of(&sent_at(3, now), now), now);
+        assert_eq!(reno.cwnd(), 6600);
+
+        // 恢复期之后发出的包被确认，恢复结束，进入拥塞避免，确认满一个窗口才增长一个包
+        let time_sent = now + Duration::from_millis(1);
+        let now = time_sent + Duration::from_millis(100);
+        for pn in 4..9 {
+            reno.on_packet_acked(&ack_of(&sent_at(pn, time_sent), now), now);
+        }
+        assert_eq!(reno.cwnd(), 6600);
+        reno.on_packet_acked(&ack_of(&sent_at(9, time_sent), now), now);
+        assert_eq!(reno.cwnd(), 7800);
+    }
+
+    #[test]
+    fn test_spurious_loss_and_persistent_congestion() {
+        let now = Instant::now();
+        let mut reno = NewReno::new();
+        reno.init(now);
+
+        let lost = sent_at(0, now);
+        let now = now + Duration::from_millis(100);
+        reno.on_congestion_event(&lost, now);
+        assert_eq!(reno.cwnd(), 6000);
+        // 误判丢包，撤销窗口缩减
+        reno.on_spurious_loss(&lost, now);
+        assert_eq!(reno.cwnd(), 12000);
+        assert_eq!(reno.ssthresh, usize::MAX);
+
+        // 持续拥塞，窗口降到最小，误判也不再恢复
+        reno.on_congestion_event(&sent_at(1, now), now);
+        reno.on_persistent_congestion(now);
+        assert_eq!(reno.cwnd(), 2400);
+        reno.on_spurious_loss(&sent_at(1, now), now);
+        assert_eq!(reno.cwnd(), 2400);
+    }
+
+    #[test]
+    fn test_spurious_loss_mixed_with_real_loss() {
+        let now = Instant::now();
+        let mut reno = NewReno::new();
+        reno.init(now);
+
+        // 同一恢复期内丢了两个包，窗口只缩减一次
+        let (lost0, lost1) = (sent_at(0, now), sent_at(1, now));
+        let now = now + Duration::from_millis(100);
+        reno.on_congestion_event(&lost0, now);
+        reno.on_congestion_event(&lost1, now);
+        assert_eq!(reno.cwnd(), 6000);
+
+        // 其中一个是误判，另一个真的丢了，窗口的缩减仍是应当的
+        reno.on_spurious_loss(&lost0, now);
+        assert_eq!(reno.cwnd(), 6000);
+        assert_eq!(reno.ssthresh, 6000);
+        // 两个都是误判，才撤销缩减
+        reno.on_spurious_loss(&lost1, now);
+        assert_eq!(reno.cwnd(), 12000);
+    }
+
+    #[test]
+    fn test_late_loss_in_ended_recovery() {
+        let now = Instant::now();
+        let mut reno = NewReno::new();
+        reno.init(now);
+
+        let (lost0, lost1) = (sent_at(0, now), sent_at(1, now));
+        let now = now + Duration::from_millis(100);
+        reno.on_congestion_event(&lost0, now);
+        assert_eq!(reno.cwnd(), 6000);
+
+        // 恢复期之后发出的包被确认，恢复结束，进入拥塞避免
+        let time_sent = now + Duration::from_millis(1);
+        let now = time_sent + Duration::from_millis(100);
+        reno.on_packet_acked(&ack_of(&sent_at(2, time_sent), now), now);
+        assert_eq!(reno.bytes_acked, 1200);
+
+        // 恢复前发出的包迟些才判定丢失，仍属那次恢复，不再缩减窗口
+        reno.on_congestion_event(&lost1, now);
+        assert_eq!(reno.cwnd(), 6000);
+        // 两个丢包都是误判，才撤销那次缩减
+        reno.on_spurious_loss(&lost0, now);
+        assert_eq!(reno.cwnd(), 6000);
+        reno.on_spurious_loss(&lost1, now);
+        assert_eq!(reno.cwnd(), 12000);
+    }
+
+    #[test]
+    fn test_app_limited() {
+        let now = Instant::now();
+        let mut reno = NewReno::new();
+        reno.init(now);
+
+        // 发送受限于应用，窗口没有用满，确认了也不增长
+        reno.set_app_limited(true);
+        let sent = sent_at(0, now);
+        let now = now + Duration::from_millis(100);
+        reno.on_packet_acked(&ack_of(&sent, now), now);
+        assert_eq!(reno.cwnd(), 12000);
+
+        reno.set_app_limited(false);
+        reno.on_packet_acked(&ack_of(&sent_at(1, now), now), now);
+        assert_eq!(reno.cwnd(), 13200);
+    }
+}
@@ -0,0 +1,170 @@
+use std::time::{Duration, Instant};
+
+/// 包序阈值的初始值，Ref. RFC 9002 §6.1.1
+pub const K_PACKET_THRESHOLD: u64 = 3;
+/// 时间阈值的初始值，以RTT的倍数计，Ref. RFC 9002 §6.1.2
+pub const K_TIME_THRESHOLD: f32 = 9.0 / 8.0;
+
+/// 丢包判定阈值的自适应配置，每个连接可以不同
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReorderingConfig {
+    /// 是否根据丢包误判放宽阈值，关闭则始终使用初始值
+    pub adaptive: bool,
+    /// 包序阈值最多放宽到这么多个包
+    pub max_packet_threshold: u64,
+    /// 时间阈值最多放宽到RTT的这么多倍
+    pub max_time_threshold: f32,
+    /// 每次误判，时间阈值放宽这么多倍RTT
+    pub time_threshold_step: f32,
+    /// 每隔这么久没有误判，阈值就回退一档，直到回到初始值
+    pub decay_period: Duration,
+}
+
+impl Default for ReorderingConfig {
+    fn default() -> Self {
+        Self {
+            adaptive: true,
+            max_packet_threshold: 20,
+            max_time_threshold: 2.0,
+            time_threshold_step: 0.25,
+            decay_period: Duration::from_secs(2),
+        }
+    }
+}
+
+/// 当前生效的丢包判定阈值。误判丢包说明网络乱序超出了阈值，就放宽之；
+/// 此后长时间没有误判，再逐步收紧回初始值，Ref. RFC 8985 §6.2
+#[derive(Debug, Clone)]
+pub struct ReorderingThreshold {
+    config: ReorderingConfig,
+    packet_threshold: u64,
+    time_threshold: f32,
+    // 上次放宽或回退阈值的时间，阈值为初始值时为None
+    last_change: Option<Instant>,
+}
+
+impl Default for ReorderingThreshold {
+    fn default() -> Self {
+        Self::new(ReorderingConfig::default())
+    }
+}
+
+impl ReorderingThreshold {
+    pub fn new(config: ReorderingConfig) -> Self {
+        Self {
+            config,
+            packet_threshold: K_PACKET_THRESHOLD,
+            time_threshold: K_TIME_THRESHOLD,
+            last_change: None,
+        }
+    }
+
+    pub fn config(&self) -> &ReorderingConfig {
+        &self.config
+    }
+
+    pub fn packet_threshold(&self) -> u64 {
+        self.packet_threshold
+    }
+
+    pub fn time_threshold(&self) -> f32 {
+        self.time_threshold
+    }
+
+    /// 某个包被误判丢失，此前其后已有reordering个包被确认。
+    /// 包序阈值放宽到足以容纳这次乱序，时间阈值放宽一档
+    pub fn on_spurious_loss(&mut self, reordering: u64, now: Instant) {
+        if !self.config.adaptive {
+            return;
+        }
+        self.packet_threshold = self
+            .packet_threshold
+            .max(reordering + 1)
+            .min(self.config.max_packet_threshold.max(K_PACKET_THRESHOLD));
+        self.time_threshold = (self.time_threshold + self.config.time_threshold_step)
+            .min(self.config.max_time_threshold.max(K_TIME_THRESHOLD));
+        self.last_change = Some(now);
+    }
+
+    /// 距上次变化每过一个decay_period，阈值回退一档
+    pub fn decay(&mut self, now: Instant) {
+        let Some(last_change) = self.last_change else {
+            return;
+        };
+        let period = self.config.decay_period;
+        if period.is_zero() {
+            self.reset();
+            return;
+        }
+        let elapsed = now.saturating_duration_since(last_change);
+        let periods = (elapsed.as_nanos() / period.as_nanos()) as u32;
+        if periods == 0 {
+            return;
+        }
+
+        self.packet_threshold = self
+            .packet_threshold
+            .saturating_sub(periods as u64)
+            .max(K_PACKET_THRESHOLD);
+        self.time_threshold = (self.time_threshold
+            - self.config.time_threshold_step * periods as f32)
+            .max(K_TIME_THRESHOLD);
+        if self.packet_threshold == K_PACKET_THRESHOLD && self.time_threshold == K_TIME_THRESHOLD {
+            self.last_change = None;
+        } else {
+            self.last_change = Some(last_change + period * periods);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.packet_threshold = K_PACKET_THRESHOLD;
+        self.time_threshold = K_TIME_THRESHOLD;
+        self.last_change = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_widen_and_decay() {
+        let now = Instant::now();
+        let mut threshold = ReorderingThreshold::default();
+        assert_eq!(threshold.packet_threshold(), K_PACKET_THRESHOLD);
+        assert_eq!(threshold.time_threshold(), K_TIME_THRESHOLD);
+
+        threshold.on_spurious_loss(5, now);
+        assert_eq!(threshold.packet_threshold(), 6);
+        assert_eq!(threshold.time_threshold(), 1.375);
+        // 放宽不会超过上限
+        threshold.on_spurious_loss(100, now);
+        threshold.on_spurious_loss(100, now);
+        threshold.on_spurious_loss(100, now);
+        assert_eq!(threshold.packet_threshold(), 20);
+        assert_eq!(threshold.time_threshold(), 2.0);
+
+        // 不足一个周期，不回退
+        threshold.decay(now + Duration::from_secs(1));
+        assert_eq!(threshold.packet_threshold(), 20);
+        // 过了一个周期，回退一档
+        threshold.decay(now + Duration::from_secs(2));
+        assert_eq!(threshold.packet_threshold(), 19);
+        assert_eq!(threshold.time_threshold(), 1.75);
+        // 很久之后，回到初始值
+        threshold.decay(now + Duration::from_secs(60));
+        assert_eq!(threshold.packet_threshold(), K_PACKET_THRESHOLD);
+        assert_eq!(threshold.time_threshold(), K_TIME_THRESHOLD);
+    }
+
+    #[test]
+    fn test_not_adaptive() {
+        let mut threshold = ReorderingThreshold::new(ReorderingConfig {
+            adaptive: false,
+            ..Default::default()
+        });
+        threshold.on_spurious_loss(10, Instant::now());
+        assert_eq!(threshold.packet_threshold(), K_PACKET_THRESHOLD);
+        assert_eq!(threshold.time_threshold(), K_TIME_THRESHOLD);
+    }
+}
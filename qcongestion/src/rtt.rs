@@ -2,7 +2,7 @@ use std::time::{Duration, Instant};
 
 const INITIAL_RTT: Duration = Duration::from_millis(333);
 const GRANULARITY: Duration = Duration::from_millis(1);
-const TIME_THRESHOLD: f32 = 1.125;
+const PERSISTENT_CONGESTION_THRESHOLD: u32 = 3;
 
 #[derive(Debug, Clone)]
 pub struct Rtt {
@@ -33,13 +33,14 @@ impl Rtt {
         latest_rtt: Duration,
         mut ack_delay: Duration,
         is_handshake_confirmed: bool,
+        now: Instant,
     ) {
         self.latest_rtt = latest_rtt;
         if self.first_rtt_sample.is_none() {
             self.min_rtt = latest_rtt;
             self.smoothed_rtt = latest_rtt;
             self.rttvar = latest_rtt / 2;
-            self.first_rtt_sample = Some(Instant::now());
+            self.first_rtt_sample = Some(now);
             return;
         }
 
@@ -66,9 +67,10 @@ impl Rtt {
         self.smoothed_rtt = self.smoothed_rtt.mul_f32(0.875) + adjusted_rtt.mul_f32(0.125);
     }
 
-    pub fn loss_delay(&self) -> Duration {
+    /// 时间阈值time_threshold以RTT的倍数计，可随乱序程度调整，Ref. RFC 9002 §6.1.2
+    pub fn loss_delay(&self, time_threshold: f32) -> Duration {
         std::cmp::max(
-            std::cmp::max(self.latest_rtt, self.smoothed_rtt).mul_f32(TIME_THRESHOLD),
+            std::cmp::max(self.latest_rtt, self.smoothed_rtt).mul_f32(time_threshold),
             GRANULARITY,
         )
     }
@@ -76,6 +78,17 @@ impl Rtt {
     pub fn pto_base_duration(&self, pto_count: u32) -> Duration {
         (self.smoothed_rtt + std::cmp::max(self.rttvar * 4, GRANULARITY)) * (1 << pto_count)
     }
+
+    /// 首次RTT采样的时间，此前发出的包不参与持续拥塞的判定
+    pub fn first_rtt_sample(&self) -> Option<Instant> {
+        self.first_rtt_sample
+    }
+
+    /// 丢包跨越的时长超过它，即判定为持续拥塞，Ref. RFC 9002 §7.6.1
+    pub fn persistent_congestion_duration(&self, max_ack_delay: Duration) -> Duration {
+        (self.smoothed_rtt + std::cmp::max(self.rttvar * 4, GRANULARITY) + max_ack_delay)
+            * PERSISTENT_CONGESTION_THRESHOLD
+    }
 }
 
 #[cfg(test)]
@@ -1,36 +1,53 @@
-use crate::path::ArcPath;
+use crate::{
+    idle::ArcIdleTimer,
+    path::{epoch_of, ArcPath, ArcPaths, PathId},
+};
 use qbase::{
     error::{Error, ErrorKind},
     frame::{AckFrame, BeFrame, ConnFrame, Frame, FrameReader, PureFrame},
     packet::{
         decrypt::{DecodeHeader, DecryptPacket, RemoteProtection},
-        header::GetType,
+        header::{GetDcid, GetType},
         keys::{ArcKeys, ArcOneRttKeys},
-        r#type::Type,
+        r#type::{
+            long::{Type::V1, Ver1},
+            Type,
+        },
         OneRttPacket, PacketNumber, PacketWrapper,
     },
     util::ArcAsyncQueue,
 };
+use qcongestion::CongestionControl;
 use qrecovery::{
     space::{ArcSpace, SpaceFrame},
     streams::{ArcDataStreams, ReceiveStream, TransmitStream},
 };
 use tokio::sync::mpsc;
 
-fn parse_packet_and_then_dispatch(
+#[allow(clippy::too_many_arguments)]
+fn parse_packet_and_then_dispatch<S>(
     payload: bytes::Bytes,
     packet_type: Type,
     path: &ArcPath,
+    paths: &ArcPaths,
+    space: &ArcSpace<S>,
     conn_frames: &ArcAsyncQueue<ConnFrame>,
     space_frames: &ArcAsyncQueue<SpaceFrame>,
-    ack_frames_tx: &mpsc::UnboundedSender<AckFrame>,
-    // on_ack_frame_rcvd: impl FnMut(AckFrame, Arc<Mutex<Rtt>>),
-) -> Result<bool, Error> {
+    ack_frames_tx: &mpsc::UnboundedSender<(u64, AckFrame)>,
+) -> Result<(bool, bool), Error>
+where
+    S: ReceiveStream + TransmitStream,
+{
+    // 包中的AckFrame先攒着，整个包解析无误后才生效，以免半途出错时确认已被处理
+    let mut ack_frames = Vec::new();
     let mut space_frame_writer = space_frames.writer();
     let mut conn_frame_writer = conn_frames.writer();
     let mut path_frame_writer = path.frames().writer();
     let mut frame_reader = FrameReader::new(payload);
     let mut is_ack_eliciting = false;
+    // 只含PATH_CHALLENGE、PATH_RESPONSE、NEW_CONNECTION_ID和PADDING帧的包是探测包，
+    // 探测包不会引起连接迁移
+    let mut is_probing = true;
     while let Some(result) = frame_reader.next() {
         match result {
             Ok(frame) => match frame {
@@ -48,16 +65,28 @@ fn parse_packet_and_then_dispatch(
 
                     match f {
                         PureFrame::Padding(_) => continue,
-                        PureFrame::Ping(_) => is_ack_eliciting = true,
+                        PureFrame::Ping(_) => {
+                            is_ack_eliciting = true;
+                            is_probing = false;
+                        }
                         PureFrame::Ack(ack) => {
-                            // TODO: 在此收到AckFrame，ArcPath需先内部处理一番
-                            let _ = ack_frames_tx.send(ack);
+                            is_probing = false;
+                            // AckFrame确认的是默认的包号空间0
+                            ack_frames.push((0, ack));
+                        }
+                        PureFrame::PathAck(f) => {
+                            is_probing = false;
+                            ack_frames.push((f.path_id.into_inner(), f.ack));
                         }
                         PureFrame::Conn(f) => {
                             is_ack_eliciting = true;
+                            if !matches!(f, ConnFrame::NewConnectionId(_)) {
+                                is_probing = false;
+                            }
                             conn_frame_writer.push(f);
                         }
                         PureFrame::Stream(f) => {
+                            is_probing = false;
                             is_ack_eliciting = true;
                             space_frame_writer.push(SpaceFrame::Stream(f));
                         }
@@ -80,6 +109,7 @@ fn parse_packet_and_then_dispatch(
                     }
 
                     is_ack_eliciting = true;
+                    is_probing = false;
                     space_frame_writer.push(SpaceFrame::Data(f, data));
                 }
             },
@@ -93,23 +123,42 @@ fn parse_packet_and_then_dispatch(
             }
         }
     }
-    Ok(is_ack_eliciting)
+    for (pn_space, ack) in ack_frames.iter() {
+        if let Err(e) = space.check_ack(*pn_space, ack) {
+            space_frame_writer.rollback();
+            conn_frame_writer.rollback();
+            path_frame_writer.rollback();
+            return Err(e);
+        }
+    }
+    // 先驱动发包路径的拥塞控制，再交给空间
+    for (pn_space, ack) in ack_frames {
+        if let Some(acked_path) = paths.acked_path(pn_space, path) {
+            acked_path.cc().on_ack(epoch_of(packet_type), &ack);
+        }
+        let _ = ack_frames_tx.send((pn_space, ack));
+    }
+    Ok((is_ack_eliciting, is_probing))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn loop_read_long_packet_and_then_dispatch_to_space_frame_queue<H, S>(
-    mut packet_rx: mpsc::UnboundedReceiver<(PacketWrapper<H>, ArcPath)>,
+    mut packet_rx: mpsc::UnboundedReceiver<(PacketWrapper<H>, PathId)>,
     keys: ArcKeys,
     space: ArcSpace<S>,
+    paths: ArcPaths,
     conn_frame_queue: ArcAsyncQueue<ConnFrame>,
     space_frame_queue: ArcAsyncQueue<SpaceFrame>,
-    ack_frames_tx: mpsc::UnboundedSender<AckFrame>,
+    ack_frames_tx: mpsc::UnboundedSender<(u64, AckFrame)>,
+    conn_error_tx: mpsc::UnboundedSender<Error>,
+    idle: ArcIdleTimer,
     need_close_space_frame_queue_at_end: bool,
 ) where
     S: ReceiveStream + TransmitStream,
-    H: GetType,
+    H: GetType + GetDcid,
     PacketWrapper<H>: DecodeHeader<Output = PacketNumber> + DecryptPacket + RemoteProtection,
 {
-    while let Some((mut packet, path)) = packet_rx.recv().await {
+    while let Some((mut packet, path_id)) = packet_rx.recv().await {
         if let Some(k) = keys.get_remote_keys().await {
             let ok = packet.remove_protection(&k.as_ref().remote.header);
             if !ok {
@@ -128,22 +177,43 @@ pub(crate) async fn loop_read_long_packet_and_then_dispatch_to_space_frame_queue
             };
 
             let packet_type = packet.header.get_type();
+            let scid = *packet.header.get_dcid();
+            let pkt_size = packet.raw_data.len();
             match packet.decrypt_packet(pn, encoded_pn.size(), &k.as_ref().remote.packet) {
                 Ok(payload) => {
+                    // 解密成功才认定路径，以免伪造的包凭空创建路径
+                    let Some(path) = paths.get_or_create(path_id, scid) else {
+                        continue;
+                    };
+                    // 收到对方的Handshake包，说明对方收到了我方发往其地址的Initial包，地址得以验证，
+                    // Ref. RFC 9000 §8.1
+                    if packet_type == Type::Long(V1(Ver1::HANDSHAKE)) {
+                        path.grant_anti_amplification();
+                    }
+                    // 解除了反放大限制导致的发送阻塞，要重新设置该路径的PTO定时器
+                    path.on_rcvd(pkt_size);
+                    path.cc().on_datagram_recv(path.is_amplification_limited());
                     match parse_packet_and_then_dispatch(
                         payload,
                         packet_type,
                         &path,
+                        &paths,
+                        &space,
                         &conn_frame_queue,
                         &space_frame_queue,
                         &ack_frames_tx,
                     ) {
-                        // TODO: path也要记录收包时间、is_ack_eliciting
-                        Ok(_is_ack_eliciting) => space.on_rcvd_pn(pn),
-                        Err(_e) => {
-                            // 解析包失败，丢弃
-                            // TODO: 该包要认的话，还得向对方返回错误信息，并终止连接
-                            continue;
+                        // path的拥塞控制器记下收包，决定何时发送AckFrame
+                        Ok((is_ack_eliciting, _)) => {
+                            space.on_rcvd_pn(pn);
+                            path.cc()
+                                .on_recv_pkt(epoch_of(packet_type), pn, is_ack_eliciting);
+                            idle.on_rcvd();
+                        }
+                        Err(e) => {
+                            // 解密成功的包中帧有误，是对方违反了协议，关闭连接并告知对方错误
+                            let _ = conn_error_tx.send(e);
+                            break;
                         }
                     }
                 }
@@ -160,15 +230,19 @@ pub(crate) async fn loop_read_long_packet_and_then_dispatch_to_space_frame_queue
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn loop_read_short_packet_and_then_dispatch_to_space_frame_queue(
-    mut packet_rx: mpsc::UnboundedReceiver<(OneRttPacket, ArcPath)>,
+    mut packet_rx: mpsc::UnboundedReceiver<(OneRttPacket, PathId)>,
     keys: ArcOneRttKeys,
     space: ArcSpace<ArcDataStreams>,
+    paths: ArcPaths,
     conn_frame_queue: ArcAsyncQueue<ConnFrame>,
     space_frame_queue: ArcAsyncQueue<SpaceFrame>,
-    ack_frames_tx: mpsc::UnboundedSender<AckFrame>,
+    ack_frames_tx: mpsc::UnboundedSender<(u64, AckFrame)>,
+    conn_error_tx: mpsc::UnboundedSender<Error>,
+    idle: ArcIdleTimer,
 ) {
-    while let Some((mut packet, path)) = packet_rx.recv().await {
+    while let Some((mut packet, path_id)) = packet_rx.recv().await {
         // 1rtt空间的header protection key是固定的，packet key则是根据包头中的key_phase_bit变化的
         if let Some((hk, pk)) = keys.get_remote_keys().await {
             let ok = packet.remove_protection(&hk.as_ref());
@@ -178,7 +252,10 @@ pub(crate) async fn loop_read_short_packet_and_then_dispatch_to_space_frame_queu
             }
 
             let (encoded_pn, key_phase) = packet.decode_header().unwrap();
-            let pn = match space.decode_pn(encoded_pn) {
+            // 多路径下，按数据包中目标连接ID的序号找到其所在的包号空间
+            let scid = *packet.header.get_dcid();
+            let pn_space = paths.rcvd_pn_space(&scid);
+            let pn = match space.decode_pn_on_path(pn_space, encoded_pn) {
                 Ok(pn) => pn,
                 Err(_e) => continue,
             };
@@ -186,27 +263,52 @@ pub(crate) async fn loop_read_short_packet_and_then_dispatch_to_space_frame_queu
             // 要根据key_phase_bit来获取packet key
             let packet_type = packet.header.get_type();
             let packet_key = pk.lock().unwrap().get_remote(key_phase, pn);
+            let pkt_size = packet.raw_data.len();
             match packet.decrypt_packet(pn, encoded_pn.size(), &packet_key.as_ref()) {
                 Ok(payload) => {
+                    let Some(path) = paths.get_or_create(path_id, scid) else {
+                        continue;
+                    };
+                    // 解除了反放大限制导致的发送阻塞，要重新设置该路径的PTO定时器
+                    path.on_rcvd(pkt_size);
+                    path.cc().on_datagram_recv(path.is_amplification_limited());
                     match parse_packet_and_then_dispatch(
                         payload,
                         packet_type,
                         &path,
+                        &paths,
+                        &space,
                         &conn_frame_queue,
                         &space_frame_queue,
                         &ack_frames_tx,
                     ) {
-                        // TODO: path也要登记其收到的包、收包时间、is_ack_eliciting，方便激发AckFrame
-                        Ok(_is_ack_eliciting) => space.on_rcvd_pn(pn),
-                        Err(_e) => {
-                            // 解析包失败，丢弃
-                            // TODO: 该包要认的话，还得向对方返回错误信息，并终止连接
-                            continue;
+                        // path的拥塞控制器记下收包，决定何时发送AckFrame
+                        Ok((is_ack_eliciting, is_probing)) => {
+                            space.on_rcvd_pn_on_path(pn_space, pn);
+                            path.cc()
+                                .on_recv_pkt(epoch_of(packet_type), pn, is_ack_eliciting);
+                            idle.on_rcvd();
+                            // 对方在新路径上发来非探测包，说明对方迁移了
+                            if !is_probing {
+                                paths.on_non_probing_pkt(&path, pn);
+                            }
+                        }
+                        Err(e) => {
+                            // 解密成功的包中帧有误，是对方违反了协议，关闭连接并告知对方错误
+                            let _ = conn_error_tx.send(e);
+                            break;
                         }
                     }
                 }
-                // Decryption failed, just ignore/discard it.
-                Err(_) => continue,
+                // Decryption failed, just ignore/discard it, unless too many packets
+                // have failed to be authenticated, exceeding the AEAD integrity limit.
+                Err(_) => {
+                    if let Err(e) = pk.lock().unwrap().on_pkt_decrypt_failed(key_phase) {
+                        let _ = conn_error_tx.send(e);
+                        break;
+                    }
+                    continue;
+                }
             }
         } else {
             break;
@@ -0,0 +1,705 @@
+use crate::endpoint::RouterRegistry;
+use qbase::{
+    cid::{ArcCidGenerator, ConnectionId, ResetToken, ResetTokenKey},
+    config::PreferredAddress,
+    error::{Error, ErrorKind},
+    frame::{BeFrame, ConnFrame, NewConnectionIdFrame, RetireConnectionIdFrame},
+    varint::{VarInt, VARINT_MAX},
+};
+use qrecovery::{index_deque::IndexDeque, reliable::ArcReliableFrameQueue};
+use std::{
+    collections::HashSet,
+    net::{SocketAddrV4, SocketAddrV6},
+    sync::{Arc, Mutex},
+};
+
+/// 即便对方的active_connection_id_limit很大，我方同时有效的连接ID也不超过该数量
+pub const MAX_ACTIVE_CIDS: u64 = 8;
+
+/// 我方签发给对方使用的连接ID。
+/// 我方保持对方手中有active_connection_id_limit个可用的连接ID，对方每退役一个，就补签一个，
+/// 签发与退役都要同步到端点的路由表中。
+#[derive(Debug)]
+pub struct RawLocalCids {
+    cid_gen: ArcCidGenerator,
+    // 无状态重置令牌由静态密钥派生，重启后依然能为这些连接ID发送无状态重置
+    reset_key: ResetTokenKey,
+    // 按序号排列，已被对方退役的为None
+    cids: IndexDeque<Option<(ConnectionId, ResetToken)>, VARINT_MAX>,
+    // 对方的active_connection_id_limit，得知对方的传输参数之前，按默认值2处理
+    active_cid_limit: u64,
+    reliable_frame_queue: ArcReliableFrameQueue,
+    registry: RouterRegistry,
+}
+
+impl RawLocalCids {
+    fn new(
+        scid: ConnectionId,
+        cid_gen: ArcCidGenerator,
+        reset_key: ResetTokenKey,
+        reliable_frame_queue: ArcReliableFrameQueue,
+        registry: RouterRegistry,
+    ) -> Self {
+        let mut cids = IndexDeque::default();
+        // 握手期间使用的源连接ID，其序号为0
+        let _ = cids.push(Some((scid, reset_key.derive(&scid))));
+        registry.add(scid);
+        Self {
+            cid_gen,
+            reset_key,
+            cids,
+            active_cid_limit: 2,
+            reliable_frame_queue,
+            registry,
+        }
+    }
+
+    fn active_count(&self) -> u64 {
+        self.cids.iter().filter(|cid| cid.is_some()).count() as u64
+    }
+
+    fn issue_new_cid(&mut self) {
+        let id = self.cid_gen.generate();
+        let reset_token = self.reset_key.derive(&id);
+        if let Ok(sequence) = self.cids.push(Some((id, reset_token))) {
+            self.registry.add(id);
+            self.reliable_frame_queue
+                .write()
+                .push_conn_frame(ConnFrame::NewConnectionId(NewConnectionIdFrame {
+                    sequence: VarInt(sequence),
+                    retire_prior_to: VarInt(self.cids.offset()),
+                    id,
+                    reset_token,
+                }));
+        }
+    }
+
+    /// 服务端在传输参数preferred_address中携带的连接ID，序号为1，不通过NEW_CONNECTION_ID帧签发。
+    /// 须在签发其它连接ID之前调用
+    fn issue_preferred_cid(&mut self) -> (ConnectionId, ResetToken) {
+        assert_eq!(
+            self.cids.largest(),
+            1,
+            "must be issued right after the handshake cid"
+        );
+        let id = self.cid_gen.generate();
+        let reset_token = self.reset_key.derive(&id);
+        let _ = self.cids.push(Some((id, reset_token)));
+        self.registry.add(id);
+        (id, reset_token)
+    }
+
+    fn issue_until_limit(&mut self) {
+        let limit = std::cmp::min(self.active_cid_limit, MAX_ACTIVE_CIDS);
+        while self.active_count() < limit {
+            self.issue_new_cid();
+        }
+    }
+
+    fn set_limit(&mut self, active_cid_limit: u64) {
+        self.active_cid_limit = active_cid_limit;
+        self.issue_until_limit();
+    }
+
+    fn recv_retire_cid_frame(&mut self, frame: &RetireConnectionIdFrame) -> Result<(), Error> {
+        let seq = frame.sequence.into_inner();
+        // Receipt of a RETIRE_CONNECTION_ID frame containing a sequence number greater
+        // than any previously sent to the peer MUST be treated as a connection error of
+        // type PROTOCOL_VIOLATION.
+        if seq >= self.cids.largest() {
+            return Err(Error::new(
+                ErrorKind::ProtocolViolation,
+                frame.frame_type(),
+                format!("connection id {seq} has not been issued"),
+            ));
+        }
+        // 重复的退役帧，或者早已退役，直接忽略
+        if let Some((cid, _)) = self.cids.get_mut(seq).and_then(Option::take) {
+            self.registry.remove(&cid);
+            let n = self.cids.iter().take_while(|cid| cid.is_none()).count();
+            self.cids.advance(n);
+            self.issue_until_limit();
+        }
+        Ok(())
+    }
+
+    fn seq_of(&self, cid: &ConnectionId) -> Option<u64> {
+        self.cids
+            .iter_with_idx()
+            .find_map(|(seq, c)| matches!(c, Some((id, _)) if id == cid).then_some(seq))
+    }
+
+    fn clear(&mut self) {
+        for (cid, _) in self.cids.iter_mut().filter_map(Option::take) {
+            self.registry.remove(&cid);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum RemoteCid {
+    // 序号中间的空洞，尚未收到
+    #[default]
+    Pending,
+    Active(ConnectionId, ResetToken),
+    Retired,
+}
+
+/// 对方签发给我方使用的连接ID。
+/// 对方要求退役某序号之前的连接ID时，我方要回应RetireConnectionIdFrame，并改用新的连接ID。
+#[derive(Debug)]
+pub struct RawRemoteCids {
+    // 按序号排列，offset即为对方要求的retire_prior_to
+    cids: IndexDeque<RemoteCid, VARINT_MAX>,
+    // 我方的active_connection_id_limit
+    active_cid_limit: u64,
+    // 当前正在使用的连接ID的序号
+    cur_seq: u64,
+    // 多路径下，其余各路径正在并行使用的连接ID的序号
+    path_seqs: HashSet<u64>,
+    reliable_frame_queue: ArcReliableFrameQueue,
+    // 正在使用的连接ID的无状态重置令牌要登记到路由表，以便识别对方发来的无状态重置
+    registry: RouterRegistry,
+}
+
+impl RawRemoteCids {
+    fn new(
+        dcid: ConnectionId,
+        active_cid_limit: u64,
+        reliable_frame_queue: ArcReliableFrameQueue,
+        registry: RouterRegistry,
+    ) -> Self {
+        let mut cids = IndexDeque::default();
+        // 握手期间使用的目标连接ID，其序号为0，其无状态重置令牌要等服务端的传输参数
+        let _ = cids.push(RemoteCid::Active(dcid, ResetToken::default()));
+        Self {
+            cids,
+            active_cid_limit,
+            cur_seq: 0,
+            path_seqs: HashSet::new(),
+            reliable_frame_queue,
+            registry,
+        }
+    }
+
+    fn set_initial_reset_token(&mut self, reset_token: ResetToken) {
+        if let Some(RemoteCid::Active(_, token)) = self.cids.get_mut(0) {
+            *token = reset_token;
+            if self.cur_seq == 0 {
+                self.registry.add_reset_token(reset_token);
+            }
+        }
+    }
+
+    fn retire(&mut self, seq: u64) {
+        self.reliable_frame_queue
+            .write()
+            .push_conn_frame(ConnFrame::RetireConnectionId(RetireConnectionIdFrame {
+                sequence: VarInt(seq),
+            }));
+    }
+
+    fn recv_new_cid_frame(&mut self, frame: &NewConnectionIdFrame) -> Result<(), Error> {
+        let seq = frame.sequence.into_inner();
+        let retire_prior_to = frame.retire_prior_to.into_inner();
+
+        // If an endpoint receives a NEW_CONNECTION_ID frame that repeats a previously
+        // issued connection ID with a different Stateless Reset Token field value or a
+        // different Sequence Number field value, or if a sequence number is used for
+        // different connection IDs, the endpoint MAY treat that receipt as a connection
+        // error of type PROTOCOL_VIOLATION.
+        for (idx, cid) in self.cids.iter_with_idx() {
+            if let RemoteCid::Active(id, reset_token) = cid {
+                let same_seq = idx == seq;
+                let same_cid = *id == frame.id;
+                if same_seq && same_cid && *reset_token == frame.reset_token {
+                    return Ok(());
+                } else if same_seq || same_cid {
+                    return Err(Error::new(
+                        ErrorKind::ProtocolViolation,
+                        frame.frame_type(),
+                        format!("connection id {seq} conflicts with the previous one"),
+                    ));
+                }
+            }
+        }
+
+        // 该序号已退役，可能是重传的帧，也可能因retire_prior_to跳过了，回应退役即可
+        if seq < self.cids.offset() {
+            self.retire(seq);
+            return Ok(());
+        }
+        if let Some(RemoteCid::Retired) = self.cids.get(seq) {
+            return Ok(());
+        }
+
+        // After processing a NEW_CONNECTION_ID frame and adding and retiring active
+        // connection IDs, if the number of active connection IDs exceeds the value
+        // advertised in its active_connection_id_limit transport parameter, an endpoint
+        // MUST close the connection with an error of type CONNECTION_ID_LIMIT_ERROR.
+        // 插入之前就要检查，否则对方给出极大的序号，中间的空洞会撑爆内存。
+        // 对方签发的序号是连续的，退役之后剩下的序号跨度也不能超出限制
+        let offset = self.cids.offset().max(retire_prior_to);
+        let active = self
+            .cids
+            .iter_with_idx()
+            .filter(|(idx, cid)| *idx >= offset && matches!(cid, RemoteCid::Active(..)))
+            .count() as u64
+            + 1;
+        if seq - offset >= self.active_cid_limit || active > self.active_cid_limit {
+            return Err(Error::new(
+                ErrorKind::ConnectionIdLimit,
+                frame.frame_type(),
+                format!(
+                    "connection id {seq} exceeds the active connection id limit {}",
+                    self.active_cid_limit
+                ),
+            ));
+        }
+
+        if retire_prior_to > self.cids.offset() {
+            let offset = self.cids.offset();
+            let retired = self
+                .cids
+                .drain_to(retire_prior_to.min(self.cids.largest()))
+                .zip(offset..)
+                .filter_map(|(cid, seq)| match cid {
+                    RemoteCid::Active(_, reset_token) => Some((seq, reset_token)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            for (seq, reset_token) in retired {
+                self.registry.remove_reset_token(&reset_token);
+                self.retire(seq);
+            }
+            // 尚未收到的序号也一并跳过，它们到来时直接回应退役
+            if self.cids.is_empty() {
+                self.cids.skip_to(retire_prior_to);
+            }
+        }
+        self.cids
+            .insert(seq, RemoteCid::Active(frame.id, frame.reset_token))?;
+
+        // 正在使用的连接ID被要求退役，改用下一个可用的
+        if self.cur_seq < self.cids.offset() {
+            self.switch();
+        }
+        Ok(())
+    }
+
+    fn current(&self) -> Option<ConnectionId> {
+        match self.cids.get(self.cur_seq) {
+            Some(RemoteCid::Active(id, _)) => Some(*id),
+            _ => None,
+        }
+    }
+
+    /// 下一个尚未使用过的连接ID，及其序号与无状态重置令牌
+    fn next_unused(&self) -> Option<(u64, ConnectionId, ResetToken)> {
+        self.cids.iter_with_idx().find_map(|(seq, cid)| match cid {
+            RemoteCid::Active(id, token)
+                if seq > self.cur_seq && !self.path_seqs.contains(&seq) =>
+            {
+                Some((seq, *id, *token))
+            }
+            _ => None,
+        })
+    }
+
+    /// 下一个尚未使用过的连接ID，并不切换过去
+    fn peek_next(&self) -> Option<ConnectionId> {
+        self.next_unused().map(|(_, id, _)| id)
+    }
+
+    /// 切换到下一个尚未使用过的连接ID，原来使用的连接ID若仍有效，则退役之
+    fn switch(&mut self) -> Option<ConnectionId> {
+        let (next_seq, next_cid, next_token) = self.next_unused()?;
+        let cur_seq = std::mem::replace(&mut self.cur_seq, next_seq);
+        if let Some(cid) = self.cids.get_mut(cur_seq) {
+            if let RemoteCid::Active(_, reset_token) = *cid {
+                *cid = RemoteCid::Retired;
+                self.registry.remove_reset_token(&reset_token);
+                self.retire(cur_seq);
+            }
+        }
+        self.registry.add_reset_token(next_token);
+        Some(next_cid)
+    }
+
+    /// 多路径下，为一条新路径分配一个尚未使用过的连接ID，与当前连接ID并行使用
+    fn assign_for_path(&mut self) -> Option<(u64, ConnectionId)> {
+        let (seq, cid, reset_token) = self.next_unused()?;
+        self.path_seqs.insert(seq);
+        self.registry.add_reset_token(reset_token);
+        Some((seq, cid))
+    }
+
+    /// 路径被放弃，其所用的连接ID也随之退役
+    fn release_path_cid(&mut self, seq: u64) {
+        if !self.path_seqs.remove(&seq) {
+            return;
+        }
+        if let Some(cid) = self.cids.get_mut(seq) {
+            if let RemoteCid::Active(_, reset_token) = *cid {
+                *cid = RemoteCid::Retired;
+                self.registry.remove_reset_token(&reset_token);
+                self.retire(seq);
+            }
+        }
+    }
+
+    fn seq_of(&self, cid: &ConnectionId) -> Option<u64> {
+        self.cids.iter_with_idx().find_map(|(seq, c)| match c {
+            RemoteCid::Active(id, _) if id == cid => Some(seq),
+            _ => None,
+        })
+    }
+
+    fn clear(&mut self) {
+        for cid in self.cids.iter() {
+            if let RemoteCid::Active(_, reset_token) = cid {
+                self.registry.remove_reset_token(reset_token);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArcLocalCids(Arc<Mutex<RawLocalCids>>);
+
+impl ArcLocalCids {
+    /// 得知对方的active_connection_id_limit后，签发足够多的连接ID
+    pub fn set_limit(&self, active_cid_limit: u64) {
+        self.0.lock().unwrap().set_limit(active_cid_limit);
+    }
+
+    pub fn recv_retire_cid_frame(&self, frame: &RetireConnectionIdFrame) -> Result<(), Error> {
+        self.0.lock().unwrap().recv_retire_cid_frame(frame)
+    }
+
+    /// 服务端生成首选地址，其中的连接ID与无状态重置令牌就地签发，放入我方的传输参数中
+    pub fn gen_preferred_address(
+        &self,
+        address_v4: Option<SocketAddrV4>,
+        address_v6: Option<SocketAddrV6>,
+    ) -> PreferredAddress {
+        let (id, reset_token) = self.0.lock().unwrap().issue_preferred_cid();
+        PreferredAddress::new(address_v4, address_v6, id, reset_token)
+    }
+
+    /// 我方连接ID的序号，多路径下据此找到数据包所在的包号空间
+    pub fn seq_of(&self, cid: &ConnectionId) -> Option<u64> {
+        self.0.lock().unwrap().seq_of(cid)
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArcRemoteCids(Arc<Mutex<RawRemoteCids>>);
+
+impl ArcRemoteCids {
+    pub fn recv_new_cid_frame(&self, frame: &NewConnectionIdFrame) -> Result<(), Error> {
+        self.0.lock().unwrap().recv_new_cid_frame(frame)
+    }
+
+    /// 当前发包所用的目标连接ID
+    pub fn current(&self) -> Option<ConnectionId> {
+        self.0.lock().unwrap().current()
+    }
+
+    /// 客户端收到服务端的首选地址，其中的连接ID视同序号为1的NEW_CONNECTION_ID帧
+    pub fn recv_preferred_address(&self, preferred: &PreferredAddress) -> Result<(), Error> {
+        self.recv_new_cid_frame(&NewConnectionIdFrame {
+            sequence: VarInt(1),
+            retire_prior_to: VarInt(0),
+            id: preferred.connection_id(),
+            reset_token: preferred.stateless_reset_token(),
+        })
+    }
+
+    /// 下一个可用的目标连接ID，主动迁移时在新路径上探测要用它，验证通过后才切换过去
+    pub fn peek_next(&self) -> Option<ConnectionId> {
+        self.0.lock().unwrap().peek_next()
+    }
+
+    /// 改用一个新的目标连接ID，没有可用的则返回None
+    pub fn switch(&self) -> Option<ConnectionId> {
+        self.0.lock().unwrap().switch()
+    }
+
+    /// 服务端在传输参数中告知的、握手期间所用连接ID的无状态重置令牌
+    pub fn set_initial_reset_token(&self, reset_token: ResetToken) {
+        self.0.lock().unwrap().set_initial_reset_token(reset_token);
+    }
+
+    /// 多路径下，为新路径分配一个尚未使用过的连接ID及其序号，没有可用的则返回None
+    pub fn assign_for_path(&self) -> Option<(u64, ConnectionId)> {
+        self.0.lock().unwrap().assign_for_path()
+    }
+
+    /// 放弃路径时，退役该路径所用的连接ID
+    pub fn release_path_cid(&self, seq: u64) {
+        self.0.lock().unwrap().release_path_cid(seq);
+    }
+
+    /// 对方连接ID的序号，多路径下以发包所用连接ID的序号标识包号空间
+    pub fn seq_of(&self, cid: &ConnectionId) -> Option<u64> {
+        self.0.lock().unwrap().seq_of(cid)
+    }
+}
+
+/// 连接ID管理器，local负责我方签发的连接ID，remote负责对方签发的连接ID
+#[derive(Debug, Clone)]
+pub struct CidManager {
+    pub local: ArcLocalCids,
+    pub remote: ArcRemoteCids,
+}
+
+impl CidManager {
+    /// cid_gen用于生成我方的连接ID，reset_key用于派生其无状态重置令牌，
+    /// active_cid_limit是我方的active_connection_id_limit传输参数
+    pub fn new(
+        scid: ConnectionId,
+        dcid: ConnectionId,
+        cid_gen: ArcCidGenerator,
+        reset_key: ResetTokenKey,
+        active_cid_limit: u64,
+        reliable_frame_queue: ArcReliableFrameQueue,
+        registry: RouterRegistry,
+    ) -> Self {
+        Self {
+            local: ArcLocalCids(Arc::new(Mutex::new(RawLocalCids::new(
+                scid,
+                cid_gen,
+                reset_key,
+                reliable_frame_queue.clone(),
+                registry.clone(),
+            )))),
+            remote: ArcRemoteCids(Arc::new(Mutex::new(RawRemoteCids::new(
+                dcid,
+                active_cid_limit,
+                reliable_frame_queue,
+                registry,
+            )))),
+        }
+    }
+
+    /// 连接终结时，将该连接的所有连接ID及无状态重置令牌从路由表中移除
+    pub fn clear(&self) {
+        self.local.clear();
+        self.remote.0.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{connection::ArcPacketEntry, endpoint::ArcRouter};
+    use qbase::{cid::RandomCidGenerator, frame::ReliableFrame};
+    use tokio::sync::mpsc;
+
+    fn entry() -> ArcPacketEntry {
+        ArcPacketEntry::new(
+            mpsc::unbounded_channel().0,
+            mpsc::unbounded_channel().0,
+            mpsc::unbounded_channel().0,
+            mpsc::unbounded_channel().0,
+            mpsc::unbounded_channel().0,
+            crate::closing::ArcCloser::default(),
+        )
+    }
+
+    fn new_cid_frame(seq: u64, retire_prior_to: u64) -> NewConnectionIdFrame {
+        NewConnectionIdFrame {
+            sequence: VarInt(seq),
+            retire_prior_to: VarInt(retire_prior_to),
+            id: ConnectionId::from_slice(&[seq as u8; 8]),
+            reset_token: ResetToken::new_with(&[seq as u8; 16]),
+        }
+    }
+
+    #[test]
+    fn test_issue_and_retire_local_cids() {
+        let router = ArcRouter::default();
+        let frames = ArcReliableFrameQueue::default();
+        let scid = ConnectionId::random_gen(8);
+        let manager = CidManager::new(
+            scid,
+            ConnectionId::random_gen(8),
+            Arc::new(RandomCidGenerator::new(8)),
+            ResetTokenKey::new(b"static key"),
+            2,
+            frames.clone(),
+            router.registry(entry()),
+        );
+        assert!(router.contains(&scid));
+
+        manager.local.set_limit(4);
+        let mut issued = vec![];
+        while let Some(frame) = frames.read().pop_front() {
+            if let ReliableFrame::Conn(ConnFrame::NewConnectionId(frame)) = frame {
+                assert!(router.contains(&frame.id));
+                issued.push(frame);
+            }
+        }
+        assert_eq!(issued.len(), 3);
+        assert_eq!(issued[2].sequence, VarInt(3));
+
+        manager
+            .local
+            .recv_retire_cid_frame(&RetireConnectionIdFrame {
+                sequence: VarInt(0),
+            })
+            .unwrap();
+        assert!(!router.contains(&scid));
+        match frames.read().pop_front() {
+            Some(ReliableFrame::Conn(ConnFrame::NewConnectionId(frame))) => {
+                assert_eq!(frame.sequence, VarInt(4));
+                assert_eq!(frame.retire_prior_to, VarInt(1));
+            }
+            _ => panic!("a new connection id should be issued"),
+        }
+
+        let err = manager
+            .local
+            .recv_retire_cid_frame(&RetireConnectionIdFrame {
+                sequence: VarInt(5),
+            })
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ProtocolViolation);
+
+        manager.local.clear();
+        assert!(issued.iter().all(|frame| !router.contains(&frame.id)));
+    }
+
+    #[test]
+    fn test_preferred_address_cid() {
+        let router = ArcRouter::default();
+        let frames = ArcReliableFrameQueue::default();
+        let manager = CidManager::new(
+            ConnectionId::random_gen(8),
+            ConnectionId::random_gen(8),
+            Arc::new(RandomCidGenerator::new(8)),
+            ResetTokenKey::new(b"static key"),
+            2,
+            frames.clone(),
+            router.registry(entry()),
+        );
+        let preferred = manager.local.gen_preferred_address(None, None);
+        assert!(router.contains(&preferred.connection_id()));
+        // 首选地址的连接ID占用序号1，不通过NEW_CONNECTION_ID帧签发
+        assert!(frames.read().front().is_none());
+
+        manager.local.set_limit(3);
+        match frames.read().pop_front() {
+            Some(ReliableFrame::Conn(ConnFrame::NewConnectionId(frame))) => {
+                assert_eq!(frame.sequence, VarInt(2));
+            }
+            _ => panic!("a new connection id should be issued"),
+        }
+        assert!(frames.read().front().is_none());
+
+        // 客户端收到首选地址，其连接ID就是下一个可用的连接ID
+        manager.remote.recv_preferred_address(&preferred).unwrap();
+        assert_eq!(manager.remote.peek_next(), Some(preferred.connection_id()));
+    }
+
+    #[test]
+    fn test_recv_new_cid_and_retire_prior_to() {
+        let router = ArcRouter::default();
+        let frames = ArcReliableFrameQueue::default();
+        let dcid = ConnectionId::random_gen(8);
+        let manager = CidManager::new(
+            ConnectionId::random_gen(8),
+            dcid,
+            Arc::new(RandomCidGenerator::new(8)),
+            ResetTokenKey::new(b"static key"),
+            2,
+            frames.clone(),
+            router.registry(entry()),
+        );
+        assert_eq!(manager.remote.current(), Some(dcid));
+
+        manager
+            .remote
+            .recv_new_cid_frame(&new_cid_frame(1, 0))
+            .unwrap();
+        // 重复的帧被忽略
+        manager
+            .remote
+            .recv_new_cid_frame(&new_cid_frame(1, 0))
+            .unwrap();
+        assert!(frames.read().front().is_none());
+
+        // 退役序号0和1，正在使用的连接ID切换到序号2，其无状态重置令牌登记到路由表
+        manager
+            .remote
+            .recv_new_cid_frame(&new_cid_frame(2, 2))
+            .unwrap();
+        let reset_token = ResetToken::new_with(&[2; 16]);
+        assert!(router.get_by_reset_token(&reset_token).is_some());
+        assert_eq!(
+            manager.remote.current(),
+            Some(ConnectionId::from_slice(&[2; 8]))
+        );
+        for seq in 0..2 {
+            assert_eq!(
+                frames.read().pop_front(),
+                Some(ReliableFrame::Conn(ConnFrame::RetireConnectionId(
+                    RetireConnectionIdFrame {
+                        sequence: VarInt(seq)
+                    }
+                )))
+            );
+        }
+
+        // 已退役的序号再次到来，直接回应退役
+        manager
+            .remote
+            .recv_new_cid_frame(&new_cid_frame(1, 0))
+            .unwrap();
+        assert!(matches!(
+            frames.read().pop_front(),
+            Some(ReliableFrame::Conn(ConnFrame::RetireConnectionId(_)))
+        ));
+
+        let mut conflict = new_cid_frame(3, 2);
+        conflict.id = ConnectionId::from_slice(&[2; 8]);
+        let err = manager.remote.recv_new_cid_frame(&conflict).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ProtocolViolation);
+
+        manager
+            .remote
+            .recv_new_cid_frame(&new_cid_frame(3, 2))
+            .unwrap();
+        let err = manager
+            .remote
+            .recv_new_cid_frame(&new_cid_frame(4, 2))
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ConnectionIdLimit);
+        // 序号跨度超出限制，插入之前就拒绝，不会为中间的空洞分配内存
+        let err = manager
+            .remote
+            .recv_new_cid_frame(&new_cid_frame(1 << 40, 2))
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ConnectionIdLimit);
+        assert_eq!(manager.remote.0.lock().unwrap().cids.len(), 2);
+
+        // 一次退役之前所有的连接ID，序号跳跃也无妨
+        manager
+            .remote
+            .recv_new_cid_frame(&new_cid_frame(1 << 40, 1 << 40))
+            .unwrap();
+        assert_eq!(manager.remote.0.lock().unwrap().cids.len(), 1);
+        assert_eq!(
+            manager.remote.current(),
+            Some(ConnectionId::from_slice(&[0; 8]))
+        );
+
+        manager.clear();
+        assert!(router.get_by_reset_token(&reset_token).is_none());
+    }
+}
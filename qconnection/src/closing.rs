@@ -0,0 +1,270 @@
+use qbase::{
+    error::{Error, ErrorKind},
+    frame::{ConnectionCloseFrame, FrameType},
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+/// 关闭或排空状态持续的时长，为3倍PTO，Ref. RFC 9000 §10.2
+pub fn closing_period(pto: Duration) -> Duration {
+    pto * 3
+}
+
+/// 连接的关闭状态，Ref. RFC 9000 §10.2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CloseState {
+    /// 连接正常
+    Open,
+    /// 我方关闭了连接，发出了CONNECTION_CLOSE；对方再发来数据包，只以CONNECTION_CLOSE回应
+    Closing(ConnectionCloseFrame),
+    /// 收到了对方的CONNECTION_CLOSE或者无状态重置，不再发送任何数据包
+    Draining(ConnectionCloseFrame),
+    /// 关闭或排空期满，连接的所有资源都已释放
+    Closed,
+}
+
+#[derive(Debug)]
+struct RawCloser {
+    state: CloseState,
+    // 有待发出的CONNECTION_CLOSE
+    need_send: bool,
+    // 进入closing状态后收到的数据包数，每当它是2的幂次才回应一次，以限制回应的频率
+    rcvd_pkts: u64,
+    // 等待连接开始关闭的任务
+    close_waker: Option<Waker>,
+    // 等待连接彻底关闭的任务
+    closed_waker: Option<Waker>,
+}
+
+impl RawCloser {
+    fn enter(&mut self, state: CloseState) {
+        self.state = state;
+        if let Some(waker) = self.close_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// 连接的关闭状态机。
+/// 连接错误、应用层主动关闭都使连接进入closing状态，收到对方的CONNECTION_CLOSE则进入draining状态，
+/// 两者都持续3倍PTO，期满后连接的资源才能释放。
+#[derive(Debug, Clone)]
+pub struct ArcCloser(Arc<Mutex<RawCloser>>);
+
+impl Default for ArcCloser {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(RawCloser {
+            state: CloseState::Open,
+            need_send: false,
+            rcvd_pkts: 0,
+            close_waker: None,
+            closed_waker: None,
+        })))
+    }
+}
+
+impl ArcCloser {
+    pub fn state(&self) -> CloseState {
+        self.0.lock().unwrap().state.clone()
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.0.lock().unwrap().state == CloseState::Open
+    }
+
+    /// 我方关闭连接，进入closing状态，并发出CONNECTION_CLOSE。
+    /// 已经在关闭中了，则忽略，返回false
+    pub fn close(&self, frame: ConnectionCloseFrame) -> bool {
+        let mut guard = self.0.lock().unwrap();
+        if guard.state != CloseState::Open {
+            return false;
+        }
+        guard.need_send = true;
+        guard.enter(CloseState::Closing(frame));
+        true
+    }
+
+    /// 收到了对方的CONNECTION_CLOSE，进入draining状态，不再发送任何数据包。
+    /// closing状态下收到，也可以直接转入draining状态，Ref. RFC 9000 §10.2.2
+    pub fn drain(&self, frame: ConnectionCloseFrame) -> bool {
+        let mut guard = self.0.lock().unwrap();
+        match guard.state {
+            CloseState::Open => {
+                guard.enter(CloseState::Draining(frame));
+                true
+            }
+            CloseState::Closing(_) => {
+                guard.need_send = false;
+                guard.state = CloseState::Draining(frame);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// closing状态下收到了对方的数据包，要以CONNECTION_CLOSE回应，但回应的频率须加以限制，
+    /// 这里每当收到的包数是2的幂次才回应一次，Ref. RFC 9000 §10.2.1
+    pub fn on_rcvd_pkt(&self) {
+        let mut guard = self.0.lock().unwrap();
+        if matches!(guard.state, CloseState::Closing(_)) {
+            guard.rcvd_pkts += 1;
+            if guard.rcvd_pkts.is_power_of_two() {
+                guard.need_send = true;
+            }
+        }
+    }
+
+    /// 读取待发送的CONNECTION_CLOSE，读出即认为已发送
+    pub fn read_frame(&self) -> Option<ConnectionCloseFrame> {
+        let mut guard = self.0.lock().unwrap();
+        match &guard.state {
+            CloseState::Closing(frame) if guard.need_send => {
+                let frame = frame.clone();
+                guard.need_send = false;
+                Some(frame)
+            }
+            _ => None,
+        }
+    }
+
+    /// 关闭或排空期满，连接彻底关闭
+    pub fn terminate(&self) {
+        let mut guard = self.0.lock().unwrap();
+        guard.state = CloseState::Closed;
+        guard.need_send = false;
+        if let Some(waker) = guard.closed_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// 等待连接开始关闭，得到关闭的原因
+    pub fn closing(&self) -> Closing {
+        Closing(self.clone())
+    }
+
+    /// 等待连接彻底关闭
+    pub fn closed(&self) -> Closed {
+        Closed(self.clone())
+    }
+}
+
+pub struct Closing(ArcCloser);
+
+impl Future for Closing {
+    type Output = Error;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut guard = self.0 .0.lock().unwrap();
+        match &guard.state {
+            CloseState::Open => {
+                guard.close_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            CloseState::Closing(frame) | CloseState::Draining(frame) => {
+                Poll::Ready(frame.clone().into())
+            }
+            CloseState::Closed => Poll::Ready(Error::new_with_default_fty(
+                ErrorKind::None,
+                "connection closed",
+            )),
+        }
+    }
+}
+
+pub struct Closed(ArcCloser);
+
+impl Future for Closed {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut guard = self.0 .0.lock().unwrap();
+        if guard.state == CloseState::Closed {
+            Poll::Ready(())
+        } else {
+            guard.closed_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// 应用层的CONNECTION_CLOSE不能出现在Initial、Handshake包中，只能改用传输层的APPLICATION_ERROR，
+/// 且不带原因，以免泄露应用层的信息，Ref. RFC 9000 §10.2.3
+pub fn conceal_app_close(frame: &ConnectionCloseFrame) -> ConnectionCloseFrame {
+    if frame.frame_type.is_some() {
+        return frame.clone();
+    }
+    ConnectionCloseFrame::new(ErrorKind::Application, Some(FrameType::Padding), "".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qbase::varint::VarInt;
+
+    #[test]
+    fn test_closing() {
+        let closer = ArcCloser::default();
+        let frame = ConnectionCloseFrame::new_app(VarInt(0), "bye".into());
+        assert!(closer.close(frame.clone()));
+        assert!(!closer.close(frame.clone()));
+        assert_eq!(closer.read_frame(), Some(frame.clone()));
+        assert_eq!(closer.read_frame(), None);
+
+        // 第1、2、4、8个收到的包才回应
+        let responses = (1..=8)
+            .filter(|_| {
+                closer.on_rcvd_pkt();
+                closer.read_frame().is_some()
+            })
+            .count();
+        assert_eq!(responses, 4);
+
+        // 对方也发来了CONNECTION_CLOSE，转入draining状态，不再回应
+        let peer = ConnectionCloseFrame::new(ErrorKind::Internal, Some(FrameType::Ping), "".into());
+        assert!(closer.drain(peer.clone()));
+        assert_eq!(closer.state(), CloseState::Draining(peer));
+        closer.on_rcvd_pkt();
+        assert_eq!(closer.read_frame(), None);
+
+        closer.terminate();
+        assert_eq!(closer.state(), CloseState::Closed);
+        assert!(!closer.drain(frame));
+    }
+
+    #[tokio::test]
+    async fn test_close_futures() {
+        let closer = ArcCloser::default();
+        let closing = tokio::spawn(closer.closing());
+        let closed = tokio::spawn(closer.closed());
+        tokio::task::yield_now().await;
+
+        let frame =
+            ConnectionCloseFrame::new(ErrorKind::FlowControl, Some(FrameType::Ping), "".into());
+        assert!(closer.drain(frame));
+        assert_eq!(closing.await.unwrap().kind, ErrorKind::FlowControl);
+        assert!(!closed.is_finished());
+        closer.terminate();
+        closed.await.unwrap();
+    }
+
+    #[test]
+    fn test_conceal_app_close() {
+        let app = ConnectionCloseFrame::new_app(VarInt(0x1234), "secret".into());
+        let concealed = conceal_app_close(&app);
+        assert_eq!(concealed.error_kind, ErrorKind::Application);
+        assert!(concealed.frame_type.is_some());
+        assert!(concealed.reason.is_empty());
+
+        let transport = ConnectionCloseFrame::new(
+            ErrorKind::ProtocolViolation,
+            Some(FrameType::Ping),
+            "".into(),
+        );
+        assert_eq!(conceal_app_close(&transport), transport);
+    }
+}
@@ -1,75 +1,232 @@
-use crate::{auto, crypto::TlsIO, handshake, path::ArcPath};
+use crate::{
+    auto,
+    cid::CidManager,
+    closing::{closing_period, conceal_app_close, ArcCloser, CloseState, Closed},
+    crypto::TlsIO,
+    endpoint::{ArcRouter, Incoming},
+    handshake::{self, ArcHandshakeConfirmed},
+    idle::ArcIdleTimer,
+    path::{ArcPath, ArcPaths, PathId, SpaceObserver, SpaceTxs},
+    timer::{ArcTimerWheel, TimerKind, TokioTime},
+    transmit::{read_1rtt_close_and_encrypt, read_close_and_encrypt, FillPolicy},
+};
+use futures::StreamExt;
 use qbase::{
+    cid::{ArcCidGenerator, ConnectionId, ResetTokenKey},
+    error::{Error, ErrorKind},
+    frame::{AckFrame, BeFrame, ConnFrame, ConnectionCloseFrame, NewTokenFrame},
     packet::{
-        keys::{ArcKeys, ArcOneRttKeys},
-        HandshakePacket, InitialPacket, OneRttPacket, SpinBit, ZeroRttPacket,
+        keys::{ArcKeys, ArcOneRttKeys, OneRttKeyEvent},
+        HandshakePacket, InitialPacket, LongHeaderBuilder, OneRttHeader, OneRttPacket, SpacePacket,
+        SpinBit, ZeroRttPacket,
     },
     streamid::Role,
+    token::{ArcTokenKeys, ServerTokens},
     util::ArcAsyncQueue,
+    varint::VarInt,
 };
+use qcongestion::{congestion::CongestionAlgorithm, reordering::ReorderingConfig, rtt::Rtt};
 use qrecovery::{
     crypto::CryptoStream,
     space::ArcSpace,
-    streams::{none::NoDataStreams, ArcDataStreams},
+    streams::{none::NoDataStreams, ArcDataStreams, ReceiveStream},
+};
+use std::{
+    borrow::Cow,
+    io,
+    net::{SocketAddr, SocketAddrV4, SocketAddrV6},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
 };
-use tokio::sync::mpsc;
+use tokio::{net::UdpSocket, sync::mpsc};
 
 /// Option是为了能丢弃前期空间，包括这些空间的收包队列，
 /// 一旦丢弃，后续再收到该空间的包，直接丢弃。
-type RxPacketsQueue<T> = Option<mpsc::UnboundedSender<(T, ArcPath)>>;
+type RxPacketsQueue<T> = Option<mpsc::UnboundedSender<(T, PathId)>>;
+
+#[derive(Debug)]
+struct PacketEntry {
+    initial_pkt_queue: RxPacketsQueue<InitialPacket>,
+    handshake_pkt_queue: RxPacketsQueue<HandshakePacket>,
+    zero_rtt_pkt_queue: RxPacketsQueue<ZeroRttPacket>,
+    one_rtt_pkt_queue: mpsc::UnboundedSender<(OneRttPacket, PathId)>,
+    stateless_reset: mpsc::UnboundedSender<()>,
+    closer: ArcCloser,
+}
+
+impl PacketEntry {
+    /// 连接关闭期间，收到的数据包都不再处理；closing状态下，只据此回应CONNECTION_CLOSE
+    fn is_closing(&self) -> bool {
+        if self.closer.is_open() {
+            return false;
+        }
+        self.closer.on_rcvd_pkt();
+        true
+    }
+}
+
+/// 连接的收包入口。一个连接可以有多个连接ID，端点路由表中的这些连接ID都指向同一个收包入口，
+/// 端点收到数据包后，经此投递到相应空间的收包队列中。
+#[derive(Debug, Clone)]
+pub struct ArcPacketEntry(Arc<Mutex<PacketEntry>>);
+
+impl ArcPacketEntry {
+    pub(crate) fn new(
+        initial_pkt_queue: mpsc::UnboundedSender<(InitialPacket, PathId)>,
+        handshake_pkt_queue: mpsc::UnboundedSender<(HandshakePacket, PathId)>,
+        zero_rtt_pkt_queue: mpsc::UnboundedSender<(ZeroRttPacket, PathId)>,
+        one_rtt_pkt_queue: mpsc::UnboundedSender<(OneRttPacket, PathId)>,
+        stateless_reset: mpsc::UnboundedSender<()>,
+        closer: ArcCloser,
+    ) -> Self {
+        Self(Arc::new(Mutex::new(PacketEntry {
+            initial_pkt_queue: Some(initial_pkt_queue),
+            handshake_pkt_queue: Some(handshake_pkt_queue),
+            zero_rtt_pkt_queue: Some(zero_rtt_pkt_queue),
+            one_rtt_pkt_queue,
+            stateless_reset,
+            closer,
+        })))
+    }
+
+    /// 端点识别出对方发来的无状态重置，连接直接进入draining状态
+    pub fn recv_stateless_reset(&self) {
+        let _ = self.0.lock().unwrap().stateless_reset.send(());
+    }
+
+    /// 数据包来自path_id所标识的路径，解密成功后才会认定该路径
+    pub fn recv_packet(&self, pkt: SpacePacket, path_id: PathId) {
+        match pkt {
+            SpacePacket::Initial(pkt) => self.recv_initial_packet(pkt, path_id),
+            SpacePacket::Handshake(pkt) => self.recv_handshake_packet(pkt, path_id),
+            SpacePacket::ZeroRtt(pkt) => self.recv_0rtt_packet(pkt, path_id),
+            SpacePacket::OneRtt(pkt) => self.recv_1rtt_packet(pkt, path_id),
+        }
+    }
+
+    pub fn recv_initial_packet(&self, pkt: InitialPacket, path_id: PathId) {
+        let entry = self.0.lock().unwrap();
+        if entry.is_closing() {
+            return;
+        }
+        if let Some(q) = entry.initial_pkt_queue.as_ref() {
+            let _ = q.send((pkt, path_id));
+        }
+    }
+
+    pub fn recv_handshake_packet(&self, pkt: HandshakePacket, path_id: PathId) {
+        let entry = self.0.lock().unwrap();
+        if entry.is_closing() {
+            return;
+        }
+        if let Some(q) = entry.handshake_pkt_queue.as_ref() {
+            let _ = q.send((pkt, path_id));
+        }
+    }
+
+    pub fn recv_0rtt_packet(&self, pkt: ZeroRttPacket, path_id: PathId) {
+        let entry = self.0.lock().unwrap();
+        if entry.is_closing() {
+            return;
+        }
+        if let Some(q) = entry.zero_rtt_pkt_queue.as_ref() {
+            let _ = q.send((pkt, path_id));
+        }
+    }
+
+    pub fn recv_1rtt_packet(&self, pkt: OneRttPacket, path_id: PathId) {
+        let entry = self.0.lock().unwrap();
+        if entry.is_closing() {
+            return;
+        }
+        // 连接终结后，收包任务退出，此时丢弃收到的包即可
+        let _ = entry.one_rtt_pkt_queue.send((pkt, path_id));
+    }
+}
 
 pub struct RawConnection {
     // Thus, a client MUST discard Initial keys when it first sends a Handshake packet
     // and a server MUST discard Initial keys when it first successfully processes a
     // Handshake packet. Endpoints MUST NOT send Initial packets after this point.
     initial_keys: ArcKeys,
-    initial_pkt_queue: RxPacketsQueue<InitialPacket>,
     // 发送数据，也可以随着升级到Handshake空间而丢弃
     initial_space: ArcSpace<NoDataStreams>,
 
     // An endpoint MUST discard its Handshake keys when the TLS handshake is confirmed.
     handshake_keys: ArcKeys,
-    handshake_pkt_queue: RxPacketsQueue<HandshakePacket>,
     // 发送数据，也可以随着升级到1RTT空间而丢弃
     handshake_space: ArcSpace<NoDataStreams>,
 
     zero_rtt_keys: ArcKeys,
-    // 发送数据，也可以随着升级到1RTT空间而丢弃
-    zero_rtt_pkt_queue: RxPacketsQueue<ZeroRttPacket>,
-    one_rtt_pkt_queue: mpsc::UnboundedSender<(OneRttPacket, ArcPath)>,
+    one_rtt_keys: ArcOneRttKeys,
+    // 收包入口，端点的路由表中，该连接的各个连接ID都指向它
+    packet_entry: ArcPacketEntry,
     data_space: ArcSpace<ArcDataStreams>,
+    cid_manager: CidManager,
+    paths: ArcPaths,
     spin: SpinBit,
+    // 客户端从缓存中取出的、该服务端此前签发的令牌，放在Initial包中，没有则为空
+    initial_token: Vec<u8>,
+    closer: ArcCloser,
+    idle: ArcIdleTimer,
+    timers: ArcTimerWheel,
+    handshake_confirmed: ArcHandshakeConfirmed,
+    tls_session: TlsIO,
+}
+
+/// 连接的本地配置，不像传输参数那样要通告给对方，每个连接可以不同
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ConnectionConfig {
+    /// 各路径的拥塞控制器所用的算法
+    pub congestion_algorithm: CongestionAlgorithm,
+    /// 各路径丢包判定阈值的自适应配置
+    pub reordering: ReorderingConfig,
 }
 
-pub fn new(tls_session: TlsIO) -> RawConnection {
+/// tokens是客户端缓存的该服务端签发的令牌；token_keys是服务端签发令牌的密钥，
+/// 服务端在握手确认后为客户端签发令牌，客户端则为None；config是该连接的本地配置
+#[allow(clippy::too_many_arguments)]
+pub fn new(
+    tls_session: TlsIO,
+    scid: ConnectionId,
+    dcid: ConnectionId,
+    cid_gen: ArcCidGenerator,
+    reset_key: ResetTokenKey,
+    router: ArcRouter,
+    tokens: ServerTokens,
+    token_keys: Option<ArcTokenKeys>,
+    config: ConnectionConfig,
+) -> RawConnection {
     let rcvd_conn_frames = ArcAsyncQueue::new();
+    let closer = ArcCloser::default();
+    // 连接的所有定时器都登记在这一个定时器轮上，由一个任务驱动
+    // 定时器轮、拥塞控制、生成AckFrame都从同一个时钟取时间
+    let clock = Arc::new(TokioTime);
+    let timers = ArcTimerWheel::new(clock.clone());
+    tokio::spawn(timers.driver());
+    let idle = ArcIdleTimer::new(
+        tls_session.local_transport_parameters().max_idle_timeout(),
+        timers.clone(),
+    );
+    let handshake_confirmed = ArcHandshakeConfirmed::default();
 
-    let (initial_pkt_tx, initial_pkt_rx) = mpsc::unbounded_channel::<(InitialPacket, ArcPath)>();
+    let (initial_pkt_tx, initial_pkt_rx) = mpsc::unbounded_channel::<(InitialPacket, PathId)>();
     let (initial_ack_tx, initial_ack_rx) = mpsc::unbounded_channel();
     let (initial_loss_tx, initial_loss_rx) = mpsc::unbounded_channel();
+    let (initial_acked_tx, initial_acked_rx) = mpsc::unbounded_channel();
     let initial_crypto_stream = CryptoStream::new(1000_000, 1000_000);
     let initial_crypto_handler = initial_crypto_stream.split();
     let initial_keys = ArcKeys::new_pending();
     let initial_space_frame_queue = ArcAsyncQueue::new();
-    let initial_space = ArcSpace::<NoDataStreams>::with_crypto_stream(initial_crypto_stream);
-    tokio::spawn(
-        auto::loop_read_long_packet_and_then_dispatch_to_space_frame_queue(
-            initial_pkt_rx,
-            initial_keys.clone(),
-            initial_space.clone(),
-            rcvd_conn_frames.clone(),
-            initial_space_frame_queue,
-            initial_ack_tx,
-            true,
-        ),
-    );
+    let initial_space =
+        ArcSpace::<NoDataStreams>::with_crypto_stream(initial_crypto_stream, clock.clone());
     tokio::spawn({
         let space = initial_space.clone();
         let mut ack_rx = initial_ack_rx;
         async move {
             // 通过rx接收并处理AckFrame，AckFrame是Path收包解包得到
-            while let Some(ack) = ack_rx.recv().await {
-                space.on_ack(ack);
+            while let Some((path_id, ack)) = ack_rx.recv().await {
+                space.on_path_ack(path_id, ack);
             }
         }
     });
@@ -78,39 +235,40 @@ pub fn new(tls_session: TlsIO) -> RawConnection {
         let mut loss_pkt_rx = initial_loss_rx;
         async move {
             // 不停地接收丢包序号，这些丢包序号由path记录反馈，更新Transmiter的状态
-            while let Some(pn) = loss_pkt_rx.recv().await {
-                space.may_loss_pkt(pn);
+            while let Some((pn_space, pn)) = loss_pkt_rx.recv().await {
+                space.may_loss_pkt_on_path(pn_space, pn);
+            }
+        }
+    });
+    tokio::spawn({
+        let space = initial_space.clone();
+        let mut acked_rx = initial_acked_rx;
+        async move {
+            // 我方发出的AckFrame被对方确认了，其所确认的收包记录不必再反馈，收包窗口向前滑动
+            while let Some((pn_space, largest)) = acked_rx.recv().await {
+                space.inactivate_rcvd_records_on_path(pn_space, largest);
             }
         }
     });
 
     let (handshake_pkt_tx, handshake_pkt_rx) =
-        mpsc::unbounded_channel::<(HandshakePacket, ArcPath)>();
+        mpsc::unbounded_channel::<(HandshakePacket, PathId)>();
     let (handshake_ack_tx, handshake_ack_rx) = mpsc::unbounded_channel();
     let (handshake_loss_tx, handshake_loss_rx) = mpsc::unbounded_channel();
+    let (handshake_acked_tx, handshake_acked_rx) = mpsc::unbounded_channel();
     let handshake_crypto_stream = CryptoStream::new(1000_000, 1000_000);
     let handshake_crypto_handler = handshake_crypto_stream.split();
     let handshake_keys = ArcKeys::new_pending();
     let handshake_space_frame_queue = ArcAsyncQueue::new();
-    let handshake_space = ArcSpace::<NoDataStreams>::with_crypto_stream(handshake_crypto_stream);
-    tokio::spawn(
-        auto::loop_read_long_packet_and_then_dispatch_to_space_frame_queue(
-            handshake_pkt_rx,
-            handshake_keys.clone(),
-            handshake_space.clone(),
-            rcvd_conn_frames.clone(),
-            handshake_space_frame_queue,
-            handshake_ack_tx,
-            true,
-        ),
-    );
+    let handshake_space =
+        ArcSpace::<NoDataStreams>::with_crypto_stream(handshake_crypto_stream, clock.clone());
     tokio::spawn({
         let space = handshake_space.clone();
         let mut ack_rx = handshake_ack_rx;
         async move {
             // 通过rx接收并处理AckFrame，AckFrame是Path收包解包得到
-            while let Some(ack) = ack_rx.recv().await {
-                space.on_ack(ack);
+            while let Some((path_id, ack)) = ack_rx.recv().await {
+                space.on_path_ack(path_id, ack);
             }
         }
     });
@@ -119,37 +277,258 @@ pub fn new(tls_session: TlsIO) -> RawConnection {
         let mut loss_pkt_rx = handshake_loss_rx;
         async move {
             // 不停地接收丢包序号，这些丢包序号由path记录反馈，更新Transmiter的状态
-            while let Some(pn) = loss_pkt_rx.recv().await {
-                space.may_loss_pkt(pn);
+            while let Some((pn_space, pn)) = loss_pkt_rx.recv().await {
+                space.may_loss_pkt_on_path(pn_space, pn);
             }
         }
     });
-    tokio::spawn(
-        handshake::exchange_initial_crypto_msg_until_getting_handshake_key(
-            tls_session.clone(),
-            handshake_keys.clone(),
-            initial_crypto_handler,
-        ),
-    );
-
-    let (zero_rtt_pkt_tx, zero_rtt_pkt_rx) = mpsc::unbounded_channel::<(ZeroRttPacket, ArcPath)>();
-    let (one_rtt_pkt_tx, one_rtt_pkt_rx) = mpsc::unbounded_channel::<(OneRttPacket, ArcPath)>();
+    tokio::spawn({
+        let space = handshake_space.clone();
+        let mut acked_rx = handshake_acked_rx;
+        async move {
+            // 我方发出的AckFrame被对方确认了，其所确认的收包记录不必再反馈，收包窗口向前滑动
+            while let Some((pn_space, largest)) = acked_rx.recv().await {
+                space.inactivate_rcvd_records_on_path(pn_space, largest);
+            }
+        }
+    });
+    let (zero_rtt_pkt_tx, zero_rtt_pkt_rx) = mpsc::unbounded_channel::<(ZeroRttPacket, PathId)>();
+    let (one_rtt_pkt_tx, one_rtt_pkt_rx) = mpsc::unbounded_channel::<(OneRttPacket, PathId)>();
     let zero_rtt_keys = ArcKeys::new_pending();
+    // 客户端恢复会话时可用0-RTT发送数据，服务端接受0-RTT时用它解密
+    tls_session.watch_0rtt_keys(zero_rtt_keys.clone());
     let one_rtt_keys = ArcOneRttKeys::new_pending();
     let one_rtt_crypto_stream = CryptoStream::new(1000_000, 1000_000);
     let _one_rtt_crypto_handler = one_rtt_crypto_stream.split();
     let data_space_frame_queue = ArcAsyncQueue::new();
-    let (data_ack_tx, data_ack_rx) = mpsc::unbounded_channel();
+    let (data_ack_tx, data_ack_rx) = mpsc::unbounded_channel::<(u64, AckFrame)>();
     let (data_loss_tx, data_loss_rx) = mpsc::unbounded_channel();
-    let data_space = ArcSpace::<ArcDataStreams>::new(Role::Client, 20, 20, one_rtt_crypto_stream);
+    let (data_acked_tx, data_acked_rx) = mpsc::unbounded_channel();
+    let data_space =
+        ArcSpace::<ArcDataStreams>::new(Role::Client, 20, 20, one_rtt_crypto_stream, clock.clone());
     let streams = data_space.data_streams();
+    let (stateless_reset_tx, stateless_reset_rx) = mpsc::unbounded_channel();
+    let packet_entry = ArcPacketEntry::new(
+        initial_pkt_tx,
+        handshake_pkt_tx,
+        zero_rtt_pkt_tx,
+        one_rtt_pkt_tx,
+        stateless_reset_tx,
+        closer.clone(),
+    );
+    let cid_manager = CidManager::new(
+        scid,
+        dcid,
+        cid_gen,
+        reset_key,
+        // 对方签发的连接ID不得超过我方通告的active_connection_id_limit
+        tls_session
+            .local_transport_parameters()
+            .active_connection_id_limit()
+            .into_inner(),
+        data_space.reliable_frame_queue(),
+        router.registry(packet_entry.clone()),
+    );
+    let (conn_error_tx, conn_error_rx) = mpsc::unbounded_channel::<Error>();
+    // 收到的数据包解密成功后，才依其来源认定所属的路径
+    let paths = ArcPaths::new(
+        cid_manager.clone(),
+        data_space.reliable_frame_queue(),
+        timers.clone(),
+    );
+    // 路径的拥塞控制器在建立路径时创建，算法、丢包判定阈值要在第一个路径建立之前选定
+    paths.set_congestion_algorithm(config.congestion_algorithm);
+    paths.set_reordering(config.reordering);
+    let local_params = tls_session.local_transport_parameters();
+    // 我方是否启用多路径，由本地传输参数决定，对方也启用了才能同时使用多条路径
+    paths.set_enable_multipath(local_params.enable_multipath());
+    // 我方在传输参数中禁止了对方主动迁移，对方换了地址发来的包，除NAT重绑定外都要丢弃
+    paths.set_disable_active_migration(local_params.disable_active_migration());
+    paths.set_space_observer(SpaceObserver::new(SpaceTxs {
+        loss: [initial_loss_tx, handshake_loss_tx, data_loss_tx],
+        acked: [initial_acked_tx, handshake_acked_tx, data_acked_tx],
+    }));
+    tokio::spawn({
+        let exchange = handshake::exchange_initial_crypto_msg_until_getting_handshake_key(
+            tls_session.clone(),
+            handshake_keys.clone(),
+            initial_crypto_handler,
+        );
+        let paths = paths.clone();
+        async move {
+            exchange.await;
+            // 有了握手密钥，PTO的探测包就不必再用Initial包发送了
+            paths.on_handshake_keys();
+        }
+    });
+    tokio::spawn(
+        auto::loop_read_long_packet_and_then_dispatch_to_space_frame_queue(
+            initial_pkt_rx,
+            initial_keys.clone(),
+            initial_space.clone(),
+            paths.clone(),
+            rcvd_conn_frames.clone(),
+            initial_space_frame_queue,
+            initial_ack_tx,
+            conn_error_tx.clone(),
+            idle.clone(),
+            true,
+        ),
+    );
+    tokio::spawn(
+        auto::loop_read_long_packet_and_then_dispatch_to_space_frame_queue(
+            handshake_pkt_rx,
+            handshake_keys.clone(),
+            handshake_space.clone(),
+            paths.clone(),
+            rcvd_conn_frames.clone(),
+            handshake_space_frame_queue,
+            handshake_ack_tx,
+            conn_error_tx.clone(),
+            idle.clone(),
+            true,
+        ),
+    );
+    tokio::spawn({
+        let cid_manager = cid_manager.clone();
+        let paths = paths.clone();
+        let mut rcvd_conn_frames = rcvd_conn_frames.clone();
+        let conn_error_tx = conn_error_tx.clone();
+        let tokens = tokens.clone();
+        let closer = closer.clone();
+        let handshake_confirmed = handshake_confirmed.clone();
+        let is_server = tls_session.is_server();
+        async move {
+            // 连接级的帧，目前只处理连接ID与多路径相关的帧
+            while let Some(frame) = rcvd_conn_frames.next().await {
+                let result = match frame {
+                    ConnFrame::NewConnectionId(frame) => {
+                        cid_manager.remote.recv_new_cid_frame(&frame)
+                    }
+                    ConnFrame::RetireConnectionId(frame) => {
+                        cid_manager.local.recv_retire_cid_frame(&frame)
+                    }
+                    ConnFrame::PathAbandon(frame) => paths.recv_path_abandon(&frame),
+                    ConnFrame::PathStatus(frame) => paths.recv_path_status(&frame),
+                    // 服务端签发的令牌，缓存起来，下次连接该服务端时使用
+                    ConnFrame::NewToken(frame) => {
+                        tokens.push(frame.token);
+                        Ok(())
+                    }
+                    // 客户端收到HANDSHAKE_DONE，握手得以确认；服务端收到它则是对方违反了协议
+                    ConnFrame::HandshakeDone(frame) if is_server => Err(Error::new(
+                        ErrorKind::ProtocolViolation,
+                        frame.frame_type(),
+                        "server received HANDSHAKE_DONE",
+                    )),
+                    ConnFrame::HandshakeDone(_) => {
+                        handshake_confirmed.confirm();
+                        Ok(())
+                    }
+                    // 对方关闭了连接，进入draining状态
+                    ConnFrame::Close(frame) => {
+                        closer.drain(frame);
+                        Ok(())
+                    }
+                    _ => Ok(()),
+                };
+                if let Err(err) = result {
+                    let _ = conn_error_tx.send(err);
+                    break;
+                }
+            }
+        }
+    });
+    tokio::spawn({
+        let mut conn_error_rx = conn_error_rx;
+        let mut stateless_reset_rx = stateless_reset_rx;
+        let initial_keys = initial_keys.clone();
+        let handshake_keys = handshake_keys.clone();
+        let zero_rtt_keys = zero_rtt_keys.clone();
+        let one_rtt_keys = one_rtt_keys.clone();
+        let cid_manager = cid_manager.clone();
+        let paths = paths.clone();
+        let closer = closer.clone();
+        let idle = idle.clone();
+        let timers = timers.clone();
+        async move {
+            let pto = || {
+                paths
+                    .active()
+                    .map(|path| path.rtt().lock().unwrap().pto_base_duration(0))
+                    .unwrap_or_else(|| Rtt::default().pto_base_duration(0))
+            };
+            // 发生连接错误，如超出了AEAD的完整性限制，我方关闭连接，进入closing状态；
+            // 收到对方的无状态重置，则直接进入draining状态。应用层主动关闭、收到对方的CONNECTION_CLOSE另有他处处理。
+            // 空闲超时则静默关闭，不发CONNECTION_CLOSE，也无需等待，Ref. RFC 9000 §10.1
+            let mut silent = false;
+            tokio::select! {
+                Some(err) = conn_error_rx.recv() => {
+                    closer.close(err.into());
+                }
+                Some(()) = stateless_reset_rx.recv() => {
+                    closer.drain(ConnectionCloseFrame::from(Error::new_with_default_fty(
+                        ErrorKind::None,
+                        "connection was reset statelessly by peer",
+                    )));
+                }
+                _ = idle.expired(pto) => {
+                    silent = closer.drain(ConnectionCloseFrame::from(Error::new_with_default_fty(
+                        ErrorKind::None,
+                        "idle timeout",
+                    )));
+                }
+                // 保活不会自行结束，连接关闭时随之丢弃
+                _ = idle.keep_alive() => {}
+                _ = closer.closing() => {}
+            };
+            // 所有的流都要立即得到通知
+            let err = closer.closing().await;
+            streams.on_conn_error(&err);
+            // closing或draining状态持续3倍PTO，期满后所有的密钥都要失效，收包任务随之退出，
+            // 端点路由表中该连接的连接ID也要一并移除
+            if !silent {
+                timers.set(TimerKind::Draining, timers.now() + closing_period(pto()));
+                timers.expired(TimerKind::Draining).await;
+            }
+            closer.terminate();
+            timers.close();
+            cid_manager.clear();
+            initial_keys.invalid();
+            handshake_keys.invalid();
+            zero_rtt_keys.invalid();
+            one_rtt_keys.invalid();
+        }
+    });
     tokio::spawn({
         let space = data_space.clone();
+        let one_rtt_keys = one_rtt_keys.clone();
         let mut ack_rx = data_ack_rx;
         async move {
-            // 通过rx接收并处理AckFrame，AckFrame是Path收包解包得到
-            while let Some(ack) = ack_rx.recv().await {
-                space.on_ack(ack);
+            // 通过rx接收并处理AckFrame，AckFrame是Path收包解包得到。
+            // 当前密钥阶段发出的包被确认了，才能再次发起密钥更新
+            while let Some((path_id, ack)) = ack_rx.recv().await {
+                one_rtt_keys.on_ack_rcvd(path_id, ack.largest.into_inner());
+                space.on_path_ack(path_id, ack);
+            }
+        }
+    });
+    tokio::spawn({
+        let mut drivers = paths.drivers();
+        async move {
+            // 路径自身不启动任务，新建路径的驱动任务都在此spawn
+            while let Some(driver) = drivers.next().await {
+                tokio::spawn(driver);
+            }
+        }
+    });
+    tokio::spawn({
+        let space = data_space.clone();
+        let mut abandoned = paths.abandoned();
+        async move {
+            // 被移除的路径，其发包空间中在途的数据包都要在其它路径上重传
+            while let Some(path_id) = abandoned.next().await {
+                space.abandon_path(path_id);
             }
         }
     });
@@ -158,8 +537,18 @@ pub fn new(tls_session: TlsIO) -> RawConnection {
         let mut loss_pkt_rx = data_loss_rx;
         async move {
             // 不停地接收丢包序号，这些丢包序号由path记录反馈，更新Transmiter的状态
-            while let Some(pn) = loss_pkt_rx.recv().await {
-                space.may_loss_pkt(pn);
+            while let Some((pn_space, pn)) = loss_pkt_rx.recv().await {
+                space.may_loss_pkt_on_path(pn_space, pn);
+            }
+        }
+    });
+    tokio::spawn({
+        let space = data_space.clone();
+        let mut acked_rx = data_acked_rx;
+        async move {
+            // 我方发出的AckFrame被对方确认了，其所确认的收包记录不必再反馈，收包窗口向前滑动
+            while let Some((pn_space, largest)) = acked_rx.recv().await {
+                space.inactivate_rcvd_records_on_path(pn_space, largest);
             }
         }
     });
@@ -168,9 +557,12 @@ pub fn new(tls_session: TlsIO) -> RawConnection {
             zero_rtt_pkt_rx,
             zero_rtt_keys.clone(),
             data_space.clone(),
+            paths.clone(),
             rcvd_conn_frames.clone(),
             data_space_frame_queue.clone(),
             data_ack_tx.clone(),
+            conn_error_tx.clone(),
+            idle.clone(),
             false,
         ),
     );
@@ -179,57 +571,288 @@ pub fn new(tls_session: TlsIO) -> RawConnection {
             one_rtt_pkt_rx,
             one_rtt_keys.clone(),
             data_space.clone(),
+            paths.clone(),
             rcvd_conn_frames.clone(),
             data_space_frame_queue,
             data_ack_tx,
+            conn_error_tx.clone(),
+            idle.clone(),
         ),
     );
     tokio::spawn(
         handshake::exchange_handshake_crypto_msg_until_getting_1rtt_key(
-            tls_session,
-            one_rtt_keys,
+            tls_session.clone(),
+            one_rtt_keys.clone(),
+            zero_rtt_keys.clone(),
+            data_space.clone(),
             handshake_crypto_handler,
+            cid_manager.clone(),
+            paths.clone(),
+            idle.clone(),
+            handshake_confirmed.clone(),
+            conn_error_tx.clone(),
         ),
     );
+    // 握手确认之后才可能发生密钥更新。每次更新，旧密钥都要保留3倍PTO以解密乱序到达的包，
+    // 期满淘汰，才能发起下一次更新；密钥用到了机密性上限而来不及更新，只能以AEAD_LIMIT_REACHED关闭连接，
+    // Ref. RFC 9001 §6.5, §6.6
+    tokio::spawn({
+        let confirmed = handshake_confirmed.confirmed();
+        let one_rtt_keys = one_rtt_keys.clone();
+        let paths = paths.clone();
+        let timers = timers.clone();
+        async move {
+            confirmed.await;
+            let pto = || {
+                paths
+                    .active()
+                    .map(|path| path.rtt().lock().unwrap().pto_base_duration(0))
+                    .unwrap_or_else(|| Rtt::default().pto_base_duration(0))
+            };
+            loop {
+                tokio::select! {
+                    event = one_rtt_keys.key_event() => match event {
+                        Some(OneRttKeyEvent::Updated) => {
+                            timers.set(TimerKind::KeyDiscard, timers.now() + pto() * 3);
+                        }
+                        Some(OneRttKeyEvent::Exhausted) => {
+                            let _ = conn_error_tx.send(Error::new_with_default_fty(
+                                ErrorKind::AeadLimitReached,
+                                "1-RTT keys reached the confidentiality limit before being updated",
+                            ));
+                            break;
+                        }
+                        None => break,
+                    },
+                    _ = timers.expired(TimerKind::KeyDiscard) => {
+                        if let Some((_, pk)) = one_rtt_keys.get_local_keys() {
+                            pk.lock().unwrap().phase_out();
+                        }
+                    }
+                }
+            }
+        }
+    });
+    // 服务端保留0-RTT密钥以解密乱序晚到的0-RTT包，握手确认后再过3倍PTO就丢弃，Ref. RFC 9001 §4.9.3
+    if tls_session.is_server() {
+        tokio::spawn({
+            let confirmed = handshake_confirmed.confirmed();
+            let zero_rtt_keys = zero_rtt_keys.clone();
+            let paths = paths.clone();
+            let timers = timers.clone();
+            async move {
+                confirmed.await;
+                let pto = paths
+                    .active()
+                    .map(|path| path.rtt().lock().unwrap().pto_base_duration(0))
+                    .unwrap_or_else(|| Rtt::default().pto_base_duration(0));
+                timers.set(TimerKind::ZeroRttKeyDiscard, timers.now() + pto * 3);
+                timers.expired(TimerKind::ZeroRttKeyDiscard).await;
+                zero_rtt_keys.invalid();
+            }
+        });
+    }
+    // 服务端在握手确认后，为客户端当前的地址签发令牌，客户端下次连接时凭此令牌可省去Retry的一个往返
+    if let Some(token_keys) = token_keys {
+        tokio::spawn({
+            let confirmed = handshake_confirmed.confirmed();
+            let paths = paths.clone();
+            let space = data_space.clone();
+            async move {
+                confirmed.await;
+                issue_token(&paths, &space, &token_keys);
+            }
+        });
+    }
 
     RawConnection {
         initial_keys,
-        initial_pkt_queue: Some(initial_pkt_tx),
         initial_space,
         handshake_keys,
-        handshake_pkt_queue: Some(handshake_pkt_tx),
         handshake_space,
         zero_rtt_keys,
-        zero_rtt_pkt_queue: Some(zero_rtt_pkt_tx),
-        one_rtt_pkt_queue: one_rtt_pkt_tx,
+        one_rtt_keys,
+        packet_entry,
         data_space,
+        cid_manager,
+        paths,
         spin: SpinBit::default(),
+        initial_token: tokens.pop().unwrap_or_default(),
+        closer,
+        idle,
+        timers,
+        handshake_confirmed,
+        tls_session,
     }
 }
 
+/// 为客户端当前的地址签发令牌，经NEW_TOKEN帧发给客户端
+fn issue_token(paths: &ArcPaths, space: &ArcSpace<ArcDataStreams>, keys: &ArcTokenKeys) {
+    let Some(path) = paths.active() else {
+        return;
+    };
+    let token = keys.mint(path.path_id().remote_ip(), SystemTime::now());
+    space
+        .reliable_frame_queue()
+        .write()
+        .push_conn_frame(ConnFrame::NewToken(NewTokenFrame { token }));
+}
+
 impl RawConnection {
-    pub fn recv_initial_packet(&mut self, pkt: InitialPacket, path: ArcPath) {
-        self.initial_pkt_queue.as_mut().map(|q| {
-            let _ = q.send((pkt, path));
-        });
+    pub fn recv_initial_packet(&mut self, pkt: InitialPacket, path_id: PathId) {
+        self.packet_entry.recv_initial_packet(pkt, path_id);
     }
 
-    pub fn recv_handshake_packet(&mut self, pkt: HandshakePacket, path: ArcPath) {
-        self.handshake_pkt_queue.as_mut().map(|q| {
-            let _ = q.send((pkt, path));
-        });
+    pub fn recv_handshake_packet(&mut self, pkt: HandshakePacket, path_id: PathId) {
+        self.packet_entry.recv_handshake_packet(pkt, path_id);
     }
 
-    pub fn recv_0rtt_packet(&mut self, pkt: ZeroRttPacket, path: ArcPath) {
-        self.zero_rtt_pkt_queue.as_mut().map(|q| {
-            let _ = q.send((pkt, path));
-        });
+    pub fn recv_0rtt_packet(&mut self, pkt: ZeroRttPacket, path_id: PathId) {
+        self.packet_entry.recv_0rtt_packet(pkt, path_id);
+    }
+
+    pub fn recv_1rtt_packet(&mut self, pkt: OneRttPacket, path_id: PathId) {
+        self.packet_entry.recv_1rtt_packet(pkt, path_id);
+    }
+
+    pub fn packet_entry(&self) -> ArcPacketEntry {
+        self.packet_entry.clone()
+    }
+
+    pub fn cid_manager(&self) -> CidManager {
+        self.cid_manager.clone()
+    }
+
+    pub fn paths(&self) -> ArcPaths {
+        self.paths.clone()
+    }
+
+    pub fn initial_token(&self) -> &[u8] {
+        &self.initial_token
+    }
+
+    /// 服务端接受端点转交来的新连接的首个Initial包。其中的令牌有效，则客户端的地址得以验证，
+    /// 新连接的路径不受反放大限制
+    pub fn accept(&mut self, incoming: Incoming) {
+        if incoming.address_validated {
+            self.paths.on_address_validated(incoming.path_id);
+        }
+        self.recv_initial_packet(incoming.packet, incoming.path_id);
+    }
+
+    /// 服务端通告首选地址，客户端握手确认之后可迁移过去，Ref. RFC 9000 §9.6。
+    /// 首选地址的连接ID就地签发，须在收到客户端的首个Initial包之前，即[`RawConnection::accept`]之前调用
+    pub fn advertise_preferred_address(
+        &self,
+        address_v4: Option<SocketAddrV4>,
+        address_v6: Option<SocketAddrV6>,
+    ) -> Result<(), rustls::Error> {
+        let mut advertised = None;
+        self.tls_session.set_preferred_address(|| {
+            let preferred = self
+                .cid_manager
+                .local
+                .gen_preferred_address(address_v4, address_v6);
+            advertised = Some(preferred);
+            preferred
+        })?;
+        // 即便禁止了对方主动迁移，客户端迁移到首选地址也要放行
+        if let Some(preferred) = advertised {
+            self.paths.set_preferred_address(preferred);
+        }
+        Ok(())
+    }
+
+    pub fn is_handshake_confirmed(&self) -> bool {
+        self.handshake_confirmed.is_confirmed()
+    }
+
+    /// 主动迁移到新的本地地址，比如网络接口变了。
+    /// 绑定新的套接字，在新路径上用一个未使用过的对方连接ID发起路径验证，验证通过后才切换过去，
+    /// 并退役原来的路径。返回新绑定的套接字，从中收到的数据报要交给端点处理。
+    pub async fn migrate(&self, new_local_addr: SocketAddr) -> io::Result<UdpSocket> {
+        let socket = UdpSocket::bind(new_local_addr).await?;
+        self.paths.migrate(socket.local_addr()?)?;
+        Ok(socket)
+    }
+
+    /// 应用层主动关闭连接，以应用层协议定义的error_code发送应用层的CONNECTION_CLOSE，连接进入closing状态。
+    /// 1-RTT密钥就绪之前，它只能改作传输层的APPLICATION_ERROR发送，见[`conceal_app_close`]
+    pub fn close(&self, error_code: VarInt, reason: impl Into<Cow<'static, str>>) {
+        self.closer
+            .close(ConnectionCloseFrame::new_app(error_code, reason.into()));
+    }
+
+    /// 设置保活间隔，空闲了这么久就发送PING帧，以免NAT绑定失效；None则不保活。
+    /// 保活间隔应小于空闲超时，否则起不到保活的作用
+    pub fn set_keep_alive(&self, interval: Option<Duration>) {
+        self.idle.set_keep_alive(interval);
+    }
+
+    pub fn idle_timer(&self) -> ArcIdleTimer {
+        self.idle.clone()
+    }
+
+    /// 连接的定时器轮，连接中需要定时的组件都在此登记截止时间
+    pub fn timers(&self) -> ArcTimerWheel {
+        self.timers.clone()
+    }
+
+    pub fn close_state(&self) -> CloseState {
+        self.closer.state()
+    }
+
+    /// 等待连接关闭或排空期满，资源全部释放
+    pub fn closed(&self) -> Closed {
+        self.closer.closed()
     }
 
-    pub fn recv_1rtt_packet(&mut self, pkt: OneRttPacket, path: ArcPath) {
-        self.one_rtt_pkt_queue
-            .send((pkt, path))
-            .expect("must success");
+    /// 连接关闭期间，若有待发送的CONNECTION_CLOSE，在当前路径上以可用的最高密钥等级写入buf，
+    /// 返回所用的路径和写入的数据包大小。Initial、Handshake包中只能携带传输层的CONNECTION_CLOSE
+    pub fn read_conn_close(&self, buf: &mut [u8]) -> Option<(ArcPath, usize)> {
+        let path = self.paths.active()?;
+        let frame = self.closer.read_frame()?;
+        let size = if self.one_rtt_keys.get_local_keys().is_some() {
+            let header = OneRttHeader {
+                spin: self.spin,
+                dcid: path.dcid(),
+            };
+            read_1rtt_close_and_encrypt(
+                buf,
+                header,
+                self.one_rtt_keys.clone(),
+                self.data_space.clone(),
+                &path,
+                self.paths.pn_space(&path),
+                &frame,
+            )
+        } else {
+            let frame = conceal_app_close(&frame);
+            let builder = LongHeaderBuilder::with_cid(path.dcid(), path.scid());
+            let (_, size) = if self.handshake_keys.get_local_keys().is_some() {
+                read_close_and_encrypt(
+                    buf,
+                    builder.handshake(),
+                    FillPolicy::Redundancy,
+                    self.handshake_keys.clone(),
+                    self.handshake_space.clone(),
+                    &path,
+                    &frame,
+                )
+            } else {
+                read_close_and_encrypt(
+                    buf,
+                    builder.initial(self.initial_token.clone()),
+                    FillPolicy::Redundancy,
+                    self.initial_keys.clone(),
+                    self.initial_space.clone(),
+                    &path,
+                    &frame,
+                )
+            };
+            size
+        };
+        (size > 0).then_some((path, size))
     }
 
     pub fn invalid_initial_keys(&self) {
@@ -1,5 +1,18 @@
+use bytes::BytesMut;
+use qbase::{
+    config::{
+        ext::{be_transport_parameters, BufMutExt},
+        PreferredAddress, TransportParameters,
+    },
+    packet::keys::ArcKeys,
+};
 use qrecovery::crypto::{CryptoStreamReader, CryptoStreamWriter};
-use rustls::quic::{Connection as TlsConnection, KeyChange};
+use rustls::{
+    quic::{
+        ClientConnection, Connection as TlsConnection, KeyChange, Keys, ServerConnection, Version,
+    },
+    ClientConfig, ServerConfig, ServerName,
+};
 use std::{
     future::Future,
     io,
@@ -16,6 +29,58 @@ use tokio::{
 pub(crate) struct TlsSession {
     connection: TlsConnection,
     wants_write: Option<Waker>,
+    // 等待0-RTT密钥的空间密钥，客户端写出ClientHello、服务端读到ClientHello之后才有0-RTT密钥
+    zero_rtt_keys: Option<ArcKeys>,
+    // 客户端恢复会话时，rustls从会话票据中取出的、上次连接时服务端的传输参数
+    remembered_params: Option<TransportParameters>,
+    // 我方的传输参数
+    local_params: TransportParameters,
+    // 服务端的TLS配置，收到ClientHello之前，可据此以新的传输参数重建TLS会话
+    server_config: Option<Arc<ServerConfig>>,
+    // 是否已开始读取握手消息，此后传输参数就不能再变了
+    started: bool,
+}
+
+impl TlsSession {
+    fn new(connection: TlsConnection, local_params: TransportParameters) -> Self {
+        Self {
+            connection,
+            wants_write: None,
+            zero_rtt_keys: None,
+            remembered_params: None,
+            local_params,
+            server_config: None,
+            started: false,
+        }
+    }
+
+    fn peer_transport_parameters(&self) -> Option<TransportParameters> {
+        self.connection
+            .quic_transport_parameters()
+            .and_then(|raw| be_transport_parameters(raw).ok())
+            .map(|(_, params)| params)
+    }
+
+    /// 0-RTT密钥只有一个方向：客户端用来加密，服务端用来解密，两个方向填入的是同一把密钥
+    fn try_install_0rtt_keys(&mut self) {
+        let Some(zero_rtt_keys) = self.zero_rtt_keys.take() else {
+            return;
+        };
+        let keys = self.connection.zero_rtt_keys().and_then(|local| {
+            let remote = self.connection.zero_rtt_keys()?;
+            Some(Keys { local, remote })
+        });
+        match keys {
+            Some(keys) => {
+                if let TlsConnection::Client(_) = self.connection {
+                    // 此时握手尚未收到服务端的传输参数，rustls给出的是会话票据中记住的那些
+                    self.remembered_params = self.peer_transport_parameters();
+                }
+                zero_rtt_keys.set_keys(keys);
+            }
+            None => self.zero_rtt_keys = Some(zero_rtt_keys),
+        }
+    }
 }
 
 pub(crate) type ArcTlsSession = Arc<Mutex<TlsSession>>;
@@ -24,19 +89,126 @@ pub(crate) type ArcTlsSession = Arc<Mutex<TlsSession>>;
 pub struct TlsIO(ArcTlsSession);
 
 impl TlsIO {
-    pub fn new_client() -> Self {
-        /*
-        Self(Arc::new(Mutex::new(TlsSession {
-            connection: todo!(),
-            wants_write: None,
-        })))
-        */
-        todo!()
+    /// 客户端的TLS会话。config中启用了enable_early_data，且其resumption中缓存有该服务端的会话票据，
+    /// 就能以0-RTT发送数据
+    pub fn new_client(
+        config: Arc<ClientConfig>,
+        server_name: ServerName,
+        params: &TransportParameters,
+    ) -> Result<Self, rustls::Error> {
+        let params = without_multipath(params);
+        let connection =
+            ClientConnection::new(config, Version::V1, server_name, encode_params(&params))?;
+        Ok(Self(Arc::new(Mutex::new(TlsSession::new(
+            TlsConnection::Client(connection),
+            params,
+        )))))
+    }
+
+    /// 服务端的TLS会话，是否接受0-RTT见[`accept_0rtt`]
+    pub fn new_server(
+        config: Arc<ServerConfig>,
+        params: &TransportParameters,
+    ) -> Result<Self, rustls::Error> {
+        let params = without_multipath(params);
+        let connection =
+            ServerConnection::new(config.clone(), Version::V1, encode_params(&params))?;
+        let mut tls_session = TlsSession::new(TlsConnection::Server(connection), params);
+        tls_session.server_config = Some(config);
+        Ok(Self(Arc::new(Mutex::new(tls_session))))
+    }
+
+    /// 服务端在传输参数中通告首选地址，gen生成首选地址，其中的连接ID须由我方签发。
+    /// 只能在收到ClientHello之前通告，因为传输参数随握手消息发出，此后就不能再变了
+    pub fn set_preferred_address(
+        &self,
+        gen: impl FnOnce() -> PreferredAddress,
+    ) -> Result<(), rustls::Error> {
+        let mut tls_session = self.0.lock().unwrap();
+        let Some(config) = tls_session.server_config.clone() else {
+            return Err(rustls::Error::General(
+                "only a server advertises a preferred address".into(),
+            ));
+        };
+        if tls_session.started {
+            return Err(rustls::Error::General(
+                "the handshake has already started".into(),
+            ));
+        }
+        let mut params = tls_session.local_params.clone();
+        params.set_preferred_address(Some(gen()));
+        let connection = ServerConnection::new(config, Version::V1, encode_params(&params))?;
+        tls_session.connection = TlsConnection::Server(connection);
+        tls_session.local_params = params;
+        Ok(())
+    }
+
+    pub fn is_server(&self) -> bool {
+        matches!(self.0.lock().unwrap().connection, TlsConnection::Server(_))
     }
 
     pub fn split_io(&self) -> (TlsReader, TlsWriter) {
         (TlsReader(self.0.clone()), TlsWriter(self.0.clone()))
     }
+
+    /// 对方的传输参数，握手过程中收到对方的传输参数之后才有
+    pub fn peer_transport_parameters(&self) -> Option<TransportParameters> {
+        self.0.lock().unwrap().peer_transport_parameters()
+    }
+
+    /// 我方的传输参数
+    pub fn local_transport_parameters(&self) -> TransportParameters {
+        self.0.lock().unwrap().local_params.clone()
+    }
+
+    /// 0-RTT密钥一旦可用，就装入keys
+    pub fn watch_0rtt_keys(&self, keys: ArcKeys) {
+        let mut tls_session = self.0.lock().unwrap();
+        tls_session.zero_rtt_keys = Some(keys);
+        tls_session.try_install_0rtt_keys();
+    }
+
+    /// 客户端发送0-RTT时所依据的、记住的服务端传输参数
+    pub fn remembered_parameters(&self) -> Option<TransportParameters> {
+        self.0.lock().unwrap().remembered_params.clone()
+    }
+
+    /// 客户端的0-RTT数据是否被服务端接受，握手完成时才有定论
+    pub fn is_early_data_accepted(&self) -> bool {
+        match &self.0.lock().unwrap().connection {
+            TlsConnection::Client(connection) => connection.is_early_data_accepted(),
+            TlsConnection::Server(_) => false,
+        }
+    }
+}
+
+/// 服务端开启0-RTT。0-RTT数据可被攻击者重放，rustls只在有状态的会话恢复下才接受0-RTT，
+/// 会话票据被取用一次即从缓存中移除，重放的ClientHello找不到票据，0-RTT也就被拒绝了，
+/// 票据的新鲜度也会检查，Ref. RFC 8446 §8.1。无状态的票据无法防重放，因此不能与0-RTT同时启用。
+pub fn accept_0rtt(config: &mut ServerConfig) -> Result<(), rustls::Error> {
+    if config.ticketer.enabled() || !config.session_storage.can_cache() {
+        return Err(rustls::Error::General(
+            "0-RTT requires stateful session resumption for anti-replay".into(),
+        ));
+    }
+    // QUIC下只能是0或0xffffffff，Ref. RFC 9001 §4.6.1
+    config.max_early_data_size = u32::MAX;
+    Ok(())
+}
+
+/// 多路径草案要求把路径ID与IV的高32位异或得出nonce，而rustls的PacketKey只以64位包号与IV异或，
+/// IV与密钥材料都不对外暴露，非0路径上的包无法与其它实现互通。
+/// 在TLS后端支持之前，不通告enable_multipath，多路径也就不会协商成功
+fn without_multipath(params: &TransportParameters) -> TransportParameters {
+    let mut params = params.clone();
+    params.set_enable_multipath(false);
+    params
+}
+
+fn encode_params(params: &TransportParameters) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+    buf.put_transport_parameters(params);
+    buf.to_vec()
 }
 
 #[derive(Debug, Clone)]
@@ -45,7 +217,9 @@ pub struct TlsReader(ArcTlsSession);
 impl TlsReader {
     pub fn read_hs(&mut self, plaintext: &[u8]) -> Result<(), rustls::Error> {
         let mut tls_session = self.0.lock().unwrap();
+        tls_session.started = true;
         tls_session.connection.read_hs(plaintext)?;
+        tls_session.try_install_0rtt_keys();
         if tls_session.connection.wants_write() {
             if let Some(waker) = tls_session.wants_write.take() {
                 waker.wake();
@@ -84,6 +258,7 @@ impl Future for TlsWriter {
         let mut buf = Vec::with_capacity(1200);
         let mut tls_session = self.0.lock().unwrap();
         let key_change = tls_session.connection.write_hs(&mut buf);
+        tls_session.try_install_0rtt_keys();
         if key_change.is_none() && buf.is_empty() {
             tls_session.wants_write = Some(cx.waker().clone());
             Poll::Pending
@@ -139,9 +314,81 @@ impl HandshakeWriter {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use rustls::server::{ClientHello, ResolvesServerCert};
+
+    struct NoCert;
+
+    impl ResolvesServerCert for NoCert {
+        fn resolve(&self, _: ClientHello) -> Option<Arc<rustls::sign::CertifiedKey>> {
+            None
+        }
+    }
+
+    fn server_config() -> ServerConfig {
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(NoCert))
+    }
+
+    #[test]
+    fn test_accept_0rtt() {
+        let mut config = server_config();
+        accept_0rtt(&mut config).unwrap();
+        assert_eq!(config.max_early_data_size, u32::MAX);
+
+        // 无状态的会话票据无法防重放
+        let mut config = server_config();
+        config.ticketer = rustls::Ticketer::new().unwrap();
+        assert!(accept_0rtt(&mut config).is_err());
+        assert_eq!(config.max_early_data_size, 0);
+    }
+
+    #[test]
+    fn test_set_preferred_address() {
+        use qbase::cid::{ConnectionId, ResetToken};
+
+        let preferred = PreferredAddress::new(
+            Some("1.2.3.4:443".parse().unwrap()),
+            None,
+            ConnectionId::from_slice(&[7; 8]),
+            ResetToken::new_with(&[7; 16]),
+        );
+        let params = TransportParameters::default();
+        let server = TlsIO::new_server(Arc::new(server_config()), &params).unwrap();
+        server.set_preferred_address(|| preferred).unwrap();
+        assert_eq!(
+            server.local_transport_parameters().preferred_address(),
+            Some(preferred)
+        );
+
+        // 开始握手之后，传输参数已随握手消息发出，不能再通告
+        let (mut reader, _) = server.split_io();
+        reader.read_hs(&[]).unwrap();
+        assert!(server.set_preferred_address(|| preferred).is_err());
+
+        // 客户端不能通告首选地址
+        let client_config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth();
+        let client = TlsIO::new_client(
+            Arc::new(client_config),
+            "localhost".try_into().unwrap(),
+            &params,
+        )
+        .unwrap();
+        assert!(client
+            .set_preferred_address(|| unreachable!("no preferred address for a client"))
+            .is_err());
+    }
 
     #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+    fn test_multipath_not_advertised() {
+        let mut params = TransportParameters::default();
+        params.set_enable_multipath(true);
+        let server = TlsIO::new_server(Arc::new(server_config()), &params).unwrap();
+        assert!(!server.local_transport_parameters().enable_multipath());
     }
 }
@@ -1,26 +1,277 @@
-use crate::{connection::RawConnection, ReceiveProtectedPacket};
+use crate::{
+    connection::ArcPacketEntry,
+    path::{relay, PathId},
+    ReceiveProtectedPacket,
+};
+use bytes::{Bytes, BytesMut};
 use qbase::{
-    cid::ConnectionId,
-    packet::{header::GetDcid, SpacePacket},
+    cid::{ArcCidGenerator, ConnectionId, ResetToken, ResetTokenKey, RESET_TOKEN_SIZE},
+    packet::{header::GetDcid, InitialPacket, Packet, PacketReader, SpacePacket},
+    token::{ArcTokenCache, ArcTokenKeys},
+};
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::SystemTime,
 };
-use std::collections::HashMap;
+use tokio::sync::mpsc;
+
+/// 无状态重置包至少21字节，看起来要像一个短包头的数据包
+pub const MIN_STATELESS_RESET_SIZE: usize = 5 + RESET_TOKEN_SIZE;
+/// 触发包不超过该长度时，回应的无状态重置只比触发包短1个字节，否则在此基础上随机长度
+const SHORT_TRIGGER_SIZE: usize = 43;
+
+#[derive(Debug, Default)]
+struct Router {
+    cids: HashMap<ConnectionId, ArcPacketEntry>,
+    // 对方签发的、我方正在使用的连接ID所对应的无状态重置令牌
+    reset_tokens: HashMap<ResetToken, ArcPacketEntry>,
+}
+
+/// 路由表，记录连接ID到连接收包入口的映射。
+/// 一个连接可以有多个连接ID，它们都指向该连接的同一个收包入口。
+#[derive(Debug, Clone, Default)]
+pub struct ArcRouter(Arc<Mutex<Router>>);
+
+impl ArcRouter {
+    pub fn get(&self, cid: &ConnectionId) -> Option<ArcPacketEntry> {
+        self.0.lock().unwrap().cids.get(cid).cloned()
+    }
+
+    pub fn contains(&self, cid: &ConnectionId) -> bool {
+        self.0.lock().unwrap().cids.contains_key(cid)
+    }
+
+    /// 根据无状态重置令牌找到对应的连接
+    pub fn get_by_reset_token(&self, token: &ResetToken) -> Option<ArcPacketEntry> {
+        self.0.lock().unwrap().reset_tokens.get(token).cloned()
+    }
+
+    /// 为某个连接创建在路由表中的登记处，连接ID的签发与退役都要经由它同步到路由表
+    pub fn registry(&self, entry: ArcPacketEntry) -> RouterRegistry {
+        RouterRegistry {
+            router: self.clone(),
+            entry,
+        }
+    }
+}
+
+/// 连接在路由表中的登记处，由连接ID管理器持有
+#[derive(Debug, Clone)]
+pub struct RouterRegistry {
+    router: ArcRouter,
+    entry: ArcPacketEntry,
+}
+
+impl RouterRegistry {
+    pub fn add(&self, cid: ConnectionId) {
+        self.router
+            .0
+            .lock()
+            .unwrap()
+            .cids
+            .insert(cid, self.entry.clone());
+    }
+
+    pub fn remove(&self, cid: &ConnectionId) {
+        self.router.0.lock().unwrap().cids.remove(cid);
+    }
+
+    pub fn add_reset_token(&self, token: ResetToken) {
+        self.router
+            .0
+            .lock()
+            .unwrap()
+            .reset_tokens
+            .insert(token, self.entry.clone());
+    }
+
+    pub fn remove_reset_token(&self, token: &ResetToken) {
+        self.router.0.lock().unwrap().reset_tokens.remove(token);
+    }
+}
+
+/// 服务端收到的、尚无连接与之对应的Initial包，应用层据此创建新连接，再交由[`accept`]处理
+///
+/// [`accept`]: crate::connection::RawConnection::accept
+#[derive(Debug)]
+pub struct Incoming {
+    pub packet: InitialPacket,
+    pub path_id: PathId,
+    // Initial包中带有有效的令牌，客户端的地址已得到验证
+    pub address_validated: bool,
+}
 
 pub struct Endpiont {
-    // 尚未实现连接迁移，多个连接id对应一个连接的功能尚未实现
-    connections: HashMap<ConnectionId, RawConnection>,
-    // 新连接的监听器
-    // listener: Listener,
+    // 数据包按目标连接ID路由到连接，再由连接按其来源认定路径，对方迁移后连接ID不变也能找到连接
+    router: ArcRouter,
+    // 新连接及连接ID管理器都用它生成我方的连接ID，收包时也用它解析短包头中目标连接ID的长度
+    cid_gen: ArcCidGenerator,
+    // 派生无状态重置令牌的静态密钥，重启前后需保持一致
+    reset_key: ResetTokenKey,
+    // 作为服务端，签发与验证地址验证令牌的密钥
+    token_keys: ArcTokenKeys,
+    // 作为客户端，缓存各服务端签发的令牌
+    token_cache: ArcTokenCache,
+    // 新连接的监听器，未监听则不接受新连接
+    listener: Option<mpsc::UnboundedSender<Incoming>>,
+}
+
+impl Endpiont {
+    pub fn new(cid_gen: ArcCidGenerator, reset_key: ResetTokenKey) -> Self {
+        Self {
+            router: ArcRouter::default(),
+            cid_gen,
+            reset_key,
+            token_keys: ArcTokenKeys::default(),
+            token_cache: ArcTokenCache::default(),
+            listener: None,
+        }
+    }
+
+    /// 作为服务端监听新连接，此后收到的、尚无连接与之对应的Initial包，都从返回的接收端取出
+    pub fn listen(&mut self) -> mpsc::UnboundedReceiver<Incoming> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.listener = Some(tx);
+        rx
+    }
+
+    pub fn router(&self) -> ArcRouter {
+        self.router.clone()
+    }
+
+    pub fn cid_generator(&self) -> ArcCidGenerator {
+        self.cid_gen.clone()
+    }
+
+    pub fn reset_key(&self) -> ResetTokenKey {
+        self.reset_key.clone()
+    }
+
+    pub fn token_keys(&self) -> ArcTokenKeys {
+        self.token_keys.clone()
+    }
+
+    pub fn token_cache(&self) -> ArcTokenCache {
+        self.token_cache.clone()
+    }
+
+    /// Initial包中的令牌是否为该路径上的客户端签发且仍有效，有效则客户端的地址得以验证
+    pub fn validate_token(&self, token: &[u8], path_id: &PathId) -> bool {
+        !token.is_empty()
+            && self
+                .token_keys
+                .validate(token, path_id.remote_ip(), SystemTime::now())
+    }
+
+    /// 解析从path_id所标识的路径上收到的数据报中的各个数据包，无法解析的部分直接丢弃。
+    /// 返回值是需要回应给该数据报来源的数据报，比如无状态重置。
+    pub fn recv_datagram(&mut self, datagram: BytesMut, path_id: PathId) -> Option<Bytes> {
+        if let Some(dcid) = self.unknown_short_dcid(&datagram) {
+            return self.on_unknown_short_datagram(&datagram, dcid);
+        }
+        for packet in PacketReader::new(datagram, self.cid_gen.clone()) {
+            match packet {
+                Ok(Packet::Space(packet)) => self.receive_protected_packet(packet, path_id),
+                Ok(_) => {
+                    // TODO: 处理版本协商包与Retry包
+                }
+                Err(_) => break,
+            }
+        }
+        None
+    }
+
+    /// 从中继代理agent处收到的数据报，剥离中继头部后，与直连路径上收到的数据报一样处理。
+    /// 需要回应的数据报同样要经中继代理转发回去，已加上中继头部。
+    pub fn recv_relayed_datagram(
+        &mut self,
+        datagram: BytesMut,
+        local: SocketAddr,
+        agent: SocketAddr,
+    ) -> Option<Bytes> {
+        let (datagram, path_id) = relay::decapsulate(datagram, local, agent)?;
+        let response = self.recv_datagram(datagram, path_id)?;
+        let (_, relayed) = relay::encapsulate(response, &path_id);
+        Some(relayed)
+    }
+
+    /// 若数据报以短包头开始，且其目标连接ID不在路由表中，返回其目标连接ID（若能解析出来的话）
+    fn unknown_short_dcid(&self, datagram: &[u8]) -> Option<Option<ConnectionId>> {
+        match datagram.first() {
+            Some(first) if first & 0x80 == 0 => {}
+            _ => return None,
+        }
+        let dcid = self
+            .cid_gen
+            .decode_len(&datagram[1..])
+            .and_then(|len| datagram.get(1..1 + len))
+            .map(ConnectionId::from_slice);
+        match dcid {
+            Some(dcid) if self.router.contains(&dcid) => None,
+            dcid => Some(dcid),
+        }
+    }
+
+    fn on_unknown_short_datagram(
+        &self,
+        datagram: &[u8],
+        dcid: Option<ConnectionId>,
+    ) -> Option<Bytes> {
+        if datagram.len() < MIN_STATELESS_RESET_SIZE {
+            return None;
+        }
+        // 数据报末尾16字节若是某个连接正在使用的无状态重置令牌，则该连接被对方重置了
+        let tail = ResetToken::new_with(&datagram[datagram.len() - RESET_TOKEN_SIZE..]);
+        if let Some(entry) = self.router.get_by_reset_token(&tail) {
+            entry.recv_stateless_reset();
+            return None;
+        }
+        dcid.and_then(|dcid| self.gen_stateless_reset(&dcid, datagram.len()))
+    }
+
+    /// 为未知连接ID生成无状态重置。
+    /// 为防止两个端点互发无状态重置陷入死循环，无状态重置必须比触发它的数据包短，
+    /// 太短的触发包无法回应，循环终将结束。
+    fn gen_stateless_reset(&self, dcid: &ConnectionId, trigger_size: usize) -> Option<Bytes> {
+        if trigger_size <= MIN_STATELESS_RESET_SIZE {
+            return None;
+        }
+        let mut rng = rand::thread_rng();
+        let size = if trigger_size <= SHORT_TRIGGER_SIZE {
+            trigger_size - 1
+        } else {
+            rng.gen_range(SHORT_TRIGGER_SIZE..trigger_size)
+        };
+        let mut packet = vec![0u8; size];
+        rng.fill(&mut packet[..size - RESET_TOKEN_SIZE]);
+        // 固定位为1，长短包头标识位为0，看起来与短包头的数据包无异
+        packet[0] = 0x40 | (packet[0] & 0x3f);
+        packet[size - RESET_TOKEN_SIZE..].copy_from_slice(&self.reset_key.derive(dcid));
+        Some(packet.into())
+    }
 }
 
 impl ReceiveProtectedPacket for Endpiont {
-    fn receive_protected_packet(&mut self, protected_packet: SpacePacket) {
+    fn receive_protected_packet(&mut self, protected_packet: SpacePacket, path_id: PathId) {
         let dcid = protected_packet.get_dcid();
-        if let Some(_conn) = self.connections.get_mut(dcid) {
-            // let _ = conn.receive_protected_packet(protected_packet);
+        if let Some(entry) = self.router.get(dcid) {
+            entry.recv_packet(protected_packet, path_id);
         } else {
             match protected_packet {
-                SpacePacket::Initial(_packet) => {
-                    // TODO: 创建新连接，并塞给Listener
+                SpacePacket::Initial(packet) => {
+                    let Some(listener) = &self.listener else {
+                        return;
+                    };
+                    // 令牌有效，则无需Retry，新连接的路径也不受反放大限制
+                    let address_validated = self.validate_token(&packet.header.token, &path_id);
+                    let _ = listener.send(Incoming {
+                        packet,
+                        path_id,
+                        address_validated,
+                    });
                 }
                 _other => {
                     // just ignore
@@ -29,3 +280,162 @@ impl ReceiveProtectedPacket for Endpiont {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::relay::WriteRelayHeader;
+    use bytes::BufMut;
+    use qbase::cid::RandomCidGenerator;
+    use std::net::SocketAddr;
+
+    fn path_id() -> PathId {
+        PathId::Direct {
+            local: "127.0.0.1:443".parse::<SocketAddr>().unwrap(),
+            remote: "127.0.0.1:5000".parse::<SocketAddr>().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_stateless_reset() {
+        let reset_key = ResetTokenKey::new(b"static key");
+        let mut endpoint = Endpiont::new(Arc::new(RandomCidGenerator::new(8)), reset_key.clone());
+        let dcid = ConnectionId::from_slice(&[1; 8]);
+
+        let mut trigger = BytesMut::from(&[0x40u8; 100][..]);
+        trigger[1..9].copy_from_slice(&dcid);
+        let reset = endpoint.recv_datagram(trigger, path_id()).unwrap();
+        assert!(reset.len() < 100 && reset.len() >= SHORT_TRIGGER_SIZE);
+        assert_eq!(reset[0] & 0xc0, 0x40);
+        assert_eq!(
+            &reset[reset.len() - RESET_TOKEN_SIZE..],
+            &reset_key.derive(&dcid)[..]
+        );
+
+        // 触发包较短时，回应的无状态重置只短1个字节
+        let mut trigger = BytesMut::from(&[0x40u8; 30][..]);
+        trigger[1..9].copy_from_slice(&dcid);
+        assert_eq!(
+            endpoint.recv_datagram(trigger, path_id()).unwrap().len(),
+            29
+        );
+
+        // 不会回应最短的无状态重置，以免陷入循环
+        let trigger = BytesMut::from(&[0x40u8; MIN_STATELESS_RESET_SIZE][..]);
+        assert!(endpoint.recv_datagram(trigger, path_id()).is_none());
+
+        // 长包头不会触发无状态重置
+        let trigger = BytesMut::from(&[0xc0u8; 100][..]);
+        assert!(endpoint.recv_datagram(trigger, path_id()).is_none());
+    }
+
+    #[test]
+    fn test_validate_token() {
+        let endpoint = Endpiont::new(
+            Arc::new(RandomCidGenerator::new(8)),
+            ResetTokenKey::new(b"static key"),
+        );
+        let token = endpoint
+            .token_keys()
+            .mint(path_id().remote_ip(), SystemTime::now());
+        assert!(endpoint.validate_token(&token, &path_id()));
+        assert!(!endpoint.validate_token(&[], &path_id()));
+        // 令牌与客户端的IP绑定，换了IP就无效了
+        let other = PathId::Direct {
+            local: "127.0.0.1:443".parse::<SocketAddr>().unwrap(),
+            remote: "10.0.0.1:5000".parse::<SocketAddr>().unwrap(),
+        };
+        assert!(!endpoint.validate_token(&token, &other));
+    }
+
+    #[test]
+    fn test_incoming_initial() {
+        let mut endpoint = Endpiont::new(
+            Arc::new(RandomCidGenerator::new(8)),
+            ResetTokenKey::new(b"static key"),
+        );
+        let initial = |token: &[u8]| {
+            let mut datagram = BytesMut::new();
+            datagram.put_u8(0xc0);
+            datagram.put_u32(1);
+            datagram.put_u8(8);
+            datagram.put_slice(&[1; 8]);
+            datagram.put_u8(8);
+            datagram.put_slice(&[2; 8]);
+            datagram.put_u8(token.len() as u8);
+            datagram.put_slice(token);
+            datagram.put_u8(32);
+            datagram.put_bytes(0, 32);
+            datagram
+        };
+
+        // 未监听，不接受新连接
+        endpoint.recv_datagram(initial(&[]), path_id());
+
+        let mut incomings = endpoint.listen();
+        endpoint.recv_datagram(initial(&[]), path_id());
+        let incoming = incomings.try_recv().unwrap();
+        assert_eq!(incoming.path_id, path_id());
+        assert!(!incoming.address_validated);
+
+        // Initial包中带有该客户端地址的有效令牌，地址得以验证
+        let token = endpoint
+            .token_keys()
+            .mint(path_id().remote_ip(), SystemTime::now());
+        endpoint.recv_datagram(initial(&token), path_id());
+        assert!(incomings.try_recv().unwrap().address_validated);
+        assert!(incomings.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_detect_stateless_reset() {
+        let mut endpoint = Endpiont::new(
+            Arc::new(RandomCidGenerator::new(8)),
+            ResetTokenKey::new(b"static key"),
+        );
+        let (reset_tx, mut reset_rx) = tokio::sync::mpsc::unbounded_channel();
+        let entry = ArcPacketEntry::new(
+            tokio::sync::mpsc::unbounded_channel().0,
+            tokio::sync::mpsc::unbounded_channel().0,
+            tokio::sync::mpsc::unbounded_channel().0,
+            tokio::sync::mpsc::unbounded_channel().0,
+            reset_tx,
+            crate::closing::ArcCloser::default(),
+        );
+        let reset_token = ResetToken::new_with(&[7; RESET_TOKEN_SIZE]);
+        endpoint
+            .router()
+            .registry(entry)
+            .add_reset_token(reset_token);
+
+        let mut reset = BytesMut::from(&[0x5au8; 40][..]);
+        reset[40 - RESET_TOKEN_SIZE..].copy_from_slice(&reset_token);
+        // 识别出无状态重置后，不会再回应无状态重置
+        assert!(endpoint.recv_datagram(reset, path_id()).is_none());
+        assert!(reset_rx.try_recv().is_ok());
+    }
+
+    #[test]
+    fn test_stateless_reset_over_relay() {
+        let reset_key = ResetTokenKey::new(b"static key");
+        let mut endpoint = Endpiont::new(Arc::new(RandomCidGenerator::new(8)), reset_key.clone());
+        let agent = "10.0.0.9:3478".parse::<SocketAddr>().unwrap();
+        let origin = "1.1.1.1:5000".parse::<SocketAddr>().unwrap();
+        let dcid = ConnectionId::from_slice(&[1; 8]);
+
+        let mut trigger = BytesMut::new();
+        trigger.put_relay_header(&origin);
+        let header_size = trigger.len();
+        trigger.put_bytes(0x40, 100);
+        trigger[header_size + 1..header_size + 9].copy_from_slice(&dcid);
+        let reset = endpoint
+            .recv_relayed_datagram(trigger, "127.0.0.1:443".parse().unwrap(), agent)
+            .unwrap();
+        // 无状态重置经中继代理回给数据报的来源
+        assert_eq!(relay::be_relay_header(&reset), Some((origin, header_size)));
+        assert_eq!(
+            &reset[reset.len() - RESET_TOKEN_SIZE..],
+            &reset_key.derive(&dcid)[..]
+        );
+    }
+}
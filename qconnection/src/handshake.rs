@@ -1,7 +1,69 @@
-use crate::crypto::TlsIO;
-use qbase::packet::keys::{ArcKeys, ArcOneRttKeys};
-use qrecovery::crypto::{CryptoStreamReader, CryptoStreamWriter};
+use crate::{cid::CidManager, crypto::TlsIO, idle::ArcIdleTimer, path::ArcPaths};
+use qbase::{
+    error::Error,
+    frame::{ConnFrame, HandshakeDoneFrame},
+    packet::keys::{ArcKeys, ArcOneRttKeys},
+};
+use qrecovery::{
+    crypto::{CryptoStreamReader, CryptoStreamWriter},
+    space::ArcSpace,
+    streams::ArcDataStreams,
+};
 use rustls::quic::KeyChange;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+use tokio::sync::mpsc;
+
+#[derive(Debug, Default)]
+struct RawHandshakeConfirmed {
+    confirmed: bool,
+    // 等待握手确认的任务
+    wakers: Vec<Waker>,
+}
+
+/// 握手是否已确认。服务端握手完成即确认，客户端收到HANDSHAKE_DONE帧才确认，Ref. RFC 9001 §4.1.2。
+/// 签发令牌、验证首选地址等都要等到握手确认之后
+#[derive(Debug, Clone, Default)]
+pub struct ArcHandshakeConfirmed(Arc<Mutex<RawHandshakeConfirmed>>);
+
+impl ArcHandshakeConfirmed {
+    pub fn confirm(&self) {
+        let mut guard = self.0.lock().unwrap();
+        guard.confirmed = true;
+        for waker in guard.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+
+    pub fn is_confirmed(&self) -> bool {
+        self.0.lock().unwrap().confirmed
+    }
+
+    /// 等待握手确认
+    pub fn confirmed(&self) -> HandshakeConfirmed {
+        HandshakeConfirmed(self.clone())
+    }
+}
+
+pub struct HandshakeConfirmed(ArcHandshakeConfirmed);
+
+impl Future for HandshakeConfirmed {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut guard = self.0 .0.lock().unwrap();
+        if guard.confirmed {
+            Poll::Ready(())
+        } else {
+            guard.wakers.push(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
 
 async fn exchange_hs(
     tls_session: TlsIO,
@@ -33,15 +95,77 @@ pub(crate) async fn exchange_initial_crypto_msg_until_getting_handshake_key(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn exchange_handshake_crypto_msg_until_getting_1rtt_key(
     tls_session: TlsIO,
     one_rtt_keys: ArcOneRttKeys,
+    zero_rtt_keys: ArcKeys,
+    data_space: ArcSpace<ArcDataStreams>,
     handshake_crypto_handler: (CryptoStreamReader, CryptoStreamWriter),
+    cid_manager: CidManager,
+    paths: ArcPaths,
+    idle: ArcIdleTimer,
+    confirmed: ArcHandshakeConfirmed,
+    conn_error_tx: mpsc::UnboundedSender<Error>,
 ) {
-    match exchange_hs(tls_session, handshake_crypto_handler).await {
+    match exchange_hs(tls_session.clone(), handshake_crypto_handler).await {
         Ok(key_change) => match key_change {
             KeyChange::OneRtt { keys, next } => {
                 one_rtt_keys.set_keys(keys, next);
+                // 服务端握手完成即确认，并以HANDSHAKE_DONE帧告知客户端，Ref. RFC 9000 §19.20
+                if tls_session.is_server() {
+                    data_space
+                        .reliable_frame_queue()
+                        .write()
+                        .push_conn_frame(ConnFrame::HandshakeDone(HandshakeDoneFrame));
+                    confirmed.confirm();
+                }
+                // 客户端有了1-RTT密钥就不能再发0-RTT包了，Ref. RFC 9001 §4.9.3。
+                // 服务端的0-RTT密钥则要保留一段时间，以便解密乱序晚到的0-RTT包，握手确认3倍PTO后才丢弃
+                let remembered = tls_session.remembered_parameters();
+                if remembered.is_some() {
+                    zero_rtt_keys.invalid();
+                }
+                // 得知对方的active_connection_id_limit，为对方签发足够多的连接ID；
+                // 服务端还会告知握手期间所用连接ID的无状态重置令牌；对方若禁止主动迁移，我方就不能主动迁移；
+                // 对方也启用了多路径，才能同时使用多条路径；空闲超时取双方max_idle_timeout中较小的那个
+                if let Some(params) = tls_session.peer_transport_parameters() {
+                    // 服务端接受了0-RTT，就不能减小客户端据以发送0-RTT数据的那些限制；
+                    // 拒绝了0-RTT，0-RTT中发送的数据都要在1-RTT中重传，流的状态也要按实际的参数重新核定
+                    if let Some(remembered) = remembered {
+                        let result = if tls_session.is_early_data_accepted() {
+                            params.validate_remembered(&remembered)
+                        } else {
+                            paths.on_0rtt_rejected();
+                            data_space.on_0rtt_rejected(&params)
+                        };
+                        if let Err(err) = result {
+                            let _ = conn_error_tx.send(err);
+                            return;
+                        }
+                    }
+                    idle.set_peer_timeout(params.max_idle_timeout());
+                    cid_manager
+                        .local
+                        .set_limit(params.active_connection_id_limit().into_inner());
+                    if let Some(reset_token) = params.statelss_reset_token() {
+                        cid_manager.remote.set_initial_reset_token(*reset_token);
+                    }
+                    paths.set_peer_disable_active_migration(params.disable_active_migration());
+                    paths.set_peer_enable_multipath(params.enable_multipath());
+                    // 服务端的首选地址，握手确认之后才能发起验证，Ref. RFC 9000 §9.6。
+                    // 验证通过后迁移过去，失败则继续使用原来的地址
+                    if let Some(preferred) = params.preferred_address() {
+                        if cid_manager
+                            .remote
+                            .recv_preferred_address(&preferred)
+                            .is_ok()
+                        {
+                            confirmed.confirmed().await;
+                            let _ = paths.migrate_to_preferred(&preferred);
+                        }
+                    }
+                }
             }
             _ => unreachable!(),
         },
@@ -0,0 +1,388 @@
+use crate::timer::{ArcTimerWheel, TimerKind};
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Waker},
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+#[derive(Debug)]
+struct RawIdleTimer {
+    // 我方的max_idle_timeout，为0表示我方不限制
+    local: Duration,
+    // 对方的max_idle_timeout，握手期间收到对方的传输参数之后才知道
+    peer: Duration,
+    // 保活间隔，空闲了这么久就发一个PING，以免NAT绑定失效
+    keep_alive: Option<Duration>,
+    // 空闲计时的起点，收到数据包、或者此后首次发出ack-eliciting包时重置
+    idle_since: Instant,
+    // 最近一次收到数据包的时间
+    last_rcvd: Instant,
+    // 最近一次发出ack-eliciting包的时间
+    last_ack_eliciting_sent: Instant,
+    // 上次收到数据包之后，是否已经发出过ack-eliciting包
+    ack_eliciting_sent: bool,
+    // 发送1-RTT包的任务，到了保活的时候要唤醒它来发PING
+    sender: Option<Waker>,
+}
+
+impl RawIdleTimer {
+    /// 双方协商的空闲超时，不计PTO的下限
+    fn negotiated(&self) -> Option<Duration> {
+        match (self.local.is_zero(), self.peer.is_zero()) {
+            (true, true) => None,
+            (true, false) => Some(self.peer),
+            (false, true) => Some(self.local),
+            (false, false) => Some(self.local.min(self.peer)),
+        }
+    }
+
+    /// 实际的保活间隔，不超过空闲超时的一半，以免PING还在路上连接就已超时
+    fn keep_alive_interval(&self) -> Option<Duration> {
+        let interval = self.keep_alive?;
+        Some(match self.negotiated() {
+            Some(timeout) => interval.min(timeout / 2),
+            None => interval,
+        })
+    }
+
+    /// 下次该发PING保活的时间，不保活则为None
+    fn keep_alive_deadline(&self) -> Option<Instant> {
+        let interval = self.keep_alive_interval()?;
+        Some(self.last_rcvd.max(self.last_ack_eliciting_sent) + interval)
+    }
+}
+
+/// 连接的空闲计时器，Ref. RFC 9000 §10.1。
+/// 双方max_idle_timeout中较小的那个即为实际的空闲超时，但不得小于3倍PTO，以免丢了几个包就超时；
+/// 收到对方的数据包、或者此后首次发出ack-eliciting包时，重新开始计时。
+/// 超时后连接静默关闭，不发送CONNECTION_CLOSE，直接丢弃连接的状态。
+/// 空闲与保活的截止时间都登记在连接的定时器轮上，时间也从它取。
+#[derive(Debug, Clone)]
+pub struct ArcIdleTimer {
+    raw: Arc<Mutex<RawIdleTimer>>,
+    wheel: ArcTimerWheel,
+    // 空闲超时被改短了，等待超时的任务要重新计算截止时间
+    changed: Arc<Notify>,
+    // 保活间隔或空闲超时变了，保活的任务要重新计算截止时间
+    keep_alive_changed: Arc<Notify>,
+}
+
+impl ArcIdleTimer {
+    pub fn new(local: Duration, wheel: ArcTimerWheel) -> Self {
+        let now = wheel.now();
+        Self {
+            raw: Arc::new(Mutex::new(RawIdleTimer {
+                local,
+                peer: Duration::ZERO,
+                keep_alive: None,
+                idle_since: now,
+                last_rcvd: now,
+                last_ack_eliciting_sent: now,
+                ack_eliciting_sent: false,
+                sender: None,
+            })),
+            wheel,
+            changed: Arc::new(Notify::new()),
+            keep_alive_changed: Arc::new(Notify::new()),
+        }
+    }
+
+    /// 收到了对方的传输参数
+    pub fn set_peer_timeout(&self, peer: Duration) {
+        self.raw.lock().unwrap().peer = peer;
+        self.changed.notify_one();
+        self.keep_alive_changed.notify_one();
+    }
+
+    /// 设置保活间隔，None表示不保活。实际的间隔不超过空闲超时的一半
+    pub fn set_keep_alive(&self, interval: Option<Duration>) {
+        self.raw.lock().unwrap().keep_alive = interval;
+        self.keep_alive_changed.notify_one();
+    }
+
+    /// 发送1-RTT包的任务登记cx的waker，到了保活的时候唤醒它
+    pub fn register_sender(&self, cx: &mut Context<'_>) {
+        let mut guard = self.raw.lock().unwrap();
+        if !guard
+            .sender
+            .as_ref()
+            .is_some_and(|w| w.will_wake(cx.waker()))
+        {
+            guard.sender = Some(cx.waker().clone());
+        }
+    }
+
+    /// 成功处理了对方的数据包
+    pub fn on_rcvd(&self) {
+        let now = self.wheel.now();
+        let mut guard = self.raw.lock().unwrap();
+        guard.idle_since = now;
+        guard.last_rcvd = now;
+        guard.ack_eliciting_sent = false;
+    }
+
+    /// 发出了数据包，只有收包后首个ack-eliciting包才重新开始空闲计时
+    pub fn on_sent(&self, is_ack_eliciting: bool) {
+        if !is_ack_eliciting {
+            return;
+        }
+        let now = self.wheel.now();
+        let mut guard = self.raw.lock().unwrap();
+        guard.last_ack_eliciting_sent = now;
+        if !guard.ack_eliciting_sent {
+            guard.idle_since = now;
+            guard.ack_eliciting_sent = true;
+        }
+    }
+
+    /// 实际生效的空闲超时，双方都不限制时为None
+    pub fn timeout(&self, pto: Duration) -> Option<Duration> {
+        let timeout = self.raw.lock().unwrap().negotiated()?;
+        Some(timeout.max(pto * 3))
+    }
+
+    /// 空闲超时的截止时间
+    pub fn deadline(&self, pto: Duration) -> Option<Instant> {
+        let timeout = self.timeout(pto)?;
+        Some(self.raw.lock().unwrap().idle_since + timeout)
+    }
+
+    /// 是否到了该发PING保活的时候，即有保活间隔这么久既没收到数据包，也没发出ack-eliciting包
+    pub fn need_keep_alive(&self) -> bool {
+        let now = self.wheel.now();
+        let guard = self.raw.lock().unwrap();
+        guard
+            .keep_alive_deadline()
+            .is_some_and(|deadline| now >= deadline)
+    }
+
+    /// 保活，截止时间登记在连接的定时器轮上，到期就唤醒发送任务去发PING。
+    /// 连接空闲时发送任务无事可做，没有这个定时器，它就不会醒来，连接终将空闲超时。
+    /// 不会自行结束，随连接关闭而被丢弃
+    pub async fn keep_alive(&self) {
+        let wheel = &self.wheel;
+        loop {
+            let deadline = self.raw.lock().unwrap().keep_alive_deadline();
+            let Some(deadline) = deadline else {
+                self.keep_alive_changed.notified().await;
+                continue;
+            };
+            let now = wheel.now();
+            let deadline = if now >= deadline {
+                if let Some(waker) = self.raw.lock().unwrap().sender.take() {
+                    waker.wake();
+                }
+                // 发送任务发出PING后截止时间随之后延；迟迟没发出，也要再等一个间隔才再次唤醒
+                let interval = self.raw.lock().unwrap().keep_alive_interval();
+                match interval {
+                    Some(interval) => now + interval,
+                    None => continue,
+                }
+            } else {
+                deadline
+            };
+            wheel.set(TimerKind::KeepAlive, deadline);
+            tokio::select! {
+                _ = wheel.expired(TimerKind::KeepAlive) => {}
+                _ = self.keep_alive_changed.notified() => {}
+            }
+        }
+    }
+
+    /// 等待空闲超时，截止时间登记在连接的定时器轮上。
+    /// pto用于取得当前的PTO，每次重新计算截止时间时都会调用
+    pub async fn expired(&self, pto: impl Fn() -> Duration) {
+        let wheel = &self.wheel;
+        loop {
+            match self.deadline(pto()) {
+                Some(deadline) if wheel.now() >= deadline => return,
+                Some(deadline) => {
+                    // 期间收到了数据包，截止时间会后延，醒来后重新计算即可；
+                    // 空闲超时被改短了，则要立即重新计算
+                    wheel.set(TimerKind::Idle, deadline);
+                    tokio::select! {
+                        _ = wheel.expired(TimerKind::Idle) => {}
+                        _ = self.changed.notified() => {}
+                    }
+                }
+                None => self.changed.notified().await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qbase::clock::{Clock, MockClock};
+
+    // 由手动拨动的时钟驱动的空闲计时器
+    fn mock_idle(local: Duration) -> (ArcIdleTimer, MockClock) {
+        let clock = MockClock::default();
+        let wheel = ArcTimerWheel::new(Arc::new(clock.clone()));
+        (ArcIdleTimer::new(local, wheel), clock)
+    }
+
+    #[test]
+    fn test_timeout() {
+        let pto = Duration::from_millis(100);
+        let (idle, _) = mock_idle(Duration::ZERO);
+        assert_eq!(idle.timeout(pto), None);
+
+        idle.set_peer_timeout(Duration::from_secs(30));
+        assert_eq!(idle.timeout(pto), Some(Duration::from_secs(30)));
+
+        let (idle, _) = mock_idle(Duration::from_secs(10));
+        assert_eq!(idle.timeout(pto), Some(Duration::from_secs(10)));
+        idle.set_peer_timeout(Duration::from_secs(30));
+        assert_eq!(idle.timeout(pto), Some(Duration::from_secs(10)));
+        // 不得小于3倍PTO
+        assert_eq!(
+            idle.timeout(Duration::from_secs(5)),
+            Some(Duration::from_secs(15))
+        );
+    }
+
+    #[test]
+    fn test_idle_deadline() {
+        let pto = Duration::from_millis(100);
+        let (idle, clock) = mock_idle(Duration::from_secs(10));
+        let start = clock.now();
+        assert_eq!(idle.deadline(pto), Some(start + Duration::from_secs(10)));
+
+        // 收包后首个ack-eliciting包重置计时，后续的则不会
+        clock.advance(Duration::from_secs(1));
+        idle.on_sent(false);
+        assert_eq!(idle.deadline(pto), Some(start + Duration::from_secs(10)));
+        idle.on_sent(true);
+        assert_eq!(idle.deadline(pto), Some(start + Duration::from_secs(11)));
+        clock.advance(Duration::from_secs(1));
+        idle.on_sent(true);
+        assert_eq!(idle.deadline(pto), Some(start + Duration::from_secs(11)));
+
+        clock.advance(Duration::from_secs(1));
+        idle.on_rcvd();
+        assert_eq!(idle.deadline(pto), Some(start + Duration::from_secs(13)));
+    }
+
+    #[tokio::test]
+    async fn test_expired_with_mock_clock() {
+        let pto = Duration::from_millis(100);
+        let (idle, clock) = mock_idle(Duration::from_secs(10));
+        let start = clock.now();
+
+        // 截止时间登记在定时器轮上，时钟拨过截止时间之前不会超时
+        let mut driver = Box::pin(idle.wheel.driver());
+        let mut expired = Box::pin(idle.expired(|| pto));
+        assert!(futures::poll!(expired.as_mut()).is_pending());
+        assert_eq!(
+            idle.wheel.deadline(TimerKind::Idle),
+            Some(start + Duration::from_secs(10))
+        );
+        clock.advance(Duration::from_secs(9));
+        assert!(futures::poll!(driver.as_mut()).is_pending());
+        assert!(futures::poll!(expired.as_mut()).is_pending());
+
+        // 拨过了截止时间，驱动定时器轮触发到期
+        clock.advance(Duration::from_secs(1));
+        assert!(futures::poll!(driver.as_mut()).is_pending());
+        assert!(futures::poll!(expired.as_mut()).is_ready());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expired_after_peer_timeout() {
+        let pto = Duration::from_millis(100);
+        let wheel = ArcTimerWheel::default();
+        tokio::spawn(wheel.driver());
+        let start = wheel.now();
+        let idle = ArcIdleTimer::new(Duration::ZERO, wheel.clone());
+        let expired = tokio::spawn({
+            let idle = idle.clone();
+            async move { idle.expired(|| pto).await }
+        });
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(!expired.is_finished());
+
+        idle.set_peer_timeout(Duration::from_secs(5));
+        expired.await.unwrap();
+        assert_eq!(wheel.now(), start + Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let (idle, clock) = mock_idle(Duration::from_secs(30));
+        assert!(!idle.need_keep_alive());
+        clock.advance(Duration::from_secs(10));
+        assert!(!idle.need_keep_alive());
+
+        idle.set_keep_alive(Some(Duration::from_secs(10)));
+        assert!(idle.need_keep_alive());
+        idle.on_sent(true);
+        assert!(!idle.need_keep_alive());
+        clock.advance(Duration::from_secs(5));
+        idle.on_rcvd();
+        clock.advance(Duration::from_secs(9));
+        assert!(!idle.need_keep_alive());
+        clock.advance(Duration::from_secs(1));
+        assert!(idle.need_keep_alive());
+    }
+
+    #[test]
+    fn test_keep_alive_interval() {
+        let (idle, clock) = mock_idle(Duration::from_secs(30));
+        idle.set_keep_alive(Some(Duration::from_secs(60)));
+        // 保活间隔不超过空闲超时的一半
+        clock.advance(Duration::from_secs(14));
+        assert!(!idle.need_keep_alive());
+        clock.advance(Duration::from_secs(1));
+        assert!(idle.need_keep_alive());
+
+        idle.set_peer_timeout(Duration::from_secs(10));
+        idle.on_rcvd();
+        clock.advance(Duration::from_secs(5));
+        assert!(idle.need_keep_alive());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keep_alive_wakes_sender() {
+        use futures::task::{waker, ArcWake};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Default)]
+        struct Counter(AtomicUsize);
+
+        impl ArcWake for Counter {
+            fn wake_by_ref(arc_self: &Arc<Self>) {
+                arc_self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let wheel = ArcTimerWheel::default();
+        tokio::spawn(wheel.driver());
+        let idle = ArcIdleTimer::new(Duration::from_secs(30), wheel);
+        idle.set_keep_alive(Some(Duration::from_secs(10)));
+        let counter = Arc::new(Counter::default());
+        let sender = waker(counter.clone());
+        idle.register_sender(&mut Context::from_waker(&sender));
+
+        tokio::spawn({
+            let idle = idle.clone();
+            async move { idle.keep_alive().await }
+        });
+
+        // 空闲了保活间隔那么久，发送任务被唤醒
+        tokio::time::sleep(Duration::from_millis(9900)).await;
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        // 发送任务发出了PING，下一次保活从此时算起
+        idle.on_sent(true);
+        idle.register_sender(&mut Context::from_waker(&sender));
+        tokio::time::sleep(Duration::from_millis(9800)).await;
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+    }
+}
@@ -1,16 +1,21 @@
+pub mod cid;
+pub mod closing;
 pub mod connection;
 pub mod crypto;
 pub mod endpoint;
+pub mod idle;
 pub mod path;
+pub mod timer;
 
 pub(crate) mod auto;
 pub(crate) mod handshake;
 pub mod transmit;
 
+use path::PathId;
 use qbase::packet::SpacePacket;
 
 pub trait ReceiveProtectedPacket {
-    fn receive_protected_packet(&mut self, protected_packet: SpacePacket);
+    fn receive_protected_packet(&mut self, protected_packet: SpacePacket, path_id: PathId);
 }
 
 #[cfg(test)]
@@ -1,17 +1,164 @@
-use qbase::{cid::ConnectionId, frame::PathFrame, util::ArcAsyncQueue};
-use qcongestion::rtt::Rtt;
+use crate::timer::{ArcTimerWheel, TimerKind};
+use futures::StreamExt;
+use qbase::{
+    cid::ConnectionId,
+    frame::{PathFrame, PathStatus},
+    packet::r#type::{
+        long::{Type::V1, Ver1},
+        Type,
+    },
+    util::ArcAsyncQueue,
+};
+use qcongestion::{
+    congestion::{ArcCC, CongestionAlgorithm, Epoch},
+    rtt::Rtt,
+    ObserveAck, ObserveLoss,
+};
 use std::{
-    net::SocketAddr,
+    fmt,
+    future::{poll_fn, Future},
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
     sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Instant,
 };
+use tokio::sync::mpsc;
+
+pub mod anti_amplifier;
+pub use anti_amplifier::AntiAmplifier;
+
+pub mod paths;
+pub use paths::ArcPaths;
+
+pub mod relay;
+pub use relay::{RelayAgent, RelaySocket};
+
+pub mod scheduler;
+
+pub mod validate;
+pub use validate::{ArcValidator, ValidateState};
+
+/// 携带路径挑战或路径响应的数据报至少要扩充到1200字节，以确认该路径支持QUIC的最小MTU
+pub const MIN_PROBE_SIZE: usize = 1200;
+/// 路径验证最多发起的挑战次数，每次等待一个PTO，总计即3倍PTO
+const MAX_CHALLENGES: u32 = 3;
+
+/// 数据包所属的拥塞控制空间，0-RTT与1-RTT包同属数据空间
+pub fn epoch_of(packet_type: Type) -> Epoch {
+    match packet_type {
+        Type::Long(V1(Ver1::INITIAL)) => Epoch::Initial,
+        Type::Long(V1(Ver1::HANDSHAKE)) => Epoch::Handshake,
+        _ => Epoch::Data,
+    }
+}
+
+/// 拥塞控制器判定的丢包、被确认了的AckFrame，经由这些通道转告相应的空间，
+/// 各通道按Epoch索引，消息为(包号空间, 包号)
+#[derive(Debug, Clone)]
+pub struct SpaceTxs {
+    pub loss: [mpsc::UnboundedSender<(u64, u64)>; Epoch::count()],
+    pub acked: [mpsc::UnboundedSender<(u64, u64)>; Epoch::count()],
+}
+
+/// 拥塞控制器判定的丢包、被确认了的AckFrame，要转告相应的空间。
+/// 只有数据空间才会区分包号空间，Initial、Handshake空间的包号空间总是0
+#[derive(Debug, Default, Clone)]
+pub struct SpaceObserver {
+    // 该路径发包所在的包号空间
+    pn_space: u64,
+    // 该路径收包所在的包号空间
+    rcvd_pn_space: u64,
+    // 没有通道的，比如测试中的路径，丢包与确认无人关心，直接忽略
+    txs: Option<SpaceTxs>,
+}
+
+impl SpaceObserver {
+    pub fn new(txs: SpaceTxs) -> Self {
+        Self {
+            pn_space: 0,
+            rcvd_pn_space: 0,
+            txs: Some(txs),
+        }
+    }
+
+    /// 某条路径专属的观察者，pn_space、rcvd_pn_space分别是该路径发包、收包所在的包号空间
+    pub fn on_path(&self, pn_space: u64, rcvd_pn_space: u64) -> Self {
+        Self {
+            pn_space,
+            rcvd_pn_space,
+            txs: self.txs.clone(),
+        }
+    }
+
+    fn pn_space_of(space: Epoch, pn_space: u64) -> u64 {
+        match space {
+            Epoch::Data => pn_space,
+            _ => 0,
+        }
+    }
+}
+
+impl ObserveLoss for SpaceObserver {
+    fn may_loss_pkt(&self, space: Epoch, pn: u64) {
+        if let Some(txs) = &self.txs {
+            let pn_space = Self::pn_space_of(space, self.pn_space);
+            let _ = txs.loss[space].send((pn_space, pn));
+        }
+    }
+}
+
+impl ObserveAck for SpaceObserver {
+    fn inactivate_rcvd_record(&self, space: Epoch, pn: u64) {
+        if let Some(txs) = &self.txs {
+            let pn_space = Self::pn_space_of(space, self.rcvd_pn_space);
+            let _ = txs.acked[space].send((pn_space, pn));
+        }
+    }
+}
+
+/// 每条路径各有一个拥塞控制器
+pub type PathCC = ArcCC<SpaceObserver, SpaceObserver>;
 
-#[derive(Debug, PartialEq, Eq)]
+/// 驱动一条路径的任务，见[`ArcPath::driver`]
+pub struct PathDriver(Pin<Box<dyn Future<Output = ()> + Send>>);
+
+impl fmt::Debug for PathDriver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PathDriver").finish_non_exhaustive()
+    }
+}
+
+impl Future for PathDriver {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+/// 经中继代理的一端地址，agent是中继代理的地址，target是该端经中继代理所见的地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct RelayAddr {
     agent: SocketAddr,
     target: SocketAddr,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl RelayAddr {
+    pub fn new(agent: SocketAddr, target: SocketAddr) -> Self {
+        Self { agent, target }
+    }
+
+    pub fn agent(&self) -> SocketAddr {
+        self.agent
+    }
+
+    pub fn target(&self) -> SocketAddr {
+        self.target
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PathId {
     Direct {
         local: SocketAddr,
@@ -23,45 +170,340 @@ pub enum PathId {
     },
 }
 
+impl PathId {
+    /// 发往该路径的UDP数据报的目的地址，经中继的路径要发往中继代理
+    pub fn dst(&self) -> SocketAddr {
+        match self {
+            PathId::Direct { remote, .. } => *remote,
+            PathId::Relay { remote, .. } => remote.agent,
+        }
+    }
+
+    /// 发往该路径的QUIC数据报之前，是否需要加上中继头部，有则返回中继代理要转发到的目标地址
+    pub fn relay_target(&self) -> Option<SocketAddr> {
+        match self {
+            PathId::Direct { .. } => None,
+            PathId::Relay { remote, .. } => Some(remote.target),
+        }
+    }
+
+    /// 对方的IP地址，经中继的路径取对方经中继代理所见的地址，地址验证令牌与之绑定
+    pub fn remote_ip(&self) -> IpAddr {
+        match self {
+            PathId::Direct { remote, .. } => remote.ip(),
+            PathId::Relay { remote, .. } => remote.target.ip(),
+        }
+    }
+
+    /// 仅对方的端口变了，多半是NAT重绑定，而非对方主动迁移
+    pub fn is_rebinding(&self, other: &PathId) -> bool {
+        match (self, other) {
+            (
+                PathId::Direct { local, remote },
+                PathId::Direct {
+                    local: other_local,
+                    remote: other_remote,
+                },
+            ) => local == other_local && remote.ip() == other_remote.ip(),
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Path {
     path_id: PathId,
     scid: ConnectionId, // scid.len == 0 表示没有使用连接id
-    dcid: ConnectionId, // dcid.len == 0 表示没有使用连接id
+    // dcid.len == 0 表示没有使用连接id；迁移时换用新的连接ID，故可变
+    dcid: Mutex<ConnectionId>,
 
-    // 待发包队列
+    // 该路径上收到的路径帧，由路径验证器处理
     frames: ArcAsyncQueue<PathFrame>,
-    rtt: Arc<Mutex<Rtt>>,
-    // TODO: 维护PTO、路径是否丢失等状态，还有BBR控制器
+    validator: ArcValidator,
+    // 该路径的拥塞控制器，RTT估计也由它维护
+    cc: PathCC,
+    // 连接的定时器轮，该路径的丢包检测、ACK延迟与路径验证定时器都登记在此
+    timers: ArcTimerWheel,
+    // 多路径下，我方设定的路径状态，以及对方通过PATH_STATUS帧告知的状态及其序号；
+    // 任一方设为Standby，该路径就只作备用
+    local_status: Mutex<PathStatus>,
+    peer_status: Mutex<(PathStatus, Option<u64>)>,
+    // 对方地址验证通过之前，该路径上的收发字节数，发送不得超过收到的3倍
+    amplifier: Mutex<AntiAmplifier>,
+    // TODO: 维护路径是否丢失等状态
     // 可重传的帧队列，因为判定了该path的包，要重传。但也可反馈给SentPacketManager，让其决定是否重传
 }
 
+impl Drop for Path {
+    fn drop(&mut self) {
+        // 结束驱动该路径的任务，并移除该路径的定时器
+        self.frames.close();
+        for kind in [
+            TimerKind::LossDetection(self.path_id),
+            TimerKind::AckDelay(self.path_id),
+            TimerKind::PathValidation(self.path_id),
+        ] {
+            self.timers.remove(kind);
+        }
+    }
+}
+
+/// 把拥塞控制器的定时器同步到定时器轮上：poll_timer轮询定时器的变化，
+/// 到期后调用on_timeout，之后定时器或重设或取消，继续同步即可
+async fn sync_timer(
+    timers: &ArcTimerWheel,
+    kind: TimerKind,
+    poll_timer: impl Fn(&mut Context<'_>, Option<Instant>) -> Poll<Option<Instant>>,
+    on_timeout: impl Fn(),
+) {
+    let mut timer = None;
+    loop {
+        let changed = poll_fn(|cx| poll_timer(cx, timer));
+        let expired = async {
+            match timer {
+                Some(_) => timers.expired(kind).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            changed = changed => {
+                timer = changed;
+                match timer {
+                    Some(deadline) => timers.set(kind, deadline),
+                    None => timers.cancel(kind),
+                }
+            }
+            _ = expired => on_timeout(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ArcPath(Arc<Path>);
 
 impl ArcPath {
-    pub fn new(path_id: PathId, scid: ConnectionId, dcid: ConnectionId) -> Self {
-        Self(Arc::new(Path {
+    pub fn new(
+        path_id: PathId,
+        scid: ConnectionId,
+        dcid: ConnectionId,
+        timers: ArcTimerWheel,
+    ) -> Self {
+        Self::with_observer(
             path_id,
             scid,
             dcid,
+            SpaceObserver::default(),
+            CongestionAlgorithm::default(),
+            timers,
+        )
+    }
+
+    /// 拥塞控制器判定的丢包、被确认了的AckFrame，经observer转告各空间；
+    /// 拥塞控制器使用algorithm指定的拥塞控制算法，其定时器登记在连接的定时器轮timers上，
+    /// 时间也从它取
+    pub fn with_observer(
+        path_id: PathId,
+        scid: ConnectionId,
+        dcid: ConnectionId,
+        observer: SpaceObserver,
+        algorithm: CongestionAlgorithm,
+        timers: ArcTimerWheel,
+    ) -> Self {
+        Self(Arc::new(Path {
+            path_id,
+            scid,
+            dcid: Mutex::new(dcid),
             frames: ArcAsyncQueue::new(),
-            rtt: Arc::new(Mutex::new(Rtt::default())),
+            validator: ArcValidator::default(),
+            cc: ArcCC::new(
+                algorithm,
+                observer.clone(),
+                observer,
+                Arc::new(timers.clone()),
+            ),
+            timers,
+            local_status: Mutex::new(PathStatus::Available),
+            peer_status: Mutex::new((PathStatus::Available, None)),
+            amplifier: Mutex::new(AntiAmplifier::default()),
         }))
     }
 
+    /// 驱动该路径的任务：处理收到的路径帧，以及拥塞控制器的丢包检测、ACK延迟定时器。
+    /// 丢包检测定时器到期，由拥塞控制器判定丢包，或者触发PTO安排发送探测包，
+    /// 否则尾包丢了就再也没有确认到来，连接就此停滞；ACK延迟定时器到期，要唤醒发送任务发出AckFrame。
+    /// 创建路径并不启动任何任务，须由连接的任务spawn它；路径被丢弃，该任务随之结束
+    pub fn driver(&self) -> PathDriver {
+        let mut frames = self.0.frames.clone();
+        let validator = self.0.validator.clone();
+        let cc = self.0.cc.clone();
+        let timers = self.0.timers.clone();
+        let path_id = self.0.path_id;
+        PathDriver(Box::pin(async move {
+            let recv_frames = async {
+                while let Some(frame) = frames.next().await {
+                    validator.recv_path_frame(frame);
+                }
+            };
+            let detect_loss = sync_timer(
+                &timers,
+                TimerKind::LossDetection(path_id),
+                |cx, seen| cc.poll_loss_detection_timer(cx, seen),
+                || cc.on_loss_detection_timeout(),
+            );
+            let delay_ack = sync_timer(
+                &timers,
+                TimerKind::AckDelay(path_id),
+                |cx, seen| cc.poll_ack_timer(cx, seen),
+                || cc.on_ack_timeout(),
+            );
+            tokio::select! {
+                _ = recv_frames => {}
+                _ = detect_loss => {}
+                _ = delay_ack => {}
+            }
+        }))
+    }
+
+    pub fn path_id(&self) -> &PathId {
+        &self.0.path_id
+    }
+
+    pub fn scid(&self) -> ConnectionId {
+        self.0.scid
+    }
+
+    /// 该路径上发包所用的目标连接ID
+    pub fn dcid(&self) -> ConnectionId {
+        *self.0.dcid.lock().unwrap()
+    }
+
+    /// 换用新的目标连接ID，原来的已退役，不可再用
+    fn set_dcid(&self, dcid: ConnectionId) {
+        *self.0.dcid.lock().unwrap() = dcid;
+    }
+
     pub fn rtt(&self) -> Arc<Mutex<Rtt>> {
-        self.0.as_ref().rtt.clone()
+        self.0.cc.rtt()
+    }
+
+    /// 该路径的拥塞控制器，发包前要从它取得发送信用，发包、收包、收到AckFrame都要告知它
+    pub fn cc(&self) -> &PathCC {
+        &self.0.cc
     }
 
     pub fn frames(&self) -> &ArcAsyncQueue<PathFrame> {
         &(self.0.as_ref().frames)
     }
+
+    pub fn status(&self) -> PathStatus {
+        let local = *self.0.local_status.lock().unwrap();
+        let (peer, _) = *self.0.peer_status.lock().unwrap();
+        if local == PathStatus::Standby || peer == PathStatus::Standby {
+            PathStatus::Standby
+        } else {
+            PathStatus::Available
+        }
+    }
+
+    pub fn set_local_status(&self, status: PathStatus) {
+        *self.0.local_status.lock().unwrap() = status;
+    }
+
+    /// 对方告知的路径状态，只有序号更大的才生效，乱序到达的旧状态直接忽略
+    pub fn recv_peer_status(&self, seq: u64, status: PathStatus) {
+        let mut peer_status = self.0.peer_status.lock().unwrap();
+        if peer_status.1.is_none_or(|largest| seq > largest) {
+            *peer_status = (status, Some(seq));
+        }
+    }
+
+    pub fn validate_state(&self) -> ValidateState {
+        self.0.validator.state()
+    }
+
+    /// 握手所在的路径，握手完成即验证了对方地址，无需再发起路径验证
+    pub fn set_validated(&self) {
+        self.0.validator.set_validated();
+        self.grant_anti_amplification();
+    }
+
+    /// 对方地址已验证，比如收到了对方的Handshake包、Initial包中带有有效的Retry令牌，
+    /// 或者该路径是我方主动发起的，解除反放大限制
+    pub fn grant_anti_amplification(&self) {
+        self.0.amplifier.lock().unwrap().grant();
+    }
+
+    /// 收到了该路径上的数据包，计入反放大限制的额度。
+    /// 返回是否因此解除了发送阻塞，是则要重新设置PTO定时器，以免双方都在等待而死锁
+    pub fn on_rcvd(&self, bytes: usize) -> bool {
+        self.0.amplifier.lock().unwrap().on_rcvd(bytes)
+    }
+
+    /// 在该路径上发出了数据包
+    pub fn on_sent(&self, bytes: usize) {
+        self.0.amplifier.lock().unwrap().on_sent(bytes);
+    }
+
+    /// 该路径还能发送的字节数，None表示不受反放大限制。路径验证通过也就验证了对方地址
+    pub fn send_quota(&self) -> Option<usize> {
+        if self.validate_state() == ValidateState::Validated {
+            return None;
+        }
+        self.0.amplifier.lock().unwrap().quota()
+    }
+
+    /// 是否已达反放大限制的上限，此时无包可发，PTO定时器也不应设置
+    pub fn is_amplification_limited(&self) -> bool {
+        self.send_quota() == Some(0)
+    }
+
+    /// 验证该路径，Ref. RFC 9000 §8.2。
+    /// 每个PTO发起一次新的挑战，3倍PTO仍未收到任何响应则验证失败。
+    /// PTO取该路径当前的PTO与初始PTO中的较大者，因为新路径的RTT可能与旧路径相去甚远。
+    pub async fn validate(&self) -> bool {
+        let validator = &self.0.validator;
+        let timers = &self.0.timers;
+        let kind = TimerKind::PathValidation(self.0.path_id);
+        let pto = std::cmp::max(
+            self.rtt().lock().unwrap().pto_base_duration(0),
+            Rtt::default().pto_base_duration(0),
+        );
+        for _ in 0..MAX_CHALLENGES {
+            if validator.state() == ValidateState::Validated {
+                break;
+            }
+            validator.challenge();
+            timers.set(kind, timers.now() + pto);
+            tokio::select! {
+                _ = validator.changed() => {}
+                _ = timers.expired(kind) => {}
+            }
+        }
+        timers.cancel(kind);
+        if validator.state() == ValidateState::Validated {
+            return true;
+        }
+        validator.fail();
+        false
+    }
+
+    /// 是否有待发送的路径帧
+    pub fn has_frames(&self) -> bool {
+        self.0.validator.has_frames()
+    }
+
+    /// 将该路径待发送的路径帧写入buf，返回写入的字节数。
+    /// 写入了路径帧的数据包，所在数据报需填充到[`MIN_PROBE_SIZE`]
+    pub fn read_frames(&self, buf: &mut [u8]) -> usize {
+        self.0.validator.read_frames(buf)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use qbase::cid::ConnectionId;
-    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use super::*;
+    use qbase::frame::PathResponseFrame;
+    use std::net::Ipv4Addr;
 
     #[tokio::test]
     async fn read_initial_packet() {
@@ -73,4 +515,102 @@ mod tests {
 
         // let _packet = path.read_1rtt_packet().await;
     }
+
+    #[test]
+    fn test_new_path_without_runtime() {
+        // 创建路径不启动任何任务，在运行时之外也可以创建
+        let path = ArcPath::new(
+            PathId::Direct {
+                local: "127.0.0.1:8080".parse().unwrap(),
+                remote: "127.0.0.1:8081".parse().unwrap(),
+            },
+            ConnectionId::from_slice(b"local cid"),
+            ConnectionId::from_slice(b"peer cid"),
+            ArcTimerWheel::default(),
+        );
+        assert_eq!(path.validate_state(), ValidateState::Unvalidated);
+    }
+
+    #[test]
+    fn test_space_observer() {
+        let (loss_txs, mut loss_rxs): (Vec<_>, Vec<_>) = (0..Epoch::count())
+            .map(|_| mpsc::unbounded_channel())
+            .unzip();
+        let (acked_txs, mut acked_rxs): (Vec<_>, Vec<_>) = (0..Epoch::count())
+            .map(|_| mpsc::unbounded_channel())
+            .unzip();
+        let observer = SpaceObserver::new(SpaceTxs {
+            loss: loss_txs.try_into().unwrap(),
+            acked: acked_txs.try_into().unwrap(),
+        })
+        .on_path(2, 3);
+
+        // 只有数据空间区分包号空间，丢包按发包所在、确认按收包所在的包号空间转告
+        observer.may_loss_pkt(Epoch::Data, 5);
+        observer.may_loss_pkt(Epoch::Initial, 1);
+        observer.inactivate_rcvd_record(Epoch::Data, 7);
+        observer.inactivate_rcvd_record(Epoch::Handshake, 4);
+        assert_eq!(loss_rxs[2].try_recv(), Ok((2, 5)));
+        assert_eq!(loss_rxs[0].try_recv(), Ok((0, 1)));
+        assert_eq!(acked_rxs[2].try_recv(), Ok((3, 7)));
+        assert_eq!(acked_rxs[1].try_recv(), Ok((0, 4)));
+
+        // 没有通道的观察者，直接忽略
+        SpaceObserver::default().may_loss_pkt(Epoch::Data, 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_path_validation() {
+        let local = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+        let remote = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8081);
+        let timers = ArcTimerWheel::default();
+        tokio::spawn(timers.driver());
+        let path = ArcPath::new(
+            PathId::Direct { local, remote },
+            ConnectionId::from_slice(b"local cid"),
+            ConnectionId::from_slice(b"peer cid"),
+            timers,
+        );
+        tokio::spawn(path.driver());
+
+        let validating = tokio::spawn({
+            let path = path.clone();
+            async move { path.validate().await }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(path.validate_state(), ValidateState::Validating);
+
+        // 对方回应了挑战，路径验证通过
+        let mut buf = [0u8; 64];
+        assert_eq!(path.read_frames(&mut buf), 9);
+        let response = PathResponseFrame::from_slice(&buf[1..9]);
+        path.frames().push(PathFrame::Response(response));
+        assert!(validating.await.unwrap());
+        assert_eq!(path.validate_state(), ValidateState::Validated);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_path_validation_timeout() {
+        let local = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+        let remote = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8081);
+        let timers = ArcTimerWheel::default();
+        tokio::spawn(timers.driver());
+        let path = ArcPath::new(
+            PathId::Direct { local, remote },
+            ConnectionId::from_slice(b"local cid"),
+            ConnectionId::from_slice(b"peer cid"),
+            timers.clone(),
+        );
+
+        // 每个PTO重发一个新的挑战，始终没有回应，3倍PTO后验证失败，定时器随之取消
+        let start = timers.now();
+        assert!(!path.validate().await);
+        assert_eq!(path.validate_state(), ValidateState::Failed);
+        assert_eq!(
+            timers.now(),
+            start + Rtt::default().pto_base_duration(0) * 3
+        );
+        let kind = TimerKind::PathValidation(*path.path_id());
+        assert_eq!(timers.deadline(kind), None);
+    }
 }
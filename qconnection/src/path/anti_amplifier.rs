@@ -0,0 +1,70 @@
+/// 反放大攻击的限制，Ref. RFC 9000 §8。
+/// 对方地址验证通过之前，发往该地址的数据不得超过从该地址收到的数据的3倍，
+/// 以免攻击者伪造源地址，借我方之手向受害者发送大量数据。
+const AMPLIFICATION_FACTOR: usize = 3;
+
+#[derive(Debug, Default)]
+pub struct AntiAmplifier {
+    rcvd: usize,
+    sent: usize,
+    // 对方地址已验证，或者是我方主动发起的路径，不再受限制
+    granted: bool,
+}
+
+impl AntiAmplifier {
+    /// 收到了数据报，返回是否因此解除了发送阻塞
+    pub fn on_rcvd(&mut self, bytes: usize) -> bool {
+        let was_limited = self.is_limited();
+        self.rcvd += bytes;
+        was_limited && !self.is_limited()
+    }
+
+    pub fn on_sent(&mut self, bytes: usize) {
+        self.sent += bytes;
+    }
+
+    pub fn grant(&mut self) {
+        self.granted = true;
+    }
+
+    /// 还能发送的字节数，None表示不受限制
+    pub fn quota(&self) -> Option<usize> {
+        if self.granted {
+            None
+        } else {
+            Some((self.rcvd * AMPLIFICATION_FACTOR).saturating_sub(self.sent))
+        }
+    }
+
+    /// 已达上限，一个字节也不能再发了
+    pub fn is_limited(&self) -> bool {
+        self.quota() == Some(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anti_amplifier() {
+        let mut amplifier = AntiAmplifier::default();
+        assert_eq!(amplifier.quota(), Some(0));
+        assert!(amplifier.is_limited());
+
+        // 收到数据报，解除了阻塞
+        assert!(amplifier.on_rcvd(1200));
+        assert_eq!(amplifier.quota(), Some(3600));
+        amplifier.on_sent(3600);
+        assert!(amplifier.is_limited());
+
+        assert!(amplifier.on_rcvd(100));
+        assert_eq!(amplifier.quota(), Some(300));
+        assert!(!amplifier.on_rcvd(100));
+        assert_eq!(amplifier.quota(), Some(600));
+
+        amplifier.grant();
+        assert_eq!(amplifier.quota(), None);
+        assert!(!amplifier.is_limited());
+    }
+}
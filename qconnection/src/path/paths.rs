@@ -0,0 +1,1075 @@
+use super::{scheduler, ArcPath, PathDriver, PathId, SpaceObserver, ValidateState};
+use crate::{cid::CidManager, timer::ArcTimerWheel};
+use qbase::{
+    cid::ConnectionId,
+    config::PreferredAddress,
+    error::{Error, ErrorKind},
+    frame::{BeFrame, ConnFrame, PathAbandonFrame, PathStatus, PathStatusFrame},
+    util::ArcAsyncQueue,
+    varint::VarInt,
+};
+use qcongestion::{congestion::CongestionAlgorithm, reordering::ReorderingConfig};
+use qrecovery::reliable::ArcReliableFrameQueue;
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug)]
+struct Paths {
+    paths: HashMap<PathId, ArcPath>,
+    // 当前发送非探测包所用的路径
+    active: Option<ArcPath>,
+    // 迁移之前所用的、最近一个已验证的路径，新路径验证失败时退回到它
+    previous: Option<ArcPath>,
+    // 收到的非探测包中最大的包号，只有更大包号的非探测包才能引起迁移，防止乱序的旧包把路径切回去
+    largest_non_probing_pn: Option<u64>,
+    // 我方是否在传输参数中禁止了对方主动迁移
+    disable_active_migration: bool,
+    // 对方是否在传输参数中禁止了我方主动迁移
+    peer_disable_active_migration: bool,
+    // 服务端通告的首选地址，客户端迁移过来不受禁止主动迁移的约束
+    preferred_address: Option<PreferredAddress>,
+    // 双方都在传输参数中启用了多路径，才能同时使用多条路径
+    enable_multipath: bool,
+    peer_enable_multipath: bool,
+    // 多路径下，对方在各路径上发包所用的我方连接ID的序号，对方的PATH_ABANDON、PATH_STATUS帧据此找到路径
+    rcvd_seqs: HashMap<u64, PathId>,
+    // 我方发出的PATH_STATUS帧的序号
+    status_seq: u64,
+    // 被移除的路径的发包空间标识，即其所用对方连接ID的序号，其中在途的数据包要在其它路径上重传
+    abandoned: ArcAsyncQueue<u64>,
+    // 新建路径的驱动任务，由连接的任务取出并spawn，见[`ArcPath::driver`]
+    drivers: ArcAsyncQueue<PathDriver>,
+    // 各路径的拥塞控制器判定的丢包、被确认了的AckFrame，经它转告各空间
+    observer: SpaceObserver,
+    // 握手密钥是否已就绪，此后新建路径的拥塞控制器也要知道
+    has_handshake_keys: bool,
+    // 各路径丢包判定阈值的自适应配置
+    reordering: ReorderingConfig,
+    // 新建路径的拥塞控制器所用的算法
+    algorithm: CongestionAlgorithm,
+    // 连接的定时器轮，新建路径的定时器登记在此，其拥塞控制器也从它取时间
+    timers: ArcTimerWheel,
+    // 凭Initial包中的令牌验证过的对方地址，这样的路径一建立就不受反放大限制
+    validated_addrs: HashSet<PathId>,
+    cid_manager: CidManager,
+    reliable_frame_queue: ArcReliableFrameQueue,
+}
+
+impl Paths {
+    fn is_multipath(&self) -> bool {
+        self.enable_multipath && self.peer_enable_multipath
+    }
+
+    /// 对方发往我方通告的首选地址的路径
+    fn is_to_preferred_address(&self, path_id: &PathId) -> bool {
+        let (Some(preferred), PathId::Direct { local, .. }) = (&self.preferred_address, path_id)
+        else {
+            return false;
+        };
+        match local {
+            SocketAddr::V4(addr) => preferred.address_v4() == Some(*addr),
+            SocketAddr::V6(addr) => preferred.address_v6() == Some(*addr),
+        }
+    }
+
+    /// 以scid收包、以dcid发包的新路径，其拥塞控制器的观察者要知道该路径收发所在的包号空间
+    fn new_path(&self, path_id: PathId, scid: ConnectionId, dcid: ConnectionId) -> ArcPath {
+        let observer = if self.is_multipath() {
+            let pn_space = self.cid_manager.remote.seq_of(&dcid).unwrap_or(0);
+            let rcvd_pn_space = self.cid_manager.local.seq_of(&scid).unwrap_or(0);
+            self.observer.on_path(pn_space, rcvd_pn_space)
+        } else {
+            self.observer.on_path(0, 0)
+        };
+        let path = ArcPath::with_observer(
+            path_id,
+            scid,
+            dcid,
+            observer,
+            self.algorithm,
+            self.timers.clone(),
+        );
+        self.drivers.push(path.driver());
+        if self.has_handshake_keys {
+            path.cc().on_handshake_keys();
+        }
+        path.cc().set_reordering(self.reordering);
+        path
+    }
+
+    fn get_or_create(&mut self, path_id: PathId, scid: ConnectionId) -> Option<ArcPath> {
+        if self.is_multipath() {
+            if let Some(seq) = self.cid_manager.local.seq_of(&scid) {
+                self.rcvd_seqs.insert(seq, path_id);
+            }
+        }
+        if let Some(path) = self.paths.get(&path_id) {
+            return Some(path.clone());
+        }
+
+        // 禁止了主动迁移，对方却换了地址，只能是NAT重绑定导致端口变化，其余情况直接丢包，
+        // 也不能回应无状态重置。但客户端迁移到我方通告的首选地址不在此列，Ref. RFC 9000 §9.6
+        if self.disable_active_migration && !self.is_to_preferred_address(&path_id) {
+            if let Some(active) = &self.active {
+                if !active.path_id().is_rebinding(&path_id) {
+                    return None;
+                }
+            }
+        }
+
+        // 多路径下，对方新开的路径与其余路径并行使用，要用一个专属的连接ID；
+        // 否则新路径上的回应也不能沿用当前的连接ID，以免新旧地址被关联起来，Ref. RFC 9000 §9.5
+        let dcid = match &self.active {
+            Some(_) if self.is_multipath() => self
+                .cid_manager
+                .remote
+                .assign_for_path()
+                .map(|(_, cid)| cid),
+            Some(_) => self.cid_manager.remote.peek_next(),
+            None => None,
+        };
+        let dcid = dcid
+            .or_else(|| self.cid_manager.remote.current())
+            .unwrap_or_default();
+        let path = self.new_path(path_id, scid, dcid);
+        if self.validated_addrs.remove(&path_id) {
+            path.grant_anti_amplification();
+        }
+        self.paths.insert(path_id, path.clone());
+        if self.active.is_none() {
+            // 第一个路径即握手所在的路径
+            self.active = Some(path.clone());
+        }
+        Some(path)
+    }
+
+    /// 对方迁移到了path上，返回是否需要验证该路径
+    fn on_non_probing_pkt(&mut self, path: &ArcPath, pn: u64) -> bool {
+        // 多路径下，对方在新路径上发包是在并行使用多条路径，而非迁移，验证通过后即参与调度。
+        // 各路径的包号空间相互独立，包号之间也无从比较
+        if self.is_multipath() {
+            if self.active.is_none() {
+                self.active = Some(path.clone());
+                return false;
+            }
+            return path.validate_state() == ValidateState::Unvalidated;
+        }
+
+        if self
+            .largest_non_probing_pn
+            .is_some_and(|largest| pn <= largest)
+        {
+            return false;
+        }
+        self.largest_non_probing_pn = Some(pn);
+
+        let Some(active) = &self.active else {
+            self.active = Some(path.clone());
+            return false;
+        };
+        if active.path_id() == path.path_id() {
+            return false;
+        }
+
+        // 仅端口变化，多半是NAT重绑定，路径特性不变，沿用原来的RTT估计；
+        // 否则新路径从初始RTT开始，拥塞控制状态也是新路径独有的，Ref. RFC 9000 §9.4
+        if active.path_id().is_rebinding(path.path_id()) {
+            *path.rtt().lock().unwrap() = active.rtt().lock().unwrap().clone();
+        }
+
+        // 向新地址发包要换用新的连接ID，以免对方的新旧地址被关联起来，Ref. RFC 9000 §9.5。
+        // 没有可用的新连接ID时，只能继续使用当前的
+        if let Some(dcid) = self.cid_manager.remote.switch() {
+            path.set_dcid(dcid);
+        }
+
+        // 对方迁移后，立即改用新路径发包，同时验证新路径
+        self.previous = self.active.replace(path.clone());
+        true
+    }
+
+    /// 我方主动迁移到新的本地地址，先建立新路径，验证通过后再切换
+    fn migrate(&mut self, local: SocketAddr) -> io::Result<ArcPath> {
+        if self.peer_disable_active_migration {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "peer disabled active migration",
+            ));
+        }
+        let Some(PathId::Direct { remote, .. }) = self.active.as_ref().map(|p| *p.path_id()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only direct paths can migrate",
+            ));
+        };
+        self.probe(PathId::Direct { local, remote })
+    }
+
+    /// 客户端迁移到服务端的首选地址，选取与当前路径同一地址族的那个。
+    /// 即便服务端禁止了主动迁移，也可以迁移到首选地址，Ref. RFC 9000 §9.6
+    fn migrate_to_preferred(&mut self, preferred: &PreferredAddress) -> io::Result<ArcPath> {
+        let Some(PathId::Direct { local, .. }) = self.active.as_ref().map(|p| *p.path_id()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only direct paths can migrate",
+            ));
+        };
+        let remote = match local {
+            SocketAddr::V4(_) => preferred.address_v4().map(SocketAddr::V4),
+            SocketAddr::V6(_) => preferred.address_v6().map(SocketAddr::V6),
+        };
+        let Some(remote) = remote else {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                "no preferred address of the same family",
+            ));
+        };
+        self.probe(PathId::Direct { local, remote })
+    }
+
+    fn probe(&mut self, path_id: PathId) -> io::Result<ArcPath> {
+        let Some(active) = &self.active else {
+            return Err(io::ErrorKind::NotConnected.into());
+        };
+        // 新路径不能沿用旧路径的连接ID，否则旧地址与新地址会被关联起来，Ref. RFC 9000 §9.5
+        let Some(dcid) = self.cid_manager.remote.peek_next() else {
+            return Err(io::Error::other(
+                "no unused connection id of the peer to migrate with",
+            ));
+        };
+
+        let path = self.new_path(path_id, active.scid(), dcid);
+        // 我方主动发起的路径，对方地址是已知的，不存在放大攻击
+        path.grant_anti_amplification();
+        self.paths.insert(path_id, path.clone());
+        Ok(path)
+    }
+
+    /// 主动迁移的新路径验证通过，切换过去，原来的路径及其连接ID都退役
+    fn on_migrated(&mut self, path: &ArcPath) {
+        if let Some(dcid) = self.cid_manager.remote.switch() {
+            path.set_dcid(dcid);
+        }
+        if let Some(previous) = self.active.replace(path.clone()) {
+            self.paths.remove(previous.path_id());
+        }
+        self.previous = None;
+    }
+
+    fn on_validated(&mut self, path: &ArcPath) {
+        if let Some(previous) = self.previous.take() {
+            if previous.path_id() != path.path_id() {
+                self.paths.remove(previous.path_id());
+            }
+        }
+    }
+
+    fn on_validation_failed(&mut self, path: &ArcPath) {
+        if self.is_multipath() {
+            self.remove_path(path.path_id());
+            return;
+        }
+        self.paths.remove(path.path_id());
+        let is_active = self
+            .active
+            .as_ref()
+            .is_some_and(|active| active.path_id() == path.path_id());
+        if is_active {
+            // 退回到迁移前最近一个已验证的路径，Ref. RFC 9000 §9.3.2。
+            // 其原来的连接ID在迁移时已退役，再换一个；没有新的，只能沿用当前的
+            if let Some(previous) = self.previous.take() {
+                let dcid = self
+                    .cid_manager
+                    .remote
+                    .switch()
+                    .or_else(|| self.cid_manager.remote.current());
+                if let Some(dcid) = dcid {
+                    previous.set_dcid(dcid);
+                }
+                self.active = Some(previous);
+            }
+        }
+    }
+
+    /// 多路径下移除一条路径，退役其专属的连接ID，通知其发包空间中在途的数据包要在其它路径上重传。
+    /// 返回该路径的标识，即其所用对方连接ID的序号
+    fn remove_path(&mut self, path_id: &PathId) -> Option<u64> {
+        let path = self.paths.remove(path_id)?;
+        self.rcvd_seqs.retain(|_, p| p != path_id);
+        let is_active = self
+            .active
+            .as_ref()
+            .is_some_and(|active| active.path_id() == path_id);
+        if is_active {
+            self.active = scheduler::min_rtt(self.paths.values(), |_| true);
+        }
+
+        let seq = self.cid_manager.remote.seq_of(&path.dcid())?;
+        // 没有可用的新连接ID时，路径只能与其它路径共用连接ID，也就共用包号空间，不能放弃
+        if !self.paths.values().any(|p| p.dcid() == path.dcid()) {
+            self.cid_manager.remote.release_path_cid(seq);
+            self.abandoned.push(seq);
+        }
+        Some(seq)
+    }
+
+    /// 多路径下，新开一条与现有路径并行的路径，比如同时使用Wi-Fi与蜂窝网络
+    fn add_path(&mut self, path_id: PathId) -> io::Result<ArcPath> {
+        if !self.is_multipath() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "multipath is not negotiated",
+            ));
+        }
+        if self.paths.contains_key(&path_id) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        let Some(active) = &self.active else {
+            return Err(io::ErrorKind::NotConnected.into());
+        };
+        let Some((_, dcid)) = self.cid_manager.remote.assign_for_path() else {
+            return Err(io::Error::other(
+                "no unused connection id of the peer for the new path",
+            ));
+        };
+        let path = self.new_path(path_id, active.scid(), dcid);
+        // 我方主动发起的路径，对方地址是已知的，不存在放大攻击
+        path.grant_anti_amplification();
+        self.paths.insert(path_id, path.clone());
+        Ok(path)
+    }
+
+    fn abandon(&mut self, path_id: &PathId, error_code: VarInt) -> io::Result<()> {
+        if !self.is_multipath() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "multipath is not negotiated",
+            ));
+        }
+        if !self.paths.contains_key(path_id) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        if self.paths.len() == 1 {
+            return Err(io::Error::other("cannot abandon the last path"));
+        }
+        if let Some(seq) = self.remove_path(path_id) {
+            self.reliable_frame_queue
+                .write()
+                .push_conn_frame(ConnFrame::PathAbandon(PathAbandonFrame {
+                    path_id: VarInt(seq),
+                    error_code,
+                }));
+        }
+        Ok(())
+    }
+
+    fn set_status(&mut self, path_id: &PathId, status: PathStatus) -> io::Result<()> {
+        if !self.is_multipath() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "multipath is not negotiated",
+            ));
+        }
+        let Some(path) = self.paths.get(path_id) else {
+            return Err(io::ErrorKind::NotFound.into());
+        };
+        path.set_local_status(status);
+        if let Some(seq) = self.cid_manager.remote.seq_of(&path.dcid()) {
+            self.status_seq += 1;
+            self.reliable_frame_queue
+                .write()
+                .push_conn_frame(ConnFrame::PathStatus(PathStatusFrame {
+                    path_id: VarInt(seq),
+                    seq: VarInt(self.status_seq),
+                    status,
+                }));
+        }
+        Ok(())
+    }
+
+    /// 对方的路径帧以其发包所用连接ID的序号标识路径，即我方连接ID的序号
+    fn check_path_frame(&self, frame: &impl BeFrame) -> Result<(), Error> {
+        if self.is_multipath() {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::ProtocolViolation,
+                frame.frame_type(),
+                "multipath is not negotiated",
+            ))
+        }
+    }
+
+    fn recv_path_abandon(&mut self, frame: &PathAbandonFrame) -> Result<(), Error> {
+        self.check_path_frame(frame)?;
+        if let Some(path_id) = self.rcvd_seqs.get(&frame.path_id.into_inner()).copied() {
+            self.remove_path(&path_id);
+        }
+        Ok(())
+    }
+
+    fn recv_path_status(&mut self, frame: &PathStatusFrame) -> Result<(), Error> {
+        self.check_path_frame(frame)?;
+        let path = self
+            .rcvd_seqs
+            .get(&frame.path_id.into_inner())
+            .and_then(|path_id| self.paths.get(path_id));
+        if let Some(path) = path {
+            path.recv_peer_status(frame.seq.into_inner(), frame.status);
+        }
+        Ok(())
+    }
+}
+
+/// 连接的所有路径。收到的数据包按其4元组找到所属路径，没有则新建；
+/// 对方在新路径上发来更大包号的非探测包，视为连接迁移或NAT重绑定，改用新路径并验证之。
+#[derive(Debug, Clone)]
+pub struct ArcPaths(Arc<Mutex<Paths>>);
+
+impl ArcPaths {
+    /// 多路径的PATH_ABANDON、PATH_STATUS帧要写入reliable_frame_queue发送
+    pub fn new(
+        cid_manager: CidManager,
+        reliable_frame_queue: ArcReliableFrameQueue,
+        timers: ArcTimerWheel,
+    ) -> Self {
+        Self(Arc::new(Mutex::new(Paths {
+            paths: HashMap::new(),
+            active: None,
+            previous: None,
+            largest_non_probing_pn: None,
+            disable_active_migration: false,
+            peer_disable_active_migration: false,
+            preferred_address: None,
+            enable_multipath: false,
+            peer_enable_multipath: false,
+            rcvd_seqs: HashMap::new(),
+            status_seq: 0,
+            abandoned: ArcAsyncQueue::new(),
+            drivers: ArcAsyncQueue::new(),
+            observer: SpaceObserver::default(),
+            has_handshake_keys: false,
+            reordering: ReorderingConfig::default(),
+            algorithm: CongestionAlgorithm::default(),
+            timers,
+            validated_addrs: HashSet::new(),
+            cid_manager,
+            reliable_frame_queue,
+        })))
+    }
+
+    /// 此后新建的路径，其拥塞控制器判定的丢包、被确认了的AckFrame都经observer转告各空间
+    pub fn set_space_observer(&self, observer: SpaceObserver) {
+        self.0.lock().unwrap().observer = observer;
+    }
+
+    /// 端点凭Initial包中的令牌验证了对方在path_id上的地址，该路径不受反放大限制，
+    /// 路径尚未建立的，建立时再解除
+    pub fn on_address_validated(&self, path_id: PathId) {
+        let mut paths = self.0.lock().unwrap();
+        match paths.paths.get(&path_id) {
+            Some(path) => path.grant_anti_amplification(),
+            None => {
+                paths.validated_addrs.insert(path_id);
+            }
+        }
+    }
+
+    /// 握手密钥已就绪，各路径的拥塞控制器据此决定PTO的探测包该用哪个空间发送
+    pub fn on_handshake_keys(&self) {
+        let mut paths = self.0.lock().unwrap();
+        paths.has_handshake_keys = true;
+        for path in paths.paths.values() {
+            path.cc().on_handshake_keys();
+        }
+    }
+
+    /// 0-RTT被拒绝，各路径的拥塞控制器作废其中在途的0-RTT包，不算丢包
+    pub fn on_0rtt_rejected(&self) {
+        for path in self.0.lock().unwrap().paths.values() {
+            path.cc().on_0rtt_rejected();
+        }
+    }
+
+    /// 设置该连接各路径丢包判定阈值的自适应配置，已有的路径也随之更新
+    pub fn set_reordering(&self, config: ReorderingConfig) {
+        let mut paths = self.0.lock().unwrap();
+        paths.reordering = config;
+        for path in paths.paths.values() {
+            path.cc().set_reordering(config);
+        }
+    }
+
+    /// 选定该连接的拥塞控制算法，须在建立路径之前设置，已有路径的拥塞控制器不受影响
+    pub fn set_congestion_algorithm(&self, algorithm: CongestionAlgorithm) {
+        self.0.lock().unwrap().algorithm = algorithm;
+    }
+
+    pub fn set_disable_active_migration(&self, disable: bool) {
+        self.0.lock().unwrap().disable_active_migration = disable;
+    }
+
+    /// 服务端通告了首选地址，客户端握手确认后可能迁移过来
+    pub fn set_preferred_address(&self, preferred: PreferredAddress) {
+        self.0.lock().unwrap().preferred_address = Some(preferred);
+    }
+
+    pub fn set_peer_disable_active_migration(&self, disable: bool) {
+        self.0.lock().unwrap().peer_disable_active_migration = disable;
+    }
+
+    pub fn set_enable_multipath(&self, enable: bool) {
+        self.0.lock().unwrap().enable_multipath = enable;
+    }
+
+    pub fn set_peer_enable_multipath(&self, enable: bool) {
+        self.0.lock().unwrap().peer_enable_multipath = enable;
+    }
+
+    pub fn is_multipath(&self) -> bool {
+        self.0.lock().unwrap().is_multipath()
+    }
+
+    /// 被移除的路径，其发包空间的标识会写入该队列，数据空间要放弃这些发包空间，重传其中在途的数据包
+    pub fn abandoned(&self) -> ArcAsyncQueue<u64> {
+        self.0.lock().unwrap().abandoned.clone()
+    }
+
+    /// 新建路径的驱动任务，连接须不断取出并spawn之，路径才能处理路径帧、检测丢包
+    pub fn drivers(&self) -> ArcAsyncQueue<PathDriver> {
+        self.0.lock().unwrap().drivers.clone()
+    }
+
+    /// 选出下一个数据包要走的路径，can_send判断路径能否发包，比如拥塞窗口是否已满。
+    /// 未启用多路径时只用当前路径；启用后，在当前路径与已验证的路径中按最小RTT调度
+    pub fn select(&self, can_send: impl Fn(&ArcPath) -> bool) -> Option<ArcPath> {
+        let paths = self.0.lock().unwrap();
+        if !paths.is_multipath() {
+            return paths.active.clone().filter(|path| can_send(path));
+        }
+        let active = paths.active.as_ref().map(|path| *path.path_id());
+        scheduler::min_rtt(
+            paths.paths.values().filter(|path| {
+                Some(*path.path_id()) == active || path.validate_state() == ValidateState::Validated
+            }),
+            can_send,
+        )
+    }
+
+    /// 在path上发包所用的包号空间，未启用多路径时只有默认的包号空间0
+    pub fn pn_space(&self, path: &ArcPath) -> u64 {
+        let paths = self.0.lock().unwrap();
+        if !paths.is_multipath() {
+            return 0;
+        }
+        paths.cid_manager.remote.seq_of(&path.dcid()).unwrap_or(0)
+    }
+
+    /// 确认pn_space中数据包的AckFrame，要交给在该包号空间发包的路径的拥塞控制器。
+    /// 未启用多路径时只有默认的包号空间0，交给收到AckFrame的路径rcvd_on即可
+    pub fn acked_path(&self, pn_space: u64, rcvd_on: &ArcPath) -> Option<ArcPath> {
+        let paths = self.0.lock().unwrap();
+        if !paths.is_multipath() {
+            return Some(rcvd_on.clone());
+        }
+        paths
+            .paths
+            .values()
+            .find(|path| paths.cid_manager.remote.seq_of(&path.dcid()).unwrap_or(0) == pn_space)
+            .cloned()
+    }
+
+    /// 收到的数据包所在的包号空间，dcid是数据包中的目标连接ID
+    pub fn rcvd_pn_space(&self, dcid: &ConnectionId) -> u64 {
+        let paths = self.0.lock().unwrap();
+        if !paths.is_multipath() {
+            return 0;
+        }
+        paths.cid_manager.local.seq_of(dcid).unwrap_or(0)
+    }
+
+    /// 多路径下，新开一条到remote的路径，验证通过后参与调度，验证失败则移除
+    pub fn add_path(&self, local: SocketAddr, remote: SocketAddr) -> io::Result<()> {
+        let path = self
+            .0
+            .lock()
+            .unwrap()
+            .add_path(PathId::Direct { local, remote })?;
+        tokio::spawn({
+            let paths = self.clone();
+            async move {
+                if !path.validate().await {
+                    paths.0.lock().unwrap().remove_path(path.path_id());
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// 放弃一条路径并告知对方，其上在途的数据包改在其它路径上重传。不能放弃最后一条路径
+    pub fn abandon(&self, path_id: &PathId, error_code: VarInt) -> io::Result<()> {
+        self.0.lock().unwrap().abandon(path_id, error_code)
+    }
+
+    /// 设定路径状态并告知对方，Standby的路径仅在没有Available的路径时才使用
+    pub fn set_status(&self, path_id: &PathId, status: PathStatus) -> io::Result<()> {
+        self.0.lock().unwrap().set_status(path_id, status)
+    }
+
+    pub fn recv_path_abandon(&self, frame: &PathAbandonFrame) -> Result<(), Error> {
+        self.0.lock().unwrap().recv_path_abandon(frame)
+    }
+
+    pub fn recv_path_status(&self, frame: &PathStatusFrame) -> Result<(), Error> {
+        self.0.lock().unwrap().recv_path_status(frame)
+    }
+
+    /// 找到数据包所属的路径，没有则新建，scid是数据包的目标连接ID。
+    /// 返回None表示该路径不被允许，数据包应丢弃
+    pub fn get_or_create(&self, path_id: PathId, scid: ConnectionId) -> Option<ArcPath> {
+        self.0.lock().unwrap().get_or_create(path_id, scid)
+    }
+
+    pub fn active(&self) -> Option<ArcPath> {
+        self.0.lock().unwrap().active.clone()
+    }
+
+    /// 主动迁移到新的本地地址，新路径验证通过后才改用它发包；验证失败则继续使用原来的路径
+    pub fn migrate(&self, local: SocketAddr) -> io::Result<()> {
+        let path = self.0.lock().unwrap().migrate(local)?;
+        self.switch_after_validated(path);
+        Ok(())
+    }
+
+    /// 握手之后，客户端验证服务端的首选地址，通过则迁移过去，否则继续使用原来的地址
+    pub fn migrate_to_preferred(&self, preferred: &PreferredAddress) -> io::Result<()> {
+        let path = self.0.lock().unwrap().migrate_to_preferred(preferred)?;
+        self.switch_after_validated(path);
+        Ok(())
+    }
+
+    fn switch_after_validated(&self, path: ArcPath) {
+        tokio::spawn({
+            let paths = self.clone();
+            async move {
+                if path.validate().await {
+                    paths.0.lock().unwrap().on_migrated(&path);
+                } else {
+                    paths.0.lock().unwrap().paths.remove(path.path_id());
+                }
+            }
+        });
+    }
+
+    /// 在path上收到了包号为pn的非探测包，若是对方迁移了，改用该路径并发起验证
+    pub fn on_non_probing_pkt(&self, path: &ArcPath, pn: u64) {
+        let need_validate = self.0.lock().unwrap().on_non_probing_pkt(path, pn);
+        if need_validate {
+            tokio::spawn({
+                let paths = self.clone();
+                let path = path.clone();
+                async move {
+                    if path.validate().await {
+                        paths.0.lock().unwrap().on_validated(&path);
+                    } else {
+                        paths.0.lock().unwrap().on_validation_failed(&path);
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cid::CidManager, connection::ArcPacketEntry, endpoint::ArcRouter, path::ValidateState,
+    };
+    use futures::StreamExt;
+    use qbase::{
+        cid::{RandomCidGenerator, ResetToken, ResetTokenKey},
+        frame::{NewConnectionIdFrame, PathFrame, PathResponseFrame, ReliableFrame},
+        varint::VarInt,
+    };
+    use qrecovery::reliable::ArcReliableFrameQueue;
+    use std::net::SocketAddr;
+    use tokio::sync::mpsc;
+
+    fn cid_manager() -> CidManager {
+        let entry = ArcPacketEntry::new(
+            mpsc::unbounded_channel().0,
+            mpsc::unbounded_channel().0,
+            mpsc::unbounded_channel().0,
+            mpsc::unbounded_channel().0,
+            mpsc::unbounded_channel().0,
+            crate::closing::ArcCloser::default(),
+        );
+        CidManager::new(
+            ConnectionId::from_slice(&[1; 8]),
+            ConnectionId::from_slice(&[2; 8]),
+            Arc::new(RandomCidGenerator::new(8)),
+            ResetTokenKey::new(b"static key"),
+            2,
+            ArcReliableFrameQueue::default(),
+            ArcRouter::default().registry(entry),
+        )
+    }
+
+    // 与连接中一样，spawn定时器轮的驱动任务
+    fn timers() -> ArcTimerWheel {
+        let timers = ArcTimerWheel::default();
+        tokio::spawn(timers.driver());
+        timers
+    }
+
+    // 与连接中一样，spawn新建路径的驱动任务
+    fn spawn_drivers(paths: &ArcPaths) {
+        let mut drivers = paths.drivers();
+        tokio::spawn(async move {
+            while let Some(driver) = drivers.next().await {
+                tokio::spawn(driver);
+            }
+        });
+    }
+
+    fn direct(local: &str, remote: &str) -> PathId {
+        PathId::Direct {
+            local: local.parse::<SocketAddr>().unwrap(),
+            remote: remote.parse::<SocketAddr>().unwrap(),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_peer_migration() {
+        let cid_manager = cid_manager();
+        let new_cid = NewConnectionIdFrame {
+            sequence: VarInt(1),
+            retire_prior_to: VarInt(0),
+            id: ConnectionId::from_slice(&[3; 8]),
+            reset_token: ResetToken::new_with(&[3; 16]),
+        };
+        cid_manager.remote.recv_new_cid_frame(&new_cid).unwrap();
+
+        let paths = ArcPaths::new(
+            cid_manager.clone(),
+            ArcReliableFrameQueue::default(),
+            timers(),
+        );
+        let scid = ConnectionId::from_slice(&[1; 8]);
+        let old = paths
+            .get_or_create(direct("10.0.0.1:443", "1.1.1.1:5000"), scid)
+            .unwrap();
+        old.rtt().lock().unwrap().smoothed_rtt = std::time::Duration::from_millis(10);
+        paths.on_non_probing_pkt(&old, 0);
+
+        // NAT重绑定，只有端口变了，沿用原来的RTT估计，并换用新的目标连接ID
+        let new = paths
+            .get_or_create(direct("10.0.0.1:443", "1.1.1.1:6000"), scid)
+            .unwrap();
+        assert_eq!(new.dcid(), new_cid.id);
+        paths.on_non_probing_pkt(&new, 1);
+        assert_eq!(paths.active().unwrap().path_id(), new.path_id());
+        assert_eq!(new.rtt().lock().unwrap().smoothed_rtt.as_millis(), 10);
+        assert_eq!(cid_manager.remote.current(), Some(new_cid.id));
+        assert_eq!(new.dcid(), new_cid.id);
+
+        // 乱序到达的旧包不会把路径切回去
+        paths.on_non_probing_pkt(&old, 0);
+        assert_eq!(paths.active().unwrap().path_id(), new.path_id());
+
+        // 新路径验证失败，退回原来的路径
+        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+        assert_eq!(new.validate_state(), ValidateState::Failed);
+        assert_eq!(paths.active().unwrap().path_id(), old.path_id());
+        // 原来的连接ID已退役，退回的路径不能再用它
+        assert_eq!(old.dcid(), new_cid.id);
+    }
+
+    #[tokio::test]
+    async fn test_disable_active_migration() {
+        let paths = ArcPaths::new(cid_manager(), ArcReliableFrameQueue::default(), timers());
+        paths.set_disable_active_migration(true);
+        let scid = ConnectionId::from_slice(&[1; 8]);
+        let _ = paths.get_or_create(direct("10.0.0.1:443", "1.1.1.1:5000"), scid);
+
+        // 仍允许NAT重绑定，但换了IP地址的包直接丢弃
+        assert!(paths
+            .get_or_create(direct("10.0.0.1:443", "1.1.1.1:6000"), scid)
+            .is_some());
+        assert!(paths
+            .get_or_create(direct("10.0.0.1:443", "2.2.2.2:5000"), scid)
+            .is_none());
+
+        // 客户端迁移到我方通告的首选地址，不受禁止主动迁移的约束
+        paths.set_preferred_address(PreferredAddress::new(
+            Some("10.0.0.2:443".parse().unwrap()),
+            None,
+            ConnectionId::from_slice(&[2; 8]),
+            ResetToken::new_with(&[2; 16]),
+        ));
+        assert!(paths
+            .get_or_create(direct("10.0.0.2:443", "2.2.2.2:5000"), scid)
+            .is_some());
+        assert!(paths
+            .get_or_create(direct("10.0.0.3:443", "2.2.2.2:5000"), scid)
+            .is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_active_migration() {
+        let cid_manager = cid_manager();
+        let paths = ArcPaths::new(
+            cid_manager.clone(),
+            ArcReliableFrameQueue::default(),
+            timers(),
+        );
+        spawn_drivers(&paths);
+        let scid = ConnectionId::from_slice(&[1; 8]);
+        let old = paths
+            .get_or_create(direct("10.0.0.1:443", "1.1.1.1:5000"), scid)
+            .unwrap();
+        let new_local = "10.0.0.2:443".parse::<SocketAddr>().unwrap();
+
+        // 对方尚未提供新的连接ID，无法迁移
+        assert!(paths.migrate(new_local).is_err());
+
+        let new_cid = NewConnectionIdFrame {
+            sequence: VarInt(1),
+            retire_prior_to: VarInt(0),
+            id: ConnectionId::from_slice(&[3; 8]),
+            reset_token: ResetToken::new_with(&[3; 16]),
+        };
+        cid_manager.remote.recv_new_cid_frame(&new_cid).unwrap();
+        paths.migrate(new_local).unwrap();
+        let new = paths
+            .get_or_create(direct("10.0.0.2:443", "1.1.1.1:5000"), scid)
+            .unwrap();
+        // 验证通过之前，仍用原来的路径和连接ID发包，新路径上的探测则用新的连接ID
+        assert_eq!(new.dcid(), new_cid.id);
+        assert_eq!(paths.active().unwrap().path_id(), old.path_id());
+        assert_eq!(cid_manager.remote.current(), Some(old.dcid()));
+
+        tokio::task::yield_now().await;
+        let mut buf = [0u8; 64];
+        assert_eq!(new.read_frames(&mut buf), 9);
+        let response = PathResponseFrame::from_slice(&buf[1..9]);
+        new.frames().push(PathFrame::Response(response));
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        assert_eq!(paths.active().unwrap().path_id(), new.path_id());
+        assert_eq!(cid_manager.remote.current(), Some(new_cid.id));
+        assert_eq!(new.dcid(), new_cid.id);
+    }
+
+    #[tokio::test]
+    async fn test_peer_disable_active_migration() {
+        let paths = ArcPaths::new(cid_manager(), ArcReliableFrameQueue::default(), timers());
+        let scid = ConnectionId::from_slice(&[1; 8]);
+        let _ = paths.get_or_create(direct("10.0.0.1:443", "1.1.1.1:5000"), scid);
+        paths.set_peer_disable_active_migration(true);
+        let err = paths.migrate("10.0.0.2:443".parse().unwrap()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_migrate_to_preferred_address() {
+        let cid_manager = cid_manager();
+        let paths = ArcPaths::new(
+            cid_manager.clone(),
+            ArcReliableFrameQueue::default(),
+            timers(),
+        );
+        let scid = ConnectionId::from_slice(&[1; 8]);
+        let _ = paths.get_or_create(direct("10.0.0.1:5000", "1.1.1.1:443"), scid);
+        // 服务端禁止了主动迁移，也不影响迁移到首选地址
+        paths.set_peer_disable_active_migration(true);
+
+        let preferred = PreferredAddress::new(
+            Some("2.2.2.2:443".parse().unwrap()),
+            None,
+            ConnectionId::from_slice(&[3; 8]),
+            ResetToken::new_with(&[3; 16]),
+        );
+        cid_manager
+            .remote
+            .recv_preferred_address(&preferred)
+            .unwrap();
+        paths.migrate_to_preferred(&preferred).unwrap();
+        let new = paths
+            .get_or_create(direct("10.0.0.1:5000", "2.2.2.2:443"), scid)
+            .unwrap();
+        assert_eq!(new.dcid(), preferred.connection_id());
+
+        // 首选地址验证失败，继续使用原来的地址
+        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+        assert_eq!(new.validate_state(), ValidateState::Failed);
+        assert_eq!(
+            paths.active().unwrap().path_id(),
+            &direct("10.0.0.1:5000", "1.1.1.1:443")
+        );
+        assert_eq!(
+            cid_manager.remote.current(),
+            Some(ConnectionId::from_slice(&[2; 8]))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_multipath() {
+        let cid_manager = cid_manager();
+        let frames = ArcReliableFrameQueue::default();
+        let paths = ArcPaths::new(cid_manager.clone(), frames.clone(), timers());
+        spawn_drivers(&paths);
+        let scid = ConnectionId::from_slice(&[1; 8]);
+        let wifi = paths
+            .get_or_create(direct("10.0.0.1:5000", "1.1.1.1:443"), scid)
+            .unwrap();
+        let cellular_local = "192.168.0.1:5000".parse::<SocketAddr>().unwrap();
+        let server = "1.1.1.1:443".parse::<SocketAddr>().unwrap();
+
+        // 未协商多路径，不能新开并行的路径
+        let err = paths.add_path(cellular_local, server).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        paths.set_enable_multipath(true);
+        paths.set_peer_enable_multipath(true);
+        let new_cid = NewConnectionIdFrame {
+            sequence: VarInt(1),
+            retire_prior_to: VarInt(0),
+            id: ConnectionId::from_slice(&[3; 8]),
+            reset_token: ResetToken::new_with(&[3; 16]),
+        };
+        cid_manager.remote.recv_new_cid_frame(&new_cid).unwrap();
+        paths.add_path(cellular_local, server).unwrap();
+        let cellular = paths
+            .get_or_create(direct("192.168.0.1:5000", "1.1.1.1:443"), scid)
+            .unwrap();
+        // 新路径用专属的连接ID，与原路径并行，当前连接ID不变；各自的包号空间以连接ID的序号标识
+        assert_eq!(cellular.dcid(), new_cid.id);
+        assert_eq!(cid_manager.remote.current(), Some(wifi.dcid()));
+        assert_eq!(paths.pn_space(&wifi), 0);
+        assert_eq!(paths.pn_space(&cellular), 1);
+
+        // 验证通过之前，新路径不参与调度
+        wifi.rtt().lock().unwrap().smoothed_rtt = std::time::Duration::from_millis(20);
+        cellular.rtt().lock().unwrap().smoothed_rtt = std::time::Duration::from_millis(10);
+        assert_eq!(paths.select(|_| true).unwrap().path_id(), wifi.path_id());
+        tokio::task::yield_now().await;
+        let mut buf = [0u8; 64];
+        assert_eq!(cellular.read_frames(&mut buf), 9);
+        let response = PathResponseFrame::from_slice(&buf[1..9]);
+        cellular.frames().push(PathFrame::Response(response));
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        assert_eq!(
+            paths.select(|_| true).unwrap().path_id(),
+            cellular.path_id()
+        );
+
+        // 将蜂窝网络设为备用，并告知对方
+        paths
+            .set_status(cellular.path_id(), PathStatus::Standby)
+            .unwrap();
+        assert_eq!(paths.select(|_| true).unwrap().path_id(), wifi.path_id());
+        assert_eq!(
+            frames.read().pop_front(),
+            Some(ReliableFrame::Conn(ConnFrame::PathStatus(
+                PathStatusFrame {
+                    path_id: VarInt(1),
+                    seq: VarInt(1),
+                    status: PathStatus::Standby,
+                }
+            )))
+        );
+
+        // Wi-Fi断了，放弃之，立即转移到备用的蜂窝网络，其上在途的数据包要重传
+        paths.abandon(wifi.path_id(), VarInt(0)).unwrap();
+        assert_eq!(
+            paths.select(|_| true).unwrap().path_id(),
+            cellular.path_id()
+        );
+        assert_eq!(paths.active().unwrap().path_id(), cellular.path_id());
+        assert_eq!(
+            frames.read().pop_front(),
+            Some(ReliableFrame::Conn(ConnFrame::PathAbandon(
+                PathAbandonFrame {
+                    path_id: VarInt(0),
+                    error_code: VarInt(0),
+                }
+            )))
+        );
+        assert_eq!(paths.abandoned().next().await, Some(0));
+
+        // 最后一条路径不能放弃
+        assert!(paths.abandon(cellular.path_id(), VarInt(0)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_recv_path_frames() {
+        let paths = ArcPaths::new(cid_manager(), ArcReliableFrameQueue::default(), timers());
+        let scid = ConnectionId::from_slice(&[1; 8]);
+        let status = PathStatusFrame {
+            path_id: VarInt(0),
+            seq: VarInt(1),
+            status: PathStatus::Standby,
+        };
+        // 未协商多路径却收到多路径的帧，是协议错误
+        let err = paths.recv_path_status(&status).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ProtocolViolation);
+
+        paths.set_enable_multipath(true);
+        paths.set_peer_enable_multipath(true);
+        let first = paths
+            .get_or_create(direct("10.0.0.1:443", "1.1.1.1:5000"), scid)
+            .unwrap();
+        let second = paths
+            .get_or_create(direct("10.0.0.1:443", "2.2.2.2:5000"), scid)
+            .unwrap();
+        // 对方在新路径上发来非探测包，并非迁移，验证新路径即可，当前路径不变
+        paths.on_non_probing_pkt(&first, 10);
+        paths.on_non_probing_pkt(&second, 0);
+        assert_eq!(paths.active().unwrap().path_id(), first.path_id());
+
+        // 对方以其发包所用连接ID的序号，即我方连接ID的序号，标识路径
+        paths.recv_path_status(&status).unwrap();
+        assert_eq!(second.status(), PathStatus::Standby);
+        paths
+            .recv_path_abandon(&PathAbandonFrame {
+                path_id: VarInt(0),
+                error_code: VarInt(0),
+            })
+            .unwrap();
+        assert_eq!(paths.active().unwrap().path_id(), first.path_id());
+    }
+
+    #[tokio::test]
+    async fn test_address_validated_by_token() {
+        let paths = ArcPaths::new(cid_manager(), ArcReliableFrameQueue::default(), timers());
+        let scid = ConnectionId::from_slice(&[1; 8]);
+
+        // 没有令牌的客户端，发送不得超过收到的3倍
+        let unvalidated = paths
+            .get_or_create(direct("10.0.0.1:443", "1.1.1.1:5000"), scid)
+            .unwrap();
+        unvalidated.on_rcvd(1200);
+        assert_eq!(unvalidated.send_quota(), Some(3600));
+        unvalidated.on_sent(3600);
+        assert!(unvalidated.is_amplification_limited());
+
+        // 端点凭Initial包中的有效令牌验证了客户端的地址，路径建立时即解除反放大限制
+        let path_id = direct("10.0.0.1:443", "2.2.2.2:5000");
+        paths.on_address_validated(path_id);
+        let validated = paths.get_or_create(path_id, scid).unwrap();
+        validated.on_rcvd(1200);
+        validated.on_sent(3600);
+        assert_eq!(validated.send_quota(), None);
+        validated.on_sent(10000);
+        assert!(!validated.is_amplification_limited());
+
+        // 已建立的路径，也随即解除限制
+        paths.on_address_validated(*unvalidated.path_id());
+        assert_eq!(unvalidated.send_quota(), None);
+    }
+}
@@ -0,0 +1,318 @@
+// 经中继代理收发的QUIC数据报，前面都要加上中继头部：
+// Relay Header {
+//   Family (8) = 4 | 6,
+//   Address (32 | 128),
+//   Port (16),
+// }
+// 发往中继代理时，头部是要转发到的目标地址；中继代理转发出去时，将头部改写为数据报的来源地址，
+// 接收方据此得知对方经中继代理后的地址。
+// 中继代理只在获得授权的对端之间转发，否则任何人都能借它向任意地址发送数据报。
+
+use super::{PathId, RelayAddr};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::{
+    collections::HashSet,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+};
+use tokio::net::UdpSocket;
+
+/// 中继头部的最大长度：1字节地址族 + 16字节IPv6地址 + 2字节端口
+pub const MAX_RELAY_HEADER_SIZE: usize = 1 + 16 + 2;
+
+const FAMILY_V4: u8 = 4;
+const FAMILY_V6: u8 = 6;
+
+/// 中继头部的长度，取决于其中地址的地址族
+pub fn relay_header_size(addr: &SocketAddr) -> usize {
+    match addr {
+        SocketAddr::V4(_) => 1 + 4 + 2,
+        SocketAddr::V6(_) => MAX_RELAY_HEADER_SIZE,
+    }
+}
+
+pub trait WriteRelayHeader: BufMut {
+    fn put_relay_header(&mut self, addr: &SocketAddr);
+}
+
+impl<T: BufMut> WriteRelayHeader for T {
+    fn put_relay_header(&mut self, addr: &SocketAddr) {
+        match addr.ip() {
+            IpAddr::V4(ip) => {
+                self.put_u8(FAMILY_V4);
+                self.put_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                self.put_u8(FAMILY_V6);
+                self.put_slice(&ip.octets());
+            }
+        }
+        self.put_u16(addr.port());
+    }
+}
+
+/// 解析中继头部，返回其中的地址与头部长度，格式不对则返回None
+pub fn be_relay_header(mut input: &[u8]) -> Option<(SocketAddr, usize)> {
+    let origin = input.len();
+    let ip = match *input.first()? {
+        FAMILY_V4 if input.len() >= 1 + 4 + 2 => {
+            input.advance(1);
+            let mut octets = [0u8; 4];
+            input.copy_to_slice(&mut octets);
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        FAMILY_V6 if input.len() >= MAX_RELAY_HEADER_SIZE => {
+            input.advance(1);
+            let mut octets = [0u8; 16];
+            input.copy_to_slice(&mut octets);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    let port = input.get_u16();
+    Some((SocketAddr::new(ip, port), origin - input.len()))
+}
+
+/// 从中继代理agent处收到了数据报，local是收包的本地地址。
+/// 剥离中继头部，返回QUIC数据报及其所属的中继路径
+pub fn decapsulate(
+    mut datagram: BytesMut,
+    local: SocketAddr,
+    agent: SocketAddr,
+) -> Option<(BytesMut, PathId)> {
+    let (origin, header_size) = be_relay_header(&datagram)?;
+    datagram.advance(header_size);
+    let path_id = PathId::Relay {
+        local: RelayAddr::new(agent, local),
+        remote: RelayAddr::new(agent, origin),
+    };
+    Some((datagram, path_id))
+}
+
+/// 要发往path_id的QUIC数据报，经中继的路径要加上中继头部。
+/// 返回UDP数据报实际要发往的地址，以及加上头部后的数据报
+pub fn encapsulate(datagram: Bytes, path_id: &PathId) -> (SocketAddr, Bytes) {
+    let Some(target) = path_id.relay_target() else {
+        return (path_id.dst(), datagram);
+    };
+    let mut relayed = BytesMut::with_capacity(relay_header_size(&target) + datagram.len());
+    relayed.put_relay_header(&target);
+    relayed.put_slice(&datagram);
+    (path_id.dst(), relayed.freeze())
+}
+
+/// 收发QUIC数据报的UDP套接字，发往经中继路径的数据报自动加上中继头部，
+/// 从中继代理agents处收到的数据报自动剥离中继头部，得到其所属的中继路径
+#[derive(Debug)]
+pub struct RelaySocket {
+    socket: UdpSocket,
+    agents: HashSet<SocketAddr>,
+}
+
+impl RelaySocket {
+    pub fn new(socket: UdpSocket, agents: impl IntoIterator<Item = SocketAddr>) -> Self {
+        Self {
+            socket,
+            agents: agents.into_iter().collect(),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// 在path_id上发送一个QUIC数据报，返回发出的QUIC数据报长度，不含中继头部
+    pub async fn send_to(&self, datagram: Bytes, path_id: &PathId) -> io::Result<usize> {
+        let len = datagram.len();
+        let (dst, datagram) = encapsulate(datagram, path_id);
+        self.socket.send_to(&datagram, dst).await?;
+        Ok(len)
+    }
+
+    /// 收到一个QUIC数据报及其所属路径，来自中继代理但中继头部格式不对的数据报直接丢弃
+    pub async fn recv_from(&self) -> io::Result<(BytesMut, PathId)> {
+        let local = self.socket.local_addr()?;
+        let mut buf = vec![0u8; u16::MAX as usize];
+        loop {
+            let (len, from) = self.socket.recv_from(&mut buf).await?;
+            let datagram = BytesMut::from(&buf[..len]);
+            if !self.agents.contains(&from) {
+                let path_id = PathId::Direct {
+                    local,
+                    remote: from,
+                };
+                return Ok((datagram, path_id));
+            }
+            if let Some(relayed) = decapsulate(datagram, local, from) {
+                return Ok(relayed);
+            }
+        }
+    }
+}
+
+/// 中继代理，替处于对称NAT之后、无法直连的两端转发QUIC数据报。
+/// 中继代理无需理解QUIC，只按中继头部转发，并将头部改写为来源地址。
+/// 来源与目标都须是获得授权的对端，中继代理才转发，以免被当作开放中继滥用
+#[derive(Debug)]
+pub struct RelayAgent {
+    socket: UdpSocket,
+    authorized: Mutex<HashSet<IpAddr>>,
+}
+
+impl RelayAgent {
+    pub async fn bind(
+        addr: SocketAddr,
+        authorized: impl IntoIterator<Item = IpAddr>,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind(addr).await?,
+            authorized: Mutex::new(authorized.into_iter().collect()),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// 授权对端经此中继代理收发，对称NAT之后端口不定，故只按IP授权
+    pub fn authorize(&self, peer: IpAddr) {
+        self.authorized.lock().unwrap().insert(peer);
+    }
+
+    /// 撤销对端的授权，此后与之相关的数据报都不再转发
+    pub fn revoke(&self, peer: IpAddr) {
+        self.authorized.lock().unwrap().remove(&peer);
+    }
+
+    fn is_authorized(&self, from: &SocketAddr, target: &SocketAddr) -> bool {
+        let authorized = self.authorized.lock().unwrap();
+        authorized.contains(&from.ip()) && authorized.contains(&target.ip())
+    }
+
+    /// 持续转发，头部格式不对、或来源与目标未获授权的数据报直接丢弃
+    pub async fn run(&self) -> io::Result<()> {
+        let mut buf = vec![0u8; u16::MAX as usize];
+        let mut forward = BytesMut::with_capacity(u16::MAX as usize);
+        loop {
+            let (len, from) = self.socket.recv_from(&mut buf).await?;
+            let Some((target, header_size)) = be_relay_header(&buf[..len]) else {
+                continue;
+            };
+            if !self.is_authorized(&from, &target) {
+                continue;
+            }
+            forward.clear();
+            forward.put_relay_header(&from);
+            forward.put_slice(&buf[header_size..len]);
+            // 目标不可达等错误只影响这一个数据报，不影响中继代理继续工作
+            let _ = self.socket.send_to(&forward, target).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_relay_header() {
+        for addr in ["1.2.3.4:443", "[::1]:8443"] {
+            let addr = addr.parse::<SocketAddr>().unwrap();
+            let mut buf = BytesMut::new();
+            buf.put_relay_header(&addr);
+            assert_eq!(buf.len(), relay_header_size(&addr));
+            assert_eq!(be_relay_header(&buf), Some((addr, buf.len())));
+        }
+        assert_eq!(be_relay_header(&[FAMILY_V4, 1, 2, 3]), None);
+        assert_eq!(be_relay_header(&[0x40; 20]), None);
+    }
+
+    #[tokio::test]
+    async fn test_relay_agent() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let agent = RelayAgent::bind("127.0.0.1:0".parse().unwrap(), [localhost])
+            .await
+            .unwrap();
+        let agent_addr = agent.local_addr().unwrap();
+        tokio::spawn(async move { agent.run().await });
+
+        let a = RelaySocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), [agent_addr]);
+        let b = RelaySocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), [agent_addr]);
+        let a_addr = a.local_addr().unwrap();
+        let b_addr = b.local_addr().unwrap();
+
+        // a经中继代理发往b的路径，由a这端加上中继头部
+        let path_id = PathId::Relay {
+            local: RelayAddr::new(agent_addr, a_addr),
+            remote: RelayAddr::new(agent_addr, b_addr),
+        };
+        let datagram = Bytes::from_static(b"quic datagram");
+        assert_eq!(a.send_to(datagram, &path_id).await.unwrap(), 13);
+
+        let (datagram, path_id) = b.recv_from().await.unwrap();
+        assert_eq!(&datagram[..], b"quic datagram");
+        assert_eq!(
+            path_id,
+            PathId::Relay {
+                local: RelayAddr::new(agent_addr, b_addr),
+                remote: RelayAddr::new(agent_addr, a_addr),
+            }
+        );
+        assert_eq!(path_id.dst(), agent_addr);
+
+        // 直连路径上的数据报原样收发
+        let direct = PathId::Direct {
+            local: b_addr,
+            remote: a_addr,
+        };
+        b.send_to(Bytes::from_static(b"direct"), &direct)
+            .await
+            .unwrap();
+        let (datagram, path_id) = a.recv_from().await.unwrap();
+        assert_eq!(&datagram[..], b"direct");
+        assert_eq!(
+            path_id,
+            PathId::Direct {
+                local: a_addr,
+                remote: b_addr,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_relay_agent_unauthorized() {
+        let agent = RelayAgent::bind("127.0.0.1:0".parse().unwrap(), [])
+            .await
+            .unwrap();
+        let agent = std::sync::Arc::new(agent);
+        let agent_addr = agent.local_addr().unwrap();
+        tokio::spawn({
+            let agent = agent.clone();
+            async move { agent.run().await }
+        });
+
+        let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut datagram = BytesMut::new();
+        datagram.put_relay_header(&b.local_addr().unwrap());
+        datagram.put_slice(b"quic datagram");
+
+        // 未获授权，中继代理不转发
+        let mut buf = [0u8; 64];
+        a.send_to(&datagram, agent_addr).await.unwrap();
+        let recv = tokio::time::timeout(Duration::from_millis(200), b.recv_from(&mut buf));
+        assert!(recv.await.is_err());
+
+        // 授权之后才转发
+        agent.authorize(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        a.send_to(&datagram, agent_addr).await.unwrap();
+        let (len, from) = b.recv_from(&mut buf).await.unwrap();
+        assert_eq!(from, agent_addr);
+        assert_eq!(
+            &buf[relay_header_size(&a.local_addr().unwrap())..len],
+            b"quic datagram"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use super::ArcPath;
+use qbase::frame::PathStatus;
+
+/// 多路径的最小RTT调度：在可发包的路径中，优先选择Available的路径中平滑RTT最小的那条；
+/// 其拥塞窗口用尽之后，can_send将其排除，数据包便溢出到RTT次小的路径上，从而聚合各路径的带宽。
+/// 只有没有可用的Available路径时，才使用Standby的路径，某条路径失效后即刻转移到其余路径上。
+pub fn min_rtt<'a>(
+    paths: impl IntoIterator<Item = &'a ArcPath>,
+    can_send: impl Fn(&ArcPath) -> bool,
+) -> Option<ArcPath> {
+    paths
+        .into_iter()
+        .filter(|path| can_send(path))
+        .min_by_key(|path| {
+            (
+                path.status() == PathStatus::Standby,
+                path.rtt().lock().unwrap().smoothed_rtt,
+            )
+        })
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::PathId;
+    use qbase::cid::ConnectionId;
+    use std::time::Duration;
+
+    fn path(remote: &str, rtt_ms: u64) -> ArcPath {
+        let path = ArcPath::new(
+            PathId::Direct {
+                local: "10.0.0.1:443".parse().unwrap(),
+                remote: remote.parse().unwrap(),
+            },
+            ConnectionId::from_slice(&[1; 8]),
+            ConnectionId::from_slice(&[2; 8]),
+            Default::default(),
+        );
+        path.rtt().lock().unwrap().smoothed_rtt = Duration::from_millis(rtt_ms);
+        path
+    }
+
+    #[tokio::test]
+    async fn test_min_rtt() {
+        let wifi = path("1.1.1.1:443", 20);
+        let cellular = path("2.2.2.2:443", 60);
+        let paths = [wifi.clone(), cellular.clone()];
+
+        let selected = min_rtt(&paths, |_| true).unwrap();
+        assert_eq!(selected.path_id(), wifi.path_id());
+
+        // Wi-Fi的拥塞窗口用尽，溢出到蜂窝网络
+        let selected = min_rtt(&paths, |p| p.path_id() != wifi.path_id()).unwrap();
+        assert_eq!(selected.path_id(), cellular.path_id());
+
+        // 对方将Wi-Fi设为备用，只要蜂窝网络可用就不再走Wi-Fi
+        wifi.recv_peer_status(1, PathStatus::Standby);
+        let selected = min_rtt(&paths, |_| true).unwrap();
+        assert_eq!(selected.path_id(), cellular.path_id());
+        // 乱序到达的旧状态不生效
+        wifi.recv_peer_status(0, PathStatus::Available);
+        assert_eq!(wifi.status(), PathStatus::Standby);
+
+        // 蜂窝网络也不可发包时，备用的Wi-Fi顶上
+        let selected = min_rtt(&paths, |p| p.path_id() != cellular.path_id()).unwrap();
+        assert_eq!(selected.path_id(), wifi.path_id());
+        assert!(min_rtt(&paths, |_| false).is_none());
+    }
+}
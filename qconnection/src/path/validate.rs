@@ -0,0 +1,167 @@
+use bytes::BufMut;
+use qbase::frame::{io::WriteFrame, BeFrame, PathChallengeFrame, PathFrame, PathResponseFrame};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::Notify;
+
+/// 路径验证的状态，Ref. RFC 9000 §8.2
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValidateState {
+    #[default]
+    Unvalidated,
+    Validating,
+    Validated,
+    Failed,
+}
+
+#[derive(Debug, Default)]
+struct Validator {
+    state: ValidateState,
+    // 已发出、尚未被响应的挑战。重传的挑战使用新的随机数，响应其中任何一个都算验证通过
+    challenges: Vec<PathChallengeFrame>,
+    // 待在该路径上发送的路径帧，包括我方的挑战，以及对对方挑战的响应
+    pending: VecDeque<PathFrame>,
+}
+
+/// 路径验证器，每条路径一个。
+/// 路径帧不进入空间的可靠帧队列：挑战丢了由验证器按PTO重新发起，响应丢了由对方重新挑战，
+/// 且响应必须在收到挑战的路径上发送。
+#[derive(Debug, Clone, Default)]
+pub struct ArcValidator {
+    raw: Arc<Mutex<Validator>>,
+    notify: Arc<Notify>,
+}
+
+impl ArcValidator {
+    pub fn state(&self) -> ValidateState {
+        self.raw.lock().unwrap().state
+    }
+
+    /// 发起一次新的挑战，挑战数据随机生成
+    pub fn challenge(&self) {
+        let mut guard = self.raw.lock().unwrap();
+        if guard.state == ValidateState::Validated {
+            return;
+        }
+        let challenge = PathChallengeFrame::random();
+        guard.state = ValidateState::Validating;
+        guard.challenges.push(challenge);
+        guard.pending.push_back(PathFrame::Challenge(challenge));
+    }
+
+    /// 路径无需验证，比如握手所在的路径，握手完成即证明了对方地址可达
+    pub fn set_validated(&self) {
+        let mut guard = self.raw.lock().unwrap();
+        guard.state = ValidateState::Validated;
+        guard.challenges.clear();
+        guard
+            .pending
+            .retain(|f| matches!(f, PathFrame::Response(_)));
+        self.notify.notify_one();
+    }
+
+    /// 超时仍未收到响应，路径验证失败
+    pub fn fail(&self) {
+        let mut guard = self.raw.lock().unwrap();
+        if guard.state == ValidateState::Validating {
+            guard.state = ValidateState::Failed;
+            guard.challenges.clear();
+            guard
+                .pending
+                .retain(|f| matches!(f, PathFrame::Response(_)));
+        }
+    }
+
+    pub fn recv_path_frame(&self, frame: PathFrame) {
+        let mut guard = self.raw.lock().unwrap();
+        match frame {
+            // 无论该路径是否验证过，都要回应对方的挑战
+            PathFrame::Challenge(challenge) => {
+                let response = PathResponseFrame::from_slice(&challenge.data);
+                guard.pending.push_back(PathFrame::Response(response));
+            }
+            // 与任何挑战都对不上的响应直接忽略
+            PathFrame::Response(response) => {
+                if guard.state == ValidateState::Validating
+                    && guard.challenges.iter().any(|c| c.data == response.data)
+                {
+                    guard.state = ValidateState::Validated;
+                    guard.challenges.clear();
+                    guard
+                        .pending
+                        .retain(|f| matches!(f, PathFrame::Response(_)));
+                    self.notify.notify_one();
+                }
+            }
+        }
+    }
+
+    /// 等待下一次状态变化：验证通过，或者在别处被标记为已验证
+    pub async fn changed(&self) {
+        self.notify.notified().await
+    }
+
+    pub fn has_frames(&self) -> bool {
+        !self.raw.lock().unwrap().pending.is_empty()
+    }
+
+    /// 将待发送的路径帧写入buf，返回写入的字节数
+    pub fn read_frames(&self, mut buf: &mut [u8]) -> usize {
+        let origin = buf.remaining_mut();
+        let mut guard = self.raw.lock().unwrap();
+        while let Some(frame) = guard.pending.front() {
+            if buf.remaining_mut() < frame.max_encoding_size() {
+                break;
+            }
+            buf.put_frame(frame);
+            guard.pending.pop_front();
+        }
+        origin - buf.remaining_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_path() {
+        let validator = ArcValidator::default();
+        assert_eq!(validator.state(), ValidateState::Unvalidated);
+
+        validator.challenge();
+        validator.challenge();
+        assert_eq!(validator.state(), ValidateState::Validating);
+        let challenges = validator.raw.lock().unwrap().challenges.clone();
+        assert_eq!(challenges.len(), 2);
+
+        // 对不上的响应被忽略
+        validator.recv_path_frame(PathFrame::Response(PathResponseFrame::from_slice(&[0; 8])));
+        assert_eq!(validator.state(), ValidateState::Validating);
+
+        // 响应较早的挑战也算验证通过
+        let response = PathResponseFrame::from_slice(&challenges[0].data);
+        validator.recv_path_frame(PathFrame::Response(response));
+        assert_eq!(validator.state(), ValidateState::Validated);
+        // 验证通过后，尚未发出的挑战不必再发
+        assert!(validator.raw.lock().unwrap().pending.is_empty());
+    }
+
+    #[test]
+    fn test_response_to_challenge() {
+        let validator = ArcValidator::default();
+        let challenge = PathChallengeFrame::random();
+        validator.recv_path_frame(PathFrame::Challenge(challenge));
+
+        let mut buf = [0u8; 32];
+        let len = validator.read_frames(&mut buf);
+        assert_eq!(len, 9);
+        assert_eq!(buf[0], 0x1b);
+        assert_eq!(&buf[1..9], &challenge.data);
+        assert_eq!(validator.read_frames(&mut buf), 0);
+        // 回应对方的挑战不改变我方的验证状态
+        assert_eq!(validator.state(), ValidateState::Unvalidated);
+    }
+}
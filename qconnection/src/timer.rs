@@ -0,0 +1,300 @@
+use crate::path::PathId;
+use qbase::clock::Clock;
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Instant,
+};
+
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// 定时器所依赖的时间源，抽象出来以便替换tokio，或者在测试中控制时间
+pub trait TimeSource: Clock {
+    fn sleep_until(&self, deadline: Instant) -> Sleep;
+}
+
+/// 基于tokio时间的时间源，tokio的时间暂停时它也随之暂停
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokioTime;
+
+impl Clock for TokioTime {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+}
+
+impl TimeSource for TokioTime {
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+}
+
+/// 测试中手动拨动的时钟，睡眠不会自行醒来，拨动时间之后由测试主动检查是否到期
+#[cfg(test)]
+impl TimeSource for qbase::clock::MockClock {
+    fn sleep_until(&self, _deadline: Instant) -> Sleep {
+        Box::pin(std::future::pending())
+    }
+}
+
+/// 连接中需要定时的各个组件。丢包检测、ACK延迟与路径验证是每条路径各有一个，
+/// 随路径创建而登记，路径销毁时取消
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimerKind {
+    LossDetection(PathId),
+    AckDelay(PathId),
+    PathValidation(PathId),
+    Idle,
+    // 空闲了保活间隔那么久，唤醒发送任务发PING
+    KeepAlive,
+    // 密钥更新后淘汰旧的1-RTT密钥
+    KeyDiscard,
+    // 服务端丢弃0-RTT密钥
+    ZeroRttKeyDiscard,
+    Draining,
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    deadline: Option<Instant>,
+    fired: bool,
+    waker: Option<Waker>,
+}
+
+#[derive(Debug)]
+struct RawTimerWheel {
+    time: Arc<dyn TimeSource>,
+    slots: HashMap<TimerKind, Slot>,
+    // 驱动任务正在等待的最早截止时间，新设的截止时间更早时要唤醒它重新计算
+    sleeping_until: Option<Instant>,
+    driver_waker: Option<Waker>,
+    closed: bool,
+}
+
+impl RawTimerWheel {
+    /// 触发所有到期的定时器，返回尚未到期的最早截止时间
+    fn fire_expired(&mut self, now: Instant) -> Option<Instant> {
+        let mut earliest: Option<Instant> = None;
+        for slot in self.slots.values_mut() {
+            match slot.deadline {
+                Some(deadline) if deadline <= now => {
+                    slot.deadline = None;
+                    slot.fired = true;
+                    if let Some(waker) = slot.waker.take() {
+                        waker.wake();
+                    }
+                }
+                Some(deadline) => {
+                    earliest = Some(earliest.map_or(deadline, |e| e.min(deadline)));
+                }
+                None => {}
+            }
+        }
+        earliest
+    }
+}
+
+/// 连接的定时器轮，连接中所有需要定时的组件都在此登记截止时间，
+/// 由一个驱动任务睡到最早的截止时间，到期后唤醒相应的组件。
+/// 这样一个连接只需一个睡眠中的Future，而不必为每个定时器各起一个任务。
+#[derive(Debug, Clone)]
+pub struct ArcTimerWheel(Arc<Mutex<RawTimerWheel>>);
+
+impl Default for ArcTimerWheel {
+    fn default() -> Self {
+        Self::new(Arc::new(TokioTime))
+    }
+}
+
+impl ArcTimerWheel {
+    pub fn new(time: Arc<dyn TimeSource>) -> Self {
+        Self(Arc::new(Mutex::new(RawTimerWheel {
+            time,
+            slots: HashMap::new(),
+            sleeping_until: None,
+            driver_waker: None,
+            closed: false,
+        })))
+    }
+
+    pub fn now(&self) -> Instant {
+        self.0.lock().unwrap().time.now()
+    }
+
+    /// 取消kind的定时器，并移除其记录，用于随路径销毁的定时器
+    pub fn remove(&self, kind: TimerKind) {
+        self.0.lock().unwrap().slots.remove(&kind);
+    }
+
+    /// 设置或者重设kind的截止时间，此前已触发而未被取走的到期也一并作废
+    pub fn set(&self, kind: TimerKind, deadline: Instant) {
+        let mut guard = self.0.lock().unwrap();
+        let slot = guard.slots.entry(kind).or_default();
+        slot.deadline = Some(deadline);
+        slot.fired = false;
+        if guard.sleeping_until.is_none_or(|t| deadline < t) {
+            if let Some(waker) = guard.driver_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    pub fn cancel(&self, kind: TimerKind) {
+        let mut guard = self.0.lock().unwrap();
+        if let Some(slot) = guard.slots.get_mut(&kind) {
+            slot.deadline = None;
+            slot.fired = false;
+        }
+    }
+
+    pub fn deadline(&self, kind: TimerKind) -> Option<Instant> {
+        let guard = self.0.lock().unwrap();
+        guard.slots.get(&kind).and_then(|slot| slot.deadline)
+    }
+
+    /// 等待kind的定时器到期，每次到期只唤醒一次
+    pub fn expired(&self, kind: TimerKind) -> Expired {
+        Expired {
+            wheel: self.clone(),
+            kind,
+        }
+    }
+
+    /// 驱动定时器轮的Future，须由连接spawn出去，关闭后结束
+    pub fn driver(&self) -> Driver {
+        Driver {
+            wheel: self.clone(),
+            sleep: None,
+        }
+    }
+
+    /// 连接终结，驱动任务随之退出，所有等待中的组件都不会再被唤醒
+    pub fn close(&self) {
+        let mut guard = self.0.lock().unwrap();
+        guard.closed = true;
+        guard.slots.clear();
+        if let Some(waker) = guard.driver_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// 各组件从定时器轮取时间，与定时器用同一个时间源
+impl Clock for ArcTimerWheel {
+    fn now(&self) -> Instant {
+        ArcTimerWheel::now(self)
+    }
+}
+
+pub struct Expired {
+    wheel: ArcTimerWheel,
+    kind: TimerKind,
+}
+
+impl Future for Expired {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut guard = self.wheel.0.lock().unwrap();
+        let slot = guard.slots.entry(self.kind).or_default();
+        if slot.fired {
+            slot.fired = false;
+            Poll::Ready(())
+        } else {
+            slot.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+pub struct Driver {
+    wheel: ArcTimerWheel,
+    // 当前睡眠的截止时间，及其睡眠Future
+    sleep: Option<(Instant, Sleep)>,
+}
+
+impl Future for Driver {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let wheel = self.wheel.clone();
+        let mut guard = wheel.0.lock().unwrap();
+        loop {
+            if guard.closed {
+                return Poll::Ready(());
+            }
+            let now = guard.time.now();
+            let Some(earliest) = guard.fire_expired(now) else {
+                self.sleep = None;
+                guard.sleeping_until = None;
+                guard.driver_waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            };
+            if self.sleep.as_ref().map(|(t, _)| *t) != Some(earliest) {
+                self.sleep = Some((earliest, guard.time.sleep_until(earliest)));
+            }
+            guard.sleeping_until = Some(earliest);
+            guard.driver_waker = Some(cx.waker().clone());
+            let (_, sleep) = self.sleep.as_mut().unwrap();
+            match sleep.as_mut().poll(cx) {
+                // 睡到了截止时间，回头触发到期的定时器
+                Poll::Ready(()) => self.sleep = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timer_wheel() {
+        let wheel = ArcTimerWheel::default();
+        let driver = tokio::spawn(wheel.driver());
+        let start = wheel.now();
+
+        wheel.set(TimerKind::Idle, start + Duration::from_secs(10));
+        wheel.set(TimerKind::KeyDiscard, start + Duration::from_millis(25));
+        wheel.set(TimerKind::Draining, start + Duration::from_secs(1));
+        wheel.cancel(TimerKind::Draining);
+
+        wheel.expired(TimerKind::KeyDiscard).await;
+        assert_eq!(wheel.now(), start + Duration::from_millis(25));
+        assert_eq!(wheel.deadline(TimerKind::KeyDiscard), None);
+
+        // 设了更早的截止时间，驱动任务要提前醒来
+        wheel.set(TimerKind::ZeroRttKeyDiscard, start + Duration::from_secs(2));
+        wheel.expired(TimerKind::ZeroRttKeyDiscard).await;
+        assert_eq!(wheel.now(), start + Duration::from_secs(2));
+
+        // 推迟截止时间
+        wheel.set(TimerKind::Idle, start + Duration::from_secs(20));
+        wheel.expired(TimerKind::Idle).await;
+        assert_eq!(wheel.now(), start + Duration::from_secs(20));
+        assert_eq!(wheel.deadline(TimerKind::Draining), None);
+
+        wheel.close();
+        driver.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expired_before_waiting() {
+        let wheel = ArcTimerWheel::default();
+        tokio::spawn(wheel.driver());
+        let start = wheel.now();
+
+        // 已经到期的定时器，之后再等也能立即得到结果，但只有一次
+        wheel.set(TimerKind::KeyDiscard, start + Duration::from_secs(1));
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        wheel.expired(TimerKind::KeyDiscard).await;
+        let again =
+            tokio::time::timeout(Duration::from_secs(1), wheel.expired(TimerKind::KeyDiscard));
+        assert!(again.await.is_err());
+    }
+}
@@ -1,19 +1,30 @@
+use crate::{
+    idle::ArcIdleTimer,
+    path::{epoch_of, ArcPath, MIN_PROBE_SIZE},
+};
 use bytes::BufMut;
 use qbase::{
+    frame::{io::WritePingFrame, ConnectionCloseFrame},
     packet::{
         header::{
             Encode, GetType, HasLength, LongHeader, Write, WriteLongHeader, WriteOneRttHeader,
+            ZeroRttHeader,
         },
         keys::{ArcKeys, ArcOneRttKeys},
         LongClearBits, OneRttHeader, ShortClearBits,
     },
     varint::{VarInt, WriteVarInt},
 };
+use qcongestion::{congestion::Epoch, CongestionControl};
 use qrecovery::{
     space::ArcSpace,
     streams::{ArcDataStreams, ReceiveStream, TransmitStream},
 };
-use std::{fmt::Debug, ops::Deref};
+use std::{
+    fmt::Debug,
+    ops::Deref,
+    task::{Context, Poll},
+};
 
 /// In order to fill the packet efficiently and reduce unnecessary copying, the data of each
 /// space is directly written on the Buffer. However, the length of the packet header is
@@ -28,17 +39,155 @@ pub enum FillPolicy {
     // Padding,     // Instead of padding frames, it's better to redundantly encode the Length.
 }
 
+/// The sample for header protection requires at least 16 bytes after the 4-byte
+/// packet number, so the packet body must be at least 20 bytes.
+const MIN_BODY_SIZE: usize = 20;
+
+/// 对方地址验证通过之前，写入的数据包受该路径反放大限制的额度约束，见[`ArcPath::send_quota`]；
+/// 此外还受该路径拥塞窗口的约束，窗口已满时登记cx的waker，窗口腾出空间后唤醒，期间只能发送ACK-only的包。
+/// PTO超时后的探测包则不受拥塞窗口的约束，Initial探测包还要填充到1200字节。
+/// 发出的包要告知该路径的拥塞控制器，发出ack-eliciting包还要告知空闲计时器idle
+#[allow(clippy::too_many_arguments)]
 pub fn read_space_and_encrypt<T, S>(
+    cx: &mut Context<'_>,
     buffer: &mut [u8],
     header: LongHeader<T>,
     fill_policy: FillPolicy,
     keys: ArcKeys,
     space: ArcSpace<S>,
+    path: &ArcPath,
+    idle: &ArcIdleTimer,
 ) -> (usize, usize)
 where
     for<'a> &'a mut [u8]: Write<T>,
-    LongHeader<T>: HasLength + GetType + Encode,
+    T: HasLength,
+    LongHeader<T>: GetType + Encode,
     S: Debug + ReceiveStream + TransmitStream,
+{
+    let epoch = epoch_of(header.get_type());
+    let ack = path.cc().need_ack(epoch);
+    let probe = path.cc().need_probe(epoch);
+    let max_header_size = header.size() + 2;
+    let min_pkt_size = max_header_size + MIN_BODY_SIZE;
+    let mut sent_pn = 0;
+    let mut is_ack_eliciting = false;
+    let limited = if probe {
+        Some(&mut *buffer)
+    } else {
+        congestion_limit(cx, buffer, path, min_pkt_size)
+    };
+    let (offset, pkt_size) = match limited {
+        Some(buffer) => encrypt_long_packet(buffer, header, fill_policy, keys, path, |body_buf| {
+            let (pn, pn_size, mut body_len, ack_eliciting) = space.read(body_buf, ack);
+            sent_pn = pn;
+            is_ack_eliciting = ack_eliciting;
+            if probe && body_len > 0 {
+                let padding_to = match epoch {
+                    Epoch::Initial => MIN_PROBE_SIZE.saturating_sub(max_header_size),
+                    _ => 0,
+                };
+                body_len += write_probe(body_buf, body_len, padding_to, &mut is_ack_eliciting);
+            }
+            if body_len == pn_size {
+                // 既没有AckFrame，也没有其它帧
+                return (pn, pn_size, 0);
+            }
+            (pn, pn_size, body_len)
+        }),
+        None => match ack {
+            Some(largest) => {
+                encrypt_long_packet(buffer, header, fill_policy, keys, path, |body_buf| {
+                    let (pn, pn_size, body_len) = space.read_ack(0, body_buf, Some((0, largest)));
+                    sent_pn = pn;
+                    (pn, pn_size, body_len)
+                })
+            }
+            None => return (0, 0),
+        },
+    };
+    if pkt_size > 0 {
+        on_pkt_sent(path, epoch, sent_pn, is_ack_eliciting, pkt_size, ack);
+        idle.on_sent(is_ack_eliciting);
+    }
+    (offset, pkt_size)
+}
+
+/// 连接关闭期间，以Initial或Handshake包发送CONNECTION_CLOSE，包中不含其它帧
+pub fn read_close_and_encrypt<T, S>(
+    buffer: &mut [u8],
+    header: LongHeader<T>,
+    fill_policy: FillPolicy,
+    keys: ArcKeys,
+    space: ArcSpace<S>,
+    path: &ArcPath,
+    frame: &ConnectionCloseFrame,
+) -> (usize, usize)
+where
+    for<'a> &'a mut [u8]: Write<T>,
+    T: HasLength,
+    LongHeader<T>: GetType + Encode,
+    S: Debug + ReceiveStream + TransmitStream,
+{
+    encrypt_long_packet(buffer, header, fill_policy, keys, path, |body_buf| {
+        space.read_conn_close(0, body_buf, frame)
+    })
+}
+
+/// 客户端在得到1-RTT密钥之前，以0-RTT包发送数据空间中的早期数据。
+/// 0-RTT包与1-RTT包共用数据空间的包号空间，握手完成前不会启用多路径，总是用0号包号空间。
+/// 0-RTT包中不得携带ACK帧，Ref. RFC 9000 §12.4；也不发送探测包，握手确认之前数据空间不设PTO，
+/// Ref. RFC 9002 §6.2.1。受该路径拥塞窗口与反放大限制的约束，同[`read_space_and_encrypt`]
+#[allow(clippy::too_many_arguments)]
+pub fn read_0rtt_data_and_encrypt(
+    cx: &mut Context<'_>,
+    buffer: &mut [u8],
+    header: ZeroRttHeader,
+    fill_policy: FillPolicy,
+    keys: ArcKeys,
+    space: ArcSpace<ArcDataStreams>,
+    path: &ArcPath,
+    idle: &ArcIdleTimer,
+) -> (usize, usize) {
+    let min_pkt_size = header.size() + 2 + MIN_BODY_SIZE;
+    let Some(buffer) = congestion_limit(cx, buffer, path, min_pkt_size) else {
+        return (0, 0);
+    };
+    let mut sent_pn = 0;
+    let mut is_ack_eliciting = false;
+    let (offset, pkt_size) =
+        encrypt_long_packet(buffer, header, fill_policy, keys, path, |body_buf| {
+            let (pn, pn_size, body_len, ack_eliciting) = space.read(body_buf, None);
+            sent_pn = pn;
+            is_ack_eliciting = ack_eliciting;
+            if body_len == pn_size {
+                return (pn, pn_size, 0);
+            }
+            (pn, pn_size, body_len)
+        });
+    if pkt_size > 0 {
+        on_pkt_sent(path, Epoch::Data, sent_pn, is_ack_eliciting, pkt_size, None);
+        idle.on_sent(is_ack_eliciting);
+    }
+    if !is_ack_eliciting {
+        // 拥塞窗口尚有空间，却没有早期数据可发
+        path.cc().on_app_limited();
+    }
+    (offset, pkt_size)
+}
+
+/// read_body向包体中写入包号与各帧，返回包号、包号编码的长度、写入的总长度
+fn encrypt_long_packet<T>(
+    buffer: &mut [u8],
+    header: LongHeader<T>,
+    fill_policy: FillPolicy,
+    keys: ArcKeys,
+    path: &ArcPath,
+    read_body: impl FnOnce(&mut [u8]) -> (u64, usize, usize),
+) -> (usize, usize)
+where
+    for<'a> &'a mut [u8]: Write<T>,
+    T: HasLength,
+    LongHeader<T>: GetType + Encode,
 {
     let keys = match keys.get_local_keys() {
         Some(keys) => keys,
@@ -46,10 +195,13 @@ where
     };
 
     let max_header_size = header.size() + 2; // 2 bytes reserved for packet length, max 16KB
+    let buffer = match limit_by_quota(buffer, path, max_header_size) {
+        Some(buffer) => buffer,
+        None => return (0, 0),
+    };
     let (mut hdr_buf, mut body_buf) = buffer.split_at_mut(max_header_size);
 
-    // TODO: Path决定是否该发送AckFrame了，如果要发送最后一个参数不再为None
-    let (pn, pn_size, mut body_len) = space.read(body_buf, None);
+    let (pn, pn_size, mut body_len) = read_body(body_buf);
     if body_len == 0 {
         // nothing to send
         return (0, 0);
@@ -58,11 +210,11 @@ where
     unsafe {
         body_buf.advance_mut(body_len);
     }
-    if body_len < 20 {
+    if body_len < MIN_BODY_SIZE {
         // The sample requires at least 16 bytes, so the length must be at least 20 bytes.
         // If it is not enough, Padding(0x0) needs to be added.
-        body_buf.put_bytes(0x0, 20 - body_len);
-        body_len = 20;
+        body_buf.put_bytes(0x0, MIN_BODY_SIZE - body_len);
+        body_len = MIN_BODY_SIZE;
     }
 
     let mut offset = 0;
@@ -117,25 +269,167 @@ where
         .encrypt_in_place(sample, &mut header[0], &mut pn_max[..pn_size])
         .unwrap();
 
+    path.on_sent(pkt_size);
     (offset, pkt_size)
 }
 
+/// pn_space是path发包所用的包号空间，未启用多路径时为0，见[`crate::path::ArcPaths::pn_space`]；
+/// rcvd_pn_space是path收包所在的包号空间，其中的包由该路径的拥塞控制器决定何时确认，
+/// 见[`crate::path::ArcPaths::rcvd_pn_space`]。
+/// 空闲了保活间隔那么久，包中没有其它ack-eliciting帧，就补一个PING帧；PTO超时后的探测包也是如此，
+/// 且不受拥塞窗口的约束
+#[allow(clippy::too_many_arguments)]
 pub fn read_1rtt_data_and_encrypt(
+    cx: &mut Context<'_>,
     buffer: &mut [u8],
     header: OneRttHeader,
     keys: ArcOneRttKeys,
     space: ArcSpace<ArcDataStreams>,
+    path: &ArcPath,
+    pn_space: u64,
+    rcvd_pn_space: u64,
+    idle: &ArcIdleTimer,
+) -> usize {
+    // 空闲时无包可发，要等保活的定时器唤醒
+    idle.register_sender(cx);
+    let header_size = header.size();
+    let ack = path.cc().need_ack(Epoch::Data);
+    let probe = path.cc().need_probe(Epoch::Data);
+    let mut sent_pn = 0;
+    let mut is_ack_eliciting = false;
+    let limited = if probe {
+        Some(&mut *buffer)
+    } else {
+        congestion_limit(cx, buffer, path, header_size + MIN_BODY_SIZE)
+    };
+    let has_credit = !probe && limited.is_some();
+    let pkt_size = match limited {
+        Some(buffer) => encrypt_1rtt_packet(buffer, header, keys, path, pn_space, |body_buf| {
+            let ack_pkt = ack.map(|largest| (rcvd_pn_space, largest));
+            let (pn, pn_size, mut body_len, ack_eliciting) =
+                space.read_on_path(pn_space, body_buf, ack_pkt);
+            sent_pn = pn;
+            if body_len == 0 {
+                return (pn, pn_size, 0);
+            }
+            is_ack_eliciting = ack_eliciting;
+            if probe {
+                body_len += write_probe(body_buf, body_len, 0, &mut is_ack_eliciting);
+            } else if !is_ack_eliciting && body_len < body_buf.len() && idle.need_keep_alive() {
+                (&mut body_buf[body_len..]).put_ping_frame();
+                body_len += 1;
+                is_ack_eliciting = true;
+            }
+
+            body_len +=
+                write_path_frames(path, body_buf, body_len, header_size, &mut is_ack_eliciting);
+            if body_len == pn_size {
+                return (pn, pn_size, 0);
+            }
+            (pn, pn_size, body_len)
+        }),
+        // 拥塞窗口已满，仍要发送ACK，以及路径验证的帧，否则路径验证会因拥塞而超时
+        None if ack.is_some() || path.has_frames() => {
+            encrypt_1rtt_packet(buffer, header, keys, path, pn_space, |body_buf| {
+                let ack_pkt = ack.map(|largest| (rcvd_pn_space, largest));
+                let (pn, pn_size, mut body_len) = space.read_ack(pn_space, body_buf, ack_pkt);
+                sent_pn = pn;
+                if body_len == 0 {
+                    return (pn, pn_size, 0);
+                }
+                body_len +=
+                    write_path_frames(path, body_buf, body_len, header_size, &mut is_ack_eliciting);
+                if body_len == pn_size {
+                    return (pn, pn_size, 0);
+                }
+                (pn, pn_size, body_len)
+            })
+        }
+        None => return 0,
+    };
+    if pkt_size > 0 {
+        on_pkt_sent(path, Epoch::Data, sent_pn, is_ack_eliciting, pkt_size, ack);
+        idle.on_sent(is_ack_eliciting);
+    }
+    if has_credit && !is_ack_eliciting {
+        // 拥塞窗口尚有空间，却没有数据可发，发送受限于应用
+        path.cc().on_app_limited();
+    }
+    pkt_size
+}
+
+/// 在body_buf的body_len之后写入该路径待发送的路径帧，返回写入的长度，含填充。
+/// 路径帧只在该路径上发送，不计入空间的发包记录，丢了也不靠空间重传
+fn write_path_frames(
+    path: &ArcPath,
+    body_buf: &mut [u8],
+    body_len: usize,
+    header_size: usize,
+    is_ack_eliciting: &mut bool,
+) -> usize {
+    let path_frames_len = path.read_frames(&mut body_buf[body_len..]);
+    if path_frames_len == 0 {
+        return 0;
+    }
+    let body_len = body_len + path_frames_len;
+    // 探测包要填充Padding至1200字节，缓冲区不足时尽力而为
+    let padding = MIN_PROBE_SIZE
+        .saturating_sub(header_size + body_len)
+        .min(body_buf.len() - body_len);
+    body_buf[body_len..body_len + padding].fill(0);
+    *is_ack_eliciting = true;
+    path_frames_len + padding
+}
+
+/// 连接关闭期间，以1-RTT包发送CONNECTION_CLOSE，包中不含其它帧
+pub fn read_1rtt_close_and_encrypt(
+    buffer: &mut [u8],
+    header: OneRttHeader,
+    keys: ArcOneRttKeys,
+    space: ArcSpace<ArcDataStreams>,
+    path: &ArcPath,
+    pn_space: u64,
+    frame: &ConnectionCloseFrame,
+) -> usize {
+    encrypt_1rtt_packet(buffer, header, keys, path, pn_space, |body_buf| {
+        space.read_conn_close(pn_space, body_buf, frame)
+    })
+}
+
+fn encrypt_1rtt_packet(
+    buffer: &mut [u8],
+    header: OneRttHeader,
+    keys: ArcOneRttKeys,
+    path: &ArcPath,
+    pn_space: u64,
+    read_body: impl FnOnce(&mut [u8]) -> (u64, usize, usize),
 ) -> usize {
     let (hpk, pk) = match keys.get_local_keys() {
         Some(keys) => keys,
         None => return 0,
     };
 
+    {
+        // Initiate a key update before the confidentiality limit is reached. If the keys
+        // can't be updated in time, they must not be used to protect any more packets,
+        // and the connection will be closed with AEAD_LIMIT_REACHED.
+        let mut pk = pk.lock().unwrap();
+        if pk.need_update() {
+            pk.update();
+        }
+        if pk.is_exhausted() {
+            return 0;
+        }
+    }
+
     let header_size = header.size();
+    let buffer = match limit_by_quota(buffer, path, header_size) {
+        Some(buffer) => buffer,
+        None => return 0,
+    };
     let (mut hdr_buf, body_buf) = buffer.split_at_mut(header_size);
 
-    // TODO: 这里path要决定是否反馈AckFrame
-    let (pn, pn_size, body_len) = space.read(body_buf, None);
+    let (pn, pn_size, body_len) = read_body(body_buf);
     if body_len == 0 {
         return 0;
     }
@@ -147,7 +441,11 @@ pub fn read_1rtt_data_and_encrypt(
     let pkt_size = header_size + body_len;
     let pkt_buffer = &mut buffer[0..pkt_size];
     // encode pn length in the first byte
-    let (key_phase, pk) = pk.lock().unwrap().get_local();
+    let (key_phase, pk) = {
+        let mut pk = pk.lock().unwrap();
+        pk.on_pkt_encrypted(pn_space, pn);
+        pk.get_local()
+    };
     let mut clear_bits = ShortClearBits::with_pn_size(pn_size);
     clear_bits.set_key_phase(key_phase);
     pkt_buffer[0] |= *clear_bits;
@@ -163,13 +461,154 @@ pub fn read_1rtt_data_and_encrypt(
         .encrypt_in_place(sample, &mut header[0], &mut pn_max[..pn_size])
         .unwrap();
 
+    path.on_sent(pkt_size);
     pkt_size
 }
 
+/// 对方地址验证通过之前，发往该路径的数据不得超过收到的3倍，Ref. RFC 9000 §8。
+/// 按剩余额度截短缓冲区，连包头加最短的包体都装不下时返回None，只能等收到对方更多的数据，
+/// 期间该路径的PTO定时器也要暂停
+fn limit_by_quota<'b>(
+    buffer: &'b mut [u8],
+    path: &ArcPath,
+    header_size: usize,
+) -> Option<&'b mut [u8]> {
+    match path.send_quota() {
+        None => Some(buffer),
+        Some(quota) if quota < header_size + MIN_BODY_SIZE => {
+            // 已无包可发，PTO定时器暂停，直到收到对方更多的数据
+            path.cc().on_anti_amplification_limited();
+            None
+        }
+        Some(quota) => {
+            let len = quota.min(buffer.len());
+            Some(&mut buffer[..len])
+        }
+    }
+}
+
+/// PTO的探测包必须是ack-eliciting的，body_buf中已写入了body_len字节，若没有ack-eliciting帧，就补一个PING帧；
+/// 再以Padding帧将包体填充到padding_to字节，缓冲区不足时尽力而为。返回追加的字节数
+fn write_probe(
+    body_buf: &mut [u8],
+    body_len: usize,
+    padding_to: usize,
+    is_ack_eliciting: &mut bool,
+) -> usize {
+    let mut len = body_len;
+    if !*is_ack_eliciting && len < body_buf.len() {
+        (&mut body_buf[len..]).put_ping_frame();
+        len += 1;
+        *is_ack_eliciting = true;
+    }
+    let padding = padding_to.saturating_sub(len).min(body_buf.len() - len);
+    body_buf[len..len + padding].fill(0);
+    len += padding;
+    len - body_len
+}
+
+/// 按该路径拥塞窗口的发送信用截短缓冲区，信用连最小的数据包都容不下时返回None，
+/// 此时cx的waker已登记在拥塞控制器上，在途的数据被确认或判定丢失后会被唤醒
+fn congestion_limit<'b>(
+    cx: &mut Context<'_>,
+    buffer: &'b mut [u8],
+    path: &ArcPath,
+    min_pkt_size: usize,
+) -> Option<&'b mut [u8]> {
+    match path.cc().poll_send(cx) {
+        Poll::Ready(credit) if credit >= min_pkt_size => {
+            let len = credit.min(buffer.len());
+            Some(&mut buffer[..len])
+        }
+        _ => None,
+    }
+}
+
+/// 发出的数据包告知该路径的拥塞控制器，ack-eliciting包才计入在途数据；
+/// 携带了AckFrame的，还要记下所确认的最大包号
+fn on_pkt_sent(
+    path: &ArcPath,
+    epoch: Epoch,
+    pn: u64,
+    is_ack_eliciting: bool,
+    pkt_size: usize,
+    ack: Option<(u64, std::time::Instant)>,
+) {
+    path.cc().on_pkt_sent(
+        epoch,
+        pn,
+        is_ack_eliciting,
+        pkt_size,
+        is_ack_eliciting,
+        ack.map(|(largest, _)| largest),
+    );
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn test_write_probe() {
+        // 包中已有ack-eliciting帧，无需补PING
+        let mut buf = [0xffu8; 64];
+        let mut is_ack_eliciting = true;
+        assert_eq!(write_probe(&mut buf, 10, 0, &mut is_ack_eliciting), 0);
+
+        // 只有AckFrame，补一个PING帧，再填充到32字节
+        let mut is_ack_eliciting = false;
+        assert_eq!(write_probe(&mut buf, 10, 32, &mut is_ack_eliciting), 22);
+        assert!(is_ack_eliciting);
+        assert_eq!(buf[10], 0x01);
+        assert!(buf[11..32].iter().all(|b| *b == 0));
+        assert_eq!(buf[32], 0xff);
+
+        // 缓冲区不足以填充到1200字节，尽力而为
+        let mut is_ack_eliciting = false;
+        assert_eq!(write_probe(&mut buf, 10, 1200, &mut is_ack_eliciting), 54);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_write_path_frames() {
+        use crate::path::PathId;
+        use qbase::cid::ConnectionId;
+
+        let path = ArcPath::new(
+            PathId::Direct {
+                local: "127.0.0.1:8080".parse().unwrap(),
+                remote: "127.0.0.1:8081".parse().unwrap(),
+            },
+            ConnectionId::from_slice(b"local cid"),
+            ConnectionId::from_slice(b"peer cid"),
+            Default::default(),
+        );
+        let mut buf = [0xffu8; 64];
+        let mut is_ack_eliciting = false;
+        assert!(!path.has_frames());
+        assert_eq!(
+            write_path_frames(&path, &mut buf, 10, 20, &mut is_ack_eliciting),
+            0
+        );
+        assert!(!is_ack_eliciting);
+
+        // 发起路径验证，PATH_CHALLENGE之后填充，缓冲区不足以填充到1200字节，尽力而为
+        tokio::spawn({
+            let path = path.clone();
+            async move { path.validate().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(path.has_frames());
+        assert_eq!(
+            write_path_frames(&path, &mut buf, 10, 20, &mut is_ack_eliciting),
+            54
+        );
+        assert!(is_ack_eliciting);
+        assert!(buf[19..].iter().all(|b| *b == 0));
+        assert!(!path.has_frames());
+    }
 }
@@ -122,6 +122,12 @@ impl<T, const LIMIT: u64> IndexDeque<T, LIMIT> {
         let _ = self.deque.drain(..n);
     }
 
+    /// 队列已空时，起始索引直接跳到idx，其间的索引视作已经滑走，免得插入时填充大量空洞
+    pub fn skip_to(&mut self, idx: u64) {
+        debug_assert!(self.deque.is_empty());
+        self.offset = self.offset.max(idx);
+    }
+
     /// This API will be used for the records of the packets that have been
     /// sent and are awaiting confirmation.
     /// The records of the sent packets can only be removed from the queue
@@ -1,5 +1,6 @@
 use crate::index_deque::IndexDeque;
 use qbase::{
+    clock::Clock,
     frame::AckFrame,
     packet::PacketNumber,
     varint::{VarInt, VARINT_MAX},
@@ -90,6 +91,7 @@ impl RcvdPktRecords {
     fn gen_ack_frame_util(
         &self,
         (largest, recv_time): (u64, Instant),
+        now: Instant,
         _capacity: usize,
     ) -> AckFrame {
         let mut iter = self
@@ -130,7 +132,11 @@ impl RcvdPktRecords {
 
         AckFrame {
             largest: unsafe { VarInt::from_u64_unchecked(largest) },
-            delay: unsafe { VarInt::from_u64_unchecked(recv_time.elapsed().as_micros() as u64) },
+            delay: unsafe {
+                VarInt::from_u64_unchecked(
+                    now.saturating_duration_since(recv_time).as_micros() as u64
+                )
+            },
             first_range: unsafe { VarInt::from_u64_unchecked(first_range as u64) },
             ranges,
             ecn: None,
@@ -151,12 +157,21 @@ impl RcvdPktRecords {
 
 /// 接收数据包队列，各处共享的，判断包是否收到以及生成ack frame，只需要读锁；
 /// 记录新收到的数据包，或者失活旧数据包并滑走，才需要写锁。
-#[derive(Debug, Clone, Default)]
+/// AckFrame中的ack delay从clock取当前时间计算，与拥塞控制用同一个时钟。
+#[derive(Debug, Clone)]
 pub struct ArcRcvdPktRecords {
     inner: Arc<RwLock<RcvdPktRecords>>,
+    clock: Arc<dyn Clock>,
 }
 
 impl ArcRcvdPktRecords {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            inner: Default::default(),
+            clock,
+        }
+    }
+
     /// 当新收到一个数据包，如果这个包很旧，那么大概率意味着是重复包，直接丢弃。
     /// 如果这个数据包号是最大的，那么它之后的空档都是尚未收到的，得记为未收到。
     /// 注意，包号合法，不代表的包内容合法，必须等到包被正确解密且其中帧被正确解出后，才能确认收到。
@@ -176,10 +191,11 @@ impl ArcRcvdPktRecords {
         (largest, recv_time): (u64, Instant),
         capacity: usize,
     ) -> AckFrame {
+        let now = self.clock.now();
         self.inner
             .read()
             .unwrap()
-            .gen_ack_frame_util((largest, recv_time), capacity)
+            .gen_ack_frame_util((largest, recv_time), now, capacity)
     }
 
     pub fn write(&self) -> ArcRcvdPktRecordsWriter<'_> {
@@ -201,6 +217,13 @@ impl ArcRcvdPktRecordsWriter<'_> {
     pub fn inactivate(&mut self, pn: u64) {
         self.guard.inactivate(pn);
     }
+
+    /// 携带largest的AckFrame被对方确认了，largest及其之前的包都不必再反馈
+    pub fn inactivate_until(&mut self, largest: u64) {
+        for pn in self.guard.queue.offset()..=largest {
+            self.guard.inactivate(pn);
+        }
+    }
 }
 
 impl Drop for ArcRcvdPktRecordsWriter<'_> {
@@ -212,10 +235,12 @@ impl Drop for ArcRcvdPktRecordsWriter<'_> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use qbase::clock::{MockClock, RealClock};
+    use std::time::Duration;
 
     #[test]
     fn test_rcvd_pkt_records() {
-        let records = ArcRcvdPktRecords::default();
+        let records = ArcRcvdPktRecords::new(Arc::new(RealClock));
         assert_eq!(records.decode_pn(PacketNumber::encode(1, 0)), Ok(1));
         assert_eq!(records.inner.read().unwrap().queue.len(), 0);
 
@@ -259,5 +284,22 @@ mod tests {
             records.decode_pn(PacketNumber::encode(9, 0)),
             Err(Error::TooOld)
         );
+
+        // 确认到25的AckFrame被确认了，25及之前的记录都滑走
+        records.write().inactivate_until(25);
+        assert_eq!(records.inner.read().unwrap().queue.len(), 5);
+    }
+
+    #[test]
+    fn test_ack_delay() {
+        let start = Instant::now();
+        let clock = MockClock::new(start);
+        let records = ArcRcvdPktRecords::new(Arc::new(clock.clone()));
+        records.on_rcvd_pn(0);
+
+        // ack delay是从收到largest到生成AckFrame所经过的时间，取自时钟而非系统时间
+        clock.advance(Duration::from_millis(25));
+        let ack = records.gen_ack_frame_util((0, start), 1000);
+        assert_eq!(ack.delay.into_inner(), 25_000);
     }
 }
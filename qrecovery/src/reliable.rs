@@ -34,6 +34,13 @@ impl RawReliableFrameQueue {
     fn pop_front(&mut self) -> Option<ReliableFrame> {
         self.queue.pop_front()
     }
+
+    fn remove(&mut self, frame: &ReliableFrame) -> bool {
+        match self.queue.iter().position(|f| f == frame) {
+            Some(idx) => self.queue.remove(idx).is_some(),
+            None => false,
+        }
+    }
 }
 
 /// Frames that need to be sent reliably, there are 3 operations:
@@ -79,6 +86,11 @@ impl ReliableFrameQueueWriter<'_> {
     pub fn push_reliable_frame(&mut self, frame: ReliableFrame) {
         self.0.push_reliable_frame(frame);
     }
+
+    /// 丢包误判，该帧其实已被对方收到，若还在队列中等待重传，就不必再发了
+    pub fn remove_reliable_frame(&mut self, frame: &ReliableFrame) -> bool {
+        self.0.remove(frame)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -134,6 +146,9 @@ impl SentPktState {
     }
 }
 
+// 判定丢失的包，在确认的最大包号超过它这么多之前，其记录都保留着，以识别丢包误判
+const LOST_RECORDS_WINDOW: u64 = 32;
+
 /// 记录已经发送的帧，尽最大努力省略内存分配。
 /// queue记录着所有发送过的帧，records记录着顺序发送的数据包包含几个帧，以及这些数据包的状态。
 /// 发送数据包的时候，往其中写入数据包的帧，
@@ -191,12 +206,24 @@ impl RawSentPktRecords {
             .map(|f| f.clone())
     }
 
+    fn is_lost(&self, pn: u64) -> bool {
+        matches!(self.records.get(pn), Some(SentPktState::Lost(_)))
+    }
+
+    // 只滑走头部连续的、不再在途的记录；判定丢失的记录要多留一阵，等待可能迟到的确认。
+    // 不含任何帧的记录也可滑走，它们要么是读取后未发出的包号，要么是只含PING等无需重传的帧，
+    // 未发出的包号永远不会被确认或判定丢失，若不滑走，记录将一直堆积
     fn auto_drain(&mut self) {
+        let largest_acked = self.largest_acked_pktno;
         let (n, f) = self
             .records
-            .iter()
-            .filter(|s| !matches!(s, SentPktState::Flighting(_)))
-            .fold((0usize, 0usize), |(n, f), s| (n + 1, f + s.nframes()));
+            .iter_with_idx()
+            .take_while(|(pn, s)| match s {
+                SentPktState::Flighting(n) => *n == 0,
+                SentPktState::Acked(_) => true,
+                SentPktState::Lost(_) => pn + LOST_RECORDS_WINDOW <= largest_acked,
+            })
+            .fold((0usize, 0usize), |(n, f), (_, s)| (n + 1, f + s.nframes()));
         self.records.advance(n);
         let _ = self.queue.drain(..f);
     }
@@ -237,6 +264,16 @@ impl RecvGuard<'_> {
     pub fn may_loss_pkt(&mut self, pn: u64) -> impl Iterator<Item = SentRecord> + '_ {
         self.inner.may_loss_pkt(pn)
     }
+
+    /// 该包此前被判定丢失了，若此时又被确认，说明是误判
+    pub fn is_lost(&self, pn: u64) -> bool {
+        self.inner.is_lost(pn)
+    }
+
+    /// 尚未滑走的发包记录的包号范围，其中可能还有在途的数据包
+    pub fn pns(&self) -> std::ops::Range<u64> {
+        self.inner.records.offset()..self.inner.records.largest()
+    }
 }
 
 impl Drop for RecvGuard<'_> {
@@ -282,9 +319,88 @@ impl Drop for SendGuard<'_> {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use qbase::{frame::MaxDataFrame, varint::VarInt};
+
+    fn max_data(n: u32) -> ReliableFrame {
+        ReliableFrame::Conn(ConnFrame::MaxData(MaxDataFrame {
+            max_data: VarInt::from_u32(n),
+        }))
+    }
+
+    // 发出一个只含一个可靠帧的包，返回其包号
+    fn send_frame(records: &ArcSentPktRecords, n: u32) -> u64 {
+        let mut send_guard = records.send();
+        let (pn, _) = send_guard.next_pn();
+        send_guard.record_reliable_frame(max_data(n));
+        pn
+    }
+
+    #[test]
+    fn test_drain_phantom_pn() {
+        let records = ArcSentPktRecords::default();
+        // 读出了包号，却没写入任何帧，这个包号不会发出
+        let phantom = records.send().next_pn().0;
+        let pn = send_frame(&records, 1);
+        assert_eq!((phantom, pn), (0, 1));
+
+        {
+            let mut recv_guard = records.receive();
+            recv_guard.update_largest(pn);
+            assert_eq!(recv_guard.on_pkt_acked(pn).count(), 1);
+        }
+        // 未发出的包号不会阻塞滑动，记录和帧都被清理了
+        let recv_guard = records.receive();
+        assert_eq!(recv_guard.pns(), 2..2);
+        assert!(recv_guard.inner.queue.is_empty());
+    }
 
     #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+    fn test_lost_then_acked() {
+        let records = ArcSentPktRecords::default();
+        let lost = send_frame(&records, 0);
+        {
+            let mut recv_guard = records.receive();
+            let frames = recv_guard.may_loss_pkt(lost).collect::<Vec<_>>();
+            assert!(matches!(&frames[..], [SentRecord::Reliable(f)] if *f == max_data(0)));
+        }
+        // 判定丢失的记录仍保留着，迟到的确认可以识别出误判
+        {
+            let mut recv_guard = records.receive();
+            assert!(recv_guard.is_lost(lost));
+            assert_eq!(recv_guard.on_pkt_acked(lost).count(), 1);
+            assert!(!recv_guard.is_lost(lost));
+            // 重复确认，不再返回帧
+            assert_eq!(recv_guard.on_pkt_acked(lost).count(), 0);
+        }
+        assert_eq!(records.receive().pns(), 1..1);
+    }
+
+    #[test]
+    fn test_lost_records_window() {
+        let records = ArcSentPktRecords::default();
+        let lost = send_frame(&records, 0);
+        records.receive().may_loss_pkt(lost).for_each(drop);
+
+        for n in 1..LOST_RECORDS_WINDOW as u32 {
+            let largest = send_frame(&records, n);
+            let mut recv_guard = records.receive();
+            recv_guard.update_largest(largest);
+            recv_guard.on_pkt_acked(largest).for_each(drop);
+        }
+        // 确认的最大包号尚未超出窗口，丢失的记录仍在
+        assert!(records.receive().is_lost(lost));
+
+        let largest = send_frame(&records, 0);
+        {
+            let mut recv_guard = records.receive();
+            recv_guard.update_largest(largest);
+            recv_guard.on_pkt_acked(largest).for_each(drop);
+        }
+        // 超出窗口，丢失的记录连同其后已确认的记录都被滑走
+        let recv_guard = records.receive();
+        assert!(!recv_guard.is_lost(lost));
+        assert_eq!(recv_guard.pns(), largest + 1..largest + 1);
+        assert!(recv_guard.inner.queue.is_empty());
     }
 }
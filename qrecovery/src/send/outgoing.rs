@@ -37,6 +37,31 @@ impl Outgoing {
         }
     }
 
+    /// 应用层已写入该流的数据量，0-RTT被拒后，它们都要在1-RTT中重新发送，受连接级流量控制的约束
+    pub fn written(&self) -> u64 {
+        let sender = self.0.lock().unwrap();
+        match &*sender {
+            Ok(Sender::Ready(s)) => s.written(),
+            Ok(Sender::Sending(s)) => s.written(),
+            Ok(Sender::DataSent(s)) => s.written(),
+            _ => 0,
+        }
+    }
+
+    /// 0-RTT被拒后，按对方实际的初始流窗口重置发送窗口。
+    /// 返回false表示已写入的数据超出了新窗口，该流无法继续，需由上层终止
+    pub fn reset_window(&self, max_data_size: u64) -> bool {
+        assert!(max_data_size <= VARINT_MAX);
+        let mut sender = self.0.lock().unwrap();
+        match sender.deref_mut() {
+            Ok(Sender::Ready(s)) => s.reset_window(max_data_size),
+            Ok(Sender::Sending(s)) => s.reset_window(max_data_size),
+            Ok(Sender::DataSent(s)) => s.reset_window(max_data_size),
+            // 已被重置或已完成的流，不受窗口影响
+            _ => true,
+        }
+    }
+
     pub fn try_read<B>(&self, sid: StreamId, mut buffer: B) -> Option<StreamFrame>
     where
         B: BufMut,
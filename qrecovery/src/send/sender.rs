@@ -108,6 +108,19 @@ impl ReadySender {
         self.shutdown_waker.is_some()
     }
 
+    pub(super) fn written(&self) -> u64 {
+        self.sndbuf.len()
+    }
+
+    pub(super) fn reset_window(&mut self, max_data_size: u64) -> bool {
+        reset_window(
+            &self.sndbuf,
+            &mut self.max_data_size,
+            &mut self.writable_waker,
+            max_data_size,
+        )
+    }
+
     pub(super) fn begin_sending(self) -> SendingSender {
         SendingSender {
             sndbuf: self.sndbuf,
@@ -211,6 +224,19 @@ impl SendingSender {
         }
     }
 
+    pub(super) fn written(&self) -> u64 {
+        self.sndbuf.len()
+    }
+
+    pub(super) fn reset_window(&mut self, max_data_size: u64) -> bool {
+        reset_window(
+            &self.sndbuf,
+            &mut self.max_data_size,
+            &mut self.writable_waker,
+            max_data_size,
+        )
+    }
+
     pub(super) fn pick_up<F>(&mut self, estimate_capacity: F) -> Option<(u64, &[u8], bool)>
     where
         F: Fn(u64) -> Option<usize>,
@@ -359,6 +385,15 @@ impl DataSentSender {
         self.sndbuf.is_all_rcvd()
     }
 
+    /// 数据已写完，只需检查已写入的数据是否仍在新窗口之内
+    pub(super) fn written(&self) -> u64 {
+        self.sndbuf.len()
+    }
+
+    pub(super) fn reset_window(&mut self, max_data_size: u64) -> bool {
+        self.sndbuf.len() <= max_data_size
+    }
+
     pub(super) fn may_loss(&mut self, range: &Range<u64>) {
         self.sndbuf.may_loss(range)
     }
@@ -432,6 +467,27 @@ impl DataSentSender {
     }
 }
 
+/// 0-RTT被拒后，按对方实际的初始流窗口重置发送窗口，与MAX_STREAM_DATA不同，新窗口可能更小。
+/// 已写入的数据超出了新窗口，返回false，这些数据无法再发送，该流只能终止
+fn reset_window(
+    sndbuf: &SendBuf,
+    cur_max_data_size: &mut u64,
+    writable_waker: &mut Option<Waker>,
+    max_data_size: u64,
+) -> bool {
+    if sndbuf.len() > max_data_size {
+        return false;
+    }
+    let enlarged = max_data_size > *cur_max_data_size;
+    *cur_max_data_size = max_data_size;
+    if enlarged {
+        if let Some(waker) = writable_waker.take() {
+            waker.wake();
+        }
+    }
+    true
+}
+
 #[derive(Default, Debug)]
 pub enum Sender {
     Ready(ReadySender),
@@ -1,20 +1,29 @@
 use super::{
     crypto::{CryptoStream, TransmitCrypto},
     rcvdpkt::{ArcRcvdPktRecords, Error as RcvPnError},
-    reliable::{ArcReliableFrameQueue, ArcSentPktRecords, SentRecord},
+    reliable::{ArcReliableFrameQueue, ArcSentPktRecords, SendGuard, SentRecord},
     streams::{none::NoDataStreams, ArcDataStreams, ReceiveStream, TransmitStream},
 };
 use bytes::{BufMut, Bytes};
 use qbase::{
-    error::Error,
+    clock::Clock,
+    config::TransportParameters,
+    error::{Error, ErrorKind},
     frame::{
-        io::{WriteAckFrame, WriteFrame},
-        AckFrame, BeFrame, DataFrame, StreamCtlFrame,
+        io::{WriteAckFrame, WriteConnectionCloseFrame, WriteFrame, WritePathAckFrame},
+        AckFrame, BeFrame, ConnectionCloseFrame, DataFrame, FrameType, PathAckFrame,
+        StreamCtlFrame,
     },
     packet::{PacketNumber, WritePacketNumber},
     streamid::Role,
+    varint::VarInt,
+};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::Instant,
 };
-use std::{fmt::Debug, sync::Arc, time::Instant};
 
 #[derive(Debug, Clone)]
 pub enum SpaceFrame {
@@ -25,46 +34,100 @@ pub enum SpaceFrame {
 #[derive(Debug)]
 struct RawSpace<T> {
     reliable_frame_queue: ArcReliableFrameQueue,
+    // 默认的包号空间，多路径下即序号为0的连接ID所在路径的包号空间
     sent_pkt_records: ArcSentPktRecords,
     rcvd_pkt_records: ArcRcvdPktRecords,
+    // 多路径下其余各路径的包号空间，Ref. draft-ietf-quic-multipath。
+    // 发包按所用目标连接ID的序号区分，收包按数据包中目标连接ID（即我方连接ID）的序号区分
+    path_sent_pkt_records: Mutex<HashMap<u64, ArcSentPktRecords>>,
+    path_rcvd_pkt_records: Mutex<HashMap<u64, ArcRcvdPktRecords>>,
     data_streams: T,
     crypto_stream: CryptoStream,
+    // 生成AckFrame时计算ack delay所用的时钟，各路径的收包空间共用
+    clock: Arc<dyn Clock>,
 }
 
 impl<T> RawSpace<T>
 where
     T: TransmitStream + ReceiveStream,
 {
+    fn new(
+        reliable_frame_queue: ArcReliableFrameQueue,
+        data_streams: T,
+        crypto_stream: CryptoStream,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            reliable_frame_queue,
+            sent_pkt_records: Default::default(),
+            rcvd_pkt_records: ArcRcvdPktRecords::new(clock.clone()),
+            path_sent_pkt_records: Default::default(),
+            path_rcvd_pkt_records: Default::default(),
+            data_streams,
+            crypto_stream,
+            clock,
+        }
+    }
+
     fn rcvd_pkt_records(&self) -> ArcRcvdPktRecords {
         self.rcvd_pkt_records.clone()
     }
 
-    fn decode_pn(&self, encoded_pn: PacketNumber) -> Result<u64, RcvPnError> {
-        self.rcvd_pkt_records.decode_pn(encoded_pn)
+    /// 发包所用的包号空间，首次在该路径上发包时创建
+    fn sent_pkt_records_on_path(&self, path_id: u64) -> ArcSentPktRecords {
+        if path_id == 0 {
+            return self.sent_pkt_records.clone();
+        }
+        let mut path_records = self.path_sent_pkt_records.lock().unwrap();
+        path_records.entry(path_id).or_default().clone()
     }
 
-    fn on_rcvd_pn(&self, pn: u64) {
-        self.rcvd_pkt_records.on_rcvd_pn(pn)
+    /// 收包所在的包号空间，首次在该路径上收包时创建
+    fn rcvd_pkt_records_on_path(&self, path_id: u64) -> ArcRcvdPktRecords {
+        if path_id == 0 {
+            return self.rcvd_pkt_records.clone();
+        }
+        let mut path_records = self.path_rcvd_pkt_records.lock().unwrap();
+        path_records
+            .entry(path_id)
+            .or_insert_with(|| ArcRcvdPktRecords::new(self.clock.clone()))
+            .clone()
     }
 
-    fn read(&self, mut buf: &mut [u8], ack_pkt: Option<(u64, Instant)>) -> (u64, usize, usize) {
+    /// 确认与丢包只针对发过包的包号空间，不会凭空创建
+    fn sent_pkt_records_if_exist(&self, path_id: u64) -> Option<ArcSentPktRecords> {
+        if path_id == 0 {
+            return Some(self.sent_pkt_records.clone());
+        }
+        self.path_sent_pkt_records
+            .lock()
+            .unwrap()
+            .get(&path_id)
+            .cloned()
+    }
+
+    fn read(
+        &self,
+        path_id: u64,
+        mut buf: &mut [u8],
+        ack_pkt: Option<(u64, (u64, Instant))>,
+    ) -> (u64, usize, usize, bool) {
         let origin = buf.remaining_mut();
 
-        let mut send_guard = self.sent_pkt_records.send();
+        let sent_pkt_records = self.sent_pkt_records_on_path(path_id);
+        let mut send_guard = sent_pkt_records.send();
         let (pn, encoded_pn) = send_guard.next_pn();
         if buf.remaining_mut() > encoded_pn.size() {
             buf.put_packet_number(encoded_pn);
         } else {
-            return (pn, encoded_pn.size(), 0);
+            return (pn, encoded_pn.size(), 0, false);
         }
 
-        if let Some(largest) = ack_pkt {
-            let ack_frame = self
-                .rcvd_pkt_records
-                .gen_ack_frame_util(largest, buf.remaining_mut());
-            buf.put_ack_frame(&ack_frame);
-            send_guard.record_ack_frame(ack_frame);
+        if let Some(ack_pkt) = ack_pkt {
+            self.write_ack(&mut buf, &mut send_guard, ack_pkt);
         }
+        // 包号与AckFrame之后若还写入了其它帧，该包就是ack-eliciting的
+        let non_eliciting_len = origin - buf.remaining_mut();
 
         {
             let mut read_frame_guard = self.reliable_frame_queue.read();
@@ -94,7 +157,34 @@ where
             }
         }
 
-        (pn, encoded_pn.size(), origin - buf.remaining_mut())
+        let len = origin - buf.remaining_mut();
+        (pn, encoded_pn.size(), len, len > non_eliciting_len)
+    }
+
+    /// 写入确认ack_pkt.0所标识的收包空间的AckFrame，默认的收包空间0以AckFrame确认，
+    /// 其余的以PathAckFrame确认，并记入发包记录，待其被确认后，此前的收包记录就不必再确认了
+    fn write_ack(
+        &self,
+        buf: &mut impl BufMut,
+        send_guard: &mut SendGuard<'_>,
+        (ack_path_id, largest): (u64, (u64, Instant)),
+    ) {
+        let rcvd_pkt_records = self.rcvd_pkt_records_on_path(ack_path_id);
+        if ack_path_id == 0 {
+            let ack_frame = rcvd_pkt_records.gen_ack_frame_util(largest, buf.remaining_mut());
+            buf.put_ack_frame(&ack_frame);
+            send_guard.record_ack_frame(ack_frame);
+        } else {
+            // 其余路径的包号空间，以PathAckFrame确认，帧类型与路径标识要预留空间
+            let path_id = VarInt::from_u64(ack_path_id).expect("path id is a varint");
+            let capacity = buf
+                .remaining_mut()
+                .saturating_sub(3 + path_id.encoding_size());
+            let ack = rcvd_pkt_records.gen_ack_frame_util(largest, capacity);
+            let path_ack_frame = PathAckFrame { path_id, ack };
+            buf.put_path_ack_frame(&path_ack_frame);
+            send_guard.record_ack_frame(path_ack_frame.ack);
+        }
     }
 
     fn receive(&self, frame: SpaceFrame) -> Result<(), Error> {
@@ -112,18 +202,38 @@ where
         Ok(())
     }
 
-    fn on_ack(&self, ack: AckFrame) {
-        let mut recv_guard = self.sent_pkt_records.receive();
+    fn on_ack(&self, path_id: u64, ack: AckFrame) {
+        let Some(sent_pkt_records) = self.sent_pkt_records_if_exist(path_id) else {
+            return;
+        };
+        let mut recv_guard = sent_pkt_records.receive();
         recv_guard.update_largest(ack.largest.into_inner());
 
-        for pn in ack.iter().flat_map(|r| r.rev()) {
+        // 确认的区间由对方决定，可能大得离谱，只遍历其中尚有发包记录的包号
+        let pns = recv_guard.pns();
+        let acked = ack
+            .iter()
+            .filter_map(|r| {
+                let start = (*r.start()).max(pns.start);
+                let end = (*r.end() + 1).min(pns.end);
+                (start < end).then_some(start..end)
+            })
+            .collect::<Vec<_>>();
+        for pn in acked.into_iter().flat_map(|r| r.rev()) {
+            // 被判定丢失的包又被确认了，其中的帧若还在等待重传，就取消掉；
+            // 数据帧无需特殊处理，发送缓冲区确认Lost区间后自然不会再重传
+            let spurious = recv_guard.is_lost(pn);
             for record in recv_guard.on_pkt_acked(pn) {
                 match record {
                     SentRecord::Ack(_) => {
                         // do nothing
                     }
-                    SentRecord::Reliable(_) => {
-                        // do nothing
+                    SentRecord::Reliable(frame) => {
+                        if spurious {
+                            self.reliable_frame_queue
+                                .write()
+                                .remove_reliable_frame(&frame);
+                        }
                     }
                     SentRecord::Data(DataFrame::Crypto(frame)) => {
                         self.crypto_stream.on_data_acked(frame);
@@ -136,8 +246,27 @@ where
         }
     }
 
-    fn may_loss_pkt(&self, pn: u64) {
-        let mut recv_pkt_guard = self.sent_pkt_records.receive();
+    fn check_ack(&self, path_id: u64, ack: &AckFrame) -> Result<(), Error> {
+        let Some(sent_pkt_records) = self.sent_pkt_records_if_exist(path_id) else {
+            return Ok(());
+        };
+        let next_pn = sent_pkt_records.receive().pns().end;
+        if ack.largest.into_inner() >= next_pn {
+            let frame_type = match path_id {
+                0 => ack.frame_type(),
+                _ => FrameType::PathAck(ack.ecn.is_some() as u8),
+            };
+            return Err(Error::new(
+                ErrorKind::ProtocolViolation,
+                frame_type,
+                format!("ack packet {} that was never sent", ack.largest),
+            ));
+        }
+        Ok(())
+    }
+
+    fn may_loss_pkt(&self, sent_pkt_records: &ArcSentPktRecords, pn: u64) {
+        let mut recv_pkt_guard = sent_pkt_records.receive();
         let mut write_frame_guard = self.reliable_frame_queue.write();
         for record in recv_pkt_guard.may_loss_pkt(pn) {
             match record {
@@ -156,6 +285,27 @@ where
             }
         }
     }
+
+    fn abandon_path(&self, path_id: u64) {
+        // 收包空间仍保留，对方在该路径上已发出的包可能还会陆续到达
+        let sent_pkt_records = if path_id == 0 {
+            Some(self.sent_pkt_records.clone())
+        } else {
+            self.path_sent_pkt_records.lock().unwrap().remove(&path_id)
+        };
+        // 被放弃的路径上在途的数据包都不会再被确认了，其中的帧要在其它路径上重传
+        if let Some(sent_pkt_records) = sent_pkt_records {
+            self.may_loss_all(&sent_pkt_records);
+        }
+    }
+
+    /// 判定该发包空间中所有在途的数据包都已丢失，其中的帧重新排队等待重传
+    fn may_loss_all(&self, sent_pkt_records: &ArcSentPktRecords) {
+        let pns = sent_pkt_records.receive().pns();
+        for pn in pns {
+            self.may_loss_pkt(sent_pkt_records, pn);
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -171,18 +321,96 @@ where
     }
 
     pub fn decode_pn(&self, encoded_pn: PacketNumber) -> Result<u64, RcvPnError> {
-        self.0.decode_pn(encoded_pn)
+        self.0.rcvd_pkt_records.decode_pn(encoded_pn)
     }
 
     pub fn on_rcvd_pn(&self, pn: u64) {
-        self.0.on_rcvd_pn(pn)
+        self.0.rcvd_pkt_records.on_rcvd_pn(pn)
+    }
+
+    /// 多路径下，在path_id所标识的包号空间中解码包号，path_id是数据包中目标连接ID的序号
+    pub fn decode_pn_on_path(
+        &self,
+        path_id: u64,
+        encoded_pn: PacketNumber,
+    ) -> Result<u64, RcvPnError> {
+        self.0
+            .rcvd_pkt_records_on_path(path_id)
+            .decode_pn(encoded_pn)
+    }
+
+    pub fn on_rcvd_pn_on_path(&self, path_id: u64, pn: u64) {
+        self.0.rcvd_pkt_records_on_path(path_id).on_rcvd_pn(pn)
+    }
+
+    /// 连接级的可靠帧，如NewConnectionIdFrame、RetireConnectionIdFrame，需写入该队列发送
+    pub fn reliable_frame_queue(&self) -> ArcReliableFrameQueue {
+        self.0.reliable_frame_queue.clone()
     }
 
     /// 要发送一个该空间的数据包，读出下一个包号，然后检车是否要发送AckFrame，
     /// 然后发送帧，最后发送数据流中的数据帧。
-    /// 返回该数据包的包号，以及大小
-    pub fn read(&self, buf: &mut [u8], ack_pkt: Option<(u64, Instant)>) -> (u64, usize, usize) {
-        self.0.read(buf, ack_pkt)
+    /// 返回该数据包的包号、包号编码的长度、写入的总长度，以及该包是否ack-eliciting
+    pub fn read(
+        &self,
+        buf: &mut [u8],
+        ack_pkt: Option<(u64, Instant)>,
+    ) -> (u64, usize, usize, bool) {
+        self.0.read(0, buf, ack_pkt.map(|largest| (0, largest)))
+    }
+
+    /// 多路径下，在path_id所标识的包号空间中发包，path_id是该路径所用目标连接ID的序号。
+    /// ack_pkt的第一项是要确认的收包空间，非0的收包空间以PathAckFrame确认
+    pub fn read_on_path(
+        &self,
+        path_id: u64,
+        buf: &mut [u8],
+        ack_pkt: Option<(u64, (u64, Instant))>,
+    ) -> (u64, usize, usize, bool) {
+        self.0.read(path_id, buf, ack_pkt)
+    }
+
+    /// 在path_id所标识的包号空间中发送只含AckFrame的数据包，返回该数据包的包号、包号编码的长度、写入的总长度。
+    /// 拥塞窗口已满时也可以发送，ACK-only的包不计入在途数据，Ref. RFC 9002 §7。
+    /// ack_pkt为None时只写入包号，由调用者追加路径帧等其它不经空间重传的帧
+    pub fn read_ack(
+        &self,
+        path_id: u64,
+        mut buf: &mut [u8],
+        ack_pkt: Option<(u64, (u64, Instant))>,
+    ) -> (u64, usize, usize) {
+        let origin = buf.remaining_mut();
+        let sent_pkt_records = self.0.sent_pkt_records_on_path(path_id);
+        let mut send_guard = sent_pkt_records.send();
+        let (pn, encoded_pn) = send_guard.next_pn();
+        if buf.remaining_mut() <= encoded_pn.size() {
+            return (pn, encoded_pn.size(), 0);
+        }
+        buf.put_packet_number(encoded_pn);
+        if let Some(ack_pkt) = ack_pkt {
+            self.0.write_ack(&mut buf, &mut send_guard, ack_pkt);
+        }
+        (pn, encoded_pn.size(), origin - buf.remaining_mut())
+    }
+
+    /// 连接关闭期间，在path_id所标识的包号空间中发送只含CONNECTION_CLOSE帧的数据包，返回该数据包的包号，以及大小。
+    /// CONNECTION_CLOSE帧不计入发包记录，丢了也不重传，而是在收到对方的数据包时重新发送，Ref. RFC 9000 §10.2.1
+    pub fn read_conn_close(
+        &self,
+        path_id: u64,
+        mut buf: &mut [u8],
+        frame: &ConnectionCloseFrame,
+    ) -> (u64, usize, usize) {
+        let origin = buf.remaining_mut();
+        let sent_pkt_records = self.0.sent_pkt_records_on_path(path_id);
+        let send_guard = sent_pkt_records.send();
+        let (pn, encoded_pn) = send_guard.next_pn();
+        if buf.remaining_mut() < encoded_pn.size() + frame.encoding_size() {
+            return (pn, encoded_pn.size(), 0);
+        }
+        buf.put_packet_number(encoded_pn);
+        buf.put_connection_close_frame(frame);
+        (pn, encoded_pn.size(), origin - buf.remaining_mut())
     }
 
     /// 接收Space相关的帧，包括数据帧
@@ -192,25 +420,54 @@ where
 
     /// 此处接收AckFrame，只负责内容，涉及RTT和传输速度控制的，path已经处理过
     pub fn on_ack(&self, ack: AckFrame) {
-        self.0.on_ack(ack);
+        self.0.on_ack(0, ack);
+    }
+
+    /// 对方确认了从未发出的包号，是违反协议的，Ref. RFC 9000 §13.1
+    pub fn check_ack(&self, path_id: u64, ack: &AckFrame) -> Result<(), Error> {
+        self.0.check_ack(path_id, ack)
+    }
+
+    /// 多路径下，确认path_id所标识的包号空间中的数据包，AckFrame确认的是序号为0的包号空间
+    pub fn on_path_ack(&self, path_id: u64, ack: AckFrame) {
+        self.0.on_ack(path_id, ack);
     }
 
     /// 当数据包在传输中丢失，通常由Path判断，通过某种通信方式告知Space，并调用该函数
     pub fn may_loss_pkt(&self, pn: u64) {
-        self.0.may_loss_pkt(pn);
+        self.0.may_loss_pkt(&self.0.sent_pkt_records, pn);
+    }
+
+    pub fn may_loss_pkt_on_path(&self, path_id: u64, pn: u64) {
+        if let Some(sent_pkt_records) = self.0.sent_pkt_records_if_exist(path_id) {
+            self.0.may_loss_pkt(&sent_pkt_records, pn);
+        }
+    }
+
+    /// 我方在path_id收包空间发出的、largest为最大确认包号的AckFrame被对方确认了，
+    /// 此前的收包记录都不必再反馈，收包记录的窗口得以向前滑动
+    pub fn inactivate_rcvd_records_on_path(&self, path_id: u64, largest: u64) {
+        self.0
+            .rcvd_pkt_records_on_path(path_id)
+            .write()
+            .inactivate_until(largest);
+    }
+
+    /// 放弃一条路径，其发包空间随之废弃，其中在途的数据包都判为丢失，待在其它路径上重传
+    pub fn abandon_path(&self, path_id: u64) {
+        self.0.abandon_path(path_id);
     }
 }
 
 impl ArcSpace<NoDataStreams> {
-    /// Initial空间和Handshake空间皆通过此函数创建
-    pub fn with_crypto_stream(crypto_stream: CryptoStream) -> Self {
-        ArcSpace(Arc::new(RawSpace {
-            reliable_frame_queue: Default::default(),
-            sent_pkt_records: Default::default(),
-            rcvd_pkt_records: Default::default(),
-            data_streams: NoDataStreams,
+    /// Initial空间和Handshake空间皆通过此函数创建，clock须与连接的拥塞控制所用时钟一致
+    pub fn with_crypto_stream(crypto_stream: CryptoStream, clock: Arc<dyn Clock>) -> Self {
+        ArcSpace(Arc::new(RawSpace::new(
+            Default::default(),
+            NoDataStreams,
             crypto_stream,
-        }))
+            clock,
+        )))
     }
 }
 
@@ -221,31 +478,286 @@ impl ArcSpace<ArcDataStreams> {
         max_bi_streams: u64,
         max_uni_streams: u64,
         crypto_stream: CryptoStream,
+        clock: Arc<dyn Clock>,
     ) -> Self {
         let reliable_frame_queue = ArcReliableFrameQueue::default();
-        ArcSpace(Arc::new(RawSpace {
-            reliable_frame_queue: reliable_frame_queue.clone(),
-            sent_pkt_records: Default::default(),
-            rcvd_pkt_records: Default::default(),
-            data_streams: ArcDataStreams::with_role_and_limit(
+        ArcSpace(Arc::new(RawSpace::new(
+            reliable_frame_queue.clone(),
+            ArcDataStreams::with_role_and_limit(
                 role,
                 max_bi_streams,
                 max_uni_streams,
                 reliable_frame_queue,
             ),
             crypto_stream,
-        }))
+            clock,
+        )))
     }
 
     pub fn data_streams(&self) -> ArcDataStreams {
         self.0.data_streams.clone()
     }
+
+    /// 服务端拒绝了0-RTT，客户端在0-RTT包中发送的所有数据都要当作丢失，在1-RTT中重传，Ref. RFC 9001 §4.6.2。
+    /// 此时还未发送过1-RTT包，数据空间中在途的都是0-RTT包。
+    /// 按记住的传输参数创建的流，要以服务端实际的传输参数params重新核定，并通知应用层。
+    /// params中的流数量上限不合法，是服务端的TRANSPORT_PARAMETER_ERROR
+    pub fn on_0rtt_rejected(&self, params: &TransportParameters) -> Result<(), Error> {
+        // 先终止超限的流，以免它们的数据被重新排队
+        self.0.data_streams.on_0rtt_rejected(
+            params.initial_max_streams_bidi().into_inner(),
+            params.initial_max_streams_uni().into_inner(),
+            [
+                params.initial_max_stream_data_bidi_remote().into_inner(),
+                params.initial_max_stream_data_uni().into_inner(),
+            ],
+            params.initial_max_data().into_inner(),
+        )?;
+        self.0.may_loss_all(&self.0.sent_pkt_records);
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use qbase::clock::RealClock;
+    use qbase::frame::{ConnFrame, MaxDataFrame, ReliableFrame};
+
+    #[test]
+    fn test_path_pn_spaces() {
+        let space =
+            ArcSpace::with_crypto_stream(CryptoStream::new(1000, 1000), Arc::new(RealClock));
+        let max_data = ConnFrame::MaxData(MaxDataFrame {
+            max_data: VarInt(0x1234),
+        });
+        space
+            .reliable_frame_queue()
+            .write()
+            .push_conn_frame(max_data.clone());
+
+        // 各路径的包号空间相互独立，包号都从0开始
+        let mut buf = [0u8; 1200];
+        let (pn, _, len, _) = space.read_on_path(1, &mut buf, None);
+        assert_eq!(pn, 0);
+        assert!(len > 0);
+        let (pn, _, _, _) = space.read(&mut buf, None);
+        assert_eq!(pn, 0);
+        let (pn, _, _, _) = space.read_on_path(1, &mut buf, None);
+        assert_eq!(pn, 1);
+        assert!(space.reliable_frame_queue().read().front().is_none());
+
+        // 未曾发过包的路径，收到对其的确认直接忽略
+        space.on_path_ack(
+            2,
+            AckFrame {
+                largest: VarInt(0),
+                delay: VarInt(0),
+                first_range: VarInt(0),
+                ranges: vec![],
+                ecn: None,
+            },
+        );
+
+        // 放弃路径1，其上在途的帧要在其它路径上重传
+        space.abandon_path(1);
+        assert_eq!(
+            space.reliable_frame_queue().read().front(),
+            Some(&ReliableFrame::Conn(max_data))
+        );
+        let (pn, _, _, _) = space.read_on_path(1, &mut buf, None);
+        assert_eq!(pn, 0);
+    }
+
     #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+    fn test_read_ack() {
+        let space =
+            ArcSpace::with_crypto_stream(CryptoStream::new(1000, 1000), Arc::new(RealClock));
+        let now = Instant::now();
+        space.on_rcvd_pn(0);
+        let mut buf = [0u8; 1200];
+
+        // 只写入了AckFrame的包不是ack-eliciting的
+        let (pn, pn_size, len, is_ack_eliciting) = space.read(&mut buf, Some((0, now)));
+        assert_eq!(pn, 0);
+        assert!(len > pn_size);
+        assert!(!is_ack_eliciting);
+
+        let max_data = ConnFrame::MaxData(MaxDataFrame {
+            max_data: VarInt(0x1234),
+        });
+        space
+            .reliable_frame_queue()
+            .write()
+            .push_conn_frame(max_data);
+        let (_, _, _, is_ack_eliciting) = space.read(&mut buf, Some((0, now)));
+        assert!(is_ack_eliciting);
+
+        // ACK-only的包不会带上其它帧
+        space
+            .reliable_frame_queue()
+            .write()
+            .push_conn_frame(ConnFrame::MaxData(MaxDataFrame {
+                max_data: VarInt(0x5678),
+            }));
+        let (pn, pn_size, len) = space.read_ack(0, &mut buf, Some((0, (0, now))));
+        assert_eq!(pn, 2);
+        assert!(len > pn_size);
+        assert!(space.reliable_frame_queue().read().front().is_some());
+        // 没有AckFrame，只写入包号
+        let (pn, pn_size, len) = space.read_ack(0, &mut buf, None);
+        assert_eq!(pn, 3);
+        assert_eq!(len, pn_size);
+    }
+
+    #[test]
+    fn test_spurious_loss() {
+        let space =
+            ArcSpace::with_crypto_stream(CryptoStream::new(1000, 1000), Arc::new(RealClock));
+        let ack = |pn: u32| AckFrame {
+            largest: VarInt::from_u32(pn),
+            delay: VarInt(0),
+            first_range: VarInt(0),
+            ranges: vec![],
+            ecn: None,
+        };
+        let max_data = |max_data: u32| {
+            ConnFrame::MaxData(MaxDataFrame {
+                max_data: VarInt::from_u32(max_data),
+            })
+        };
+        let mut buf = [0u8; 1200];
+        for n in 0..2 {
+            space
+                .reliable_frame_queue()
+                .write()
+                .push_conn_frame(max_data(n));
+            space.read(&mut buf, None);
+        }
+
+        // 0号包被判定丢失，其中的帧等待重传，又收到了它迟到的确认，就不必再重传了
+        space.may_loss_pkt(0);
+        assert_eq!(
+            space.reliable_frame_queue().read().front(),
+            Some(&ReliableFrame::Conn(max_data(0)))
+        );
+        space.on_ack(ack(0));
+        assert!(space.reliable_frame_queue().read().front().is_none());
+
+        // 1号包被判定丢失，其中的帧已经重传了，迟到的确认也无妨
+        space.may_loss_pkt(1);
+        let (pn, _, _, _) = space.read(&mut buf, None);
+        assert_eq!(pn, 2);
+        space.on_ack(ack(2));
+        space.on_ack(ack(1));
+        assert!(space.reliable_frame_queue().read().front().is_none());
+    }
+
+    #[test]
+    fn test_ack_huge_range() {
+        let space =
+            ArcSpace::with_crypto_stream(CryptoStream::new(1000, 1000), Arc::new(RealClock));
+        let mut buf = [0u8; 1200];
+        for n in 0..2 {
+            space
+                .reliable_frame_queue()
+                .write()
+                .push_conn_frame(ConnFrame::MaxData(MaxDataFrame {
+                    max_data: VarInt::from_u32(n),
+                }));
+            space.read(&mut buf, None);
+        }
+
+        // 确认了从未发出的包号，违反协议
+        let huge = AckFrame {
+            largest: VarInt::from_u64((1 << 62) - 1).unwrap(),
+            delay: VarInt(0),
+            first_range: VarInt::from_u64((1 << 62) - 1).unwrap(),
+            ranges: vec![],
+            ecn: None,
+        };
+        assert!(space.check_ack(0, &huge).is_err());
+        // 即便如此，超大的确认区间也只遍历已发出的包号，不会卡住
+        space.on_ack(huge.clone());
+
+        let ack = AckFrame {
+            largest: VarInt::from_u32(1),
+            delay: VarInt(0),
+            first_range: VarInt::from_u32(1),
+            ranges: vec![],
+            ecn: None,
+        };
+        assert!(space.check_ack(0, &ack).is_ok());
+        // 没有在其它包号空间发过包，也就无从判断
+        assert!(space.check_ack(1, &huge).is_ok());
+    }
+
+    #[test]
+    fn test_read_conn_close() {
+        use qbase::{error::ErrorKind, frame::FrameType};
+
+        let space =
+            ArcSpace::with_crypto_stream(CryptoStream::new(1000, 1000), Arc::new(RealClock));
+        let frame = ConnectionCloseFrame::new(
+            ErrorKind::ProtocolViolation,
+            Some(FrameType::Ping),
+            "bye".into(),
+        );
+        let mut buf = [0u8; 1200];
+        let (pn, pn_size, len) = space.read_conn_close(0, &mut buf, &frame);
+        assert_eq!(pn, 0);
+        assert_eq!(len, pn_size + frame.encoding_size());
+        // 缓冲区不够，什么也不写
+        let (_, _, len) = space.read_conn_close(0, &mut buf[..4], &frame);
+        assert_eq!(len, 0);
+
+        // 关闭时发出的包不计入发包记录，判定丢失也不会重传
+        let (pn, _, _) = space.read_conn_close(0, &mut buf, &frame);
+        assert_eq!(pn, 2);
+        space.may_loss_pkt(0);
+        assert!(space.reliable_frame_queue().read().front().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_0rtt_rejected() {
+        use futures::StreamExt;
+        use qbase::streamid::StreamId;
+        use tokio::io::AsyncWriteExt;
+
+        let space = ArcSpace::new(
+            Role::Client,
+            3,
+            2,
+            CryptoStream::new(1000, 1000),
+            Arc::new(RealClock),
+        );
+        let streams = space.data_streams();
+        let mut writers = vec![];
+        for len in [100, 10, 10, 10] {
+            let (_reader, mut writer) = streams.open_bi().await.unwrap().unwrap();
+            writer.write_all(&vec![0u8; len]).await.unwrap();
+            writers.push(writer);
+        }
+
+        // 服务端实际只允许3个双向流，流窗口只有50字节，连接级窗口也只有15字节
+        let mut params = TransportParameters::default();
+        params.set_initial_max_streams_bidi(VarInt(3));
+        params.set_initial_max_stream_data_bidi_remote(VarInt(50));
+        params.set_initial_max_data(VarInt(15));
+        space.on_0rtt_rejected(&params).unwrap();
+
+        let sid = |id: u32| StreamId::from(VarInt::from_u32(id));
+        let event = streams.zero_rtt_rejected().next().await.unwrap();
+        assert_eq!(event.requeued, vec![sid(4)]);
+        assert_eq!(event.aborted, vec![sid(0), sid(8), sid(12)]);
+        assert!(writers[0].write(&[0]).await.is_err());
+        assert!(writers[1].write(&[0]).await.is_ok());
+        assert!(writers[2].write(&[0]).await.is_err());
+
+        // 流数量上限超出2^60，是服务端的TRANSPORT_PARAMETER_ERROR
+        params.set_initial_max_streams_bidi(VarInt::from_u64((1 << 60) + 1).unwrap());
+        assert!(space.on_0rtt_rejected(&params).is_err());
+        // 超出上限的流ID不再分配，只能等对方的MAX_STREAMS
+        assert!(futures::poll!(streams.open_bi()).is_pending());
     }
 }
@@ -1,6 +1,11 @@
 use crate::{recv::Reader, reliable::ArcReliableFrameQueue, send::Writer};
 use futures::Future;
-use qbase::{error::Error, frame::*, streamid::Role};
+use qbase::{
+    error::Error,
+    frame::*,
+    streamid::{Role, StreamId},
+    util::ArcAsyncQueue,
+};
 use std::{
     fmt::Debug,
     pin::Pin,
@@ -32,6 +37,16 @@ pub mod data;
 pub mod listener;
 pub mod none;
 
+/// 服务端拒绝了0-RTT，客户端在0-RTT中发送的数据都要在1-RTT中重传。
+/// 服务端从未处理过这些数据，重传并不构成重放，但应用层仍可据此决定是否放弃那些非幂等的请求
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ZeroRttRejected {
+    /// 数据已重新排队，将在1-RTT中重传的流
+    pub requeued: Vec<StreamId>,
+    /// 超出了服务端实际的流数量上限或流窗口，被迫终止的流
+    pub aborted: Vec<StreamId>,
+}
+
 #[derive(Debug, Clone)]
 pub struct ArcDataStreams(Arc<data::RawDataStreams>);
 
@@ -98,6 +113,22 @@ impl ArcDataStreams {
     pub fn listener(&self) -> listener::ArcListener {
         self.0.listener()
     }
+
+    /// 0-RTT被拒的通知，至多一次，见[`ZeroRttRejected`]
+    pub fn zero_rtt_rejected(&self) -> ArcAsyncQueue<ZeroRttRejected> {
+        self.0.zero_rtt_rejected()
+    }
+
+    pub(crate) fn on_0rtt_rejected(
+        &self,
+        max_bi_streams: u64,
+        max_uni_streams: u64,
+        max_stream_data: [u64; 2],
+        max_data: u64,
+    ) -> Result<(), Error> {
+        self.0
+            .on_0rtt_rejected(max_bi_streams, max_uni_streams, max_stream_data, max_data)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -1,4 +1,4 @@
-use super::listener::ArcListener;
+use super::{listener::ArcListener, ZeroRttRejected};
 use crate::{
     recv::{self, Incoming, Reader},
     reliable::ArcReliableFrameQueue,
@@ -8,6 +8,7 @@ use qbase::{
     error::{Error as QuicError, ErrorKind},
     frame::*,
     streamid::{AcceptSid, Dir, ExceedLimitError, Role, StreamId, StreamIds},
+    util::ArcAsyncQueue,
     varint::VarInt,
 };
 use std::{
@@ -114,6 +115,8 @@ pub(super) struct RawDataStreams {
     input: ArcInput,
     // 对方主动创建的流
     listener: ArcListener,
+    // 0-RTT被拒的通知，交由应用层决定是否重放那些非幂等的请求
+    zero_rtt_rejected: ArcAsyncQueue<ZeroRttRejected>,
 
     // 该queue与space中的transmitter中的frame_queue共享，为了方便向transmitter中写入帧
     reliable_frame_queue: ArcReliableFrameQueue,
@@ -338,6 +341,7 @@ impl super::ReceiveStream for RawDataStreams {
         output.on_conn_error(err);
         input.on_conn_error(err);
         listener.on_conn_error(err);
+        self.zero_rtt_rejected.close();
     }
 }
 
@@ -354,10 +358,76 @@ impl RawDataStreams {
             output: ArcOutput::default(),
             input: ArcInput::default(),
             listener: ArcListener::default(),
+            zero_rtt_rejected: ArcAsyncQueue::new(),
             reliable_frame_queue,
         }
     }
 
+    pub(super) fn zero_rtt_rejected(&self) -> ArcAsyncQueue<ZeroRttRejected> {
+        self.zero_rtt_rejected.clone()
+    }
+
+    /// 0-RTT被拒，按对方实际的传输参数重新核定我方已创建的流：
+    /// 流ID超出了新上限、或已写入的数据超出了新的流窗口的，只能终止；其余的流照常在1-RTT中发送。
+    /// 已写入的数据都要重新发送，总量也不能超过对方的initial_max_data，按流ID从小到大，超出的流也只能终止。
+    /// max_stream_data依次是对方给我方创建的双向流、单向流的初始窗口
+    pub(super) fn on_0rtt_rejected(
+        &self,
+        max_bi_streams: u64,
+        max_uni_streams: u64,
+        max_stream_data: [u64; 2],
+        max_data: u64,
+    ) -> Result<(), QuicError> {
+        let Ok(mut output) = self.output.guard() else {
+            return Ok(());
+        };
+        let Ok(mut input) = self.input.guard() else {
+            return Ok(());
+        };
+        let local = &self.stream_ids.local;
+        local.reset_max_sid(Dir::Bi, max_bi_streams)?;
+        local.reset_max_sid(Dir::Uni, max_uni_streams)?;
+
+        let mut event = ZeroRttRejected::default();
+        let Ok(set) = output.inner.as_mut() else {
+            unreachable!("output is invalid")
+        };
+        let mut sids: Vec<StreamId> = set
+            .keys()
+            .filter(|sid| sid.role() == self.role)
+            .copied()
+            .collect();
+        sids.sort();
+        let mut conn_data = 0;
+        for sid in sids {
+            let outgoing = &set[&sid];
+            let written = outgoing.written();
+            if local.is_permitted(sid)
+                && conn_data + written <= max_data
+                && outgoing.reset_window(max_stream_data[sid.dir() as usize])
+            {
+                conn_data += written;
+                event.requeued.push(sid);
+                continue;
+            }
+            let err = QuicError::new_with_default_fty(
+                ErrorKind::StreamLimit,
+                format!("{sid} exceeds the limits of peer after 0-RTT was rejected"),
+            );
+            if let Some(outgoing) = set.remove(&sid) {
+                outgoing.on_conn_error(&err);
+            }
+            if let Ok(set) = input.inner.as_mut() {
+                if let Some(incoming) = set.remove(&sid) {
+                    incoming.on_conn_error(&err);
+                }
+            }
+            event.aborted.push(sid);
+        }
+        self.zero_rtt_rejected.push(event);
+        Ok(())
+    }
+
     pub(super) fn poll_open_bi_stream(
         &self,
         cx: &mut Context<'_>,